@@ -0,0 +1,119 @@
+/*
+ * modlem: A graphics importer/exporter for Lemmings
+ * Copyright (C) 2022–2026 David Gow <david@davidgow.net>
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use json::Value;
+use planar_bmp::{PaletteRGB, PlanarBMP};
+
+/// The minimum width of an atlas. Wider pieces will widen the atlas to fit.
+const MIN_ATLAS_WIDTH : usize = 512;
+
+/// A single image (and its mask) placed in an atlas.
+pub struct AtlasEntry {
+    pub name : String,
+    pub x : usize,
+    pub y : usize,
+    pub width : usize,
+    pub height : usize,
+    /// Extra fields describing this entry, for its JSON description (see schemas::atlas()).
+    pub metadata : Vec<(&'static str, Value)>,
+    image : PlanarBMP,
+    mask : PlanarBMP,
+}
+
+/// Collects a number of images, and packs them into a single image (and a parallel mask image),
+/// using a simple 'shelf' packing algorithm.
+#[derive(Default)]
+pub struct Atlas {
+    pub entries : Vec<AtlasEntry>,
+    pub width : usize,
+    pub height : usize,
+}
+
+impl Atlas {
+    pub fn new() -> Atlas {
+        Atlas::default()
+    }
+
+    /// Add an image and its (1-plane) mask to the atlas. Positions are assigned by pack().
+    pub fn add(&mut self, name : &str, image : PlanarBMP, mask : PlanarBMP, metadata : Vec<(&'static str, Value)>) {
+        assert_eq!(image.width, mask.width);
+        assert_eq!(image.height, mask.height);
+        self.entries.push(AtlasEntry {
+            name : name.to_string(),
+            x : 0,
+            y : 0,
+            width : image.width,
+            height : image.height,
+            metadata,
+            image,
+            mask,
+        });
+    }
+
+    /// Assign a position to every entry. Entries are placed, tallest first, left-to-right in
+    /// rows ('shelves'), starting a new shelf whenever the current one is full.
+    pub fn pack(&mut self) {
+        let widest = self.entries.iter().map(|e| e.width).max().unwrap_or(0);
+        self.width = std::cmp::max(MIN_ATLAS_WIDTH, widest.next_multiple_of(8));
+
+        let mut order : Vec<usize> = (0..self.entries.len()).collect();
+        order.sort_by(|&a, &b| self.entries[b].height.cmp(&self.entries[a].height));
+
+        let mut shelf_x = 0;
+        let mut shelf_y = 0;
+        let mut shelf_height = 0;
+        for idx in order {
+            let entry = &mut self.entries[idx];
+            if shelf_x + entry.width > self.width {
+                shelf_y += shelf_height;
+                shelf_x = 0;
+                shelf_height = 0;
+            }
+            entry.x = shelf_x;
+            entry.y = shelf_y;
+            shelf_x += entry.width;
+            shelf_height = std::cmp::max(shelf_height, entry.height);
+        }
+        self.height = shelf_y + shelf_height;
+    }
+
+    /// Assign a position to every entry, with each on its own row, in the order they were added.
+    pub fn pack_rows(&mut self) {
+        let widest = self.entries.iter().map(|e| e.width).max().unwrap_or(0);
+        self.width = widest.next_multiple_of(8);
+
+        let mut row_y = 0;
+        for entry in &mut self.entries {
+            entry.x = 0;
+            entry.y = row_y;
+            row_y += entry.height;
+        }
+        self.height = row_y;
+    }
+
+    /// Render the packed atlas, returning the image and the mask.
+    pub fn render(&self, planes : usize, pal : &PaletteRGB) -> (PlanarBMP, PlanarBMP) {
+        let height = std::cmp::max(self.height, 1);
+        let mut image = PlanarBMP::new(self.width, height, planes, pal).unwrap();
+        let mut mask = PlanarBMP::new(self.width, height, 1, pal).unwrap();
+        for entry in &self.entries {
+            image.blit(&entry.image, entry.x, entry.y);
+            mask.blit(&entry.mask, entry.x, entry.y);
+        }
+        (image, mask)
+    }
+}
@@ -0,0 +1,191 @@
+/*
+ * modlem: A graphics importer/exporter for Lemmings
+ * Copyright (C) 2022–2026 David Gow <david@davidgow.net>
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use binary_io::OutputFile;
+use case_sensitivity::match_existing_case;
+use log::Log;
+use std::path::{Path, PathBuf};
+
+/// The name of the backup of @path, i.e. [path].bak.
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".bak");
+    PathBuf::from(backup)
+}
+
+/// The name of the file @path is written to until it's finished, i.e. [path].new.
+fn new_path(path: &Path) -> PathBuf {
+    let mut new = path.as_os_str().to_owned();
+    new.push(".new");
+    PathBuf::from(new)
+}
+
+/// The file @path is a symlink to (following any links that links to), or @path itself if it isn't
+/// one, or it's a dangling link.
+fn link_target(path: &Path) -> PathBuf {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// A file being written in place of another, by create_with_backup(). It's written to
+/// [path].new, which only replaces the file (backing it up first) when finish() is called, so
+/// the original is left as it was if anything goes wrong before then.
+pub struct ReplacementFile {
+    file: Option<OutputFile>,
+    new_path: PathBuf,
+    path: PathBuf,
+    backup: bool,
+    log: Log,
+    replaced: bool,
+}
+
+impl ReplacementFile {
+    /// Finish writing the file, and put it in place of the original (if there was one). If
+    /// backups were asked for, the original is renamed to [path].bak first, unless there's a
+    /// backup already: it's never replaced, as it's most likely the original, rather than the
+    /// output of an earlier run.
+    pub fn finish(mut self) -> std::io::Result<()> {
+        let log = &self.log;
+        let path = self.path.as_path();
+        self.file.take().unwrap().finish()
+            .map_err(|err| std::io::Error::new(err.kind(), format!("Error writing {}: {}", self.new_path.display(), err)))?;
+        if self.backup && path.exists() {
+            let backup = backup_path(path);
+            if backup.exists() {
+                log_detail!(log, "Keeping the existing backup {}", backup.display());
+            } else {
+                log_detail!(log, "Backing up {} to {}", path.display(), backup.display());
+                if let Err(err) = std::fs::rename(path, &backup) {
+                    return Err(std::io::Error::new(err.kind(), format!("Error backing up {}: {}", path.display(), err)));
+                }
+            }
+        }
+        std::fs::rename(&self.new_path, path)
+            .map_err(|err| std::io::Error::new(err.kind(), format!("Error replacing {}: {}", path.display(), err)))?;
+        self.replaced = true;
+        Ok(())
+    }
+}
+
+impl std::io::Write for ReplacementFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.file.as_mut().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.as_mut().unwrap().flush()
+    }
+}
+
+impl Drop for ReplacementFile {
+    fn drop(&mut self) {
+        // It wasn't finished (or couldn't be), so the original stays as it is.
+        if !self.replaced {
+            drop(self.file.take());
+            let _ = std::fs::remove_file(&self.new_path);
+        }
+    }
+}
+
+/// Create @path for writing (see ReplacementFile), backing up any existing file when it's
+/// finished if @backup is set, so that the original game files aren't lost.
+/// If @path exists with its name in a different case, that file is the one replaced. If it's a
+/// symlink, the file it links to is the one backed up and replaced, and the link is left as it is.
+pub fn create_with_backup(path: &Path, backup: bool, log: &Log) -> std::io::Result<ReplacementFile> {
+    let existing = match_existing_case(path);
+    if existing != path {
+        log_info!(log, "Writing {} instead of {}, as it's already there", existing.display(), path.display());
+    }
+    let target = link_target(&existing);
+    if target != existing {
+        log_info!(log, "Writing {}, which {} links to", target.display(), existing.display());
+    }
+    let new_path = new_path(&target);
+    let file = OutputFile::create(&new_path).map_err(|err| std::io::Error::new(err.kind(), format!("Error opening {}: {}", new_path.display(), err)))?;
+    Ok(ReplacementFile { file: Some(file), new_path, path: target, backup, log: *log, replaced: false })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// Replace @path with @data, backing it up.
+    fn write_with_backup(path: &Path, data: &[u8]) {
+        let mut file = create_with_backup(path, true, &Log::default()).unwrap();
+        file.write_all(data).unwrap();
+        file.finish().unwrap();
+    }
+
+    #[test]
+    fn backup_create_with_backup() {
+        let dir = std::env::temp_dir().join(format!("modlem_backup_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("main.dat");
+        std::fs::write(&path, b"original").unwrap();
+
+        write_with_backup(&path, b"first");
+        write_with_backup(&path, b"second");
+        assert_eq!(std::fs::read(&path).unwrap(), b"second");
+        assert_eq!(std::fs::read(backup_path(&path)).unwrap(), b"original");
+
+        // Until it's finished, the file's left as it is, and if it never is, nothing's changed.
+        let mut file = create_with_backup(&path, true, &Log::default()).unwrap();
+        file.write_all(b"third").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"second");
+        drop(file);
+        assert_eq!(std::fs::read(&path).unwrap(), b"second");
+        assert!(!new_path(&path).exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn backup_create_with_backup_existing_case() {
+        let dir = std::env::temp_dir().join(format!("modlem_backup_case_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("GROUND0O.DAT"), b"original").unwrap();
+
+        write_with_backup(&dir.join("ground0o.dat"), b"new");
+        let mut names: Vec<_> = std::fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        names.sort();
+        assert_eq!(names, ["GROUND0O.DAT", "GROUND0O.DAT.bak"]);
+        assert_eq!(std::fs::read(dir.join("GROUND0O.DAT")).unwrap(), b"new");
+        assert_eq!(std::fs::read(dir.join("GROUND0O.DAT.bak")).unwrap(), b"original");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn backup_create_with_backup_symlink() {
+        let dir = std::env::temp_dir().join(format!("modlem_backup_symlink_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("game")).unwrap();
+        std::fs::write(dir.join("game/main.dat"), b"original").unwrap();
+        std::os::unix::fs::symlink(dir.join("game/main.dat"), dir.join("MAIN.DAT")).unwrap();
+
+        write_with_backup(&dir.join("main.dat"), b"new");
+        assert!(std::fs::symlink_metadata(dir.join("MAIN.DAT")).unwrap().file_type().is_symlink());
+        assert_eq!(std::fs::read(dir.join("MAIN.DAT")).unwrap(), b"new");
+        assert_eq!(std::fs::read(dir.join("game/main.dat.bak")).unwrap(), b"original");
+        assert!(!dir.join("main.dat").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
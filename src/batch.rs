@@ -0,0 +1,125 @@
+/*
+ * modlem: A graphics importer/exporter for Lemmings
+ * Copyright (C) 2022–2026 David Gow <david@davidgow.net>
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Batch files: a modlem command line on each line, as it would be typed after "modlem", with
+//! blank lines and comments (from a # to the end of the line) ignored. Arguments with spaces
+//! in can be quoted, with "double" or 'single' quotes. Within double quotes, \" and \\ stand
+//! for " and \.
+
+/// One of the commands in a batch file.
+#[derive(Debug, PartialEq)]
+pub struct BatchCommand {
+    /// The line it's on, counting from 1.
+    pub line : usize,
+    pub args : Vec<String>,
+}
+
+/// How a command in a batch file went.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Ok,
+    /// It ran, but found something wrong (as check-level and diff-levels do).
+    Problems,
+    Failed,
+    /// It wasn't run, as an earlier command failed.
+    Skipped,
+}
+
+impl Outcome {
+    pub fn name(self) -> &'static str {
+        match self {
+            Outcome::Ok => "ok",
+            Outcome::Problems => "problems",
+            Outcome::Failed => "failed",
+            Outcome::Skipped => "skipped",
+        }
+    }
+}
+
+/// Split @line into its arguments, or say what's wrong with it.
+pub fn split_line(line : &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        match chars.peek() {
+            None | Some('#') => return Ok(args),
+            _ => {}
+        }
+        let mut arg = String::new();
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+            match c {
+                '\'' => loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => arg.push(c),
+                        None => return Err("Unterminated ' quote".to_string()),
+                    }
+                },
+                '"' => loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') if chars.peek().is_some_and(|c| *c == '"' || *c == '\\') => arg.push(chars.next().unwrap()),
+                        Some(c) => arg.push(c),
+                        None => return Err("Unterminated \" quote".to_string()),
+                    }
+                },
+                c => arg.push(c),
+            }
+        }
+        args.push(arg);
+    }
+}
+
+/// Parse the batch file @text into its commands.
+pub fn parse(text : &str) -> Result<Vec<BatchCommand>, String> {
+    let mut commands = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let args = split_line(line).map_err(|err| format!("{}: {}", index + 1, err))?;
+        if !args.is_empty() {
+            commands.push(BatchCommand { line : index + 1, args });
+        }
+    }
+    Ok(commands)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch_parse() {
+        let text = "# Build the pack\n\
+                    create-levels pack --no-backup   # the levels\n\
+                    \n\
+                    render-level \"my levels.dat\" 3 -o 'level 3.png'\n\
+                    create-set \"C:\\\\themes\\\\theme9.txt\" --strict\n\
+                    list-levels a#b.dat \"say \\\"hi\\\"\"\n";
+        let commands = parse(text).unwrap();
+        let args : Vec<Vec<&str>> = commands.iter().map(|command| command.args.iter().map(|arg| arg.as_str()).collect()).collect();
+        assert_eq!(args, [
+            vec!["create-levels", "pack", "--no-backup"],
+            vec!["render-level", "my levels.dat", "3", "-o", "level 3.png"],
+            vec!["create-set", "C:\\themes\\theme9.txt", "--strict"],
+            vec!["list-levels", "a#b.dat", "say \"hi\""],
+        ]);
+        assert_eq!(commands.iter().map(|command| command.line).collect::<Vec<_>>(), [2, 4, 5, 6]);
+        assert_eq!(split_line("extract-lvl level000.dat 3 -o C:\\out.lvl").unwrap()[4], "C:\\out.lvl");
+
+        assert_eq!(parse("extract-all\nrender-level \"level000.dat 3\n"), Err("2: Unterminated \" quote".to_string()));
+    }
+}
@@ -17,6 +17,67 @@
 
 #![allow(dead_code)]
 
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// An integer type which can be read and written by read_int() and write_int().
+pub trait Integer : Sized {
+    /// The integer's bytes, as [u8; size].
+    type Bytes : AsRef<[u8]> + AsMut<[u8]> + Default;
+    fn from_le_bytes(bytes : Self::Bytes) -> Self;
+    fn from_be_bytes(bytes : Self::Bytes) -> Self;
+    fn to_le_bytes(self) -> Self::Bytes;
+    fn to_be_bytes(self) -> Self::Bytes;
+}
+
+macro_rules! impl_integer {
+    ($($int:ident : $size:expr),*) => {
+        $(impl Integer for $int {
+            type Bytes = [u8; $size];
+            fn from_le_bytes(bytes : Self::Bytes) -> Self { $int::from_le_bytes(bytes) }
+            fn from_be_bytes(bytes : Self::Bytes) -> Self { $int::from_be_bytes(bytes) }
+            fn to_le_bytes(self) -> Self::Bytes { $int::to_le_bytes(self) }
+            fn to_be_bytes(self) -> Self::Bytes { $int::to_be_bytes(self) }
+        })*
+    };
+}
+
+impl_integer!(u8 : 1, i8 : 1, u16 : 2, i16 : 2, u32 : 4, i32 : 4);
+
+/// A byte order: either Le (little-endian) or Be (big-endian).
+pub trait Endianness {
+    fn decode<T : Integer>(bytes : T::Bytes) -> T;
+    fn encode<T : Integer>(value : T) -> T::Bytes;
+}
+
+/// Little-endian: the least significant byte first.
+pub struct Le;
+/// Big-endian: the most significant byte first.
+pub struct Be;
+
+impl Endianness for Le {
+    fn decode<T : Integer>(bytes : T::Bytes) -> T { T::from_le_bytes(bytes) }
+    fn encode<T : Integer>(value : T) -> T::Bytes { value.to_le_bytes() }
+}
+
+impl Endianness for Be {
+    fn decode<T : Integer>(bytes : T::Bytes) -> T { T::from_be_bytes(bytes) }
+    fn encode<T : Integer>(value : T) -> T::Bytes { value.to_be_bytes() }
+}
+
+/// Reads an integer of type T, in byte order E, e.g. read_int::<u32, Be>(reader).
+pub fn read_int<T : Integer, E : Endianness>(reader : &mut dyn std::io::Read) -> std::io::Result<T> {
+    let mut bytes = T::Bytes::default();
+    reader.read_exact(bytes.as_mut())?;
+    Ok(E::decode(bytes))
+}
+
+/// Writes an integer of type T, in byte order E, e.g. write_int::<u32, Be>(value, writer).
+pub fn write_int<T : Integer, E : Endianness>(value : T, writer : &mut dyn std::io::Write) -> std::io::Result<()> {
+    writer.write_all(E::encode(value).as_ref())
+}
+
 /// Reads a single byte (unsigned, 8-bit integer) from an input stream.
 pub fn read_byte(reader: &mut dyn std::io::Read) -> std::io::Result<u8> {
     let mut out_byte : u8 = 0;
@@ -26,30 +87,82 @@ pub fn read_byte(reader: &mut dyn std::io::Read) -> std::io::Result<u8> {
 
 /// Reads a little-endian unsigned 16-bit integer from a stream.
 pub fn read_le16(reader : &mut dyn std::io::Read) -> std::io::Result<u16> {
-    let mut raw_bytes = [0_u8; 2];
-    reader.read_exact(&mut raw_bytes)?;
-    Ok(u16::from_le_bytes(raw_bytes))
+    read_int::<u16, Le>(reader)
 }
 
 /// Reads a little-endian unsigned 32-bit integer from a stream.
 pub fn read_le32(reader : &mut dyn std::io::Read) -> std::io::Result<u32> {
-    let mut raw_bytes = [0_u8; 4];
-    reader.read_exact(&mut raw_bytes)?;
-    Ok(u32::from_le_bytes(raw_bytes))
+    read_int::<u32, Le>(reader)
 }
 
 /// Reads a big-endian unsigned 16-bit integer from a stream.
 pub fn read_be16(reader : &mut dyn std::io::Read) -> std::io::Result<u16> {
-    let mut raw_bytes = [0_u8; 2];
-    reader.read_exact(&mut raw_bytes)?;
-    Ok(u16::from_be_bytes(raw_bytes))
+    read_int::<u16, Be>(reader)
 }
 
 /// Reads a big-endian unsigned 32-bit integer from a stream.
 pub fn read_be32(reader : &mut dyn std::io::Read) -> std::io::Result<u32> {
-    let mut raw_bytes = [0_u8; 4];
-    reader.read_exact(&mut raw_bytes)?;
-    Ok(u32::from_be_bytes(raw_bytes))
+    read_int::<u32, Be>(reader)
+}
+
+/// Reads a little-endian signed 16-bit integer from a stream.
+pub fn read_le_i16(reader : &mut dyn std::io::Read) -> std::io::Result<i16> {
+    read_int::<i16, Le>(reader)
+}
+
+/// Reads a big-endian signed 16-bit integer from a stream.
+pub fn read_be_i16(reader : &mut dyn std::io::Read) -> std::io::Result<i16> {
+    read_int::<i16, Be>(reader)
+}
+
+/// Reads a little-endian signed 32-bit integer from a stream.
+pub fn read_le_i32(reader : &mut dyn std::io::Read) -> std::io::Result<i32> {
+    read_int::<i32, Le>(reader)
+}
+
+/// Reads @len bytes from a stream. They aren't all allocated up front, so a bad length from
+/// a corrupt or truncated file is only an error, rather than running out of memory.
+pub fn read_bytes(reader : &mut dyn std::io::Read, len : usize) -> std::io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    Read::take(reader, len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len {
+        return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof,
+            format!("expected {} bytes, but there were only {}", len, bytes.len())));
+    }
+    Ok(bytes)
+}
+
+/// Reads a fixed number of bytes from a stream.
+pub fn read_array<const N : usize>(reader : &mut dyn std::io::Read) -> std::io::Result<[u8; N]> {
+    let mut bytes = [0_u8; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Reads a @len byte field, and returns it without any trailing @pad bytes. Every other byte
+/// is kept as-is.
+pub fn read_fixed_bytes(reader : &mut dyn std::io::Read, len : usize, pad : u8) -> std::io::Result<Vec<u8>> {
+    let mut bytes = read_bytes(reader, len)?;
+    let trimmed = bytes.iter().rposition(|&c| c != pad).map_or(0, |end| end + 1);
+    bytes.truncate(trimmed);
+    Ok(bytes)
+}
+
+/// Reads a @len byte Latin-1 string, without any trailing @pad bytes. As every byte is a
+/// Latin-1 character, nothing is lost.
+pub fn read_fixed_str(reader : &mut dyn std::io::Read, len : usize, pad : u8) -> std::io::Result<String> {
+    Ok(read_fixed_bytes(reader, len, pad)?.iter().map(|&c| c as char).collect())
+}
+
+/// Reads and discards @len bytes from a stream, which doesn't have to be seekable.
+pub fn skip_bytes(reader : &mut dyn std::io::Read, mut len : usize) -> std::io::Result<()> {
+    let mut scratch = [0_u8; 256];
+    while len > 0 {
+        let chunk = len.min(scratch.len());
+        reader.read_exact(&mut scratch[..chunk])?;
+        len -= chunk;
+    }
+    Ok(())
 }
 
 /// Writes a single byte (unsigned, 8-bit integer) to a stream.
@@ -59,24 +172,545 @@ pub fn write_byte(out_byte : u8, writer: &mut dyn std::io::Write) -> std::io::Re
 
 /// Writes a big-endian unsigned 16-bit integer to a stream.
 pub fn write_be16(out_val : u16, writer: &mut dyn std::io::Write) -> std::io::Result<()> {
-    let raw_bytes = out_val.to_be_bytes();
-    writer.write_all(&raw_bytes)
+    write_int::<u16, Be>(out_val, writer)
 }
 
 /// Writes a big-endian unsigned 32-bit integer to a stream.
 pub fn write_be32(out_val : u32, writer: &mut dyn std::io::Write) -> std::io::Result<()> {
-    let raw_bytes = out_val.to_be_bytes();
-    writer.write_all(&raw_bytes)
+    write_int::<u32, Be>(out_val, writer)
 }
 
 /// Writes a little-endian unsigned 16-bit integer to a stream.
 pub fn write_le16(out_val : u16, writer: &mut dyn std::io::Write) -> std::io::Result<()> {
-    let raw_bytes = out_val.to_le_bytes();
-    writer.write_all(&raw_bytes)
+    write_int::<u16, Le>(out_val, writer)
 }
 
 /// Writes a little-endian unsigned 32-bit integer to a stream.
 pub fn write_le32(out_val : u32, writer: &mut dyn std::io::Write) -> std::io::Result<()> {
-    let raw_bytes = out_val.to_le_bytes();
-    writer.write_all(&raw_bytes)
+    write_int::<u32, Le>(out_val, writer)
+}
+
+/// Writes a little-endian signed 16-bit integer to a stream.
+pub fn write_le_i16(out_val : i16, writer: &mut dyn std::io::Write) -> std::io::Result<()> {
+    write_int::<i16, Le>(out_val, writer)
+}
+
+/// Writes a big-endian signed 16-bit integer to a stream.
+pub fn write_be_i16(out_val : i16, writer: &mut dyn std::io::Write) -> std::io::Result<()> {
+    write_int::<i16, Be>(out_val, writer)
+}
+
+/// Writes a little-endian signed 32-bit integer to a stream.
+pub fn write_le_i32(out_val : i32, writer: &mut dyn std::io::Write) -> std::io::Result<()> {
+    write_int::<i32, Le>(out_val, writer)
+}
+
+/// Writes @bytes as a @len byte field, padded with @pad. If there are more than @len bytes,
+/// it's an error, unless @truncate is set, in which case only the first @len are written.
+pub fn write_fixed_bytes(bytes : &[u8], len : usize, pad : u8, truncate : bool, writer : &mut dyn std::io::Write) -> std::io::Result<()> {
+    if bytes.len() > len && !truncate {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+            format!("expected at most {} bytes, but there were {}", len, bytes.len())));
+    }
+    let used = bytes.len().min(len);
+    writer.write_all(&bytes[..used])?;
+    writer.write_all(&vec![pad; len - used])
+}
+
+/// Writes @s as a @len byte Latin-1 string, padded with @pad. Over-length strings are treated
+/// as in write_fixed_bytes(), and characters outside Latin-1 are an error.
+pub fn write_fixed_str(s : &str, len : usize, pad : u8, truncate : bool, writer : &mut dyn std::io::Write) -> std::io::Result<()> {
+    let mut bytes = Vec::with_capacity(s.len());
+    for c in s.chars() {
+        match c as u32 {
+            0..=0xFF => bytes.push(c as u8),
+            _ => return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+                format!("'{}' in \"{}\" isn't a Latin-1 character", c, s))),
+        }
+    }
+    write_fixed_bytes(&bytes, len, pad, truncate, writer)
+}
+
+/// Put @context (like "reading object 12") in front of any error's message, so it says what
+/// was being read. The error keeps its kind, and contexts can be nested.
+pub fn with_context<T, C : std::fmt::Display>(result : std::io::Result<T>, context : C) -> std::io::Result<T> {
+    result.map_err(|err| std::io::Error::new(err.kind(), format!("{}: {}", context, err)))
+}
+
+/// Name the field being read in any error, as "field @field: ...".
+pub fn with_field<T>(field : &'static str, result : std::io::Result<T>) -> std::io::Result<T> {
+    with_context(result, format_args!("field {}", field))
+}
+
+/// Reads from a slice of bytes, keeping track of the position, so errors can say where in the
+/// data they were. It can be used as a Read, with the same errors.
+///
+/// ```
+/// let mut reader = modlem::ByteReader::new(&[0x34, 0x12, 0x56]);
+/// assert_eq!(reader.read_le16()?, 0x1234);
+/// let err = reader.read_le16().unwrap_err();
+/// assert_eq!(err.to_string(), "expected 2 bytes at offset 2, but there were only 1");
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct ByteReader<'a> {
+    data : &'a [u8],
+    position : usize,
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(data : &'a [u8]) -> ByteReader<'a> {
+        ByteReader { data, position : 0 }
+    }
+
+    /// The offset of the next byte to be read.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// The number of bytes left to read.
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.position
+    }
+
+    /// Returns the next @len bytes, without copying them. If there aren't enough, nothing is
+    /// read, and the error says where they were expected.
+    pub fn take_slice(&mut self, len : usize) -> std::io::Result<&'a [u8]> {
+        if len > self.remaining() {
+            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof,
+                format!("expected {} bytes at offset {}, but there were only {}", len, self.position, self.remaining())));
+        }
+        let slice = &self.data[self.position..self.position + len];
+        self.position += len;
+        Ok(slice)
+    }
+
+    pub fn read_array<const N : usize>(&mut self) -> std::io::Result<[u8; N]> {
+        let mut bytes = [0_u8; N];
+        bytes.copy_from_slice(self.take_slice(N)?);
+        Ok(bytes)
+    }
+
+    /// Reads an integer of type T, in byte order E, as read_int() does.
+    pub fn read_int<T : Integer, E : Endianness>(&mut self) -> std::io::Result<T> {
+        let mut bytes = T::Bytes::default();
+        let len = bytes.as_ref().len();
+        bytes.as_mut().copy_from_slice(self.take_slice(len)?);
+        Ok(E::decode(bytes))
+    }
+
+    pub fn read_u8(&mut self) -> std::io::Result<u8> {
+        self.read_int::<u8, Le>()
+    }
+
+    pub fn read_le16(&mut self) -> std::io::Result<u16> {
+        self.read_int::<u16, Le>()
+    }
+
+    pub fn read_le32(&mut self) -> std::io::Result<u32> {
+        self.read_int::<u32, Le>()
+    }
+
+    pub fn read_be16(&mut self) -> std::io::Result<u16> {
+        self.read_int::<u16, Be>()
+    }
+
+    pub fn read_be32(&mut self) -> std::io::Result<u32> {
+        self.read_int::<u32, Be>()
+    }
+
+    pub fn read_le_i16(&mut self) -> std::io::Result<i16> {
+        self.read_int::<i16, Le>()
+    }
+
+    pub fn read_be_i16(&mut self) -> std::io::Result<i16> {
+        self.read_int::<i16, Be>()
+    }
+
+    pub fn read_le_i32(&mut self) -> std::io::Result<i32> {
+        self.read_int::<i32, Le>()
+    }
+}
+
+impl<'a> Read for ByteReader<'a> {
+    fn read(&mut self, buf : &mut [u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(self.remaining());
+        buf[..len].copy_from_slice(self.take_slice(len)?);
+        Ok(len)
+    }
+
+    fn read_exact(&mut self, buf : &mut [u8]) -> std::io::Result<()> {
+        buf.copy_from_slice(self.take_slice(buf.len())?);
+        Ok(())
+    }
+}
+
+/// Wraps a reader so the start of the data can be looked at (e.g. to check a file's signature)
+/// before reading it. Anything peeked at is then read again as normal.
+pub struct SniffReader<'a> {
+    reader : &'a mut dyn Read,
+    buffer : Vec<u8>,
+    position : usize,
+}
+
+impl<'a> SniffReader<'a> {
+    pub fn new(reader : &'a mut dyn Read) -> SniffReader<'a> {
+        SniffReader { reader, buffer : Vec::new(), position : 0 }
+    }
+
+    /// Returns the next @len bytes without reading them, or fewer if the data ends first.
+    pub fn peek(&mut self, len : usize) -> std::io::Result<&[u8]> {
+        let wanted = self.position + len;
+        if self.buffer.len() < wanted {
+            let have = self.buffer.len();
+            Read::take(&mut *self.reader, (wanted - have) as u64).read_to_end(&mut self.buffer)?;
+        }
+        let end = wanted.min(self.buffer.len());
+        Ok(&self.buffer[self.position..end])
+    }
+}
+
+impl<'a> Read for SniffReader<'a> {
+    fn read(&mut self, buf : &mut [u8]) -> std::io::Result<usize> {
+        if self.position == self.buffer.len() {
+            return self.reader.read(buf);
+        }
+        let len = buf.len().min(self.buffer.len() - self.position);
+        buf[..len].copy_from_slice(&self.buffer[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+/// Opens @path for reading, buffered, as most of the readers here take a few bytes at a time.
+pub fn open_buffered<P : AsRef<Path>>(path : P) -> std::io::Result<BufReader<File>> {
+    File::open(path).map(BufReader::new)
+}
+
+/// A file being written, buffered. Call finish() once it's all written, to find out whether
+/// it was: it's flushed when dropped too, but any error then is lost.
+pub struct OutputFile {
+    writer : BufWriter<File>,
+}
+
+impl OutputFile {
+    /// Creates (or truncates) @path.
+    pub fn create<P : AsRef<Path>>(path : P) -> std::io::Result<OutputFile> {
+        let file = File::create(path.as_ref())?;
+        Ok(OutputFile { writer : BufWriter::new(file) })
+    }
+
+    /// Write out whatever's still buffered, and close the file.
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf : &[u8]) -> std::io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl Drop for OutputFile {
+    fn drop(&mut self) {
+        // Anything which cares whether this worked has called finish() already.
+        let _ = self.writer.flush();
+    }
+}
+
+/// A fixed-size structure in a binary file.
+pub trait BinaryRecord : Sized {
+    fn read_from(reader : &mut dyn std::io::Read) -> std::io::Result<Self>;
+    fn write_to(&self, writer : &mut dyn std::io::Write) -> std::io::Result<()>;
+    /// The number of bytes the record takes up in the file.
+    fn byte_size() -> usize;
+}
+
+/// Implement BinaryRecord for a struct, given each of its fields in the order they're stored
+/// along with how: u8, le16, le32, be16 or be32. A field which isn't stored is "skip", and
+/// left as its default when read. Read errors name the field which failed. A function given after "then" is called on the record once
+/// it's been read, to fill in anything which is worked out from the rest.
+macro_rules! binary_record {
+    (@read u8, $reader:ident) => { $crate::binary_io::read_byte($reader) };
+    (@read le16, $reader:ident) => { $crate::binary_io::read_le16($reader) };
+    (@read le32, $reader:ident) => { $crate::binary_io::read_le32($reader) };
+    (@read be16, $reader:ident) => { $crate::binary_io::read_be16($reader) };
+    (@read be32, $reader:ident) => { $crate::binary_io::read_be32($reader) };
+    (@read skip, $reader:ident) => { Ok(Default::default()) };
+    (@write u8, $val:expr, $writer:ident) => { $crate::binary_io::write_byte($val, $writer)? };
+    (@write le16, $val:expr, $writer:ident) => { $crate::binary_io::write_le16($val, $writer)? };
+    (@write le32, $val:expr, $writer:ident) => { $crate::binary_io::write_le32($val, $writer)? };
+    (@write be16, $val:expr, $writer:ident) => { $crate::binary_io::write_be16($val, $writer)? };
+    (@write be32, $val:expr, $writer:ident) => { $crate::binary_io::write_be32($val, $writer)? };
+    (@write skip, $val:expr, $writer:ident) => { () };
+    (@size u8) => { 1 };
+    (@size le16) => { 2 };
+    (@size le32) => { 4 };
+    (@size be16) => { 2 };
+    (@size be32) => { 4 };
+    (@size skip) => { 0 };
+    ($record:ident { $($field:ident : $kind:ident),* $(,)* } $(then $after_read:path)*) => {
+        impl $crate::binary_io::BinaryRecord for $record {
+            fn read_from(reader : &mut dyn std::io::Read) -> std::io::Result<$record> {
+                #[allow(unused_mut)]
+                let mut record = $record {
+                    $($field : $crate::binary_io::with_field(stringify!($field), binary_record!(@read $kind, reader))?),*
+                };
+                $($after_read(&mut record);)*
+                Ok(record)
+            }
+
+            fn write_to(&self, writer : &mut dyn std::io::Write) -> std::io::Result<()> {
+                $(binary_record!(@write $kind, self.$field, writer);)*
+                Ok(())
+            }
+
+            fn byte_size() -> usize {
+                0 $(+ binary_record!(@size $kind))*
+            }
+        }
+    };
+}
+
+/// Check that any bytes read as a @T are written back out the same, and that it takes exactly
+/// T::byte_size() of them. The bytes are pseudo-random, but the same each time.
+#[cfg(test)]
+pub fn check_round_trips<T : BinaryRecord>() {
+    let mut state = 0x2545_f491_u32;
+    for _ in 0..100 {
+        let bytes : Vec<u8> = (0..T::byte_size()).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            (state >> 24) as u8
+        }).collect();
+        let record = T::read_from(&mut bytes.as_slice()).unwrap();
+        let mut written = Vec::new();
+        record.write_to(&mut written).unwrap();
+        assert_eq!(written, bytes);
+        assert!(T::read_from(&mut &bytes[1..]).is_err());
+    }
+}
+
+/// Writes a RIFF WAVE file containing 8-bit unsigned mono samples.
+pub fn write_wav_u8(samples : &[u8], sample_rate : u32, writer: &mut dyn std::io::Write) -> std::io::Result<()> {
+    let padding = samples.len() % 2;
+    writer.write_all(b"RIFF")?;
+    write_le32((36 + samples.len() + padding) as u32, writer)?;
+    writer.write_all(b"WAVE")?;
+
+    writer.write_all(b"fmt ")?;
+    write_le32(16, writer)?; // Size of the format chunk
+    write_le16(1, writer)?; // PCM
+    write_le16(1, writer)?; // Channels
+    write_le32(sample_rate, writer)?;
+    write_le32(sample_rate, writer)?; // Bytes per second
+    write_le16(1, writer)?; // Bytes per sample frame
+    write_le16(8, writer)?; // Bits per sample
+
+    writer.write_all(b"data")?;
+    write_le32(samples.len() as u32, writer)?;
+    writer.write_all(samples)?;
+    if padding != 0 {
+        // RIFF chunks are padded to an even length.
+        write_byte(0, writer)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct Example {
+        flags : u8,
+        little : u16,
+        big : u32,
+        sum : u32,
+    }
+
+    fn add_up(example : &mut Example) {
+        example.sum = example.flags as u32 + example.little as u32 + example.big;
+    }
+
+    binary_record!(Example { flags : u8, little : le16, big : be32, sum : skip } then add_up);
+
+    #[test]
+    fn binary_io_bytes() {
+        let data : Vec<u8> = (0..=255).cycle().take(1000).collect();
+        let mut reader = data.as_slice();
+        assert_eq!(read_array::<3>(&mut reader).unwrap(), [0, 1, 2]);
+        skip_bytes(&mut reader, 600).unwrap();
+        assert_eq!(read_bytes(&mut reader, 2).unwrap(), [(603 % 256) as u8, (604 % 256) as u8]);
+        assert_eq!(read_bytes(&mut reader, 0).unwrap(), []);
+
+        // Lengths past the end are errors, however large.
+        let err = read_bytes(&mut reader, usize::MAX).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+        assert!(read_bytes(&mut &data[..10], 11).is_err());
+        assert!(skip_bytes(&mut &data[..10], 11).is_err());
+        assert!(read_array::<11>(&mut &data[..10]).is_err());
+    }
+
+    #[test]
+    fn binary_io_signed() {
+        let data = [0xFF, 0xFF, 0x00, 0x80, 0x80, 0x00, 0xFF, 0x7F, 0x7F, 0xFF];
+        let mut reader = &data[..];
+        assert_eq!(read_le_i16(&mut reader).unwrap(), -1);
+        assert_eq!(read_le_i16(&mut reader).unwrap(), i16::MIN);
+        assert_eq!(read_be_i16(&mut reader).unwrap(), i16::MIN);
+        assert_eq!(read_le_i16(&mut reader).unwrap(), 0x7FFF);
+        assert_eq!(read_be_i16(&mut reader).unwrap(), 0x7FFF);
+        assert_eq!(read_le_i32(&mut &[0xFF, 0xFF, 0xFF, 0xFF][..]).unwrap(), -1);
+        assert_eq!(read_le_i32(&mut &[0x00, 0x80, 0xFF, 0xFF][..]).unwrap(), i16::MIN as i32);
+        assert_eq!(read_le_i32(&mut &[0x00, 0x00, 0x00, 0x80][..]).unwrap(), i32::MIN);
+        assert!(read_be_i16(&mut &data[..1]).is_err());
+
+        let mut written = Vec::new();
+        write_le_i16(-1, &mut written).unwrap();
+        write_le_i16(i16::MIN, &mut written).unwrap();
+        write_be_i16(i16::MIN, &mut written).unwrap();
+        write_le_i16(0x7FFF, &mut written).unwrap();
+        write_be_i16(0x7FFF, &mut written).unwrap();
+        assert_eq!(written, data);
+        written.clear();
+        write_le_i32(-1, &mut written).unwrap();
+        write_le_i32(i16::MIN as i32, &mut written).unwrap();
+        write_le_i32(0x7FFF, &mut written).unwrap();
+        assert_eq!(written, [0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x80, 0xFF, 0xFF, 0xFF, 0x7F, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn binary_io_endianness() {
+        let data = [0x80, 0x01, 0x02, 0xFF];
+        assert_eq!(read_int::<u32, Le>(&mut &data[..]).unwrap(), 0xFF020180);
+        assert_eq!(read_int::<u32, Be>(&mut &data[..]).unwrap(), 0x800102FF);
+        assert_eq!(read_int::<i32, Be>(&mut &data[..]).unwrap(), 0x800102FF_u32 as i32);
+        assert_eq!(read_int::<i8, Le>(&mut &data[..]).unwrap(), -128);
+        assert_eq!(read_int::<u16, Be>(&mut &data[..]).unwrap(), read_be16(&mut &data[..]).unwrap());
+        assert_eq!(read_int::<i16, Le>(&mut &data[..]).unwrap(), read_le_i16(&mut &data[..]).unwrap());
+        assert_eq!(ByteReader::new(&data).read_int::<u16, Le>().unwrap(), 0x0180);
+        assert!(read_int::<u32, Le>(&mut &data[..3]).is_err());
+
+        let mut written = Vec::new();
+        write_int::<u32, Le>(0xFF020180, &mut written).unwrap();
+        write_int::<i16, Be>(-2, &mut written).unwrap();
+        write_int::<u8, Be>(7, &mut written).unwrap();
+        assert_eq!(written, [0x80, 0x01, 0x02, 0xFF, 0xFF, 0xFE, 7]);
+    }
+
+    #[test]
+    fn binary_io_fixed_strings() {
+        let data = b"Just dig!\xA0\xFF   \0\0";
+        assert_eq!(read_fixed_str(&mut &data[..], 14, b' ').unwrap(), "Just dig!\u{A0}\u{FF}");
+        assert_eq!(read_fixed_str(&mut &data[..], 16, b'\0').unwrap(), "Just dig!\u{A0}\u{FF}   ");
+        assert_eq!(read_fixed_bytes(&mut &data[..], 14, b' ').unwrap(), b"Just dig!\xA0\xFF");
+        assert_eq!(read_fixed_bytes(&mut &data[9..], 2, b'\xFF').unwrap(), b"\xA0");
+        assert_eq!(read_fixed_str(&mut &b"    "[..], 4, b' ').unwrap(), "");
+        assert!(read_fixed_str(&mut &data[..], 17, b' ').is_err());
+
+        let mut written = Vec::new();
+        write_fixed_str("Just dig!\u{A0}\u{FF}", 16, b' ', false, &mut written).unwrap();
+        assert_eq!(written, b"Just dig!\xA0\xFF     ");
+        written.clear();
+        write_fixed_bytes(b"Just", 4, b' ', false, &mut written).unwrap();
+        write_fixed_bytes(b"", 2, b'\0', false, &mut written).unwrap();
+        assert_eq!(written, b"Just\0\0");
+
+        // Anything too long is an error, unless it's to be truncated.
+        let err = write_fixed_str("Just dig!", 4, b' ', false, &mut written).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(written.len(), 6);
+        write_fixed_str("Just dig!", 4, b' ', true, &mut written).unwrap();
+        assert_eq!(&written[6..], b"Just");
+        let err = write_fixed_str("Lemming \u{263A}", 32, b' ', false, &mut written).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(written.len(), 10);
+    }
+
+    #[test]
+    fn binary_io_byte_reader() {
+        let data = [0x01, 0xFF, 0xFE, 0x12, 0x34, 0x56, 0x78, 0x9A];
+        let mut reader = ByteReader::new(&data);
+        assert_eq!(reader.read_u8().unwrap(), 1);
+        assert_eq!(reader.read_le_i16().unwrap(), -257);
+        assert_eq!(reader.position(), 3);
+        assert_eq!(reader.take_slice(2).unwrap(), [0x12, 0x34]);
+        assert_eq!(reader.remaining(), 3);
+
+        // Running out is an error saying where, and doesn't read anything.
+        let err = reader.read_be32().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+        assert_eq!(err.to_string(), "expected 4 bytes at offset 5, but there were only 3");
+        assert_eq!(reader.position(), 5);
+
+        // The same goes for reading it as a Read.
+        assert_eq!(read_be16(&mut reader).unwrap(), 0x5678);
+        let err = read_le32(&mut reader).unwrap_err();
+        assert_eq!(err.to_string(), "expected 4 bytes at offset 7, but there were only 1");
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!((rest, reader.remaining()), (vec![0x9A], 0));
+        assert_eq!(ByteReader::new(&data).read_be32().unwrap(), 0x01FFFE12);
+    }
+
+    #[test]
+    fn binary_io_sniff_reader() {
+        let data : Vec<u8> = (0..100).collect();
+        let mut source = data.as_slice();
+        let mut reader = SniffReader::new(&mut source);
+        assert_eq!(reader.peek(2).unwrap(), [0, 1]);
+        assert_eq!(reader.peek(4).unwrap(), [0, 1, 2, 3]);
+        assert_eq!(read_byte(&mut reader).unwrap(), 0);
+        assert_eq!(reader.peek(2).unwrap(), [1, 2]);
+        assert_eq!(read_be32(&mut reader).unwrap(), 0x01020304);
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, &data[5..]);
+        assert_eq!(reader.peek(8).unwrap(), []);
+
+        // Peeking past the end just gives what there is.
+        let mut short = &data[..3];
+        assert_eq!(SniffReader::new(&mut short).peek(8).unwrap(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn binary_io_buffered_files() {
+        let path = std::env::temp_dir().join(format!("modlem_binary_io_{}.bin", std::process::id()));
+        {
+            let mut out = OutputFile::create(&path).unwrap();
+            for i in 0..1000 {
+                write_le16(i, &mut out).unwrap();
+            }
+            out.finish().unwrap();
+        }
+        let mut reader = open_buffered(&path).unwrap();
+        for i in 0..1000 {
+            assert_eq!(read_le16(&mut reader).unwrap(), i);
+        }
+        assert!(read_byte(&mut reader).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn binary_record_fields() {
+        assert_eq!(Example::byte_size(), 7);
+        let example = Example::read_from(&mut &[1, 0x34, 0x12, 0, 0, 0x56, 0x78][..]).unwrap();
+        assert_eq!((example.flags, example.little, example.big), (1, 0x1234, 0x5678));
+        assert_eq!(example.sum, 1 + 0x1234 + 0x5678);
+
+        let mut written = Vec::new();
+        Example { big : 0x01020304, ..Default::default() }.write_to(&mut written).unwrap();
+        assert_eq!(written, [0, 0, 0, 1, 2, 3, 4]);
+        check_round_trips::<Example>();
+
+        let err = with_context(Example::read_from(&mut &[1, 0x34, 0x12, 0][..]), "reading example 3").unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+        assert!(err.to_string().starts_with("reading example 3: field big: "));
+    }
 }
@@ -15,21 +15,383 @@
  * this program. If not, see <https://www.gnu.org/licenses/>.
  */
 
-/// Performs a case-insensitive search for a file in a given path, returning the case-corrected path.
-pub fn find_file_in_dir(dir: &std::path::Path, name : &str) -> std::io::Result<std::path::PathBuf> {
-	assert!(dir.is_dir());
+/// Lists @dir, with an error which says what's wrong with it (and where it is) if that fails.
+fn read_dir(dir: &std::path::Path) -> std::io::Result<std::fs::ReadDir> {
+	std::fs::read_dir(dir).map_err(|err| {
+		let message = match err.kind() {
+			std::io::ErrorKind::NotFound => format!("{} doesn't exist", dir.display()),
+			std::io::ErrorKind::NotADirectory => format!("{} isn't a directory", dir.display()),
+			std::io::ErrorKind::PermissionDenied => format!("{} can't be read (permission denied)", dir.display()),
+			_ => format!("{}: {}", dir.display(), err),
+		};
+		std::io::Error::new(err.kind(), message)
+	})
+}
+
+/// The error for @name not being in @dir (or the @max_depth levels below it).
+fn not_found(dir: &std::path::Path, name : &str, max_depth: usize) -> std::io::Error {
+	let below = match max_depth {
+		0 => String::new(),
+		1 => " (or the directories in it)".to_string(),
+		usize::MAX => " (or anywhere below it)".to_string(),
+		depth => format!(" (or up to {} directories below it)", depth),
+	};
+	std::io::Error::new(std::io::ErrorKind::NotFound, format!("{} isn't in {}{}", name, dir.display(), below))
+}
+
+/// Whether @a and @b are the same file, e.g. because one is a symlink to the other, or they're
+/// both links to the same file.
+#[cfg(unix)]
+pub fn same_file(a: &std::path::Path, b: &std::path::Path) -> bool {
+	use std::os::unix::fs::MetadataExt;
+	match (std::fs::metadata(a), std::fs::metadata(b)) {
+		(Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+		_ => false,
+	}
+}
+
+/// Whether @a and @b are the same file, e.g. because one is a symlink to the other, or they're
+/// both links to the same file.
+#[cfg(not(unix))]
+pub fn same_file(a: &std::path::Path, b: &std::path::Path) -> bool {
+	match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+		(Ok(a), Ok(b)) => a == b,
+		_ => false,
+	}
+}
+
+/// The files in a directory tree, by depth, then by their lowercase names. A name only has more than
+/// one path if it's in several directories at the same depth.
+type Listing = Vec<std::collections::HashMap<std::ffi::OsString, Vec<std::path::PathBuf>>>;
+
+/// Finds files case-insensitively in a directory, and (down to @max_depth levels) the directories
+/// below it. The directories are only read once, on the first lookup, however many files are
+/// looked for, so call refresh() if they might have changed since.
+///
+/// ```no_run
+/// let files = modlem::FileLocator::new(std::path::Path::new("/games/lemmings"), 2);
+/// let main_dat = files.find("main.dat")?;
+/// let sets = files.find_matching("ground?o.dat")?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Clone)]
+pub struct FileLocator {
+	dir: std::path::PathBuf,
+	max_depth: usize,
+	listing: std::cell::RefCell<Option<Listing>>,
+}
+
+impl FileLocator {
+	/// A locator for files in @dir, and @max_depth levels of directories below it (0 for none).
+	pub fn new(dir: &std::path::Path, max_depth: usize) -> FileLocator {
+		FileLocator { dir: dir.to_path_buf(), max_depth, listing: std::cell::RefCell::new(None) }
+	}
+
+	pub fn dir(&self) -> &std::path::Path {
+		&self.dir
+	}
+
+	pub fn max_depth(&self) -> usize {
+		self.max_depth
+	}
 
-	for filename in std::fs::read_dir(dir)? {
-		let filename = filename?;
-		let name_osstr = filename.file_name();
-		if name_osstr.eq_ignore_ascii_case(name) {
-			return Ok(filename.path());
+	/// Forget what's in the directories, so that they're read again by the next lookup.
+	pub fn refresh(&self) {
+		*self.listing.borrow_mut() = None;
+	}
+
+	/// Lists @dir, and the directories below it, by lowercase name. Symlinks to directories are
+	/// followed, but each directory is only listed once, however many ways there are to get to it.
+	fn read_listing(&self) -> std::io::Result<Listing> {
+		let mut listing = Listing::new();
+		let mut dirs = vec![self.dir.clone()];
+		let mut seen_dirs = std::collections::HashSet::new();
+		for depth in 0..=self.max_depth {
+			let mut files = std::collections::HashMap::new();
+			let mut subdirs = Vec::new();
+			for dir in &dirs {
+				if let Ok(canonical) = std::fs::canonicalize(dir) {
+					if !seen_dirs.insert(canonical) {
+						continue;
+					}
+				}
+				let entries = match read_dir(dir) {
+					Ok(entries) => entries,
+					// Subdirectories we can't read are skipped, but not @dir itself.
+					Err(_) if depth > 0 => continue,
+					Err(err) => return Err(err),
+				};
+				for entry in entries {
+					let entry = entry?;
+					let file_type = entry.file_type()?;
+					if file_type.is_dir() || (file_type.is_symlink() && entry.path().is_dir()) {
+						subdirs.push(entry.path());
+					}
+					files.entry(entry.file_name().to_ascii_lowercase()).or_insert_with(Vec::new).push(entry.path());
+				}
+			}
+			listing.push(files);
+			if subdirs.is_empty() {
+				break;
+			}
+			dirs = subdirs;
+		}
+		Ok(listing)
+	}
+
+	/// Calls @f with the listing, reading it first if need be.
+	fn with_listing<T>(&self, f: impl FnOnce(&Listing) -> T) -> std::io::Result<T> {
+		let mut listing = self.listing.borrow_mut();
+		if listing.is_none() {
+			*listing = Some(self.read_listing()?);
+		}
+		Ok(f(listing.as_ref().unwrap()))
+	}
+
+	/// Performs a case-insensitive search for @name, returning the case-corrected path. Matches
+	/// closer to the directory win, but if there's more than one at the same depth, the error
+	/// lists them all rather than picking one. Names which are all for the same file (see
+	/// same_file()) only count as one match, which is the first of them by path.
+	pub fn find(&self, name : &str) -> std::io::Result<std::path::PathBuf> {
+		self.with_listing(|listing| self.find_in_listing(listing, name))?
+	}
+
+	fn find_in_listing(&self, listing: &Listing, name : &str) -> std::io::Result<std::path::PathBuf> {
+		let key = std::ffi::OsString::from(name.to_ascii_lowercase());
+		for files in listing {
+			if let Some(matches) = files.get(&key) {
+				let mut matches = matches.clone();
+				matches.sort();
+				let mut distinct: Vec<std::path::PathBuf> = Vec::new();
+				for path in matches {
+					if !distinct.iter().any(|other| same_file(other, &path)) {
+						distinct.push(path);
+					}
+				}
+				if distinct.len() == 1 {
+					return Ok(distinct.remove(0));
+				}
+				let candidates: Vec<String> = distinct.iter().map(|path| path.display().to_string()).collect();
+				return Err(std::io::Error::other(format!("{} is ambiguous, it could be any of {}", name, candidates.join(", "))));
+			}
 		}
+		Err(not_found(&self.dir, name, self.max_depth))
 	}
-	Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+
+	/// Finds every file whose name matches @pattern (see matches_pattern()), at any depth,
+	/// sorted by path.
+	pub fn find_matching(&self, pattern : &str) -> std::io::Result<Vec<std::path::PathBuf>> {
+		self.with_listing(|listing| {
+			let mut matches = Vec::new();
+			for files in listing {
+				for (name, paths) in files {
+					if name.to_str().is_some_and(|name| matches_pattern(name, pattern)) {
+						matches.extend(paths.iter().cloned());
+					}
+				}
+			}
+			matches.sort();
+			matches
+		})
+	}
+}
+
+/// Whether @name matches @pattern, ignoring ASCII case, where ? in @pattern matches any one
+/// character, and * matches any number of them (including none).
+pub fn matches_pattern(name : &str, pattern : &str) -> bool {
+	let name: Vec<char> = name.chars().collect();
+	let pattern: Vec<char> = pattern.chars().collect();
+	let (mut n, mut p) = (0, 0);
+	// The last * seen, and where in @name it's matched up to, so it can take another character
+	// if what follows doesn't match.
+	let mut star = None;
+	while n < name.len() {
+		if p < pattern.len() && (pattern[p] == '?' || pattern[p].eq_ignore_ascii_case(&name[n])) {
+			n += 1;
+			p += 1;
+		} else if p < pattern.len() && pattern[p] == '*' {
+			star = Some((p, n));
+			p += 1;
+		} else if let Some((star_p, star_n)) = star {
+			star = Some((star_p, star_n + 1));
+			p = star_p + 1;
+			n = star_n + 1;
+		} else {
+			return false;
+		}
+	}
+	pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Finds every file in @dir whose name matches @pattern (see matches_pattern()), sorted.
+pub fn find_files_matching(dir: &std::path::Path, pattern : &str) -> std::io::Result<Vec<std::path::PathBuf>> {
+	FileLocator::new(dir, 0).find_matching(pattern)
+}
+
+/// Performs a case-insensitive search for a file in a given path, returning the case-corrected path.
+pub fn find_file_in_dir(dir: &std::path::Path, name : &str) -> std::io::Result<std::path::PathBuf> {
+	FileLocator::new(dir, 0).find(name)
 }
 
 /// Performs a case-insensitive search for a file in the current directory, returning the case-corrected path.
 pub fn find_file_in_current_dir(name : &str) -> std::io::Result<std::path::PathBuf> {
 	find_file_in_dir(&std::env::current_dir()?, name)
 }
+
+/// Performs a case-insensitive search for a file in @dir and its subdirectories, down to
+/// @max_depth levels below it (0 only looks in @dir itself). See FileLocator::find().
+pub fn find_file_recursive(dir: &std::path::Path, name : &str, max_depth: usize) -> std::io::Result<std::path::PathBuf> {
+	FileLocator::new(dir, max_depth).find(name)
+}
+
+/// If @path doesn't exist, but there's a file whose name differs from it only in case in the same
+/// directory, returns that file's path instead, so that it's replaced rather than joined by a
+/// second copy which DOS would never see.
+pub fn match_existing_case(path: &std::path::Path) -> std::path::PathBuf {
+	if path.exists() {
+		return path.to_path_buf();
+	}
+	let name = match path.file_name().and_then(|name| name.to_str()) {
+		Some(name) => name,
+		None => return path.to_path_buf(),
+	};
+	let dir = match path.parent() {
+		Some(dir) if !dir.as_os_str().is_empty() => dir,
+		_ => std::path::Path::new("."),
+	};
+	match find_file_in_dir(dir, name) {
+		Ok(existing) => existing,
+		Err(_) => path.to_path_buf(),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn case_sensitivity_find_file_recursive() {
+		let dir = std::env::temp_dir().join(format!("modlem_case_sensitivity_{}", std::process::id()));
+		std::fs::create_dir_all(dir.join("LEMMINGS/DATA")).unwrap();
+		std::fs::create_dir_all(dir.join("ohno/data")).unwrap();
+		std::fs::write(dir.join("LEMMINGS/DATA/GROUND0O.DAT"), b"").unwrap();
+		std::fs::write(dir.join("LEMMINGS/DATA/MAIN.DAT"), b"").unwrap();
+		std::fs::write(dir.join("ohno/data/main.dat"), b"").unwrap();
+		std::fs::write(dir.join("Level000.dat"), b"").unwrap();
+
+		assert_eq!(find_file_recursive(&dir, "ground0o.dat", 2).unwrap(), dir.join("LEMMINGS/DATA/GROUND0O.DAT"));
+		assert_eq!(find_file_recursive(&dir, "ground0o.dat", 1).unwrap_err().kind(), std::io::ErrorKind::NotFound);
+		assert_eq!(find_file_recursive(&dir, "level000.dat", 0).unwrap(), dir.join("Level000.dat"));
+
+		let err = find_file_recursive(&dir, "main.dat", 2).unwrap_err();
+		assert_eq!(err.to_string(), format!("main.dat is ambiguous, it could be any of {}, {}", dir.join("LEMMINGS/DATA/MAIN.DAT").display(), dir.join("ohno/data/main.dat").display()));
+
+		// A closer match isn't ambiguous.
+		std::fs::write(dir.join("Main.dat"), b"").unwrap();
+		assert_eq!(find_file_recursive(&dir, "main.dat", 2).unwrap(), dir.join("Main.dat"));
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn case_sensitivity_file_locator() {
+		let dir = std::env::temp_dir().join(format!("modlem_file_locator_{}", std::process::id()));
+		std::fs::create_dir_all(dir.join("DATA")).unwrap();
+		std::fs::write(dir.join("DATA/VGAGR0.DAT"), b"").unwrap();
+
+		let locator = FileLocator::new(&dir, 1);
+		assert_eq!(locator.find("vgagr0.dat").unwrap(), dir.join("DATA/VGAGR0.DAT"));
+		assert_eq!(locator.find("Ground0o.dat").unwrap_err().kind(), std::io::ErrorKind::NotFound);
+
+		// New files aren't seen until the locator is refreshed.
+		std::fs::write(dir.join("ground0o.dat"), b"").unwrap();
+		assert_eq!(locator.find("Ground0o.dat").unwrap_err().kind(), std::io::ErrorKind::NotFound);
+		locator.refresh();
+		assert_eq!(locator.find("Ground0o.dat").unwrap(), dir.join("ground0o.dat"));
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn case_sensitivity_matches_pattern() {
+		assert!(matches_pattern("GROUND0O.DAT", "ground?o.dat"));
+		assert!(!matches_pattern("ground10o.dat", "ground?o.dat"));
+		assert!(matches_pattern("level000.dat", "level*.dat"));
+		assert!(matches_pattern("level.dat", "level*.dat"));
+		assert!(matches_pattern("Level012.DAT.bak", "*.*"));
+		assert!(!matches_pattern("level000.dat.bak", "level*.dat"));
+		assert!(matches_pattern("vgagr0.dat", "*gr*.dat"));
+		assert!(matches_pattern("", "*"));
+		assert!(!matches_pattern("", "?"));
+	}
+
+	#[test]
+	fn case_sensitivity_find_files_matching() {
+		let dir = std::env::temp_dir().join(format!("modlem_find_files_matching_{}", std::process::id()));
+		std::fs::create_dir_all(dir.join("sub")).unwrap();
+		for name in &["GROUND1O.DAT", "ground0o.dat", "ground0o.dat.bak", "ground10o.dat", "sub/ground2o.dat"] {
+			std::fs::write(dir.join(name), b"").unwrap();
+		}
+
+		assert_eq!(find_files_matching(&dir, "ground?o.dat").unwrap(), [dir.join("GROUND1O.DAT"), dir.join("ground0o.dat")]);
+		assert_eq!(FileLocator::new(&dir, 1).find_matching("Ground?O.dat").unwrap(), [dir.join("GROUND1O.DAT"), dir.join("ground0o.dat"), dir.join("sub/ground2o.dat")]);
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	#[cfg(unix)]
+	fn case_sensitivity_symlinks() {
+		use std::os::unix::fs::symlink;
+		let dir = std::env::temp_dir().join(format!("modlem_case_sensitivity_symlinks_{}", std::process::id()));
+		let game = dir.join("game");
+		let farm = dir.join("farm");
+		std::fs::create_dir_all(game.join("DATA")).unwrap();
+		std::fs::create_dir_all(farm.join("a")).unwrap();
+		std::fs::create_dir_all(farm.join("b")).unwrap();
+		std::fs::write(game.join("DATA/MAIN.DAT"), b"").unwrap();
+		std::fs::write(game.join("DATA/VGAGR0.DAT"), b"").unwrap();
+
+		// Two links to the same file, at the same depth, aren't ambiguous.
+		symlink(game.join("DATA/MAIN.DAT"), farm.join("a/main.dat")).unwrap();
+		symlink(game.join("DATA/MAIN.DAT"), farm.join("b/Main.Dat")).unwrap();
+		assert_eq!(find_file_recursive(&farm, "main.dat", 1).unwrap(), farm.join("a/main.dat"));
+		// Links to different files are.
+		symlink(game.join("DATA/VGAGR0.DAT"), farm.join("a/vgagr0.dat")).unwrap();
+		std::fs::write(farm.join("b/VGAGR0.DAT"), b"").unwrap();
+		assert_eq!(find_file_recursive(&farm, "vgagr0.dat", 1).unwrap_err().kind(), std::io::ErrorKind::Other);
+
+		// Links to directories are followed, but a loop doesn't go on forever.
+		symlink(&game, farm.join("lemmings")).unwrap();
+		symlink(&farm, farm.join("a/loop")).unwrap();
+		assert_eq!(find_file_recursive(&dir.join("farm/lemmings"), "main.dat", usize::MAX).unwrap(), farm.join("lemmings/DATA/MAIN.DAT"));
+		assert_eq!(FileLocator::new(&farm, usize::MAX).find_matching("*.dat").unwrap().len(), 6);
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn case_sensitivity_errors() {
+		let dir = std::env::temp_dir().join(format!("modlem_case_sensitivity_errors_{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		std::fs::write(dir.join("main.dat"), b"").unwrap();
+
+		let err = find_file_in_dir(&dir, "ground0o.dat").unwrap_err();
+		assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+		assert_eq!(err.to_string(), format!("ground0o.dat isn't in {}", dir.display()));
+		let err = find_file_recursive(&dir, "ground0o.dat", 2).unwrap_err();
+		assert_eq!(err.to_string(), format!("ground0o.dat isn't in {} (or up to 2 directories below it)", dir.display()));
+
+		let missing = dir.join("lemmings");
+		let err = find_file_in_dir(&missing, "main.dat").unwrap_err();
+		assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+		assert_eq!(err.to_string(), format!("{} doesn't exist", missing.display()));
+
+		let file = dir.join("main.dat");
+		let err = find_file_recursive(&file, "main.dat", 2).unwrap_err();
+		assert_eq!(err.kind(), std::io::ErrorKind::NotADirectory);
+		assert_eq!(err.to_string(), format!("{} isn't a directory", file.display()));
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+}
@@ -0,0 +1,487 @@
+/*
+ * modlem: A graphics importer/exporter for Lemmings
+ * Copyright (C) 2022–2026 David Gow <david@davidgow.net>
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The arguments each of modlem's commands takes, for checking them and showing help.
+
+/// How a command is used: its arguments, and what it does.
+pub struct CommandSpec {
+    pub name : &'static str,
+    /// The arguments, as shown after the name in the usage line.
+    pub usage : &'static str,
+    /// What the command does, and what its options mean, a line at a time.
+    pub help : &'static [&'static str],
+    /// How many arguments (other than options) it takes: at least min_args, and at most
+    /// max_args, if there's a limit.
+    pub min_args : usize,
+    pub max_args : Option<usize>,
+    /// The options which are followed by a value, with each spelling of them.
+    pub options : &'static [&'static str],
+    /// The options which aren't.
+    pub flags : &'static [&'static str],
+}
+
+/// Why a command's arguments weren't accepted.
+#[derive(Debug, PartialEq)]
+pub enum ArgsError {
+    /// --help (or -h) was given.
+    Help,
+    /// They were wrong, for the reason given.
+    Invalid(String),
+}
+
+impl CommandSpec {
+    /// Write the usage line and help for the command to @out.
+    pub fn write_usage(&self, out : &mut dyn std::io::Write) {
+        writeln!(out, "\tmodlem {} {}", self.name, self.usage).unwrap();
+        for line in self.help {
+            writeln!(out, "\t\t{}", line).unwrap();
+        }
+    }
+
+    /// Check the arguments @args given after the command's name, returning them with the
+    /// arguments which aren't options first, in order, and then the options, each followed by
+    /// its value if it has one.
+    pub fn check_args(&self, args : &[String]) -> Result<Vec<String>, ArgsError> {
+        let mut positional = Vec::new();
+        let mut options = Vec::new();
+        let mut arg_iter = args.iter();
+        while let Some(arg) = arg_iter.next() {
+            if arg == "-" {
+                return Err(ArgsError::Invalid("modlem doesn't read from standard input, so \"-\" can't be used as a file".to_string()));
+            } else if !arg.starts_with('-') {
+                positional.push(arg.clone());
+            } else if arg == "--help" || arg == "-h" {
+                return Err(ArgsError::Help);
+            } else if self.options.contains(&arg.as_str()) {
+                match arg_iter.next() {
+                    Some(value) => options.extend_from_slice(&[arg.clone(), value.clone()]),
+                    None => return Err(ArgsError::Invalid(format!("{} needs a value", arg))),
+                }
+            } else if self.flags.contains(&arg.as_str()) {
+                options.push(arg.clone());
+            } else {
+                return Err(ArgsError::Invalid(format!("{} doesn't take a \"{}\" option", self.name, arg)));
+            }
+        }
+
+        if positional.len() < self.min_args {
+            return Err(ArgsError::Invalid(format!("{} needs more arguments than that", self.name)));
+        }
+        if let Some(max_args) = self.max_args {
+            if positional.len() > max_args {
+                return Err(ArgsError::Invalid(format!("{} doesn't take the argument \"{}\"", self.name, positional[max_args])));
+            }
+        }
+        positional.extend(options);
+        Ok(positional)
+    }
+}
+
+/// Find the command called @name.
+pub fn find_command(name : &str) -> Option<&'static CommandSpec> {
+    COMMANDS.iter().find(|command| command.name == name)
+}
+
+/// Every command, in the order they're listed by help.
+pub const COMMANDS : &[CommandSpec] = &[
+    CommandSpec {
+        name: "extract-set",
+        usage: "<n>|all [--atlas] [--check-masks] [--format bmp|png] [--force | --skip-existing]",
+        help: &[
+            "Extracts graphics set <n>, or every graphics set (ground?o.dat file) in the data directory.",
+            "--check-masks fails if any piece's mask doesn't match its graphics.",
+            "--format png (or --png) writes .png files instead of .bmp files.",
+            "--atlas packs all graphics into one image (setN_atlas.bmp) with a JSON index.",
+        ],
+        min_args: 1,
+        max_args: Some(1),
+        options: &["--format"],
+        flags: &["--atlas", "--check-masks", "--png", "--force", "--skip-existing"],
+    },
+    CommandSpec {
+        name: "create-set",
+        usage: "<script-name> [--no-backup] [--strict]",
+        help: &[
+            "Creates a graphics set from a script file.",
+        ],
+        min_args: 1,
+        max_args: Some(1),
+        options: &[],
+        flags: &["--no-backup", "--strict"],
+    },
+    CommandSpec {
+        name: "extract-main",
+        usage: "[--xmas] [--ohno | --cga] [--spec <file>] [--raw-sounds] [--out-dir <dir>] [--prefix <prefix>] [--anim-gif] [--format bmp|png] [--list] [--palette-file <file>] [--split-glyphs] [--split-panel] [--player2-palette] [--atlas] [--force | --skip-existing]",
+        help: &[
+            "Extracts main.dat into its constituent files, in the current directory (or <dir>).",
+            "Each filename starts with <prefix>.",
+            "--anim-gif also writes each lemming animation as an animated GIF.",
+            "--format png (or --png) writes paletted .png files instead of .bmp files.",
+            "--split-glyphs writes each character of the fonts to its own image (e.g. menuanim_menufont_A.bmp).",
+            "--split-panel also writes each skill panel button to its own image (e.g. interface_lo_skills_lo_digger.bmp).",
+            "--player2-palette also writes the lemming animations in the second player's colours (e.g. lemming_walk_r_p2.bmp).",
+            "--atlas also writes every lemming animation to one image, a row per animation, described by lemming_atlas.json.",
+            "--palette-file replaces colours of the built-in palettes with those listed in <file>.",
+            "--list shows each section's size and the files it would write, without writing anything.",
+            "The game version is detected automatically, or can be forced with --ohno or --cga (the CGA version's cgamain.dat).",
+            "--spec reads the main.dat layout from <file> instead.",
+            "--raw-sounds saves the PC speaker sounds as-is (pcspkr.snd), rather than as text.",
+        ],
+        min_args: 0,
+        max_args: Some(0),
+        options: &["--palette-file", "--format", "--out-dir", "--prefix", "--spec"],
+        flags: &["--xmas", "--christmas", "--raw-sounds", "--anim-gif", "--png", "--list", "--split-glyphs", "--split-panel", "--player2-palette", "--atlas", "--ohno", "--cga", "--force", "--skip-existing"],
+    },
+    CommandSpec {
+        name: "create-main",
+        usage: "[--xmas] [--ohno | --cga] [--spec <file>] [--out <file>] [--in-dir <dir>] [--prefix <prefix>] [--raw-sounds] [--format bmp|png] [--palette-file <file>] [--no-backup] [--base <main.dat> [--only <section>]...]",
+        help: &[
+            "Creates a main.dat (cgamain.dat with --cga, or <file>) from bitmaps in the current directory (or <dir>).",
+            "Images may be .bmp or .png files. If both exist, --format picks which is used (default bmp).",
+            "Colours which have moved within an image's palette are remapped to match the built-in palettes",
+            "(the Christmas ones with --xmas, or as changed by --palette-file); any others give a warning.",
+            "--base takes any missing files (or whole sections) from an existing main.dat.",
+            "--only rebuilds just the named section (e.g. lemming, menu, sounds), copying the rest from the base.",
+        ],
+        min_args: 0,
+        max_args: Some(0),
+        options: &["--spec", "--out", "--palette-file", "--in-dir", "--prefix", "--base", "--only", "--format"],
+        flags: &["--no-backup", "--xmas", "--christmas", "--raw-sounds", "--ohno", "--cga"],
+    },
+    CommandSpec {
+        name: "roundtrip-main",
+        usage: "[--xmas] [--ohno | --cga] [--spec <file>]",
+        help: &[
+            "Extracts main.dat to a temporary directory and rebuilds it, reporting any bytes which differ.",
+        ],
+        min_args: 0,
+        max_args: Some(0),
+        options: &["--spec"],
+        flags: &["--xmas", "--christmas", "--ohno", "--cga"],
+    },
+    CommandSpec {
+        name: "render-pcspkr",
+        usage: "<file> [--wav-dir <dir>] [--force | --skip-existing]",
+        help: &[
+            "Renders each PC speaker sound in <file> (pcspkr.txt or pcspkr.snd) to a WAV file, for previewing.",
+        ],
+        min_args: 1,
+        max_args: Some(1),
+        options: &["--wav-dir"],
+        flags: &["--force", "--skip-existing"],
+    },
+    CommandSpec {
+        name: "extract-all",
+        usage: "[--out-dir <dir>] [--force | --skip-existing]",
+        help: &[
+            "Extracts everything in the data directory to <dir> (default the current directory): each graphics set to sets/<n>/,",
+            "main.dat to main/, and the levels in each levelNNN.dat (or any other .dat file of levels, as the demos have) to levels/,",
+            "then lists what was extracted and what wasn't.",
+            "Anything which can't be extracted doesn't stop the rest.",
+        ],
+        min_args: 0,
+        max_args: Some(0),
+        options: &["--out-dir"],
+        flags: &["--force", "--skip-existing"],
+    },
+    CommandSpec {
+        name: "extract-dat",
+        usage: "<name> [--force | --skip-existing]",
+        help: &[
+            "Decompresses <name>.dat into <name>.000, <name>.001, etc.",
+        ],
+        min_args: 1,
+        max_args: Some(1),
+        options: &[],
+        flags: &["--force", "--skip-existing"],
+    },
+    CommandSpec {
+        name: "extract-levels",
+        usage: "<file> [--raw] [--variant original|onml|holiday] [--force | --skip-existing]",
+        help: &[
+            "Extracts each level in <file> (e.g. level000.dat) to level000.000.txt, level000.001.txt, etc.",
+            "--raw saves the levels as-is (level000.000, etc.), as extract-dat does.",
+            "--variant gives the game the levels are from, if it can't be told from their graphics sets.",
+        ],
+        min_args: 1,
+        max_args: Some(1),
+        options: &["--variant"],
+        flags: &["--raw", "--force", "--skip-existing"],
+    },
+    CommandSpec {
+        name: "create-levels",
+        usage: "<name> [--ground-dir <dir>] [--strict] [--no-backup]",
+        help: &[
+            "Creates <name>.dat from the levels in <name>.000.txt, <name>.001.txt, etc.",
+            "Warns about stats out of the game's range, and anything placed outside the level (using the graphics sets in <dir>",
+            "for their sizes, if given).",
+            "--strict fails instead.",
+        ],
+        min_args: 1,
+        max_args: Some(1),
+        options: &["--ground-dir"],
+        flags: &["--no-backup", "--strict"],
+    },
+    CommandSpec {
+        name: "repack-levels",
+        usage: "<manifest> [--out-dir <dir>] [--prefix <name>] [--per-file <n>] [--variant original|onml|holiday] [--no-backup]",
+        help: &[
+            "Writes the levels listed in <manifest> (a file and level index per line) to level000.dat, level001.dat, etc.,",
+            "with <n> (default 8) in each. --variant gives the game they're for (default original).",
+        ],
+        min_args: 1,
+        max_args: Some(1),
+        options: &["--out-dir", "--prefix", "--per-file", "--variant"],
+        flags: &["--no-backup"],
+    },
+    CommandSpec {
+        name: "extract-lvl",
+        usage: "<file> <index> [-o <file>] [--force | --skip-existing]",
+        help: &[
+            "Saves level <index> (from 0) of <file> as a raw .lvl file (default <file>.<index>.lvl).",
+        ],
+        min_args: 2,
+        max_args: Some(2),
+        options: &["-o", "--out"],
+        flags: &["--force", "--skip-existing"],
+    },
+    CommandSpec {
+        name: "list-levels",
+        usage: "<file>... [--json] [--variant original|onml|holiday] [--set-map <map>]",
+        help: &[
+            "Lists the name, graphics set and stats of each level in the given files (e.g. level000.dat).",
+            "--json prints them as JSON instead (see the README for its fields).",
+        ],
+        min_args: 1,
+        max_args: None,
+        options: &["--set-map", "--variant"],
+        flags: &["--json"],
+    },
+    CommandSpec {
+        name: "new-level",
+        usage: "(--template <file>:<index> | --blank [--set <n>]) [--variant original|onml|holiday] [-o <text file>] [--force | --skip-existing]",
+        help: &[
+            "Writes a level to a text file (default level.txt) to start a new one from: either level <index> of <file>,",
+            "or a blank level for graphics set <n> (default 0), with just an entrance and an exit.",
+        ],
+        min_args: 0,
+        max_args: Some(0),
+        options: &["--template", "--set", "--variant", "-o", "--out"],
+        flags: &["--blank", "--force", "--skip-existing"],
+    },
+    CommandSpec {
+        name: "export-nxlv",
+        usage: "<file> <index> [-o <file>] [--style <style>] [--variant original|onml|holiday] [--set-map <map>] [--force | --skip-existing]",
+        help: &[
+            "Exports level <index> (from 0) of <file> as a NeoLemmix level (default <file>.<index>.nxlv).",
+            "--style gives the NeoLemmix style to use for the level's graphics set.",
+        ],
+        min_args: 2,
+        max_args: Some(2),
+        options: &["--variant", "--style", "--set-map", "-o", "--out"],
+        flags: &["--force", "--skip-existing"],
+    },
+    CommandSpec {
+        name: "stats-levels",
+        usage: "<file>... [--ground-dir <dir>] [--variant original|onml|holiday] [--set-map <map>] [--json]",
+        help: &[
+            "Counts how often the levels in the given files use each terrain piece and object of their graphics sets,",
+            "and how much space the unused ones take up.",
+            "--json prints the counts as JSON instead (see the README for its fields).",
+        ],
+        min_args: 1,
+        max_args: None,
+        options: &["--ground-dir", "--variant", "--set-map"],
+        flags: &["--json"],
+    },
+    CommandSpec {
+        name: "diff-levels",
+        usage: "<file a> <file b> [--brief]",
+        help: &[
+            "Compares the levels in two files (e.g. level000.dat), level by level, and lists what changed.",
+            "--brief lists only which levels changed.",
+        ],
+        min_args: 2,
+        max_args: Some(2),
+        options: &[],
+        flags: &["--brief"],
+    },
+    CommandSpec {
+        name: "check-level",
+        usage: "<file> [--ground-dir <dir>] [--variant original|onml|holiday] [--set-map <map>]",
+        help: &[
+            "Checks each level in <file> against its graphics set (from <dir>, default the data directory),",
+            "and exits with an error if any use objects or terrain the set doesn't have, or are otherwise invalid",
+            "(as are sections which aren't levels, and files with no levels).",
+        ],
+        min_args: 1,
+        max_args: Some(1),
+        options: &["--ground-dir", "--set-map", "--variant"],
+        flags: &[],
+    },
+    CommandSpec {
+        name: "render-level",
+        usage: "<file> <index> [--ground-dir <dir>] [--variant original|onml|holiday] [--set-map <map>] [-o <image>] [--mask <image>] [--minimap <image>] [--steel] [--show-triggers] [--force | --skip-existing]",
+        help: &[
+            "Draws level <index> (from 0) of <file> (e.g. level000.dat) to <image> (.png or .bmp, default <file>.<index>.png),",
+            "using the graphics set in <dir> (default the data directory).",
+            "--mask also draws the terrain mask (where lemmings can walk) to a separate image.",
+            "--minimap also draws the terrain mask at the scale of the game's minimap (1/16 by 1/8).",
+            "--steel tints the areas of steel.",
+            "--show-triggers outlines each object's trigger area, coloured by what it does.",
+            "--set-map gives the files for each graphics set number, as <n>=<ground file>,... or a set map file.",
+        ],
+        min_args: 2,
+        max_args: Some(2),
+        options: &["--ground-dir", "--set-map", "--variant", "-o", "--out", "--mask", "--minimap"],
+        flags: &["--steel", "--show-triggers", "--force", "--skip-existing"],
+    },
+    CommandSpec {
+        name: "render-all-levels",
+        usage: "<file>... [--ground-dir <dir>] [--out-dir <dir>] [--variant original|onml|holiday] [--set-map <map>] [--steel] [--show-triggers] [--force | --skip-existing]",
+        help: &[
+            "Draws every level in the given files to <dir> (default the current directory), as <file>.<index>.png.",
+            "With --verbose (-v), also prints how long each level took.",
+        ],
+        min_args: 1,
+        max_args: None,
+        options: &["--ground-dir", "--out-dir", "--set-map", "--variant"],
+        flags: &["--steel", "--show-triggers", "--force", "--skip-existing"],
+    },
+    CommandSpec {
+        name: "extract-vgaspec",
+        usage: "<n> [-o <image>] [--force | --skip-existing]",
+        help: &[
+            "Extracts the special graphics in vgaspec<n>.dat to <image> (.png or .bmp, default vgaspec<n>.png), with its palette.",
+        ],
+        min_args: 1,
+        max_args: Some(1),
+        options: &["-o", "--out"],
+        flags: &["--force", "--skip-existing"],
+    },
+    CommandSpec {
+        name: "create-vgaspec",
+        usage: "<image> [-o <file>] [--no-backup]",
+        help: &[
+            "Creates special graphics (default <image> with a .dat extension) from a 960×160 image using colours 0 to 7.",
+        ],
+        min_args: 1,
+        max_args: Some(1),
+        options: &["-o", "--out"],
+        flags: &["--no-backup"],
+    },
+    CommandSpec {
+        name: "extract-oddtable",
+        usage: "[<file>] [--levels <dir>] [-o <text file>] [--force | --skip-existing]",
+        help: &[
+            "Extracts the alternative level stats in <file> (default oddtable.dat) to a text file (default oddtable.txt).",
+            "--levels labels each entry with its level, from the levelNNN.dat files in <dir>.",
+        ],
+        min_args: 0,
+        max_args: Some(1),
+        options: &["--levels", "-o", "--out"],
+        flags: &["--force", "--skip-existing"],
+    },
+    CommandSpec {
+        name: "create-oddtable",
+        usage: "[<text file>] [-o <file>] [--no-backup]",
+        help: &[
+            "Creates <file> (default oddtable.dat) from a text file (default oddtable.txt).",
+        ],
+        min_args: 0,
+        max_args: Some(1),
+        options: &["-o", "--out"],
+        flags: &["--no-backup"],
+    },
+    CommandSpec {
+        name: "create-dat",
+        usage: "<name> [--no-backup]",
+        help: &[
+            "Compressed <name>.000, <name>.001, etc. into <name>.dat",
+        ],
+        min_args: 1,
+        max_args: Some(1),
+        options: &[],
+        flags: &["--no-backup"],
+    },
+    CommandSpec {
+        name: "diff-dat",
+        usage: "<file a> <file b>",
+        help: &[
+            "Compares two .dat files, section by section, once they're decompressed, and shows where each section first differs.",
+            "Exits with status 1 if any do.",
+        ],
+        min_args: 2,
+        max_args: Some(2),
+        options: &[],
+        flags: &[],
+    },
+    CommandSpec {
+        name: "info",
+        usage: "<file> [--json]",
+        help: &[
+            "Works out what sort of file <file> is (a graphics set, main.dat, levels, etc.) from what's in it,",
+            "and lists its sections, if it has any.",
+            "--json prints the same as JSON instead (see the README for its fields).",
+        ],
+        min_args: 1,
+        max_args: Some(1),
+        options: &[],
+        flags: &["--json"],
+    },
+    CommandSpec {
+        name: "batch",
+        usage: "<file> [--keep-going]",
+        help: &[
+            "Runs each command in <file>, one per line, as it would be typed after \"modlem\" (# starts a comment).",
+            "The options given before \"batch\" apply to each. The game's files are only read once between them.",
+            "Stops at the first command which fails, unless --keep-going is given, then lists how each went.",
+        ],
+        min_args: 1,
+        max_args: Some(1),
+        options: &[],
+        flags: &["--keep-going"],
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args : &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn commands_check_args() {
+        let render = find_command("render-level").unwrap();
+        assert_eq!(render.check_args(&args(&["--steel", "level000.dat", "-o", "out.png", "3"])),
+            Ok(args(&["level000.dat", "3", "--steel", "-o", "out.png"])));
+        assert_eq!(render.check_args(&args(&["level000.dat"])), Err(ArgsError::Invalid("render-level needs more arguments than that".to_string())));
+        assert_eq!(render.check_args(&args(&["level000.dat", "3", "4"])), Err(ArgsError::Invalid("render-level doesn't take the argument \"4\"".to_string())));
+        assert_eq!(render.check_args(&args(&["level000.dat", "3", "--stee"])), Err(ArgsError::Invalid("render-level doesn't take a \"--stee\" option".to_string())));
+        assert_eq!(render.check_args(&args(&["level000.dat", "3", "-o"])), Err(ArgsError::Invalid("-o needs a value".to_string())));
+        assert_eq!(render.check_args(&args(&["--help"])), Err(ArgsError::Help));
+
+        // Values can look like options, and - on its own is a value but never a file.
+        let extract_main = find_command("extract-main").unwrap();
+        assert_eq!(extract_main.check_args(&args(&["--prefix", "-x"])), Ok(args(&["--prefix", "-x"])));
+        assert_eq!(extract_main.check_args(&args(&["--prefix", "-"])), Ok(args(&["--prefix", "-"])));
+        assert!(find_command("list-levels").unwrap().check_args(&args(&["-", "a.dat", "b.dat"])).is_err());
+        assert!(find_command("extract-everything").is_none());
+    }
+}
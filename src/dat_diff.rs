@@ -0,0 +1,133 @@
+/*
+ * modlem: A graphics importer/exporter for Lemmings
+ * Copyright (C) 2022–2026 David Gow <david@davidgow.net>
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Comparing the sections of two .dat files, once they're decompressed. (The compressed data
+//! can differ for the same section, depending on what compressed it.)
+
+/// How many bytes are shown either side of a difference.
+const CONTEXT_BYTES : usize = 8;
+
+/// How a section of one .dat file compares to the same section of another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SectionDiff {
+    Identical,
+    /// They first differ at this offset, which is the end of the shorter if one is the start of
+    /// the other.
+    DiffersAt(usize),
+    OnlyInA,
+    OnlyInB,
+}
+
+/// The offset of the first byte which differs between @a and @b, or None if they're the same.
+pub fn first_difference(a : &[u8], b : &[u8]) -> Option<usize> {
+    match a.iter().zip(b).position(|(byte_a, byte_b)| byte_a != byte_b) {
+        Some(offset) => Some(offset),
+        None if a.len() != b.len() => Some(a.len().min(b.len())),
+        None => None,
+    }
+}
+
+/// Compare each of the decompressed @sections_a with the one at the same index in @sections_b.
+pub fn diff_sections(sections_a : &[Vec<u8>], sections_b : &[Vec<u8>]) -> Vec<SectionDiff> {
+    (0..sections_a.len().max(sections_b.len())).map(|index| {
+        match (sections_a.get(index), sections_b.get(index)) {
+            (Some(a), Some(b)) => first_difference(a, b).map_or(SectionDiff::Identical, SectionDiff::DiffersAt),
+            (Some(_), None) => SectionDiff::OnlyInA,
+            _ => SectionDiff::OnlyInB,
+        }
+    }).collect()
+}
+
+/// The bytes of @data around @offset, in hex, with the one at @offset in brackets (or "[end]"
+/// if @data ends there), after the offset of the first.
+pub fn hex_context(data : &[u8], offset : usize) -> String {
+    let start = offset.saturating_sub(CONTEXT_BYTES).min(data.len());
+    let end = (offset + CONTEXT_BYTES + 1).min(data.len());
+    let mut bytes : Vec<String> = (start..end).map(|i| {
+        if i == offset { format!("[{:02x}]", data[i]) } else { format!("{:02x}", data[i]) }
+    }).collect();
+    if offset >= data.len() {
+        bytes.push("[end]".to_string());
+    }
+    format!("{:#06x}: {}", start, bytes.join(" "))
+}
+
+/// Write what @diffs says about each section to @out: whether it's identical, where it differs
+/// (with the bytes around it, from @sections_a and @sections_b), or which file it's only in.
+/// @name_a and @name_b are the files' names.
+pub fn write_report(diffs : &[SectionDiff], sections_a : &[Vec<u8>], sections_b : &[Vec<u8>], name_a : &str, name_b : &str, out : &mut dyn std::io::Write) -> std::io::Result<()> {
+    if sections_a.len() != sections_b.len() {
+        writeln!(out, "{} has {} sections, and {} has {}", name_a, sections_a.len(), name_b, sections_b.len())?;
+    }
+    let width = name_a.len().max(name_b.len());
+    for (index, diff) in diffs.iter().enumerate() {
+        match *diff {
+            SectionDiff::Identical => writeln!(out, "Section {}: identical ({} bytes)", index, sections_a[index].len())?,
+            SectionDiff::DiffersAt(offset) => {
+                let (a, b) = (&sections_a[index], &sections_b[index]);
+                if a.len() == b.len() {
+                    writeln!(out, "Section {}: differs at offset {} ({} bytes)", index, offset, a.len())?;
+                } else {
+                    writeln!(out, "Section {}: differs at offset {} ({} bytes in {}, {} in {})", index, offset, a.len(), name_a, b.len(), name_b)?;
+                }
+                writeln!(out, "\t{:<width$}  {}", name_a, hex_context(a, offset), width = width)?;
+                writeln!(out, "\t{:<width$}  {}", name_b, hex_context(b, offset), width = width)?;
+            }
+            SectionDiff::OnlyInA => writeln!(out, "Section {}: only in {} ({} bytes)", index, name_a, sections_a[index].len())?,
+            SectionDiff::OnlyInB => writeln!(out, "Section {}: only in {} ({} bytes)", index, name_b, sections_b[index].len())?,
+        }
+    }
+    let num_differing = diffs.iter().filter(|diff| **diff != SectionDiff::Identical).count();
+    if num_differing == 0 {
+        writeln!(out, "No sections differ")
+    } else {
+        writeln!(out, "{} of the {} sections differ", num_differing, diffs.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dat_diff_sections() {
+        let long : Vec<u8> = (0..40).collect();
+        let mut changed = long.clone();
+        changed[20] = 0xff;
+        let a = vec![b"same".to_vec(), long.clone(), long.clone(), b"extra".to_vec()];
+        let b = vec![b"same".to_vec(), changed, long[..30].to_vec()];
+        let diffs = diff_sections(&a, &b);
+        assert_eq!(diffs, [SectionDiff::Identical, SectionDiff::DiffersAt(20), SectionDiff::DiffersAt(30), SectionDiff::OnlyInA]);
+        assert_eq!(diff_sections(&b, &a)[3], SectionDiff::OnlyInB);
+
+        assert_eq!(hex_context(&b[1], 20), "0x000c: 0c 0d 0e 0f 10 11 12 13 [ff] 15 16 17 18 19 1a 1b 1c");
+        assert_eq!(hex_context(&b[2], 30), "0x0016: 16 17 18 19 1a 1b 1c 1d [end]");
+        assert_eq!(hex_context(b"ab", 1), "0x0000: 61 [62]");
+
+        let mut report = Vec::new();
+        write_report(&diffs, &a, &b, "a.dat", "bb.dat", &mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        let lines : Vec<&str> = report.lines().collect();
+        assert_eq!(lines[0], "a.dat has 4 sections, and bb.dat has 3");
+        assert_eq!(lines[1], "Section 0: identical (4 bytes)");
+        assert_eq!(lines[2], "Section 1: differs at offset 20 (40 bytes)");
+        assert_eq!(lines[3], "\ta.dat   0x000c: 0c 0d 0e 0f 10 11 12 13 [14] 15 16 17 18 19 1a 1b 1c");
+        assert_eq!(lines[5], "Section 2: differs at offset 30 (40 bytes in a.dat, 30 in bb.dat)");
+        assert_eq!(lines[8], "Section 3: only in a.dat (5 bytes)");
+        assert_eq!(lines[9], "3 of the 4 sections differ");
+    }
+}
@@ -1,4 +1,6 @@
 use binary_io::*;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
 
 /*
  * The Lemmings 'dat' format consists of several 'sections', concatenated together.
@@ -9,6 +11,22 @@ use binary_io::*;
  * decompressed data to share a buffer.
  */
 
+/// The header before each section's compressed data.
+struct DatSectionHeader {
+    num_bits_in_first_byte : u8,
+    checksum : u8,
+    uncomp_size : u32,
+    /// Including the header.
+    comp_size : u32,
+}
+
+binary_record!(DatSectionHeader {
+    num_bits_in_first_byte : u8,
+    checksum : u8,
+    uncomp_size : be32,
+    comp_size : be32,
+});
+
 pub struct DatSection
 {
     uncomp_size : u32,
@@ -211,26 +229,26 @@ impl DatSection
             dat_section.checksum ^= b;
         }
 
-        println!("Compressed Dat Section from {} bytes to {} bytes", dat_section.uncomp_size, dat_section.comp_size);
         dat_section
     }
 
     /// Reads a DatSection from a file and verifies the checksum (but doesn't decompress it)
     pub fn from_file(reader : &mut dyn std::io::Read) -> std::io::Result<DatSection> {
-        let num_bits_in_first_byte = read_byte(reader)?;
-        let checksum = read_byte(reader)?;
-        let uncomp_size = read_be32(reader)?;
-        let comp_size = read_be32(reader)?;
+        let DatSectionHeader { num_bits_in_first_byte, checksum, uncomp_size, comp_size } = DatSectionHeader::read_from(reader)?;
+
+        // The header is 10 bytes, and there's always at least one byte of data (two if the
+        // first byte is empty).
+        if comp_size < if num_bits_in_first_byte == 0 { 12 } else { 11 } || num_bits_in_first_byte > 8 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid section header"));
+        }
 
-        let mut comp_data = vec![0; (comp_size - 10) as usize];
-        reader.read_exact(&mut comp_data)?;
+        let comp_data = read_bytes(reader, (comp_size - 10) as usize)?;
         let mut data_checksum = 0;
         for b in &comp_data {
             data_checksum ^= b;
         }
         if data_checksum != checksum {
-            println!("Expected checksum {:x}, got {:x}\n", checksum, data_checksum);
-            Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "checksum invalid"))
+            Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("checksum invalid (expected {:x}, got {:x})", checksum, data_checksum)))
         } else {
             Ok(DatSection {
                 uncomp_size,
@@ -244,21 +262,36 @@ impl DatSection
         }
     }
 
+    /// The size of the section once it's decompressed.
+    pub fn uncompressed_size(&self) -> usize {
+        self.uncomp_size as usize
+    }
+
+    /// The size of the section as it's stored, including its header.
+    pub fn compressed_size(&self) -> usize {
+        self.comp_size as usize
+    }
+
     /// Writes an already-compressed the section to a file.
     pub fn write(self, writer : &mut dyn std::io::Write) -> std::io::Result<()> {
-        write_byte(self.num_bits_in_first_byte, writer)?;
-        write_byte(self.checksum, writer)?; /* checksum */
-        write_be32(self.uncomp_size, writer)?;
-        write_be32(self.comp_size, writer)?;
+        DatSectionHeader {
+            num_bits_in_first_byte : self.num_bits_in_first_byte,
+            checksum : self.checksum,
+            uncomp_size : self.uncomp_size,
+            comp_size : self.comp_size,
+        }.write_to(writer)?;
         writer.write_all(self.comp_data.as_slice())?;
         Ok(())
     }
 
     /// Read 'bits' bits of compressed data, in reverse, from the compressed stream.
-    fn read_bits(&mut self, bits: u32) -> u32 {
+    fn read_bits(&mut self, bits: u32) -> std::io::Result<u32> {
         let mut val : u32 = 0;
         for _n in 0..bits {
-            let cur_byte = self.comp_data[self.byte_offset as usize];
+            let cur_byte = match self.comp_data.get(self.byte_offset as usize) {
+                Some(&byte) => byte,
+                None => return Err(corrupt_data()),
+            };
             let bit = if ((1 << self.bit_offset) & cur_byte) != 0 { 1 } else { 0 };
             let bits_in_byte = if (self.byte_offset == self.comp_size - 11) && (self.num_bits_in_first_byte != 0) { self.num_bits_in_first_byte as u32 } else { 8 };
             self.bit_offset += 1;
@@ -270,102 +303,255 @@ impl DatSection
             }
             val = (val << 1) | bit;
         }
-        val
+        Ok(val)
     }
 
-    /// Decompressed a DatSection.
-    pub fn decompress(&mut self) -> std::vec::Vec<u8> {
+    /// Decompressed a DatSection. Returns an error (rather than panicking) if the compressed
+    /// data doesn't make sense.
+    pub fn decompress(&mut self) -> std::io::Result<std::vec::Vec<u8>> {
+        // The best the compression can do is 256 bytes from a 23-bit command, so anything larger
+        // than this is certainly corrupt (and may well be too big to allocate).
+        if self.uncomp_size as u64 > self.comp_data.len() as u64 * 8 * 12 {
+            return Err(corrupt_data());
+        }
         let mut output : std::vec::Vec::<u8> = vec![0; self.uncomp_size as usize];
-        let mut i = (self.uncomp_size - 1) as usize;
-
-        while i > 0 {
-            match self.read_bits(1) {
-                0 => {
-                    // Commands starting with '0' are two bits.
-                    match self.read_bits(1) {
-                        0 => {
-                            // Raw bytes.
-                            let len = self.read_bits(3) + 1;
-                            i += 1;
-                            for _b in 0..len {
-                                i -= 1;
-                                output[i] = self.read_bits(8) as u8;
-                            }
-                        }
-                        1 => {
-                            // Two-byte reference
-                            let raw_off = self.read_bits(8);
-                            let off = i + 1 + raw_off as usize;
-                            output[i] = output[off];
-                            i -= 1;
-                            output[i] = output[off-1];
-                        }
-                        _ => {
-                            panic!("Bad two-bit command! A bit was neither 0 nor 1!");
-                        }
-                    }
-                }
-                1 => {
-                    // Commands which start with a 1 are 3-bits
-
-                    // Read the remaining two bits
-                    let cmd = self.read_bits(2);
-
-                    match cmd {
-                        // '100' Three byte match
-                        0 => {
-                            let raw_off = self.read_bits(9);
-                            let off = i + 1 + raw_off as usize;
-                            output[i] = output[off];
-                            i -= 1;
-                            output[i] = output[off-1];
-                            i -= 1;
-                            output[i] = output[off-2];
-                        }
-                        // '101' Four byte match
-                        1 => {
-                            let off = i + 1 + self.read_bits(10) as usize;
-                            output[i] = output[off];
-                            i -= 1;
-                            output[i] = output[off-1];
-                            i -= 1;
-                            output[i] = output[off-2];
-                            i -= 1;
-                            output[i] = output[off-3];
-                        }
-                        // '110' n-byte match
-                        2 => {
-                            let len = self.read_bits(8) + 1;
-                            let raw_off = self.read_bits(12) as usize;
-                            i += 1;
-                            for _b in 0..len {
-                                i -= 1;
-                                output[i] = output[i + raw_off + 1];
-                            }
-                        }
-                        // '111' big literal (8 bit length)
-                        3 => {
-                            let len = self.read_bits(8) + 9;
-                            i += 1;
-                            for _b in 0..len {
-                                i -= 1;
-                                output[i] = self.read_bits(8) as u8;
-                            }
-                        }
-                        _ => {
-                            panic!("Bad 3-bit command 1{}", cmd);
-                        }
+
+        // Data is decompressed from the end backwards, until the byte at offset 0 has been
+        // written. @remaining is the number of bytes still to be written.
+        let mut remaining = output.len();
+        while remaining > 0 {
+            // Each command is either a run of literal bytes, or a copy of a run of bytes which
+            // were already written (i.e., which are later in the output).
+            let (len, copy_offset) = match self.read_bits(1)? {
+                // Commands starting with '0' are two bits.
+                0 => match self.read_bits(1)? {
+                    // Raw bytes.
+                    0 => (self.read_bits(3)? + 1, None),
+                    // Two-byte reference
+                    _ => (2, Some(self.read_bits(8)?)),
+                },
+                // Commands which start with a 1 are 3-bits
+                _ => match self.read_bits(2)? {
+                    // '100' Three byte match
+                    0 => (3, Some(self.read_bits(9)?)),
+                    // '101' Four byte match
+                    1 => (4, Some(self.read_bits(10)?)),
+                    // '110' n-byte match
+                    2 => {
+                        let len = self.read_bits(8)? + 1;
+                        (len, Some(self.read_bits(12)?))
                     }
+                    // '111' big literal (8 bit length)
+                    _ => (self.read_bits(8)? + 9, None),
+                },
+            };
+            for _b in 0..len {
+                if remaining == 0 {
+                    return Err(corrupt_data());
                 }
-                _ => {
-                    panic!("Looks like we've got a bit whose value is not 0 or 1!");
-                }
+                remaining -= 1;
+                output[remaining] = match copy_offset {
+                    None => self.read_bits(8)? as u8,
+                    Some(offset) => match output.get(remaining + offset as usize + 1) {
+                        Some(&byte) => byte,
+                        None => return Err(corrupt_data()),
+                    },
+                };
             }
-            if i == 0 {
-                break;
-            }
-            i -= 1;
         }
-        output
+        Ok(output)
+    }
+}
+
+/// Where a section is in a .dat file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SectionLocation {
+    pub offset : u64,
+    /// Including the header.
+    pub len : u64,
+}
+
+/// Find every section in a .dat file, without reading their data. It's an error if the last
+/// one runs past the end of the file.
+pub fn section_locations(file : &mut File) -> std::io::Result<Vec<SectionLocation>> {
+    let file_len = file.seek(SeekFrom::End(0))?;
+    let mut locations = Vec::new();
+    let mut offset = file.seek(SeekFrom::Start(0))?;
+    while offset < file_len {
+        let header = with_context(DatSectionHeader::read_from(file), format_args!("reading section {}", locations.len()))?;
+        let len = header.comp_size as u64;
+        if len < DatSectionHeader::byte_size() as u64 || offset + len > file_len {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+                format!("section {} at offset {} runs past the end of the file", locations.len(), offset)));
+        }
+        locations.push(SectionLocation { offset, len });
+        offset = file.seek(SeekFrom::Start(offset + len))?;
+    }
+    Ok(locations)
+}
+
+/// Remove section @index of a .dat file, and everything after it.
+pub fn truncate_sections(file : &mut File, index : usize) -> std::io::Result<()> {
+    let locations = section_locations(file)?;
+    if let Some(location) = locations.get(index) {
+        file.set_len(location.offset)?;
+    }
+    file.seek(SeekFrom::End(0))?;
+    Ok(())
+}
+
+/// Replace section @index of a .dat file with @section, in place, or add it at the end if
+/// @index is the number of sections. If it's a different size, the sections after it are
+/// moved up or down to fit, so they're read into memory first. The file is left broken if
+/// writing fails partway, so make a backup (see backup::create_with_backup()) beforehand.
+pub fn replace_section(file : &mut File, index : usize, section : DatSection) -> std::io::Result<()> {
+    let locations = section_locations(file)?;
+    let file_len = file.seek(SeekFrom::End(0))?;
+    let (offset, old_len) = match locations.get(index) {
+        Some(location) => (location.offset, location.len),
+        None if index == locations.len() => (file_len, 0),
+        None => return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+            format!("there's no section {} (the file has {})", index, locations.len()))),
+    };
+    let mut new_data = Vec::new();
+    section.write(&mut new_data)?;
+
+    let mut tail = Vec::new();
+    if new_data.len() as u64 != old_len {
+        file.seek(SeekFrom::Start(offset + old_len))?;
+        file.read_to_end(&mut tail)?;
+    }
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(&new_data)?;
+    file.write_all(&tail)?;
+    if new_data.len() as u64 != old_len {
+        file.set_len(file_len - old_len + new_data.len() as u64)?;
+    }
+    file.flush()
+}
+
+/// Decompress every section of the .dat file @data.
+pub fn decompress_all(data : &[u8]) -> std::io::Result<Vec<Vec<u8>>> {
+    let mut reader = data;
+    let mut sections = Vec::new();
+    while !reader.is_empty() {
+        let context = format_args!("section {} at offset {}", sections.len(), data.len() - reader.len());
+        let mut section = with_context(DatSection::from_file(&mut reader), context)?;
+        sections.push(with_context(section.decompress(), context)?);
+    }
+    Ok(sections)
+}
+
+fn corrupt_data() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, "compressed data is corrupt")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn dat_section_round_trip() {
+        // Both literals and repeated runs, with a distinctive first byte.
+        let mut data = vec![0x49_u8, 1, 2, 3];
+        for i in 0..600 {
+            data.push((i / 7) as u8);
+        }
+        let mut compressed = Vec::<u8>::new();
+        DatSection::from_data(&data, data.len()).write(&mut compressed).unwrap();
+        let mut section = DatSection::from_file(&mut compressed.as_slice()).unwrap();
+        assert_eq!(section.decompress().unwrap(), data);
+    }
+    #[test]
+    fn dat_section_header() {
+        assert_eq!(DatSectionHeader::byte_size(), 10);
+        check_round_trips::<DatSectionHeader>();
+    }
+    #[test]
+    fn dat_section_corrupt() {
+        let data : Vec<u8> = (0..300).map(|i| (i % 13) as u8).collect();
+        let mut compressed = Vec::<u8>::new();
+        DatSection::from_data(&data, data.len()).write(&mut compressed).unwrap();
+        // Truncated sections, and ones which claim to be larger than they are, are errors.
+        assert!(DatSection::from_file(&mut &compressed[..compressed.len() - 1]).is_err());
+        compressed[5] += 1;
+        let mut section = DatSection::from_file(&mut compressed.as_slice()).unwrap();
+        assert!(section.decompress().is_err());
+        let err = decompress_all(&compressed).unwrap_err();
+        assert!(err.to_string().starts_with("section 0 at offset 0: "), "{}", err);
+    }
+    /// Write a .dat file made of a section for each of @sections, to patch in place.
+    fn dat_file(name : &str, sections : &[&[u8]]) -> (std::path::PathBuf, File) {
+        let path = std::env::temp_dir().join(format!("modlem_{}_{}.dat", name, std::process::id()));
+        let mut data = Vec::new();
+        for section in sections {
+            DatSection::from_data(section, section.len()).write(&mut data).unwrap();
+        }
+        std::fs::write(&path, &data).unwrap();
+        let file = std::fs::OpenOptions::new().read(true).write(true).open(&path).unwrap();
+        (path, file)
+    }
+    /// Decompress every section of @file.
+    fn dat_file_sections(file : &mut File) -> Vec<Vec<u8>> {
+        let mut data = Vec::new();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_to_end(&mut data).unwrap();
+        decompress_all(&data).unwrap()
+    }
+    #[test]
+    fn dat_section_replace() {
+        let long : Vec<u8> = (0..2000).map(|i| (i * 7 % 251) as u8).collect();
+        let (path, mut file) = dat_file("replace", &[b"first", &long, b"third"]);
+        let locations = section_locations(&mut file).unwrap();
+        assert_eq!(locations.len(), 3);
+        assert_eq!(locations[1].offset, locations[0].len);
+        assert_eq!(locations[2].offset + locations[2].len, file.metadata().unwrap().len());
+
+        // Shrinking the middle section moves the last one down...
+        replace_section(&mut file, 1, DatSection::from_data(b"second", 6)).unwrap();
+        assert_eq!(dat_file_sections(&mut file), [&b"first"[..], b"second", b"third"]);
+        let shrunk_len = file.metadata().unwrap().len();
+        assert!(shrunk_len < locations[2].offset);
+        // ...and growing it moves it back up.
+        replace_section(&mut file, 1, DatSection::from_data(&long, long.len())).unwrap();
+        assert_eq!(dat_file_sections(&mut file), [&b"first"[..], &long, b"third"]);
+        assert_eq!(section_locations(&mut file).unwrap(), locations);
+
+        // The same size doesn't need to move anything.
+        replace_section(&mut file, 0, DatSection::from_data(b"FIRST", 5)).unwrap();
+        assert_eq!(section_locations(&mut file).unwrap(), locations);
+        assert_eq!(dat_file_sections(&mut file), [&b"FIRST"[..], &long, b"third"]);
+
+        // The last section, and one past it (which adds a section).
+        replace_section(&mut file, 2, DatSection::from_data(&long, long.len())).unwrap();
+        replace_section(&mut file, 3, DatSection::from_data(b"fourth", 6)).unwrap();
+        assert_eq!(dat_file_sections(&mut file), [&b"FIRST"[..], &long, &long, b"fourth"]);
+        let err = replace_section(&mut file, 5, DatSection::from_data(b"sixth", 5)).unwrap_err();
+        assert_eq!(err.to_string(), "there's no section 5 (the file has 4)");
+
+        truncate_sections(&mut file, 1).unwrap();
+        assert_eq!(dat_file_sections(&mut file), [&b"FIRST"[..]]);
+        truncate_sections(&mut file, 1).unwrap();
+        assert_eq!(section_locations(&mut file).unwrap().len(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+    #[test]
+    fn dat_section_locations_truncated() {
+        let (path, mut file) = dat_file("truncated", &[b"first", b"second"]);
+        let len = file.metadata().unwrap().len();
+        file.set_len(len - 1).unwrap();
+        let err = section_locations(&mut file).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("section 1 at offset "));
+        // Nothing is written to a file which can't be read.
+        assert!(replace_section(&mut file, 0, DatSection::from_data(b"new", 3)).is_err());
+        assert_eq!(file.metadata().unwrap().len(), len - 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+    #[test]
+    fn dat_section_empty() {
+        let mut compressed = Vec::<u8>::new();
+        DatSection::from_data(&[], 0).write(&mut compressed).unwrap();
+        let mut section = DatSection::from_file(&mut compressed.as_slice()).unwrap();
+        assert!(section.decompress().unwrap().is_empty());
     }
 }
@@ -0,0 +1,142 @@
+/*
+ * modlem: A graphics importer/exporter for Lemmings
+ * Copyright (C) 2022–2026 David Gow <david@davidgow.net>
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The errors modlem's commands stop with. main() prints them, and exits with the code for
+//! their kind, so scripts can tell a mistake on the command line from a bad file.
+
+use modlem::parser::ParseError;
+use std::fmt;
+
+/// What sort of thing went wrong.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The command line was wrong: an unknown command or option, or a bad argument.
+    Usage,
+    /// A file (or script) couldn't be understood, or had something wrong in it.
+    Data,
+    /// A file couldn't be found, read or written.
+    Io,
+}
+
+impl ErrorKind {
+    /// The exit code for an error of this kind.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::Usage => 2,
+            ErrorKind::Data => 3,
+            ErrorKind::Io => 4,
+        }
+    }
+}
+
+/// An error which stops a command, with the message main() prints for it.
+#[derive(Debug)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn new<S: Into<String>>(kind: ErrorKind, message: S) -> Error {
+        Error { kind, message: message.into() }
+    }
+
+    pub fn usage<S: Into<String>>(message: S) -> Error {
+        Error::new(ErrorKind::Usage, message)
+    }
+
+    pub fn data<S: Into<String>>(message: S) -> Error {
+        Error::new(ErrorKind::Data, message)
+    }
+
+    pub fn io<S: Into<String>>(message: S) -> Error {
+        Error::new(ErrorKind::Io, message)
+    }
+
+    /// The same error, with @context (such as which file it was reading) before its message.
+    pub fn context(self, context: &str) -> Error {
+        Error { kind: self.kind, message: format!("{}: {}", context, self.message) }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Error {
+        let message = match err.snippet() {
+            Some(snippet) => format!("{}\n{}", err, snippet),
+            None => err.to_string(),
+        };
+        Error::data(message)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Error {
+        if let Some(parse_err) = ParseError::from_io_error(&err) {
+            return Error::from(parse_err.clone());
+        }
+        let kind = match err.kind() {
+            std::io::ErrorKind::InvalidData | std::io::ErrorKind::UnexpectedEof => ErrorKind::Data,
+            std::io::ErrorKind::InvalidInput => ErrorKind::Usage,
+            _ => ErrorKind::Io,
+        };
+        Error::new(kind, err.to_string())
+    }
+}
+
+/// Adds context to the error of a Result, as it's turned into an Error.
+pub trait Context<T> {
+    /// Put @context() before the error's message, if there is one.
+    fn context<F: FnOnce() -> String>(self, context: F) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> Context<T> for std::result::Result<T, E> {
+    fn context<F: FnOnce() -> String>(self, context: F) -> Result<T> {
+        self.map_err(|err| err.into().context(&context()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_kinds() {
+        let not_found = std::io::Error::new(std::io::ErrorKind::NotFound, "No such file");
+        let err = Err::<(), _>(not_found).context(|| "Error opening level000.dat".to_string()).unwrap_err();
+        assert_eq!((err.kind, err.message.as_str()), (ErrorKind::Io, "Error opening level000.dat: No such file"));
+
+        let bad_data = std::io::Error::new(std::io::ErrorKind::InvalidData, "Not a BMP file");
+        assert_eq!(Error::from(bad_data).kind, ErrorKind::Data);
+
+        let mut lex = modlem::parser::Lexer::from_str("Set five");
+        lex.expect_ident("Set").unwrap();
+        let parse_err = lex.get_int_literal().unwrap_err();
+        let err = Error::from(std::io::Error::from(parse_err.clone()));
+        assert_eq!(err.kind, ErrorKind::Data);
+        assert!(err.message.starts_with(&parse_err.to_string()));
+        assert!(err.message.contains("\n"));
+        assert_eq!(ErrorKind::Usage.exit_code(), 2);
+    }
+}
@@ -0,0 +1,290 @@
+/*
+ * modlem: A graphics importer/exporter for Lemmings
+ * Copyright (C) 2022–2026 David Gow <david@davidgow.net>
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Working out what sort of Lemmings file something is, from what's in it. Most of the game's
+//! files are called something.dat, whatever they hold, so the name isn't much help.
+
+use dat_section::DatSection;
+use graphics_set;
+use level;
+use main_dat::MainDatVariant;
+use oddtable;
+use vgaspec::VgaSpec;
+
+/// What a file looks like it is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FileKind {
+    /// groundXo.dat: the object and terrain headers, and palettes, of a graphics set.
+    GroundHeader,
+    /// vgagrX.dat: the terrain and object graphics of a graphics set.
+    GraphicsData,
+    MainDat(MainDatVariant),
+    /// levelNNN.dat: a .dat file of levels.
+    Levels,
+    /// A single level, as a .lvl file.
+    Level,
+    /// vgaspecX.dat: the picture for a special graphics level.
+    VgaSpec,
+    OddTable,
+    /// A .dat file, but not one of the kinds above.
+    Dat,
+    /// The start of a .dat file, which then stops making sense.
+    DamagedDat,
+    Unknown,
+}
+
+impl FileKind {
+    /// Whether files of this kind are made up of compressed sections.
+    pub fn has_sections(self) -> bool {
+        !matches!(self, FileKind::GroundHeader | FileKind::Level | FileKind::OddTable | FileKind::Unknown)
+    }
+
+    /// A name for the kind which won't change, for --json.
+    pub fn id(self) -> &'static str {
+        match self {
+            FileKind::GroundHeader => "ground",
+            FileKind::GraphicsData => "vgagr",
+            FileKind::MainDat(_) => "main",
+            FileKind::Levels => "levels",
+            FileKind::Level => "level",
+            FileKind::VgaSpec => "vgaspec",
+            FileKind::OddTable => "oddtable",
+            FileKind::Dat => "dat",
+            FileKind::DamagedDat => "damaged_dat",
+            FileKind::Unknown => "unknown",
+        }
+    }
+
+    /// What the kind is, as in "looks like ...".
+    pub fn description(self) -> String {
+        match self {
+            FileKind::GroundHeader => "a graphics set header file (groundXo.dat)".to_string(),
+            FileKind::GraphicsData => "a graphics set data file (vgagrX.dat)".to_string(),
+            FileKind::MainDat(variant) => format!("{}, laid out as for {:?}", variant.file_name(), variant),
+            FileKind::Levels => "a level file (levelNNN.dat)".to_string(),
+            FileKind::Level => "a single level (.lvl)".to_string(),
+            FileKind::VgaSpec => "special graphics (vgaspecX.dat)".to_string(),
+            FileKind::OddTable => "the alternative level stats (oddtable.dat)".to_string(),
+            FileKind::Dat => "a .dat file of some other kind".to_string(),
+            FileKind::DamagedDat => "a damaged .dat file".to_string(),
+            FileKind::Unknown => "a file modlem doesn't know about".to_string(),
+        }
+    }
+}
+
+/// One of the sections of a .dat file.
+pub struct SectionInfo {
+    pub offset : usize,
+    /// Including the header.
+    pub compressed_size : usize,
+    pub uncompressed_size : usize,
+    /// The decompressed data, or why it couldn't be decompressed.
+    pub data : Result<Vec<u8>, String>,
+}
+
+/// What's in a file, and what it looks like it is.
+pub struct FileInfo {
+    pub len : usize,
+    pub kind : FileKind,
+    /// The sections, if it starts like a .dat file.
+    pub sections : Vec<SectionInfo>,
+    /// Why the sections stop before the end of the file, if they do.
+    pub section_error : Option<String>,
+}
+
+impl FileInfo {
+    /// Look at the contents of a file, @data, to see what it is. Any data at all is fine: if
+    /// it's not something modlem knows, the kind is FileKind::Unknown.
+    pub fn identify(data : &[u8]) -> FileInfo {
+        let (sections, section_error) = read_sections(data);
+        let kind = if !sections.is_empty() && section_error.is_none() {
+            dat_kind(&sections)
+        } else if let Some(kind) = raw_kind(data) {
+            kind
+        } else if !sections.is_empty() {
+            FileKind::DamagedDat
+        } else {
+            FileKind::Unknown
+        };
+        FileInfo { len : data.len(), kind, sections, section_error }
+    }
+
+    /// Write what the file (called @name) looks like, and a table of its sections (if it has
+    /// any), to @out.
+    pub fn write(&self, name : &str, out : &mut dyn std::io::Write) -> std::io::Result<()> {
+        let num_sections = self.sections.len();
+        match self.kind {
+            FileKind::Unknown => writeln!(out, "{}: {} bytes, which don't look like any file modlem knows about", name, self.len)?,
+            kind if kind.has_sections() => writeln!(out, "{}: {} bytes, which look like {} with {} section{}", name, self.len,
+                kind.description(), num_sections, if num_sections == 1 { "" } else { "s" })?,
+            kind => writeln!(out, "{}: {} bytes, which look like {}", name, self.len, kind.description())?,
+        }
+        if !self.kind.has_sections() {
+            return Ok(());
+        }
+
+        writeln!(out, "Section  Offset  Compressed  Decompressed")?;
+        for (index, section) in self.sections.iter().enumerate() {
+            match &section.data {
+                Ok(_) => writeln!(out, "{:>7}  {:>6}  {:>10}  {:>12}", index, section.offset, section.compressed_size, section.uncompressed_size)?,
+                Err(err) => writeln!(out, "{:>7}  {:>6}  {:>10}  {:>12}  ({})", index, section.offset, section.compressed_size, section.uncompressed_size, err)?,
+            }
+        }
+        if let Some(err) = &self.section_error {
+            let end = self.sections.last().map_or(0, |section| section.offset + section.compressed_size);
+            writeln!(out, "The rest, from offset {}, isn't a section: {}", end, err)?;
+        }
+        Ok(())
+    }
+}
+
+/// Read the sections at the start of @data, until the end of it, or the first which isn't a
+/// section (returning what's wrong with it).
+fn read_sections(data : &[u8]) -> (Vec<SectionInfo>, Option<String>) {
+    let mut sections = Vec::new();
+    let mut reader = data;
+    while !reader.is_empty() {
+        let offset = data.len() - reader.len();
+        let mut section = match DatSection::from_file(&mut reader) {
+            Ok(section) => section,
+            Err(err) => return (sections, Some(err.to_string())),
+        };
+        sections.push(SectionInfo {
+            offset,
+            compressed_size : data.len() - reader.len() - offset,
+            uncompressed_size : section.uncompressed_size(),
+            data : section.decompress().map_err(|err| err.to_string()),
+        });
+    }
+    (sections, None)
+}
+
+/// What a file made up of @sections looks like.
+fn dat_kind(sections : &[SectionInfo]) -> FileKind {
+    let sizes : Vec<Option<usize>> = sections.iter().map(|section| section.data.as_ref().ok().map(|data| data.len())).collect();
+    if let Some(variant) = MainDatVariant::detect(&sizes) {
+        return FileKind::MainDat(variant);
+    }
+    if sizes.iter().all(|size| *size == Some(level::LEVEL_SIZE)) {
+        return FileKind::Levels;
+    }
+    match sections {
+        [section] if section.data.as_ref().is_ok_and(|data| VgaSpec::decode(data).is_ok()) => FileKind::VgaSpec,
+        [_, _] => FileKind::GraphicsData,
+        _ => FileKind::Dat,
+    }
+}
+
+/// What @data looks like, if it's one of the files which aren't compressed.
+fn raw_kind(data : &[u8]) -> Option<FileKind> {
+    // Palettes are 6-bit VGA values (and EGA values are less than that, too).
+    let palettes_valid = |palettes : &[u8]| palettes.iter().all(|&value| value < 64);
+    if data.len() == graphics_set::ground_file_size() && palettes_valid(&data[data.len() - graphics_set::Palettes::BYTE_SIZE..]) {
+        return Some(FileKind::GroundHeader);
+    }
+    if data.len() == level::LEVEL_SIZE && level::Level::from_bytes(data, None).is_ok() {
+        return Some(FileKind::Level);
+    }
+    // Each entry ends with a level name, which is plain text.
+    let names_valid = |entries : &[oddtable::OddTableEntry]| entries.iter().all(|entry| entry.name.iter().all(|&c| (b' '..=b'~').contains(&c)));
+    match oddtable::decode(data) {
+        Ok(entries) if !entries.is_empty() && names_valid(&entries) => Some(FileKind::OddTable),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use planar_bmp::{PaletteRGB, PlanarBMP};
+    use vgaspec::{VGASPEC_HEIGHT, VGASPEC_WIDTH};
+
+    /// A .dat file with a section for each of @sections.
+    fn dat_file(sections : &[&[u8]]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for section in sections {
+            DatSection::from_data(section, section.len()).write(&mut data).unwrap();
+        }
+        data
+    }
+
+    #[test]
+    fn file_info_kinds() {
+        let level = [0_u8; level::LEVEL_SIZE];
+        let levels = dat_file(&[&level, &level, &level]);
+        let info = FileInfo::identify(&levels);
+        assert_eq!(info.kind, FileKind::Levels);
+        assert_eq!(info.sections.len(), 3);
+        assert_eq!(info.sections[1].offset, info.sections[0].compressed_size);
+        assert_eq!(info.sections[2].uncompressed_size, level::LEVEL_SIZE);
+        assert_eq!(FileInfo::identify(&level).kind, FileKind::Level);
+
+        assert_eq!(FileInfo::identify(&dat_file(&[b"terrain", b"objects"])).kind, FileKind::GraphicsData);
+        assert_eq!(FileInfo::identify(&dat_file(&[b"one", b"two", b"three"])).kind, FileKind::Dat);
+
+        let image = PlanarBMP::new(VGASPEC_WIDTH, VGASPEC_HEIGHT, 3, &PaletteRGB::new(8)).unwrap();
+        let vgaspec = VgaSpec::from_image(&image).unwrap().encode();
+        assert_eq!(FileInfo::identify(&dat_file(&[&vgaspec])).kind, FileKind::VgaSpec);
+
+        let mut ground = vec![0_u8; graphics_set::ground_file_size()];
+        assert_eq!(ground.len(), 1056);
+        assert_eq!(FileInfo::identify(&ground).kind, FileKind::GroundHeader);
+        // Not with palette values out of range.
+        *ground.last_mut().unwrap() = 64;
+        assert_eq!(FileInfo::identify(&ground).kind, FileKind::Unknown);
+
+        let entries = vec![oddtable::OddTableEntry::default(); 16];
+        assert_eq!(FileInfo::identify(&oddtable::encode(&entries)).kind, FileKind::OddTable);
+
+        // Sections followed by something else.
+        let mut damaged = dat_file(&[b"first", b"second"]);
+        damaged.extend_from_slice(b"Oh no!");
+        let info = FileInfo::identify(&damaged);
+        assert_eq!(info.kind, FileKind::DamagedDat);
+        assert_eq!(info.sections.len(), 2);
+        let mut out = Vec::new();
+        info.write("damaged.dat", &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with(&format!("damaged.dat: {} bytes, which look like a damaged .dat file with 2 sections\n", damaged.len())), "{}", out);
+        assert!(out.contains(&format!("\nThe rest, from offset {}, isn't a section: ", damaged.len() - 6)), "{}", out);
+    }
+
+    #[test]
+    fn file_info_garbage() {
+        // Whatever the data, there's an answer.
+        let mut state = 12345_u32;
+        let mut garbage = Vec::new();
+        for _ in 0..4096 {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            garbage.push((state >> 16) as u8);
+        }
+        let levels = dat_file(&[&[0_u8; level::LEVEL_SIZE], b"Let's go!"]);
+        for data in [&garbage[..], &levels[..]] {
+            for len in (0..data.len()).step_by(7) {
+                let info = FileInfo::identify(&data[..len]);
+                info.write("file", &mut Vec::new()).unwrap();
+            }
+        }
+        assert_eq!(FileInfo::identify(&[]).kind, FileKind::Unknown);
+        // A corrupt section is still listed.
+        let mut corrupt = dat_file(&[&garbage[..300]]);
+        corrupt[5] ^= 0x10;
+        let info = FileInfo::identify(&corrupt);
+        assert_eq!(info.kind, FileKind::Dat);
+        assert!(info.sections[0].data.is_err());
+    }
+}
@@ -0,0 +1,219 @@
+/*
+ * modlem: A graphics importer/exporter for Lemmings
+ * Copyright (C) 2022–2026 David Gow <david@davidgow.net>
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use binary_io::*;
+use std::collections::HashMap;
+
+/*
+ * A minimal (animated) GIF writer, for export only. Every frame covers the
+ * whole image and shares the global palette, which is all we need for
+ * Lemmings' animations.
+ */
+
+/// The largest code the LZW compressor may use (GIF codes are at most 12 bits).
+const MAX_LZW_CODE : u16 = 4095;
+
+/// Packs variable-width codes into bytes, least significant bit first.
+struct BitWriter {
+    bytes : Vec<u8>,
+    current : u32,
+    num_bits : u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, code : u16, width : u32) {
+        self.current |= (code as u32) << self.num_bits;
+        self.num_bits += width;
+        while self.num_bits >= 8 {
+            self.bytes.push(self.current as u8);
+            self.current >>= 8;
+            self.num_bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.num_bits > 0 {
+            self.bytes.push(self.current as u8);
+        }
+        self.bytes
+    }
+}
+
+/// Compress some pixels with GIF's variant of LZW.
+fn lzw_compress(pixels : &[u8], min_code_size : u32) -> Vec<u8> {
+    let clear_code = 1_u16 << min_code_size;
+    let end_code = clear_code + 1;
+    let mut writer = BitWriter { bytes : Vec::new(), current : 0, num_bits : 0 };
+    let mut table = HashMap::<(u16, u8), u16>::new();
+    let mut next_code = end_code + 1;
+    let mut code_width = min_code_size + 1;
+
+    writer.write(clear_code, code_width);
+    let mut prefix : Option<u16> = None;
+    for &pixel in pixels {
+        let current = match prefix {
+            None => {
+                prefix = Some(pixel as u16);
+                continue;
+            },
+            Some(current) => current,
+        };
+        if let Some(&code) = table.get(&(current, pixel)) {
+            prefix = Some(code);
+            continue;
+        }
+        writer.write(current, code_width);
+        if next_code > MAX_LZW_CODE {
+            // The table is full: start again.
+            writer.write(clear_code, code_width);
+            table.clear();
+            next_code = end_code + 1;
+            code_width = min_code_size + 1;
+        } else {
+            table.insert((current, pixel), next_code);
+            if next_code == (1 << code_width) && code_width < 12 {
+                code_width += 1;
+            }
+            next_code += 1;
+        }
+        prefix = Some(pixel as u16);
+    }
+    if let Some(current) = prefix {
+        writer.write(current, code_width);
+    }
+    writer.write(end_code, code_width);
+    writer.finish()
+}
+
+/// Write an animated GIF. Each frame in @frames contains one palette index per pixel, row by
+/// row, and is shown for @delay hundredths of a second. If @transparent_index is given, that
+/// palette entry is transparent. The animation loops forever.
+pub fn write_animated(writer : &mut dyn std::io::Write, width : usize, height : usize, palette : &[(u8, u8, u8)], frames : &[Vec<u8>], delay : u16, transparent_index : Option<u8>) -> std::io::Result<()> {
+    // The palette size must be a power of two, of at least 2 entries.
+    let mut palette_bits = 1;
+    while (1 << palette_bits) < palette.len() {
+        palette_bits += 1;
+    }
+    assert!(palette_bits <= 8);
+
+    writer.write_all(b"GIF89a")?;
+    write_le16(width as u16, writer)?;
+    write_le16(height as u16, writer)?;
+    write_byte(0x80 | ((palette_bits - 1) << 4) as u8 | (palette_bits - 1) as u8, writer)?;
+    write_byte(0, writer)?; // Background colour
+    write_byte(0, writer)?; // Aspect ratio (unspecified)
+    for i in 0..(1 << palette_bits) {
+        let (r, g, b) = palette.get(i).copied().unwrap_or((0, 0, 0));
+        writer.write_all(&[r, g, b])?;
+    }
+
+    // Loop forever (the NETSCAPE2.0 application extension).
+    writer.write_all(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01")?;
+    write_le16(0, writer)?;
+    write_byte(0, writer)?;
+
+    for frame in frames {
+        assert_eq!(frame.len(), width * height);
+
+        // Graphic control extension: set the delay and transparency, and clear each frame
+        // to the background before drawing the next.
+        writer.write_all(&[0x21, 0xF9, 4])?;
+        let disposal = 2 << 2;
+        write_byte(disposal | if transparent_index.is_some() { 1 } else { 0 }, writer)?;
+        write_le16(delay, writer)?;
+        write_byte(transparent_index.unwrap_or(0), writer)?;
+        write_byte(0, writer)?;
+
+        // Image descriptor, covering the whole image.
+        write_byte(0x2C, writer)?;
+        write_le16(0, writer)?;
+        write_le16(0, writer)?;
+        write_le16(width as u16, writer)?;
+        write_le16(height as u16, writer)?;
+        write_byte(0, writer)?;
+
+        let min_code_size = std::cmp::max(2, palette_bits);
+        write_byte(min_code_size as u8, writer)?;
+        for block in lzw_compress(frame, min_code_size).chunks(255) {
+            write_byte(block.len() as u8, writer)?;
+            writer.write_all(block)?;
+        }
+        write_byte(0, writer)?;
+    }
+
+    write_byte(0x3B, writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A straightforward GIF LZW decoder, to check the compressor against.
+    fn lzw_decompress(data : &[u8], min_code_size : u32) -> Vec<u8> {
+        let clear_code = 1_usize << min_code_size;
+        let end_code = clear_code + 1;
+        let mut table : Vec<Vec<u8>> = Vec::new();
+        let mut code_width = min_code_size + 1;
+        let mut bit_pos = 0;
+        let mut output = Vec::new();
+        let mut prev : Option<Vec<u8>> = None;
+        loop {
+            let mut code = 0;
+            for i in 0..code_width as usize {
+                let bit = (data[(bit_pos + i) / 8] >> ((bit_pos + i) % 8)) & 1;
+                code |= (bit as usize) << i;
+            }
+            bit_pos += code_width as usize;
+            if code == clear_code {
+                table = (0..clear_code).map(|i| vec![i as u8]).collect();
+                table.push(vec![]);
+                table.push(vec![]);
+                code_width = min_code_size + 1;
+                prev = None;
+                continue;
+            }
+            if code == end_code {
+                return output;
+            }
+            let entry = if code < table.len() {
+                table[code].clone()
+            } else {
+                let mut entry = prev.clone().unwrap();
+                entry.push(entry[0]);
+                entry
+            };
+            output.extend_from_slice(&entry);
+            if let Some(mut new_entry) = prev {
+                new_entry.push(entry[0]);
+                table.push(new_entry);
+                if table.len() == (1 << code_width) && code_width < 12 {
+                    code_width += 1;
+                }
+            }
+            prev = Some(entry);
+        }
+    }
+
+    #[test]
+    fn gif_lzw_round_trip() {
+        let simple = vec![0, 0, 0, 1, 1, 0, 0, 0, 0, 1, 2, 3];
+        assert_eq!(lzw_decompress(&lzw_compress(&simple, 2), 2), simple);
+        // Enough varied data to fill the table and force a clear code.
+        let long : Vec<u8> = (0..20000_u32).map(|i| ((i * 7919) % 251 % 16) as u8).collect();
+        assert_eq!(lzw_decompress(&lzw_compress(&long, 4), 4), long);
+    }
+}
@@ -18,12 +18,17 @@
 #![allow(clippy::identity_op)]
 
 use std::convert::TryInto;
-use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use binary_io::*;
 use dat_section::DatSection;
 use planar_bmp;
+use palettes;
 use parser;
+use atlas;
+use backup;
+use schemas;
+use log::Log;
+use outputs::PendingOutputs;
 
 #[derive(Debug)]
 #[derive(Default)]
@@ -51,12 +56,37 @@ pub struct ObjectHeader
     pub trap_sound : u8,
 }
 
+binary_record!(ObjectHeader {
+    animation_flags : le16,
+    frame_start : u8,
+    frame_end : u8,
+    width : u8,
+    height : u8,
+    animation_frame_data_size : le16,
+    mask_offset : le16,
+    _unknown0 : le16,
+    _unknown1 : le16,
+    trigger_x : le16,
+    trigger_y : le16,
+    trigger_w : u8,
+    trigger_h : u8,
+    trigger_effect_id : u8,
+    animation_offset : le16,
+    preview_frame_offset : le16,
+    preview_frame_number : skip,
+    _unknown2 : le16,
+    trap_sound : u8,
+} then ObjectHeader::find_preview_frame);
+
 impl std::fmt::Display for ObjectHeader {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "{{\n")?;
         writeln!(f, "\tanimation_flags = {}\n", self.animation_flags)?;
         writeln!(f, "\tframes = ({},{})\n", self.frame_start, self.frame_end)?;
-        //writeln!(f, "\tsize = ({},{})\n", self.width, self.height)?;
+        // Objects with image data take their size from the bitmap.
+        if !self.has_image_data() {
+            writeln!(f, "\tsize = ({},{})\n", self.width, self.height)?;
+        }
         writeln!(f, "\ttrigger = ({},{},{},{})\n", self.trigger_x, self.trigger_y, self.trigger_w, self.trigger_h)?;
         writeln!(f, "\ttrigger_effect = {}\n", self.trigger_effect_id)?;
         writeln!(f, "\tpreview_frame = {}\n", self.preview_frame_number)?;
@@ -66,55 +96,24 @@ impl std::fmt::Display for ObjectHeader {
 }
 
 impl ObjectHeader {
-    pub fn read(reader : &mut dyn std::io::Read) -> std::io::Result<ObjectHeader> {
-        let mut oh = ObjectHeader {
-            animation_flags : read_le16(reader)?,
-            frame_start : read_byte(reader)?,
-            frame_end : read_byte(reader)?,
-            width : read_byte(reader)?,
-            height : read_byte(reader)?,
-            animation_frame_data_size : read_le16(reader)?,
-            mask_offset : read_le16(reader)?,
-            _unknown0 : read_le16(reader)?,
-            _unknown1 : read_le16(reader)?,
-            trigger_x : read_le16(reader)?,
-            trigger_y : read_le16(reader)?,
-            trigger_w : read_byte(reader)?,
-            trigger_h : read_byte(reader)?,
-            trigger_effect_id : read_byte(reader)?,
-            animation_offset : read_le16(reader)?,
-            preview_frame_offset : read_le16(reader)?,
-            preview_frame_number : 0,
-            _unknown2 : read_le16(reader)?,
-            trap_sound : read_byte(reader)?,
-        };
-        if oh.animation_frame_data_size != 0 {
-            oh.preview_frame_number = ((oh.preview_frame_offset - oh.animation_offset) / oh.animation_frame_data_size) as u8;
+    /// Work out which frame the preview is, which isn't stored itself.
+    fn find_preview_frame(&mut self) {
+        if let Some(preview_frame) = self.preview_frame_offset.checked_sub(self.animation_offset).and_then(|off| off.checked_div(self.animation_frame_data_size)) {
+            self.preview_frame_number = preview_frame as u8;
         }
-        Ok(oh)
-    }
-    pub fn write(&self, writer: &mut dyn std::io::Write) -> std::io::Result<()> {
-        write_le16(self.animation_flags, writer)?;
-        write_byte(self.frame_start, writer)?;
-        write_byte(self.frame_end, writer)?;
-        write_byte(self.width, writer)?;
-        write_byte(self.height, writer)?;
-        write_le16(self.animation_frame_data_size, writer)?;
-        write_le16(self.mask_offset, writer)?;
-        write_le16(self._unknown0, writer)?;
-        write_le16(self._unknown1, writer)?;
-        write_le16(self.trigger_x, writer)?;
-        write_le16(self.trigger_y, writer)?;
-        write_byte(self.trigger_w, writer)?;
-        write_byte(self.trigger_h, writer)?;
-        write_byte(self.trigger_effect_id, writer)?;
-        write_le16(self.animation_offset, writer)?;
-        write_le16(self.preview_frame_offset, writer)?;
-        write_le16(self._unknown2, writer)?;
-        write_byte(self.trap_sound, writer)?;
-        Ok(())
-    }
-    pub fn parse(lex: &mut parser::Lexer) -> ObjectHeader {
+    }
+    /// Some shipped objects have a size, but no frames (or no frame data). These have no image.
+    pub fn has_image_data(&self) -> bool {
+        self.width != 0 && self.height != 0 && self.frame_end != 0 && self.animation_frame_data_size != 0
+    }
+    /// The number of bytes the object's frames take up in the (decompressed) object data.
+    pub fn data_size(&self) -> usize {
+        if self.has_image_data() { self.animation_frame_data_size as usize * self.frame_end as usize } else { 0 }
+    }
+    /// The properties which can be set in an object's header in a script.
+    pub const PROPERTIES : [&'static str; 7] = ["animation_flags", "frames", "size", "trigger", "trigger_effect", "preview_frame", "trap_sound"];
+
+    pub fn parse(lex: &mut parser::Lexer) -> Result<ObjectHeader, parser::ParseError> {
         let mut res = ObjectHeader {
             animation_flags : 0,
             frame_start : 0,
@@ -137,46 +136,59 @@ impl ObjectHeader {
             trap_sound : 0
         };
 
-        lex.expect_symbol('{');
+        let mut seen = parser::SeenProperties::default();
+        lex.expect_symbol('{')?;
         loop {
-            let tok = lex.next_token();
-            match tok.unwrap() {
+            let tok = match lex.next_spanned()? {
+                Some(tok) => tok,
+                None => return Err(lex.error("an object property or '}'", None)),
+            };
+            match tok.token.borrowed() {
                 parser::Token::Ident(var) => {
-                    lex.expect_symbol('=');
+                    if !Self::PROPERTIES.contains(&var) {
+                        return Err(tok.unknown_name_error("an object property", &Self::PROPERTIES));
+                    }
+                    seen.check(lex, var)?;
+                    lex.expect_symbol('=')?;
                     match var {
                         "animation_flags" => {
-                            res.animation_flags = lex.get_int_literal() as u16;
+                            res.animation_flags = lex.get_u16("animation_flags")?;
                         },
                         "frames" => {
-                            lex.expect_symbol('(');
-                            res.frame_start = lex.get_int_literal() as u8;
-                            lex.expect_symbol(',');
-                            res.frame_end = lex.get_int_literal() as u8;
-                            lex.expect_symbol(')');
+                            lex.expect_symbol('(')?;
+                            res.frame_start = lex.get_u8("the first frame")?;
+                            lex.expect_symbol(',')?;
+                            res.frame_end = lex.get_u8("the number of frames")?;
+                            lex.expect_list_end(')')?;
+                        },
+                        "size" => {
+                            lex.expect_symbol('(')?;
+                            res.width = lex.get_u8("the width")?;
+                            lex.expect_symbol(',')?;
+                            res.height = lex.get_u8("the height")?;
+                            lex.expect_list_end(')')?;
                         },
                         "trigger" => {
-                            lex.expect_symbol('(');
-                            res.trigger_x = lex.get_int_literal() as u16;
-                            lex.expect_symbol(',');
-                            res.trigger_y = lex.get_int_literal() as u16;
-                            lex.expect_symbol(',');
-                            res.trigger_w = lex.get_int_literal() as u8;
-                            lex.expect_symbol(',');
-                            res.trigger_h = lex.get_int_literal() as u8;
-                            lex.expect_symbol(')');
+                            lex.expect_symbol('(')?;
+                            res.trigger_x = lex.get_u16("the trigger x")?;
+                            lex.expect_symbol(',')?;
+                            res.trigger_y = lex.get_u16("the trigger y")?;
+                            lex.expect_symbol(',')?;
+                            res.trigger_w = lex.get_u8("the trigger width")?;
+                            lex.expect_symbol(',')?;
+                            res.trigger_h = lex.get_u8("the trigger height")?;
+                            lex.expect_list_end(')')?;
                         },
                         "trigger_effect" => {
-                            res.trigger_effect_id = lex.get_int_literal() as u8;
+                            res.trigger_effect_id = lex.get_u8("trigger_effect")?;
                         },
                         "preview_frame" => {
-                            res.preview_frame_number = lex.get_int_literal() as u8;
+                            res.preview_frame_number = lex.get_u8("preview_frame")?;
                         },
                         "trap_sound" => {
-                            res.trap_sound = lex.get_int_literal() as u8;
+                            res.trap_sound = lex.get_u8("trap_sound")?;
                         },
-                        _ => {
-                            panic!("Unknown object property {}", var);
-                        }
+                        _ => unreachable!("{} is in ObjectHeader::PROPERTIES, but isn't handled", var),
                     }
                 }
                 parser::Token::Symbol('}') => {
@@ -184,11 +196,11 @@ impl ObjectHeader {
                     break;
                 }
                 _ => {
-                    panic!("Invalid object spec");
+                    return Err(tok.error("an object property or '}'"));
                 }
             }
         }
-        res
+        Ok(res)
     }
 }
 #[derive(Debug)]
@@ -203,25 +215,20 @@ pub struct TerrainHeader
     pub _unknown1 : u16,
 }
 
+binary_record!(TerrainHeader {
+    width : u8,
+    height : u8,
+    gfx_offset : le16,
+    mask_offset : le16,
+    _unknown1 : le16,
+});
+
 impl TerrainHeader
 {
-    pub fn read(reader : &mut dyn std::io::Read) -> std::io::Result<TerrainHeader> {
-        Ok(TerrainHeader {
-            width : read_byte(reader)?,
-            height : read_byte(reader)?,
-            gfx_offset : read_le16(reader)?,
-            mask_offset : read_le16(reader)?,
-            _unknown1 : read_le16(reader)?,
-        })
-    }
-
-    pub fn write(&self, writer : &mut dyn std::io::Write) -> std::io::Result<()> {
-        write_byte(self.width, writer)?;
-        write_byte(self.height, writer)?; /* checksum */
-        write_le16(self.gfx_offset, writer)?;
-        write_le16(self.mask_offset, writer)?;
-        write_le16(self._unknown1, writer)?;
-        Ok(())
+    /// The number of bytes the piece takes up in the (decompressed) terrain data: 4 planes of
+    /// graphics and 1 of mask.
+    pub fn data_size(&self) -> usize {
+        self.width as usize * self.height as usize / 8 * 5
     }
 }
 
@@ -237,11 +244,40 @@ pub struct Palettes
     pub vga_preview : [u8; 24],
 }
 
+/// Parse a block of 8 EGA colours, each given as (r, g, b) with components from 0 to 3.
+fn parse_ega_8(lex : &mut parser::Lexer) -> Result<[u8; 8], parser::ParseError> {
+    let mut colours = [0u8; 8];
+    lex.expect_symbol('{')?;
+    for (i, colour) in colours.iter_mut().enumerate() {
+        lex.expect_symbol('(')?;
+        let red = lex.get_int_in_range("an EGA red value", 0, 3)? as u8;
+        lex.expect_symbol(',')?;
+        let green = lex.get_int_in_range("an EGA green value", 0, 3)? as u8;
+        lex.expect_symbol(',')?;
+        let blue = lex.get_int_in_range("an EGA blue value", 0, 3)? as u8;
+        lex.expect_list_end(')')?;
+        *colour = red << 4 | green << 2 | blue;
+        if i < 7 {
+            lex.expect_symbol(',')?;
+        }
+    }
+    lex.expect_list_end('}')?;
+    Ok(colours)
+}
+
+/// Parse the 8 colours of one of the VGA palettes in a graphics set.
+fn parse_vga_8(lex : &mut parser::Lexer) -> Result<[u8; 24], parser::ParseError> {
+    let colours = palettes::parse_vga_colours(lex)?;
+    colours.try_into().map_err(|colours : Vec<u8>| parser::ParseError::new(lex.token_start(), "8 colours", format!("{} colours", colours.len() / 3)))
+}
+
 impl Palettes
 {
+    /// The size of the palettes at the end of a groundXo.dat file.
+    pub const BYTE_SIZE : usize = 32 * 3;
+
     pub fn read(reader : &mut dyn std::io::Read) -> std::io::Result<Palettes> {
-        let mut raw_pal = [0u8; 32 * 3];
-        reader.read_exact(&mut raw_pal)?;
+        let raw_pal : [u8; Palettes::BYTE_SIZE] = read_array(reader)?;
         Ok(Palettes {
             ega_custom : raw_pal[0..8].try_into().unwrap(),
             ega_standard : raw_pal[8..16].try_into().unwrap(),
@@ -252,140 +288,44 @@ impl Palettes
         })
     }
 
-    pub fn write(&self, writer : &mut dyn std::io::Write) {
-        writer.write_all(&self.ega_custom).unwrap();
-        writer.write_all(&self.ega_standard).unwrap();
-        writer.write_all(&self.ega_preview).unwrap();
-        writer.write_all(&self.vga_custom).unwrap();
-        writer.write_all(&self.vga_standard).unwrap();
-        writer.write_all(&self.vga_preview).unwrap();
+    pub fn write(&self, writer : &mut dyn std::io::Write) -> std::io::Result<()> {
+        writer.write_all(&self.ega_custom)?;
+        writer.write_all(&self.ega_standard)?;
+        writer.write_all(&self.ega_preview)?;
+        writer.write_all(&self.vga_custom)?;
+        writer.write_all(&self.vga_standard)?;
+        writer.write_all(&self.vga_preview)
     }
 
+    /// The palettes which can be set in a script.
+    pub const NAMES : [&'static str; 6] = ["ega_custom", "ega_standard", "ega_preview", "vga_custom", "vga_standard", "vga_preview"];
+
     /// Parse a palette config from a text file lexer.
-    pub fn parse(lex: &mut parser::Lexer) -> Palettes {
+    pub fn parse(lex: &mut parser::Lexer) -> Result<Palettes, parser::ParseError> {
         let mut pal = Palettes::default();
 
-        lex.expect_symbol('{');
+        let mut seen = parser::SeenProperties::default();
+        lex.expect_symbol('{')?;
         loop {
-            let tok = lex.next_token();
-            match tok.unwrap() {
+            let tok = match lex.next_spanned()? {
+                Some(tok) => tok,
+                None => return Err(lex.error("a palette name or '}'", None)),
+            };
+            match tok.token.borrowed() {
                 parser::Token::Ident(var) => {
-                    lex.expect_symbol('=');
+                    if !Self::NAMES.contains(&var) {
+                        return Err(tok.unknown_name_error("a palette name", &Self::NAMES));
+                    }
+                    seen.check(lex, var)?;
+                    lex.expect_symbol('=')?;
                     match var {
-                        "ega_custom" => {
-                            lex.expect_symbol('{');
-                            for i in 0..8 {
-                                lex.expect_symbol('(');
-                                let red = lex.get_int_literal() as u8;
-                                lex.expect_symbol(',');
-                                let green = lex.get_int_literal() as u8;
-                                lex.expect_symbol(',');
-                                let blue = lex.get_int_literal() as u8;
-                                lex.expect_symbol(')');
-                                let val = red << 4 | green << 2 | blue;
-                                pal.ega_custom[i] = val;
-                                if i < 7 {
-                                    lex.expect_symbol(',');
-                                }
-                            }
-                            lex.expect_symbol('}');
-                        },
-                        "ega_standard" => {
-                            lex.expect_symbol('{');
-                            for i in 0..8 {
-                                lex.expect_symbol('(');
-                                let red = lex.get_int_literal() as u8;
-                                lex.expect_symbol(',');
-                                let green = lex.get_int_literal() as u8;
-                                lex.expect_symbol(',');
-                                let blue = lex.get_int_literal() as u8;
-                                lex.expect_symbol(')');
-                                let val = red << 4 | green << 2 | blue;
-                                pal.ega_standard[i] = val;
-                                if i < 7 {
-                                    lex.expect_symbol(',');
-                                }
-                            }
-                            lex.expect_symbol('}');
-                        },
-                        "ega_preview" => {
-                            lex.expect_symbol('{');
-                            for i in 0..8 {
-                                lex.expect_symbol('(');
-                                let red = lex.get_int_literal() as u8;
-                                lex.expect_symbol(',');
-                                let green = lex.get_int_literal() as u8;
-                                lex.expect_symbol(',');
-                                let blue = lex.get_int_literal() as u8;
-                                lex.expect_symbol(')');
-                                let val = red << 4 | green << 2 | blue;
-                                pal.ega_preview[i] = val;
-                                if i < 7 {
-                                    lex.expect_symbol(',');
-                                }
-                            }
-                            lex.expect_symbol('}');
-                        },
-                        "vga_custom" => {
-                            lex.expect_symbol('{');
-                            for i in 0..8 {
-                                lex.expect_symbol('(');
-                                let red = lex.get_int_literal() as u8;
-                                lex.expect_symbol(',');
-                                let green = lex.get_int_literal() as u8;
-                                lex.expect_symbol(',');
-                                let blue = lex.get_int_literal() as u8;
-                                lex.expect_symbol(')');
-                                pal.vga_custom[i*3 + 0] = red;
-                                pal.vga_custom[i*3 + 1] = green;
-                                pal.vga_custom[i*3 + 2] = blue;
-                                if i < 7 {
-                                    lex.expect_symbol(',');
-                                }
-                            }
-                            lex.expect_symbol('}');
-                        },
-                        "vga_standard" => {
-                            lex.expect_symbol('{');
-                            for i in 0..8 {
-                                lex.expect_symbol('(');
-                                let red = lex.get_int_literal() as u8;
-                                lex.expect_symbol(',');
-                                let green = lex.get_int_literal() as u8;
-                                lex.expect_symbol(',');
-                                let blue = lex.get_int_literal() as u8;
-                                lex.expect_symbol(')');
-                                pal.vga_standard[i*3 + 0] = red;
-                                pal.vga_standard[i*3 + 1] = green;
-                                pal.vga_standard[i*3 + 2] = blue;
-                                if i < 7 {
-                                    lex.expect_symbol(',');
-                                }
-                            }
-                            lex.expect_symbol('}');
-                        },
-                        "vga_preview" => {
-                            lex.expect_symbol('{');
-                            for i in 0..8 {
-                                lex.expect_symbol('(');
-                                let red = lex.get_int_literal() as u8;
-                                lex.expect_symbol(',');
-                                let green = lex.get_int_literal() as u8;
-                                lex.expect_symbol(',');
-                                let blue = lex.get_int_literal() as u8;
-                                lex.expect_symbol(')');
-                                pal.vga_preview[i*3 + 0] = red;
-                                pal.vga_preview[i*3 + 1] = green;
-                                pal.vga_preview[i*3 + 2] = blue;
-                                if i < 7 {
-                                    lex.expect_symbol(',');
-                                }
-                            }
-                            lex.expect_symbol('}');
-                        },
-                        _ => {
-                            panic!("Unknown palette {}", var);
-                        }
+                        "ega_custom" => pal.ega_custom = parse_ega_8(lex)?,
+                        "ega_standard" => pal.ega_standard = parse_ega_8(lex)?,
+                        "ega_preview" => pal.ega_preview = parse_ega_8(lex)?,
+                        "vga_custom" => pal.vga_custom = parse_vga_8(lex)?,
+                        "vga_standard" => pal.vga_standard = parse_vga_8(lex)?,
+                        "vga_preview" => pal.vga_preview = parse_vga_8(lex)?,
+                        _ => unreachable!("{} is in Palettes::NAMES, but isn't handled", var),
                     }
                 }
                 parser::Token::Symbol('}') => {
@@ -393,11 +333,11 @@ impl Palettes
                     break;
                 }
                 _ => {
-                    panic!("Invalid palettes spec");
+                    return Err(tok.error("a palette name or '}'"));
                 }
             }
         }
-        pal
+        Ok(pal)
     }
 
 }
@@ -457,141 +397,488 @@ impl std::fmt::Display for Palettes {
     }
 }
 
-pub struct ExtractOptions<'a> {
-    pub terrain_filename_pattern : &'a str,
-    pub terrain_mask_filename_pattern: Option<&'a str>,
-    pub object_filename_pattern : &'a str,
-    pub object_mask_filename_pattern : Option<&'a str>,
+/// Options controlling how a graphics set is written out. Use ExtractOptions::builder() to
+/// construct one.
+#[derive(Clone)]
+pub struct ExtractOptions {
+    /// The directory all of the images are written to.
+    pub output_dir : PathBuf,
+    /// Filename patterns: '#' is replaced with the slot index. The extension is added automatically.
+    pub terrain_filename_pattern : String,
+    pub terrain_mask_filename_pattern: Option<String>,
+    pub object_filename_pattern : String,
+    pub object_mask_filename_pattern : Option<String>,
     pub ega_mode : bool,
+    /// Treat any mismatch between a piece's graphics and its mask as an error, rather than a warning.
+    pub check_masks : bool,
+    /// Write .png files instead of .bmp files. (create-set reads either.)
+    pub png : bool,
+    pub terrain_only : bool,
+    pub objects_only : bool,
+    pub log : Log,
 }
 
-impl<'a> Default for ExtractOptions<'a> {
+impl Default for ExtractOptions {
     fn default() -> Self {
         ExtractOptions {
-            terrain_filename_pattern : "terrain#.bmp",
+            output_dir : PathBuf::new(),
+            terrain_filename_pattern : "terrain#".to_string(),
             terrain_mask_filename_pattern : None,
-            object_filename_pattern : "obj#.bmp",
+            object_filename_pattern : "obj#".to_string(),
             object_mask_filename_pattern : None,
             ega_mode : false,
+            check_masks : false,
+            png : false,
+            terrain_only : false,
+            objects_only : false,
+            log : Log::default(),
+        }
+    }
+}
+
+impl ExtractOptions {
+    pub fn builder() -> ExtractOptionsBuilder {
+        ExtractOptionsBuilder { options : ExtractOptions::default() }
+    }
+
+    /// Get the path (and filename to use in the script) for a given pattern and slot.
+    fn output_path(&self, pattern : &str, index : usize) -> PathBuf {
+        let extension = if self.png { "png" } else { "bmp" };
+        self.output_dir.join(format!("{}.{}", pattern.replace("#", &index.to_string()), extension))
+    }
+}
+
+/// Builds an ExtractOptions, e.g.:
+/// ExtractOptions::builder().output_dir("out").png(true).objects_only(true).build()
+pub struct ExtractOptionsBuilder {
+    options : ExtractOptions,
+}
+
+impl ExtractOptionsBuilder {
+    pub fn output_dir<P: AsRef<Path>>(mut self, dir : P) -> Self {
+        self.options.output_dir = dir.as_ref().to_path_buf();
+        self
+    }
+
+    pub fn terrain_filename_pattern(mut self, pattern : &str) -> Self {
+        self.options.terrain_filename_pattern = pattern.to_string();
+        self
+    }
+
+    /// Write terrain masks to separate files. Otherwise, they're placed next to the graphics.
+    pub fn terrain_mask_filename_pattern(mut self, pattern : &str) -> Self {
+        self.options.terrain_mask_filename_pattern = Some(pattern.to_string());
+        self
+    }
+
+    pub fn object_filename_pattern(mut self, pattern : &str) -> Self {
+        self.options.object_filename_pattern = pattern.to_string();
+        self
+    }
+
+    /// Write object masks to separate files. Otherwise, they're placed next to the graphics.
+    pub fn object_mask_filename_pattern(mut self, pattern : &str) -> Self {
+        self.options.object_mask_filename_pattern = Some(pattern.to_string());
+        self
+    }
+
+    pub fn ega_mode(mut self, ega_mode : bool) -> Self {
+        self.options.ega_mode = ega_mode;
+        self
+    }
+
+    pub fn check_masks(mut self, check_masks : bool) -> Self {
+        self.options.check_masks = check_masks;
+        self
+    }
+
+    pub fn png(mut self, png : bool) -> Self {
+        self.options.png = png;
+        self
+    }
+
+    pub fn terrain_only(mut self, terrain_only : bool) -> Self {
+        self.options.terrain_only = terrain_only;
+        self
+    }
+
+    pub fn objects_only(mut self, objects_only : bool) -> Self {
+        self.options.objects_only = objects_only;
+        self
+    }
+
+    pub fn log(mut self, log : Log) -> Self {
+        self.options.log = log;
+        self
+    }
+
+    pub fn build(self) -> ExtractOptions {
+        self.options
+    }
+}
+
+/// The number of terrain pieces in a graphics set.
+const NUM_TERRAIN_SLOTS : usize = 64;
+
+/// The number of objects in a graphics set.
+const NUM_OBJECT_SLOTS : usize = 16;
+
+/// The size of a groundXo.dat file: the object headers, the terrain headers, then the palettes.
+pub fn ground_file_size() -> usize {
+    ObjectHeader::byte_size() * NUM_OBJECT_SLOTS + TerrainHeader::byte_size() * NUM_TERRAIN_SLOTS + Palettes::BYTE_SIZE
+}
+
+/// A decoded terrain piece.
+pub struct TerrainPiece {
+    /// The slot this piece occupies in the graphics set (as used by levels).
+    pub index : usize,
+    pub header : TerrainHeader,
+    pub image : planar_bmp::PlanarBMP,
+    pub mask : planar_bmp::PlanarBMP,
+}
+
+/// A decoded object, with each frame's graphics and (1-plane) mask.
+pub struct GraphicsObject {
+    /// The slot this object occupies in the graphics set (as used by levels).
+    pub index : usize,
+    pub header : ObjectHeader,
+    /// Empty for objects with no image data.
+    pub frames : Vec<(planar_bmp::PlanarBMP, planar_bmp::PlanarBMP)>,
+}
+
+/// A fully decoded graphics set. Empty slots are omitted.
+pub struct GraphicsSet {
+    pub terrain : Vec<TerrainPiece>,
+    pub objects : Vec<GraphicsObject>,
+    pub palettes : Palettes,
+    /// The palette used for the decoded images.
+    pub palette : planar_bmp::PaletteRGB,
+}
+
+/// The @len bytes of @data at @offset, which hold a piece's @what, or an InvalidData error if
+/// they run past the end of it.
+fn piece_data<'a>(data : &'a [u8], offset : usize, len : usize, what : &str) -> std::io::Result<&'a [u8]> {
+    data.get(offset..offset + len).ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData,
+        format!("its {} ({} bytes at offset {}) run past the end of the data, which is {} bytes", what, len, offset, data.len())))
+}
+
+/// Decode a terrain piece into its graphics and (1-plane) mask images.
+fn decode_terrain(header : &TerrainHeader, terrain_data : &[u8], pal : &planar_bmp::PaletteRGB) -> std::io::Result<(planar_bmp::PlanarBMP, planar_bmp::PlanarBMP)> {
+    let width = header.width as usize;
+    let height = header.height as usize;
+    let plane_size = width * height / 8;
+    let gfx_offset = header.gfx_offset as usize;
+    let mask_offset = header.mask_offset as usize;
+    let image = planar_bmp::PlanarBMP::from_contiguous_data(piece_data(terrain_data, gfx_offset, plane_size * 4, "graphics")?, width, height, 4, pal);
+    let mask = planar_bmp::PlanarBMP::from_contiguous_data(piece_data(terrain_data, mask_offset, plane_size, "mask")?, width, height, 1, pal);
+    Ok((image, mask))
+}
+
+/// Decode a single frame of an object into its graphics and (1-plane) mask images.
+fn decode_object_frame(header : &ObjectHeader, object_data : &[u8], frame : usize, pal : &planar_bmp::PaletteRGB) -> std::io::Result<(planar_bmp::PlanarBMP, planar_bmp::PlanarBMP)> {
+    let width = header.width as usize;
+    let height = header.height as usize;
+    let plane_len = width * height / 8;
+    let frame_offset = header.animation_offset as usize + header.animation_frame_data_size as usize * frame;
+    let mask_offset = frame_offset + header.mask_offset as usize;
+    let image = planar_bmp::PlanarBMP::from_contiguous_data(piece_data(object_data, frame_offset, plane_len * 4, "graphics")?, width, height, 4, pal);
+    let mask = planar_bmp::PlanarBMP::from_contiguous_data(piece_data(object_data, mask_offset, plane_len, "mask")?, width, height, 1, pal);
+    Ok((image, mask))
+}
+
+/// Compare an image against its mask, returning the number of pixels which are drawn but masked
+/// out, and the number which are masked in but not drawn (i.e., colour 0).
+fn count_mask_mismatches(image : &planar_bmp::PlanarBMP, mask : &planar_bmp::PlanarBMP) -> (usize, usize) {
+    let mut drawn_but_masked = 0;
+    let mut masked_but_blank = 0;
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let drawn = image.get_packed_pixel(x, y) != 0;
+            let solid = mask.get_packed_pixel(x, y) != 0;
+            if drawn && !solid {
+                drawn_but_masked += 1;
+            } else if solid && !drawn {
+                masked_but_blank += 1;
+            }
         }
     }
+    (drawn_but_masked, masked_but_blank)
 }
 
-/// Extract a graphics set
-pub fn extract_graphics_set(script : &mut dyn std::io::Write, header_file : &mut dyn std::io::Read, data_file : &mut dyn std::io::Read, options : &ExtractOptions) {
+/// Read and decode a graphics set from its header (groundXo.dat) and data (vgagrX.dat) files.
+///
+/// ```no_run
+/// let mut header = std::fs::File::open("ground0o.dat")?;
+/// let mut data = std::fs::File::open("vgagr0.dat")?;
+/// let set = modlem::graphics_set::read_graphics_set(&mut header, &mut data, false)?;
+/// println!("{} terrain pieces, {} objects", set.terrain.len(), set.objects.len());
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn read_graphics_set(header_file : &mut dyn std::io::Read, data_file : &mut dyn std::io::Read, ega_mode : bool) -> std::io::Result<GraphicsSet> {
+    let terrain_data = with_context(DatSection::from_file(data_file).and_then(|mut section| section.decompress()), "reading the terrain graphics")?;
+    let object_data = with_context(DatSection::from_file(data_file).and_then(|mut section| section.decompress()), "reading the object graphics")?;
 
-    let mut terrain_section = DatSection::from_file(data_file).unwrap();
-    let terrain_data = terrain_section.decompress();
-    let mut object_section = DatSection::from_file(data_file).unwrap();
-    let object_data = object_section.decompress();
+    // Read the headers from memory, so any errors say where they were.
+    let mut header_data = Vec::new();
+    header_file.read_to_end(&mut header_data)?;
+    let mut header_reader = ByteReader::new(&header_data);
 
     let mut obj_headers = Vec::<ObjectHeader>::new();
-    for i in 0..16 {
-        obj_headers.push(ObjectHeader::read(header_file).unwrap());
-        println!("Object {}: {:?}", i, obj_headers[i]);
+    for i in 0..NUM_OBJECT_SLOTS {
+        obj_headers.push(with_context(ObjectHeader::read_from(&mut header_reader), format_args!("reading object {}", i))?);
     }
 
     let mut terrain_headers = Vec::<TerrainHeader>::new();
-    for i in 0..64 {
-        terrain_headers.push(TerrainHeader::read(header_file).unwrap());
-        println!("Terrain {}: {:?}", i, terrain_headers[i]);
+    for i in 0..NUM_TERRAIN_SLOTS {
+        terrain_headers.push(with_context(TerrainHeader::read_from(&mut header_reader), format_args!("reading terrain {}", i))?);
     }
 
-    let all_pals = Palettes::read(header_file).unwrap();
-    let mut pal = planar_bmp::PaletteRGB::new(16);
-    if options.ega_mode {
-        pal.set_ega_data(0, 8, all_pals.ega_standard.as_slice());
-        pal.set_ega_data(8, 8, all_pals.ega_custom.as_slice());
+    let palettes = with_context(Palettes::read(&mut header_reader), "reading the palettes")?;
+    let mut palette = planar_bmp::PaletteRGB::new(16);
+    if ega_mode {
+        palette.set_ega_data(0, 8, palettes.ega_standard.as_slice());
+        palette.set_ega_data(8, 8, palettes.ega_custom.as_slice());
     } else {
-        pal.set_vga_data(0, 8, all_pals.vga_standard.as_slice());
-        pal.set_vga_data(8, 8, all_pals.vga_custom.as_slice());
-    }
-
-    for (i, terrain_header) in terrain_headers.iter().enumerate() {
-        if terrain_header.width == 0 { break; }
-        let outfile_name = options.terrain_filename_pattern.replace("#", &i.to_string());
-        let plane_size = terrain_header.width as usize * terrain_header.height as usize / 8;
-        let image_size = plane_size * 4;
-        let terrain_image = planar_bmp::PlanarBMP::from_contiguous_data(&terrain_data[terrain_header.gfx_offset as usize..(terrain_header.gfx_offset as usize + image_size)], terrain_header.width as usize, terrain_header.height as usize, 4, &pal);
-        let mask_image_1bpp = planar_bmp::PlanarBMP::from_contiguous_data(&terrain_data[terrain_header.mask_offset as usize..(terrain_header.mask_offset as usize + plane_size)], terrain_header.width as usize, terrain_header.height as usize, 1, &pal);
-        if options.terrain_mask_filename_pattern.is_none() {
-            // Combine the mask and image into one
-            let mut output_image = planar_bmp::PlanarBMP::new(terrain_header.width as usize * 2, terrain_header.height as usize, 4, &pal);
-            let mask_image_4bpp = planar_bmp::PlanarBMP::from_swizzle(&mask_image_1bpp, vec![0, 0, 0, 0]);
-            output_image.blit(&terrain_image, 0, 0);
-            output_image.blit(&mask_image_4bpp, terrain_header.width as usize, 0);
-            let out_path = Path::new(outfile_name.as_str());
-            let mut output_file = File::create(out_path).unwrap();
-            output_image.save_as_file(&mut output_file);
-            writeln!(script, "Terrain \"{}\"", outfile_name).unwrap();
+        palette.set_vga_data(0, 8, palettes.vga_standard.as_slice());
+        palette.set_vga_data(8, 8, palettes.vga_custom.as_slice());
+    }
+
+    let mut terrain = Vec::<TerrainPiece>::new();
+    for (index, header) in terrain_headers.into_iter().enumerate() {
+        if header.width == 0 { continue; }
+        let (image, mask) = with_context(decode_terrain(&header, &terrain_data, &palette), format_args!("terrain piece {}", index))?;
+        terrain.push(TerrainPiece { index, header, image, mask });
+    }
+
+    let mut objects = Vec::<GraphicsObject>::new();
+    for (index, header) in obj_headers.into_iter().enumerate() {
+        if header.width == 0 { continue; }
+        let mut frames = Vec::new();
+        if header.has_image_data() {
+            for frame in 0..header.frame_end as usize {
+                frames.push(with_context(decode_object_frame(&header, &object_data, frame, &palette), format_args!("object {} frame {}", index, frame))?);
+            }
+        }
+        objects.push(GraphicsObject { index, header, frames });
+    }
+
+    Ok(GraphicsSet { terrain, objects, palettes, palette })
+}
+
+/// Read and decode a graphics set, like read_graphics_set(), listing its pieces to @log (when
+/// debugging).
+pub fn decode_graphics_set(header_file : &mut dyn std::io::Read, data_file : &mut dyn std::io::Read, ega_mode : bool, log : &Log) -> std::io::Result<GraphicsSet> {
+    let set = read_graphics_set(header_file, data_file, ega_mode)?;
+    for object in &set.objects {
+        log_debug!(log, "Object {}: {:?}", object.index, object.header);
+    }
+    for piece in &set.terrain {
+        log_debug!(log, "Terrain {}: {:?}", piece.index, piece.header);
+    }
+    Ok(set)
+}
+
+/// Add an image to @outputs, in the format selected by the options.
+fn save_image(image : &planar_bmp::PlanarBMP, path : &Path, options : &ExtractOptions, outputs : &PendingOutputs) {
+    let mut output_file = outputs.create(path);
+    if options.png {
+        image.save_as_png(&mut output_file);
+    } else {
+        image.save_as_file(&mut output_file);
+    }
+}
+
+/// Write a decoded graphics set out: the images are added to @outputs, and the script
+/// describing them is written to @script. With check_masks, it's an error (of kind InvalidData)
+/// if any of the masks don't match their graphics.
+pub fn write_graphics_set(set : &GraphicsSet, script : &mut dyn std::io::Write, options : &ExtractOptions, outputs : &PendingOutputs) -> std::io::Result<()> {
+    let pal = &set.palette;
+    let mut mask_warnings = Vec::<String>::new();
+
+    for piece in &set.terrain {
+        if options.objects_only { break; }
+        let i = piece.index;
+        let out_path = options.output_path(&options.terrain_filename_pattern, i);
+        let (drawn_but_masked, masked_but_blank) = count_mask_mismatches(&piece.image, &piece.mask);
+        if drawn_but_masked != 0 || masked_but_blank != 0 {
+            mask_warnings.push(format!("terrain {}: {} pixels drawn but masked out, {} pixels masked in but not drawn", i, drawn_but_masked, masked_but_blank));
         }
-        else {
-            let maskfile_name = options.terrain_mask_filename_pattern.unwrap().replace("#", &i.to_string());
-            let out_path = Path::new(outfile_name.as_str());
-            let mut output_file = File::create(out_path).unwrap();
-            let mask_path = Path::new(maskfile_name.as_str());
-            let mut mask_file = File::create(mask_path).unwrap();
-            terrain_image.save_as_file(&mut output_file);
-            mask_image_1bpp.save_as_file(&mut mask_file);
-            writeln!(script, "Terrain \"{}\" Mask \"{}\"", outfile_name, maskfile_name).unwrap();
+        if let Some(terrain_mask_filename_pattern) = &options.terrain_mask_filename_pattern {
+            let mask_path = options.output_path(terrain_mask_filename_pattern, i);
+            log_detail!(options.log, "Terrain {} -> {} and {}", i, out_path.display(), mask_path.display());
+            save_image(&piece.image, &out_path, options, outputs);
+            save_image(&piece.mask, &mask_path, options, outputs);
+            writeln!(script, "Terrain {} {} Mask {}", i, parser::quote(&out_path.to_string_lossy()), parser::quote(&mask_path.to_string_lossy())).unwrap();
+        } else {
+            // Combine the mask and image into one
+            let mut output_image = planar_bmp::PlanarBMP::new(piece.image.width * 2, piece.image.height, 4, pal).unwrap();
+            let mask_image_4bpp = planar_bmp::PlanarBMP::from_swizzle(&piece.mask, vec![0, 0, 0, 0]);
+            output_image.blit(&piece.image, 0, 0);
+            output_image.blit(&mask_image_4bpp, piece.image.width, 0);
+            log_detail!(options.log, "Terrain {} -> {}", i, out_path.display());
+            save_image(&output_image, &out_path, options, outputs);
+            writeln!(script, "Terrain {} {}", i, parser::quote(&out_path.to_string_lossy())).unwrap();
         }
     }
 
-    for (i, obj_header) in obj_headers.iter().enumerate() {
-        if obj_header.width == 0 { break; }
-        let filmstrip_width = obj_header.width as usize * if options.object_mask_filename_pattern.is_none() { 2 } else { 1 };
-        let mut filmstrip_image = planar_bmp::PlanarBMP::new(filmstrip_width, obj_header.height as usize * obj_header.frame_end as usize, 4, &pal);
-        let outfile_name = options.object_filename_pattern.replace("#", &i.to_string());
-        let out_path = Path::new(outfile_name.as_str());
+    for object in &set.objects {
+        if options.terrain_only { break; }
+        let i = object.index;
+        let obj_header = &object.header;
+        if object.frames.is_empty() {
+            // There's nothing to extract, but keep the header so the object can be recreated.
+            log_warning!(options.log, "Warning: object {} has no image data, writing its header only", i);
+            writeln!(script, "Object {} = {}", i, obj_header).unwrap();
+            continue;
+        }
+        let width = obj_header.width as usize;
+        let height = obj_header.height as usize;
+        let filmstrip_width = width * if options.object_mask_filename_pattern.is_none() { 2 } else { 1 };
+        let mut filmstrip_image = planar_bmp::PlanarBMP::new(filmstrip_width, height * object.frames.len(), 4, pal).unwrap();
+        let out_path = options.output_path(&options.object_filename_pattern, i);
 
-        let mask_fname = if options.object_mask_filename_pattern.is_some() { Some(options.object_mask_filename_pattern.unwrap().replace("#", &i.to_string())) } else { None };
-        let mut mask_bmp = if mask_fname.is_some() {
-            writeln!(script, "Object \"{}\" Mask \"{}\" = {}", outfile_name, mask_fname.as_ref().unwrap(), obj_headers[i]).unwrap();
-            Some(planar_bmp::PlanarBMP::new(filmstrip_width, obj_header.height as usize * obj_header.frame_end as usize, 1, &pal))
+        let mask_path = options.object_mask_filename_pattern.as_ref().map(|pattern| options.output_path(pattern, i));
+        let mut mask_bmp = if let Some(mask_path) = &mask_path {
+            writeln!(script, "Object {} {} Mask {} = {}", i, parser::quote(&out_path.to_string_lossy()), parser::quote(&mask_path.to_string_lossy()), obj_header).unwrap();
+            Some(planar_bmp::PlanarBMP::new(filmstrip_width, height * object.frames.len(), 1, pal).unwrap())
         } else {
-            writeln!(script, "Object \"{}\" = {}", outfile_name, obj_headers[i]).unwrap();
+            writeln!(script, "Object {} {} = {}", i, parser::quote(&out_path.to_string_lossy()), obj_header).unwrap();
             None
         };
 
-        for frame in 0..obj_header.frame_end {
-            let frame_size = obj_header.animation_frame_data_size as usize;
-            let plane_len = obj_header.width as usize * obj_header.height as usize / 8;
-            //assert_eq!(planar_size, mask_len * 4);
-            let frame_offset = obj_header.animation_offset as usize + frame_size * frame as usize;
-            let mask_offset = frame_offset + obj_header.mask_offset as usize;
-            let object_image = planar_bmp::PlanarBMP::from_contiguous_data(&object_data[frame_offset..(frame_offset + plane_len * 4)], obj_header.width as usize, obj_header.height as usize, 4, &pal);
-            let object_mask_1bpp = planar_bmp::PlanarBMP::from_contiguous_data(&object_data[mask_offset..(mask_offset + plane_len)], obj_header.width as usize, obj_header.height as usize, 1, &pal);
-            filmstrip_image.blit(&object_image, 0, frame as usize * obj_header.height as usize);
+        let mut drawn_but_masked = 0;
+        let mut masked_but_blank = 0;
+        for (frame, (object_image, object_mask_1bpp)) in object.frames.iter().enumerate() {
+            let (frame_drawn_but_masked, frame_masked_but_blank) = count_mask_mismatches(object_image, object_mask_1bpp);
+            drawn_but_masked += frame_drawn_but_masked;
+            masked_but_blank += frame_masked_but_blank;
+            filmstrip_image.blit(object_image, 0, frame * height);
             if let Some(ref mut mask_bmp) = mask_bmp {
                 // Write the mask to a separate file.
-                mask_bmp.blit(&object_mask_1bpp, 0, frame as usize * obj_header.height as usize);
+                mask_bmp.blit(object_mask_1bpp, 0, frame * height);
             } else {
                 // Put it in the filmstrip image.
-                let object_mask_4bpp = planar_bmp::PlanarBMP::from_swizzle(&object_mask_1bpp, vec![0, 0, 0, 0]);
-                filmstrip_image.blit(&object_mask_4bpp, obj_header.width as usize, frame as usize * obj_header.height as usize);
+                let object_mask_4bpp = planar_bmp::PlanarBMP::from_swizzle(object_mask_1bpp, vec![0, 0, 0, 0]);
+                filmstrip_image.blit(&object_mask_4bpp, width, frame * height);
             }
         }
-        let mut output_file = File::create(out_path).unwrap();
-        filmstrip_image.save_as_file(&mut output_file);
-        if let Some(mask_bmp) = mask_bmp {
-            let mask_path = Path::new(mask_fname.as_ref().unwrap().as_str());
-            let mut mask_file = File::create(mask_path).unwrap();
-            mask_bmp.save_as_file(&mut mask_file);
+        if drawn_but_masked != 0 || masked_but_blank != 0 {
+            mask_warnings.push(format!("object {}: {} pixels drawn but masked out, {} pixels masked in but not drawn", i, drawn_but_masked, masked_but_blank));
+        }
+        log_detail!(options.log, "Object {} ({} frame{}) -> {}", i, object.frames.len(), if object.frames.len() == 1 { "" } else { "s" }, out_path.display());
+        save_image(&filmstrip_image, &out_path, options, outputs);
+        if let (Some(mask_bmp), Some(mask_path)) = (mask_bmp, mask_path) {
+            save_image(&mask_bmp, &mask_path, options, outputs);
         }
     }
 
-    writeln!(script, "Palettes = {}", &all_pals).unwrap();
+    writeln!(script, "Palettes = {}", &set.palettes).unwrap();
+
+    for warning in &mask_warnings {
+        log_warning!(options.log, "Warning: mask mismatch in {}", warning);
+    }
+    if options.check_masks && !mask_warnings.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+                                       format!("{} pieces have masks which don't match their graphics", mask_warnings.len())));
+    }
+    Ok(())
 }
 
-pub fn create_graphics_set(lexer : &mut parser::Lexer) {
-    lexer.expect_ident("HeaderFile");
-    let header_filename = lexer.get_string_literal();
+/// Write a graphics set as a single atlas image (plus a parallel mask image), with a JSON
+/// file describing where each terrain piece and object frame lives in the atlas.
+/// Files are named [prefix].bmp and [prefix]_mask.bmp (or .png, with @png) and [prefix].json, and
+/// added to @outputs.
+pub fn write_graphics_set_atlas(set : &GraphicsSet, prefix : &str, png : bool, outputs : &PendingOutputs) {
+    let mut atlas = atlas::Atlas::new();
 
-    lexer.expect_ident("DataFile");
-    let data_filename = lexer.get_string_literal();
+    for piece in &set.terrain {
+        atlas.add(&format!("terrain{}", piece.index), piece.image.clone(), piece.mask.clone(), schemas::terrain_atlas_fields(piece));
+    }
 
-    let mut object_headers = Vec::<ObjectHeader>::new();
-    let mut terrain_headers = Vec::<TerrainHeader>::new();
+    for object in &set.objects {
+        for (frame, (object_image, object_mask)) in object.frames.iter().enumerate() {
+            atlas.add(&format!("obj{}_frame{}", object.index, frame), object_image.clone(), object_mask.clone(), schemas::object_atlas_fields(object, frame));
+        }
+    }
+
+    atlas.pack();
+    let (atlas_image, atlas_mask) = atlas.render(4, &set.palette);
+
+    let format = if png { planar_bmp::ImageFormat::Png } else { planar_bmp::ImageFormat::Bmp };
+    let image_filename = format!("{}.{}", prefix, format.extension());
+    let mask_filename = format!("{}_mask.{}", prefix, format.extension());
+    let json_filename = format!("{}.json", prefix);
+
+    atlas_image.save_as_format(format, &mut outputs.create(&image_filename));
+    atlas_mask.save_as_format(format, &mut outputs.create(&mask_filename));
+    let mut json_file = outputs.create(&json_filename);
+    schemas::atlas(&atlas, &image_filename, &mask_filename).write(&mut json_file).unwrap();
+}
+
+/// Parse the optional slot index which may follow a Terrain or Object keyword.
+fn parse_slot_index(lexer : &mut parser::Lexer) -> Result<Option<usize>, parser::ParseError> {
+    if let Some(parser::Token::NumericLiteral(_)) = lexer.peek_token()? {
+        let index = lexer.get_int_literal()?;
+        if index < 0 {
+            return Err(lexer.error("a slot index", Some(&parser::Token::NumericLiteral(index))));
+        }
+        Ok(Some(index as usize))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Pick the slot for a new entry: either the explicitly requested one, or the next free slot
+/// after the previously assigned one. It's an error (at the entry's keyword or index) if the
+/// slot is already in use, or none are free.
+fn allocate_slot<T>(lexer : &parser::Lexer, slots : &[Option<T>], explicit_index : Option<usize>, next_slot : &mut usize, kind : &str) -> Result<usize, parser::ParseError> {
+    let index = match explicit_index {
+        Some(index) => {
+            if index >= slots.len() {
+                return Err(lexer.invalid(format!("{} index {} is out of range (must be less than {})", kind, index, slots.len())));
+            }
+            if slots[index].is_some() {
+                return Err(lexer.invalid(format!("Duplicate {} index {}", kind, index)));
+            }
+            index
+        }
+        None => {
+            match (*next_slot..slots.len()).find(|&i| slots[i].is_none()) {
+                Some(index) => index,
+                None => return Err(lexer.invalid(format!("Too many {} entries (at most {} are allowed)", kind, slots.len()))),
+            }
+        }
+    };
+    *next_slot = index + 1;
+    Ok(index)
+}
+
+/// Read the image named by the string literal just lexed, @fname. If it can't be read, the
+/// error points at the name.
+fn load_script_image(lexer : &parser::Lexer, fname : &str) -> Result<planar_bmp::PlanarBMP, parser::ParseError> {
+    open_buffered(parser::file_path(fname))
+        .and_then(|mut file| planar_bmp::load_image(&mut file))
+        .map_err(|err| lexer.invalid(format!("Couldn't read {}: {}", fname, err)))
+}
+
+/// Create a graphics set from a script. If @backup is set, any existing output files are renamed
+/// to [file].bak first. Stops at the first error in the script (including an image it names
+/// which can't be read), before writing anything. Errors in the script are ParseErrors (see
+/// ParseError::from_io_error()).
+pub fn create_graphics_set(lexer : &mut parser::Lexer, backup : bool, log : &Log) -> std::io::Result<()> {
+    let mut seen_files = parser::SeenProperties::default();
+    lexer.expect_ident("HeaderFile")?;
+    seen_files.check(lexer, "HeaderFile")?;
+    let mut header_filename = lexer.get_string_literal()?;
+
+    lexer.expect_ident("DataFile")?;
+    seen_files.check(lexer, "DataFile")?;
+    let mut data_filename = lexer.get_string_literal()?;
+
+    let mut object_headers : Vec<Option<ObjectHeader>> = (0..NUM_OBJECT_SLOTS).map(|_| None).collect();
+    let mut next_object_slot = 0;
+    let mut terrain_headers : Vec<Option<TerrainHeader>> = (0..NUM_TERRAIN_SLOTS).map(|_| None).collect();
+    let mut next_terrain_slot = 0;
 
     let mut terrain_data = Vec::<u8>::new();
     let mut object_data = Vec::<u8>::new();
@@ -599,19 +886,26 @@ pub fn create_graphics_set(lexer : &mut parser::Lexer) {
     let mut pal = Palettes::default();
 
     loop {
-        let entry_type = lexer.next_token();
-        match entry_type {
+        let entry_type = lexer.next_token()?;
+        match entry_type.as_ref().map(parser::Token::borrowed) {
             None => { break; }
             Some(parser::Token::Ident("Terrain")) => {
-                let terrain_fname = lexer.get_string_literal();
-                let mut terrain_file = std::fs::File::open(&terrain_fname).unwrap();
-                let mask_fname = if lexer.is_next_ident("Mask") {
-                    lexer.next_token(); // Discard the keyword.
-                    Some(lexer.get_string_literal())
+                let explicit_index = parse_slot_index(lexer)?;
+                let slot = allocate_slot(lexer, &terrain_headers, explicit_index, &mut next_terrain_slot, "terrain")?;
+                let terrain_fname = lexer.get_string_literal()?;
+                let terrain_bmp = load_script_image(lexer, &terrain_fname)?;
+                let mask_bmp = if lexer.is_next_ident("Mask") {
+                    lexer.next_token()?; // Discard the keyword.
+                    let mask_fname = lexer.get_string_literal()?;
+                    let mask_bmp = load_script_image(lexer, &mask_fname)?;
+                    if (mask_bmp.width, mask_bmp.height) != (terrain_bmp.width, terrain_bmp.height) {
+                        return Err(lexer.invalid(format!("{} is {}x{}, but {} is {}x{} (they should be the same size)",
+                                                         mask_fname, mask_bmp.width, mask_bmp.height, terrain_fname, terrain_bmp.width, terrain_bmp.height)).into());
+                    }
+                    Some(mask_bmp)
                 } else { None };
-                let terrain_bmp = planar_bmp::PlanarBMP::from_file(&mut terrain_file).unwrap();
                 let terrain_offset = terrain_data.len();
-                let terrain_width = if mask_fname.is_some() { terrain_bmp.width } else { terrain_bmp.width / 2 }; // Make room for the mask.
+                let terrain_width = if mask_bmp.is_some() { terrain_bmp.width } else { terrain_bmp.width / 2 }; // Make room for the mask.
 
                 terrain_data.append(&mut terrain_bmp.get_plane_data(0, 0, 0, terrain_width, terrain_bmp.height));
                 terrain_data.append(&mut terrain_bmp.get_plane_data(1, 0, 0, terrain_width, terrain_bmp.height));
@@ -619,12 +913,8 @@ pub fn create_graphics_set(lexer : &mut parser::Lexer) {
                 terrain_data.append(&mut terrain_bmp.get_plane_data(3, 0, 0, terrain_width, terrain_bmp.height));
 
                 let mask_offset = terrain_data.len();
-                if let Some(mask_fname) = mask_fname {
+                if let Some(mask_bmp) = mask_bmp {
                     // Mask is in a separate bitmap
-                    let mut mask_file = std::fs::File::open(mask_fname).unwrap();
-                    let mask_bmp = planar_bmp::PlanarBMP::from_file(&mut mask_file).unwrap();
-                    assert_eq!(terrain_width, mask_bmp.width);
-                    assert_eq!(terrain_bmp.height, mask_bmp.height);
                     terrain_data.append(&mut mask_bmp.get_plane_data(0, 0, 0, mask_bmp.width, mask_bmp.height));
                 } else {
                     // Extract the mask from the same bitmap.
@@ -632,8 +922,8 @@ pub fn create_graphics_set(lexer : &mut parser::Lexer) {
                 }
 
 
-                terrain_headers.push(TerrainHeader {
-                    width: terrain_bmp.width as u8,
+                terrain_headers[slot] = Some(TerrainHeader {
+                    width: terrain_width as u8,
                     height: terrain_bmp.height as u8,
                     gfx_offset: terrain_offset as u16,
                     mask_offset: mask_offset as u16,
@@ -642,34 +932,53 @@ pub fn create_graphics_set(lexer : &mut parser::Lexer) {
 
             }
             Some(parser::Token::Ident("Object")) => {
-                let object_fname = lexer.get_string_literal();
-                let mask_fname = if lexer.is_next_ident("Mask") {
-                    lexer.next_token(); // Discard the keyword.
-                    Some(lexer.get_string_literal())
-                } else { None };
-                let mut object_file = std::fs::File::open(object_fname).unwrap();
-                let object_bmp = planar_bmp::PlanarBMP::from_file(&mut object_file).unwrap();
-                let object_width = if mask_fname.is_none() { object_bmp.width / 2 } else { object_bmp.width };
+                let explicit_index = parse_slot_index(lexer)?;
+                let slot = allocate_slot(lexer, &object_headers, explicit_index, &mut next_object_slot, "object")?;
+                if lexer.is_next_ident("Empty") {
+                    // A deliberately empty slot: write a default header.
+                    lexer.next_token()?; // Discard the keyword.
+                    object_headers[slot] = Some(ObjectHeader::default());
+                    continue;
+                }
+                if lexer.peek_token()? == Some(parser::Token::Symbol('=')) {
+                    // An object with a header, but no image data.
+                    lexer.expect_symbol('=')?;
+                    let mut object_header = ObjectHeader::parse(lexer)?;
+                    object_header.animation_offset = object_data.len() as u16;
+                    object_header.preview_frame_offset = object_header.animation_offset;
+                    object_headers[slot] = Some(object_header);
+                    continue;
+                }
+                let object_fname = lexer.get_string_literal()?;
+                let object_bmp = load_script_image(lexer, &object_fname)?;
 
                 // Open a separate mask .bmp if one exists
-                let mask_bmp = if let Some(mask_fname) = mask_fname {
-                    let mut mask_file = std::fs::File::open(mask_fname).unwrap();
-                    let mask_bmp = planar_bmp::PlanarBMP::from_file(&mut mask_file).unwrap();
-                    assert_eq!(mask_bmp.width, object_bmp.width);
-                    assert_eq!(mask_bmp.height, object_bmp.height);
+                let mask_bmp = if lexer.is_next_ident("Mask") {
+                    lexer.next_token()?; // Discard the keyword.
+                    let mask_fname = lexer.get_string_literal()?;
+                    let mask_bmp = load_script_image(lexer, &mask_fname)?;
+                    if (mask_bmp.width, mask_bmp.height) != (object_bmp.width, object_bmp.height) {
+                        return Err(lexer.invalid(format!("{} is {}x{}, but {} is {}x{} (they should be the same size)",
+                                                         mask_fname, mask_bmp.width, mask_bmp.height, object_fname, object_bmp.width, object_bmp.height)).into());
+                    }
                     Some(mask_bmp)
                 } else { None };
+                let object_width = if mask_bmp.is_none() { object_bmp.width / 2 } else { object_bmp.width };
 
                 // Get the info.
-                lexer.expect_symbol('=');
-                let mut object_header = ObjectHeader::parse(lexer);
+                lexer.expect_symbol('=')?;
+                let mut object_header = ObjectHeader::parse(lexer)?;
+                if object_header.frame_end == 0 {
+                    return Err(lexer.invalid(format!("{} needs at least one frame", object_fname)).into());
+                }
 
                 let frame_height = object_bmp.height / object_header.frame_end as usize;
                 object_header.animation_offset = object_data.len() as u16;
                 object_header.width = object_width as u8;
                 object_header.height = frame_height as u8;
                 // 5 planes (4 graphics, 1 mask) per frame.
-                object_header.animation_frame_data_size = ((object_bmp.width * frame_height / 8) * 5) as u16;
+                let plane_len = object_width * frame_height / 8;
+                object_header.animation_frame_data_size = (plane_len * 5) as u16;
                 object_header.preview_frame_offset = object_header.animation_offset + (object_header.animation_frame_data_size * object_header.preview_frame_number as u16);
 
                 for frame in 0..object_header.frame_end as usize {
@@ -686,20 +995,27 @@ pub fn create_graphics_set(lexer : &mut parser::Lexer) {
                     }
                 }
 
-                // The mask offset is always 0. This is because:
-                // - the offset is relative to the _end_ of the frame's graphics data
-                // - we always store the mask immediately after the graphics data, for each frame.
-                // If, for example, we stored all of the masks at the end, this would be different.
-                object_header.mask_offset = 0_u16;
+                // The mask offset is relative to the start of each frame's data, and we always store
+                // the mask immediately after the 4 planes of graphics data.
+                object_header.mask_offset = (plane_len * 4) as u16;
 
-                object_headers.push(object_header);
+                object_headers[slot] = Some(object_header);
             }
             Some(parser::Token::Ident("Palettes")) => {
-                lexer.expect_symbol('=');
-                pal = Palettes::parse(lexer);
+                lexer.expect_symbol('=')?;
+                pal = Palettes::parse(lexer)?;
+            }
+            Some(parser::Token::Ident(name @ ("HeaderFile" | "DataFile"))) => {
+                seen_files.check(lexer, name)?;
+                let filename = lexer.get_string_literal()?;
+                if name == "HeaderFile" {
+                    header_filename = filename;
+                } else {
+                    data_filename = filename;
+                }
             }
             _ => {
-                panic!("Unknown token {:?}", entry_type);
+                return Err(lexer.error("Terrain, Object or Palettes", entry_type.as_ref()).into());
             }
 
         }
@@ -708,38 +1024,158 @@ pub fn create_graphics_set(lexer : &mut parser::Lexer) {
     // Now we've finished parsing the script, compress the data.
     let terrain_section = DatSection::from_data(&terrain_data[..], terrain_data.len());
     let object_section = DatSection::from_data(&object_data[..], object_data.len());
+    log_detail!(log, "Compressed the terrain from {} bytes to {} bytes", terrain_section.uncompressed_size(), terrain_section.compressed_size());
+    log_detail!(log, "Compressed the objects from {} bytes to {} bytes", object_section.uncompressed_size(), object_section.compressed_size());
 
     // Open the output file
-    let data_path = Path::new(&data_filename);
+    let data_path = parser::file_path(&data_filename);
 
-    let mut data = match File::create(data_path) {
-        Err(err) => panic!("Error opening {}: {}", data_filename, err),
-        Ok(file) => file,
-    };
+    log_info!(log, "Writing {}", data_path.display());
+    let mut data = backup::create_with_backup(&data_path, backup, log)?;
 
-    terrain_section.write(&mut data).unwrap();
-    object_section.write(&mut data).unwrap();
+    terrain_section.write(&mut data)?;
+    object_section.write(&mut data)?;
     // TODO: Palette section
+    data.finish()?;
 
     // Now write out the headers
-    let header_path = Path::new(&header_filename);
-    let mut header = match File::create(header_path) {
-        Err(err) => panic!("Error opening {}: {}", header_filename, err),
-        Ok(file) => file,
-    };
+    let header_path = parser::file_path(&header_filename);
+    log_info!(log, "Writing {}", header_path.display());
+    let mut header = backup::create_with_backup(&header_path, backup, log)?;
 
     let null_object_header = ObjectHeader::default();
-    for i in 0..16 {
-        let object_header = if i < object_headers.len() { &object_headers[i] } else { &null_object_header };
-        object_header.write(&mut header).unwrap();
+    for object_header in &object_headers {
+        object_header.as_ref().unwrap_or(&null_object_header).write_to(&mut header)?;
     }
 
+    // Any slots which weren't filled are written as empty (width 0) headers.
     let null_terrain_header = TerrainHeader::default();
-    for i in 0..64 {
-        let terrain_header = if i < terrain_headers.len() { &terrain_headers[i] } else { &null_terrain_header };
-        terrain_header.write(&mut header).unwrap();
+    for terrain_header in &terrain_headers {
+        terrain_header.as_ref().unwrap_or(&null_terrain_header).write_to(&mut header)?;
     }
 
     // Now the palette
-    pal.write(&mut header);
+    pal.write(&mut header)?;
+    header.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    #[test]
+    fn graphics_set_script_names() {
+        // Everything the scripts are written with has to parse, so the lists can't miss any.
+        let header = ObjectHeader { frame_end : 4, trigger_w : 2, trap_sound : 9, ..Default::default() };
+        let text = header.to_string();
+        for property in ObjectHeader::PROPERTIES {
+            assert!(text.contains(&format!("\t{} = ", property)), "{}", property);
+        }
+        let parsed = ObjectHeader::parse(&mut parser::Lexer::from_str(&text)).unwrap();
+        assert_eq!((parsed.frame_end, parsed.trigger_w, parsed.trap_sound), (4, 2, 9));
+
+        let text = Palettes::default().to_string();
+        for name in Palettes::NAMES {
+            assert!(text.contains(&format!("\t{} = ", name)), "{}", name);
+        }
+        assert!(Palettes::parse(&mut parser::Lexer::from_str(&text)).is_ok());
+
+        let err = ObjectHeader::parse(&mut parser::Lexer::from_str("{ trigge = (0, 0, 1, 1) }")).unwrap_err();
+        assert_eq!(err.to_string(), "1:3: expected an object property but found 'trigge' (did you mean 'trigger'?)");
+    }
+
+    #[test]
+    fn graphics_set_headers() {
+        assert_eq!((ObjectHeader::byte_size(), TerrainHeader::byte_size()), (28, 8));
+        check_round_trips::<ObjectHeader>();
+        check_round_trips::<TerrainHeader>();
+
+        // The preview frame isn't stored, but worked out from its offset.
+        let header = ObjectHeader { frame_end : 4, animation_frame_data_size : 40, animation_offset : 100, preview_frame_offset : 180, ..Default::default() };
+        let mut data = Vec::new();
+        header.write_to(&mut data).unwrap();
+        assert_eq!(ObjectHeader::read_from(&mut data.as_slice()).unwrap().preview_frame_number, 2);
+
+        // A truncated header says which field it ran out in.
+        let err = with_context(ObjectHeader::read_from(&mut &data[..17]), "reading object 12").unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+        assert!(err.to_string().starts_with("reading object 12: field trigger_y: "));
+        let err = ObjectHeader::read_from(&mut ByteReader::new(&data[..17])).unwrap_err();
+        assert_eq!(err.to_string(), "field trigger_y: expected 2 bytes at offset 16, but there were only 1");
+    }
+
+    #[test]
+    fn graphics_set_trailing_commas() {
+        let header = ObjectHeader::parse(&mut parser::Lexer::from_str("{ frames = (0, 8,) size = (16, 8,) trigger = (1, 2, 3, 4,) }")).unwrap();
+        assert_eq!((header.frame_end, header.height, header.trigger_h), (8, 8, 4));
+        let err = ObjectHeader::parse(&mut parser::Lexer::from_str("{ frames = (0, 8,,) }")).unwrap_err();
+        assert_eq!(err.to_string(), "1:18: expected ')' but found ','");
+
+        // Palettes are written without them, but read the same with them added.
+        let pal = Palettes { ega_custom : [0b00_01_10, 1, 2, 3, 4, 5, 6, 0b11_11_11], vga_custom : [7; 24], ..Palettes::default() };
+        let text = pal.to_string();
+        assert!(!text.contains(",)") && !text.contains(",}"));
+        let with_commas = text.replace(")}", "),}").replace(")", ",)");
+        assert_eq!(Palettes::parse(&mut parser::Lexer::from_str(&with_commas)).unwrap().to_string(), text);
+        assert_eq!(Palettes::parse(&mut parser::Lexer::from_str(&text)).unwrap().ega_custom, pal.ega_custom);
+    }
+
+    #[test]
+    fn graphics_set_duplicates() {
+        let text = "{\n\ttrigger_effect = 4\n\tframes = (0, 2)\n\ttrigger_effect = 5\n}";
+        let mut lexer = parser::Lexer::from_str(text);
+        assert_eq!(ObjectHeader::parse(&mut lexer).unwrap().trigger_effect_id, 5);
+        let warnings = lexer.take_warnings();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].to_string(), "4:2: warning: trigger_effect is already set on line 2, and only the last one counts");
+        // The same goes for a lexer reading from a stream, which has owned identifiers.
+        let mut lexer = parser::Lexer::from_reader(Box::new(text.as_bytes()));
+        assert_eq!(ObjectHeader::parse(&mut lexer).unwrap().trigger_effect_id, 5);
+        assert_eq!(lexer.take_warnings(), warnings);
+
+        let mut lexer = parser::Lexer::from_str(text);
+        lexer.set_strict(true);
+        let err = ObjectHeader::parse(&mut lexer).unwrap_err();
+        assert_eq!(err.to_string(), "4:2: expected trigger_effect only once but found 'trigger_effect' (it's already set on line 2)");
+
+        let mut lexer = parser::Lexer::from_str("{ ega_preview = { (0, 0, 0), (0, 0, 0), (0, 0, 0), (0, 0, 0), (0, 0, 0), (0, 0, 0), (0, 0, 0), (0, 0, 0) }\n\
+                                                 ega_preview = { (0, 0, 0), (0, 0, 0), (0, 0, 0), (0, 0, 0), (0, 0, 0), (0, 0, 0), (0, 0, 0), (0, 0, 0) } }");
+        Palettes::parse(&mut lexer).unwrap();
+        assert_eq!(lexer.take_warnings().len(), 1);
+
+        // Nothing is written when the script has an error.
+        let mut lexer = parser::Lexer::from_str("HeaderFile \"ground9o.dat\"\nDataFile \"vgagr9.dat\"\nHeaderFile \"ground8o.dat\"\n");
+        lexer.set_strict(true);
+        let err = create_graphics_set(&mut lexer, false, &Log::default()).unwrap_err();
+        assert_eq!(err.to_string(), "3:1: expected HeaderFile only once but found 'HeaderFile' (it's already set on line 1)");
+    }
+
+    #[test]
+    fn graphics_set_windows_paths() {
+        let dir = std::env::temp_dir().join(format!("modlem_paths_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("tiles")).unwrap();
+        let bmp = planar_bmp::PlanarBMP::new(16, 8, 4, &planar_bmp::PaletteRGB::new(16)).unwrap();
+        bmp.save_as_file(&mut File::create(dir.join("tiles/rock.bmp")).unwrap());
+
+        // Escaped and unescaped backslashes, and forward slashes, all find the same file.
+        let dir_name = dir.to_string_lossy();
+        let script = format!("HeaderFile \"{0}/ground9o.dat\"\nDataFile \"{0}\\\\vgagr9.dat\"\n\
+                              Terrain \"{0}\\\\tiles\\\\rock.bmp\"\nTerrain \"{0}\\tiles\\rock.bmp\"\nTerrain \"{0}/tiles/rock.bmp\"\n", dir_name);
+        let mut lexer = parser::Lexer::from_str(&script);
+        create_graphics_set(&mut lexer, false, &Log::default()).unwrap();
+        let warnings = lexer.take_warnings();
+        assert_eq!(warnings.len(), 2);
+        assert_eq!((warnings[0].line, warnings[0].column), (4, 10 + dir_name.chars().count()));
+
+        let mut header = File::open(dir.join("ground9o.dat")).unwrap();
+        for _ in 0..NUM_OBJECT_SLOTS {
+            ObjectHeader::read_from(&mut header).unwrap();
+        }
+        for _ in 0..3 {
+            assert_eq!(TerrainHeader::read_from(&mut header).unwrap().width, 8);
+        }
+        assert!(dir.join("vgagr9.dat").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
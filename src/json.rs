@@ -0,0 +1,360 @@
+/*
+ * modlem: A graphics importer/exporter for Lemmings
+ * Copyright (C) 2022–2026 David Gow <david@davidgow.net>
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Just enough JSON to print what the informational commands find with --json, and to describe
+//! atlases. What each of them writes is built in schemas.rs.
+//!
+//! The items of the outermost list (or the fields of the outermost object, and the items of any
+//! lists they hold) are written one to a line, and everything inside them on the same line, so
+//! the output is easy to read (and diff) as well as parse.
+
+/// A JSON value. Only integers are needed, so numbers are i64s.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(i64),
+    String(String),
+    Array(Vec<Value>),
+    /// The fields, in the order they're written.
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// An object with @fields, in that order.
+    pub fn object<I : IntoIterator<Item = (&'static str, Value)>>(fields : I) -> Value {
+        Value::Object(fields.into_iter().map(|(name, value)| (name.to_string(), value)).collect())
+    }
+
+    /// Write the value to @writer, followed by a newline.
+    pub fn write(&self, writer : &mut dyn std::io::Write) -> std::io::Result<()> {
+        let mut text = String::new();
+        match self {
+            Value::Array(items) if !items.is_empty() => {
+                text.push_str("[\n");
+                for (i, item) in items.iter().enumerate() {
+                    text.push('\t');
+                    item.write_inline(&mut text);
+                    text.push_str(if i + 1 < items.len() { ",\n" } else { "\n" });
+                }
+                text.push(']');
+            }
+            Value::Object(fields) if !fields.is_empty() => {
+                text.push_str("{\n");
+                for (i, (name, value)) in fields.iter().enumerate() {
+                    text.push('\t');
+                    text.push_str(&quote(name));
+                    text.push_str(": ");
+                    match value {
+                        Value::Array(items) if !items.is_empty() => {
+                            text.push_str("[\n");
+                            for (j, item) in items.iter().enumerate() {
+                                text.push_str("\t\t");
+                                item.write_inline(&mut text);
+                                text.push_str(if j + 1 < items.len() { ",\n" } else { "\n" });
+                            }
+                            text.push_str("\t]");
+                        }
+                        _ => value.write_inline(&mut text),
+                    }
+                    text.push_str(if i + 1 < fields.len() { ",\n" } else { "\n" });
+                }
+                text.push('}');
+            }
+            _ => self.write_inline(&mut text),
+        }
+        writeln!(writer, "{}", text)
+    }
+
+    /// Add the value to @text, all on one line.
+    fn write_inline(&self, text : &mut String) {
+        match self {
+            Value::Null => text.push_str("null"),
+            Value::Bool(value) => text.push_str(if *value { "true" } else { "false" }),
+            Value::Number(value) => text.push_str(&value.to_string()),
+            Value::String(value) => text.push_str(&quote(value)),
+            Value::Array(items) => {
+                text.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        text.push_str(", ");
+                    }
+                    item.write_inline(text);
+                }
+                text.push(']');
+            }
+            Value::Object(fields) if fields.is_empty() => text.push_str("{}"),
+            Value::Object(fields) => {
+                text.push_str("{ ");
+                for (i, (name, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        text.push_str(", ");
+                    }
+                    text.push_str(&quote(name));
+                    text.push_str(": ");
+                    value.write_inline(text);
+                }
+                text.push_str(" }");
+            }
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value : bool) -> Value {
+        Value::Bool(value)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(value : usize) -> Value {
+        Value::Number(value as i64)
+    }
+}
+
+impl From<u8> for Value {
+    fn from(value : u8) -> Value {
+        Value::Number(value as i64)
+    }
+}
+
+impl From<u16> for Value {
+    fn from(value : u16) -> Value {
+        Value::Number(value as i64)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value : &str) -> Value {
+        Value::String(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value : String) -> Value {
+        Value::String(value)
+    }
+}
+
+impl<T : Into<Value>> From<Option<T>> for Value {
+    fn from(value : Option<T>) -> Value {
+        value.map_or(Value::Null, |value| value.into())
+    }
+}
+
+impl<T : Into<Value>> From<Vec<T>> for Value {
+    fn from(items : Vec<T>) -> Value {
+        Value::Array(items.into_iter().map(|item| item.into()).collect())
+    }
+}
+
+/// Quote @text as a JSON string.
+pub fn quote(text : &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Parse @text, which must be a single JSON value (with integers for any numbers), to check
+/// what was written.
+#[cfg(test)]
+pub fn parse(text : &str) -> Result<Value, String> {
+    let mut parser = Parser { chars : text.chars().peekable() };
+    let value = parser.value()?;
+    parser.skip_space();
+    match parser.chars.next() {
+        None => Ok(value),
+        Some(c) => Err(format!("'{}' after the value", c)),
+    }
+}
+
+#[cfg(test)]
+struct Parser<'a> {
+    chars : std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+#[cfg(test)]
+impl Parser<'_> {
+    fn skip_space(&mut self) {
+        while self.chars.next_if(|c| matches!(c, ' ' | '\t' | '\n' | '\r')).is_some() {}
+    }
+
+    fn expect(&mut self, expected : char) -> Result<(), String> {
+        self.skip_space();
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            c => Err(format!("expected '{}', got {:?}", expected, c)),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_space();
+        match self.chars.peek().copied() {
+            Some('n') => self.word("null", Value::Null),
+            Some('t') => self.word("true", Value::Bool(true)),
+            Some('f') => self.word("false", Value::Bool(false)),
+            Some('"') => self.string().map(Value::String),
+            Some('[') => {
+                self.chars.next();
+                let mut items = Vec::new();
+                self.skip_space();
+                if self.chars.next_if_eq(&']').is_some() {
+                    return Ok(Value::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    self.skip_space();
+                    match self.chars.next() {
+                        Some(',') => continue,
+                        Some(']') => return Ok(Value::Array(items)),
+                        c => return Err(format!("expected ',' or ']', got {:?}", c)),
+                    }
+                }
+            }
+            Some('{') => {
+                self.chars.next();
+                let mut fields = Vec::new();
+                self.skip_space();
+                if self.chars.next_if_eq(&'}').is_some() {
+                    return Ok(Value::Object(fields));
+                }
+                loop {
+                    self.skip_space();
+                    let name = self.string()?;
+                    if fields.iter().any(|(field, _)| *field == name) {
+                        return Err(format!("\"{}\" is there twice", name));
+                    }
+                    self.expect(':')?;
+                    fields.push((name, self.value()?));
+                    self.skip_space();
+                    match self.chars.next() {
+                        Some(',') => continue,
+                        Some('}') => return Ok(Value::Object(fields)),
+                        c => return Err(format!("expected ',' or '}}', got {:?}", c)),
+                    }
+                }
+            }
+            Some(c) if c == '-' || c.is_ascii_digit() => {
+                let mut number = String::new();
+                while let Some(c) = self.chars.next_if(|c| *c == '-' || c.is_ascii_digit()) {
+                    number.push(c);
+                }
+                number.parse().map(Value::Number).map_err(|_| format!("bad number {}", number))
+            }
+            c => Err(format!("expected a value, got {:?}", c)),
+        }
+    }
+
+    fn word(&mut self, word : &str, value : Value) -> Result<Value, String> {
+        for expected in word.chars() {
+            if self.chars.next() != Some(expected) {
+                return Err(format!("expected {}", word));
+            }
+        }
+        Ok(value)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        if self.chars.next() != Some('"') {
+            return Err("expected a string".to_string());
+        }
+        let mut text = String::new();
+        loop {
+            match self.chars.next() {
+                None => return Err("unterminated string".to_string()),
+                Some('"') => return Ok(text),
+                Some('\\') => match self.chars.next() {
+                    Some(c @ ('"' | '\\' | '/')) => text.push(c),
+                    Some('n') => text.push('\n'),
+                    Some('t') => text.push('\t'),
+                    Some('r') => text.push('\r'),
+                    Some('b') => text.push('\u{8}'),
+                    Some('f') => text.push('\u{c}'),
+                    Some('u') => {
+                        let hex : String = (0..4).filter_map(|_| self.chars.next()).collect();
+                        let c = u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32);
+                        text.push(c.ok_or_else(|| format!("bad escape \\u{}", hex))?);
+                    }
+                    c => return Err(format!("bad escape {:?}", c)),
+                },
+                Some(c) if (c as u32) < 0x20 => return Err(format!("unescaped control character {:?}", c)),
+                Some(c) => text.push(c),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write @value, as the commands do.
+    fn written(value : &Value) -> String {
+        let mut text = Vec::new();
+        value.write(&mut text).unwrap();
+        String::from_utf8(text).unwrap()
+    }
+
+    #[test]
+    fn json_write() {
+        let value = Value::object([
+            ("name", "Just \"dig\"!\\\n".into()),
+            ("index", 3_usize.into()),
+            ("offset", Value::Number(-5)),
+            ("levels", vec![vec![Value::Null, Value::Bool(true)].into(), Value::object([])].into()),
+            ("none", Value::Array(Vec::new())),
+            ("skills", Value::object([("dig", 10_u16.into()), ("bash", Value::from(None::<u16>))])),
+        ]);
+        let text = written(&value);
+        assert_eq!(text, "{\n\
+                          \t\"name\": \"Just \\\"dig\\\"!\\\\\\u000a\",\n\
+                          \t\"index\": 3,\n\
+                          \t\"offset\": -5,\n\
+                          \t\"levels\": [\n\
+                          \t\t[null, true],\n\
+                          \t\t{}\n\
+                          \t],\n\
+                          \t\"none\": [],\n\
+                          \t\"skills\": { \"dig\": 10, \"bash\": null }\n\
+                          }\n");
+        assert_eq!(parse(&text), Ok(value));
+
+        let list : Value = vec![Value::from("a"), Value::from("é")].into();
+        assert_eq!(written(&list), "[\n\t\"a\",\n\t\"é\"\n]\n");
+        assert_eq!(parse(&written(&list)), Ok(list));
+        assert_eq!(written(&Value::Array(Vec::new())), "[]\n");
+    }
+
+    #[test]
+    fn json_validator() {
+        assert!(parse("{ \"a\": 1, }").is_err());
+        assert!(parse("[1, 2").is_err());
+        assert!(parse("{ \"a\": 1, \"a\": 2 }").is_err());
+        assert!(parse("\"tab\there\"").is_err());
+        assert!(parse("[] []").is_err());
+        assert!(parse("{ a: 1 }").is_err());
+        assert_eq!(parse(" [ ] "), Ok(Value::Array(Vec::new())));
+    }
+}
@@ -0,0 +1,1847 @@
+/*
+ * modlem: A graphics importer/exporter for Lemmings
+ * Copyright (C) 2022–2026 David Gow <david@davidgow.net>
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use binary_io::*;
+use graphics_set;
+use log::Log;
+use parser;
+use planar_bmp::{self, PlanarBMP};
+
+/*
+ * A level, in the 2048-byte format used by the DOS versions (and the .lvl files of
+ * LemEdit and Windows Lemmings). The levelNNN.dat files are ordinary .dat files with
+ * one level per section. Everything is big-endian:
+ *
+ *   0x000  The header: release rate, number of lemmings, number to rescue, time
+ *          limit (in minutes), the 8 skill counts, the screen start position, the
+ *          graphics set, the special graphics set and an unused word.
+ *   0x020  32 objects, 8 bytes each. All zeroes is an empty slot.
+ *   0x120  400 terrain pieces, 4 bytes each. All ones is an empty slot.
+ *   0x760  32 steel areas, 4 bytes each. All zeroes is an empty slot.
+ *   0x7E0  The level name, 32 characters padded with spaces.
+ *
+ * The positions are stored in a few different ways (see the read() functions below),
+ * but every bit is kept, so a level can always be written back unchanged.
+ *
+ * Oh No! More Lemmings and Holiday Lemmings use the same format, but level editors
+ * number their graphics sets after the original game's (see LevelVariant), and
+ * neither game has special graphics, so that word doesn't mean the same thing.
+ * Which game a level is for isn't stored in the level, so it's guessed from the
+ * graphics set where possible, and recorded in the text format.
+ */
+
+/// The size of a level, in bytes.
+pub const LEVEL_SIZE : usize = 2048;
+pub const NUM_LEVEL_OBJECTS : usize = 32;
+pub const NUM_LEVEL_TERRAIN : usize = 400;
+pub const NUM_LEVEL_STEEL : usize = 32;
+pub const LEVEL_NAME_LEN : usize = 32;
+
+/// The size of a level, in pixels.
+pub const LEVEL_WIDTH : usize = 1600;
+pub const LEVEL_HEIGHT : usize = 160;
+/// The largest terrain piece or object frame: their sizes are stored in a byte.
+pub const MAX_PIECE_SIZE : usize = 255;
+
+/// How much smaller the minimap is than the level, horizontally and vertically.
+pub const MINIMAP_SCALE_X : usize = 16;
+pub const MINIMAP_SCALE_Y : usize = 8;
+
+/// Steel areas are stored in units of this many pixels.
+pub const STEEL_GRANULARITY : i32 = 4;
+/// The colour steel is tinted towards by Level::tint_steel(), as 6-bit VGA values.
+const STEEL_TINT : [u8; 3] = [24, 40, 63];
+
+/// Objects' trigger areas are stored in units of this many pixels.
+pub const TRIGGER_GRANULARITY : i32 = 4;
+/// What happens when a lemming reaches an object's trigger area (its trigger_effect_id).
+pub const TRIGGER_EXIT : u8 = 1;
+pub const TRIGGER_TRAP : u8 = 4;
+pub const TRIGGER_WATER : u8 = 5;
+pub const TRIGGER_FIRE : u8 = 6;
+pub const TRIGGER_ONE_WAY_LEFT : u8 = 7;
+pub const TRIGGER_ONE_WAY_RIGHT : u8 = 8;
+/// The first colour used by Level::draw_triggers(), after those used by Level::tint_steel().
+pub const TRIGGER_COLOUR_BASE : usize = 32;
+/// The colours draw_triggers() uses, as 6-bit VGA values: for exits, traps, water, fire,
+/// one-way walls and anything else, in that order.
+const TRIGGER_COLOURS : [[u8; 3]; 6] = [[0, 63, 0], [63, 0, 0], [0, 63, 63], [63, 32, 0], [63, 63, 0], [63, 63, 63]];
+
+/// The ranges (inclusive) of the stats the game accepts.
+pub const RELEASE_RATE_RANGE : (u16, u16) = (1, 99);
+pub const MAX_LEMMINGS : u16 = 80;
+pub const TIME_LIMIT_RANGE : (u16, u16) = (1, 99);
+pub const MAX_SKILL_COUNT : u16 = 99;
+
+/// The names of the skills, in the order they're stored.
+pub const SKILL_NAMES : [&str; 8] = ["climb", "float", "bomb", "block", "build", "bash", "mine", "dig"];
+
+/// Object modifier: only drawn where there's no terrain.
+pub const OBJECT_NO_OVERWRITE : u8 = 0x80;
+/// Object modifier: only drawn on top of terrain.
+pub const OBJECT_ONLY_ON_TERRAIN : u8 = 0x40;
+/// Object display flag: drawn upside-down.
+pub const OBJECT_UPSIDE_DOWN : u8 = 0x80;
+/// The rest of the display byte, which is always this in the original levels.
+pub const OBJECT_DISPLAY_DEFAULT : u8 = 0x0F;
+/// The objects every graphics set has in the same slots: the exit and the entrance (trapdoor).
+pub const OBJECT_EXIT : u16 = 0;
+pub const OBJECT_ENTRANCE : u16 = 1;
+
+/// Terrain flag: only drawn where there's no terrain already.
+pub const TERRAIN_NO_OVERWRITE : u8 = 0x8;
+/// Terrain flag: drawn upside-down.
+pub const TERRAIN_UPSIDE_DOWN : u8 = 0x4;
+/// Terrain flag: removes terrain, rather than adding it.
+pub const TERRAIN_ERASE : u8 = 0x2;
+/// The names of the terrain flags in the text format (except TERRAIN_UPSIDE_DOWN, which is
+/// "flip"). No-overwrite pieces are drawn behind the terrain already there.
+const TERRAIN_FLAG_NAMES : [(u8, &str); 2] = [(TERRAIN_NO_OVERWRITE, "behind"), (TERRAIN_ERASE, "erase")];
+
+/// Which game a level is from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LevelVariant {
+    Original,
+    /// Oh No! More Lemmings.
+    Onml,
+    /// Holiday Lemmings (Christmas Lemmings).
+    Holiday,
+}
+
+impl LevelVariant {
+    pub const ALL : [LevelVariant; 3] = [LevelVariant::Original, LevelVariant::Onml, LevelVariant::Holiday];
+
+    /// The name used in the text format, and on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            LevelVariant::Original => "original",
+            LevelVariant::Onml => "onml",
+            LevelVariant::Holiday => "holiday",
+        }
+    }
+
+    pub fn from_name(name : &str) -> Option<LevelVariant> {
+        LevelVariant::ALL.iter().copied().find(|variant| variant.name() == name)
+    }
+
+    /// The number of this game's first graphics set, in the combined numbering used by
+    /// level editors: ONML's sets follow the original game's, and Holiday Lemmings' follow
+    /// ONML's. The games themselves count their own sets from 0.
+    pub fn first_graphics_set(self) -> u16 {
+        match self {
+            LevelVariant::Original => 0,
+            LevelVariant::Onml => 5,
+            LevelVariant::Holiday => 9,
+        }
+    }
+
+    /// The number of graphics sets (groundNo.dat files) the game has.
+    pub fn num_graphics_sets(self) -> u16 {
+        match self {
+            LevelVariant::Original => 5,
+            LevelVariant::Onml => 4,
+            LevelVariant::Holiday => 2,
+        }
+    }
+
+    /// Whether the game has special graphics (vgaspecN.dat) levels.
+    pub fn has_special_graphics(self) -> bool {
+        self == LevelVariant::Original
+    }
+
+    /// Guess the game from a level's graphics set. Sets in the original game's range could
+    /// be from any of them (as each game counts from 0), so those give None.
+    pub fn detect(graphics_set : u16) -> Option<LevelVariant> {
+        LevelVariant::ALL.iter().copied().skip(1).find(|variant| {
+            (variant.first_graphics_set()..variant.first_graphics_set() + variant.num_graphics_sets()).contains(&graphics_set)
+        })
+    }
+}
+
+/// An object (entrance, exit, trap, decoration, etc.) placed in a level.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LevelObject {
+    /// Position in pixels. The game only places objects on multiples of 8 horizontally.
+    pub x : i32,
+    pub y : i32,
+    /// The object's slot in the graphics set.
+    pub id : u16,
+    pub modifier : u8,
+    pub display : u8,
+}
+
+impl LevelObject {
+    /// Read an object, returning None for an empty slot.
+    pub fn read(reader : &mut dyn std::io::Read) -> std::io::Result<Option<LevelObject>> {
+        let raw : [u8; 8] = read_array(reader)?;
+        if raw == [0; 8] {
+            return Ok(None);
+        }
+        let mut fields = &raw[..];
+        Ok(Some(LevelObject {
+            // Offset by 16, so that objects can hang off the left edge.
+            x : read_be_i16(&mut fields)? as i32 - 16,
+            y : read_be_i16(&mut fields)? as i32,
+            id : read_be16(&mut fields)?,
+            modifier : read_byte(&mut fields)?,
+            display : read_byte(&mut fields)?,
+        }))
+    }
+
+    pub fn write(&self, writer : &mut dyn std::io::Write) -> std::io::Result<()> {
+        write_be_i16((self.x + 16) as i16, writer)?;
+        write_be_i16(self.y as i16, writer)?;
+        write_be16(self.id, writer)?;
+        write_byte(self.modifier, writer)?;
+        write_byte(self.display, writer)
+    }
+
+    pub fn is_upside_down(&self) -> bool {
+        self.display & OBJECT_UPSIDE_DOWN != 0
+    }
+}
+
+/// A terrain piece placed in a level.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TerrainPlacement {
+    /// Position in pixels.
+    pub x : i32,
+    pub y : i32,
+    /// The piece's slot in the graphics set.
+    pub piece : u8,
+    /// TERRAIN_* flags (4 bits).
+    pub flags : u8,
+}
+
+impl TerrainPlacement {
+    /// Read a terrain piece, returning None for an empty slot.
+    pub fn read(reader : &mut dyn std::io::Read) -> std::io::Result<Option<TerrainPlacement>> {
+        let raw = read_be32(reader)?;
+        if raw == 0xFFFF_FFFF {
+            return Ok(None);
+        }
+        // 4 bits of flags, 12 bits of x (offset by 16), a signed 9-bit y (offset by 4),
+        // and 7 bits of piece number.
+        let y = ((raw >> 7) & 0x1FF) as i32;
+        let y = if y >= 0x100 { y - 0x200 } else { y };
+        Ok(Some(TerrainPlacement {
+            x : ((raw >> 16) & 0xFFF) as i32 - 16,
+            y : y - 4,
+            piece : (raw & 0x7F) as u8,
+            flags : (raw >> 28) as u8,
+        }))
+    }
+
+    pub fn write(&self, writer : &mut dyn std::io::Write) -> std::io::Result<()> {
+        let raw = ((self.flags as u32 & 0xF) << 28)
+            | ((((self.x + 16) as u32) & 0xFFF) << 16)
+            | ((((self.y + 4) as u32) & 0x1FF) << 7)
+            | (self.piece as u32 & 0x7F);
+        write_be32(raw, writer)
+    }
+}
+
+/// An area of steel (which can't be dug through), in pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SteelArea {
+    pub x : i32,
+    pub y : i32,
+    pub width : i32,
+    pub height : i32,
+    /// The last byte, which is always 0 in the original levels.
+    pub _unknown0 : u8,
+}
+
+impl SteelArea {
+    /// Read a steel area, returning None for an empty slot.
+    pub fn read(reader : &mut dyn std::io::Read) -> std::io::Result<Option<SteelArea>> {
+        let raw : [u8; 4] = read_array(reader)?;
+        if raw == [0; 4] {
+            return Ok(None);
+        }
+        // Everything is in units of 4 pixels: 9 bits of x (offset by 16 pixels), 7 bits
+        // of y, then the width and height (minus one) in a nibble each.
+        let position = u16::from_be_bytes([raw[0], raw[1]]) as i32;
+        Ok(Some(SteelArea {
+            x : (position >> 7) * 4 - 16,
+            y : (position & 0x7F) * 4,
+            width : ((raw[2] >> 4) as i32 + 1) * 4,
+            height : ((raw[2] & 0xF) as i32 + 1) * 4,
+            _unknown0 : raw[3],
+        }))
+    }
+
+    pub fn write(&self, writer : &mut dyn std::io::Write) -> std::io::Result<()> {
+        let position = ((((self.x + 16) / 4) & 0x1FF) << 7) | ((self.y / 4) & 0x7F);
+        write_be16(position as u16, writer)?;
+        write_byte(((((self.width / 4 - 1) & 0xF) << 4) | ((self.height / 4 - 1) & 0xF)) as u8, writer)?;
+        write_byte(self._unknown0, writer)
+    }
+}
+
+/// A decoded level. The objects, terrain and steel keep every slot (including the
+/// empty ones), so they're written back in the same place.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Level {
+    pub release_rate : u16,
+    pub num_lemmings : u16,
+    pub num_to_rescue : u16,
+    /// In minutes.
+    pub time_limit : u16,
+    /// In the order of SKILL_NAMES.
+    pub skills : [u16; 8],
+    /// The x position of the left edge of the screen when the level starts.
+    pub screen_start : u16,
+    /// Either counted from 0 for the level's game, or in the combined numbering (see
+    /// LevelVariant::first_graphics_set()). Use ground_set() for the file to load.
+    pub graphics_set : u16,
+    /// The special graphics (vgaspecN.dat) used instead of terrain, or 0 for none. Only the
+    /// original game has these: see special_graphics_set().
+    pub special_graphics : u16,
+    pub _unknown0 : u16,
+    pub objects : Vec<Option<LevelObject>>,
+    pub terrain : Vec<Option<TerrainPlacement>>,
+    pub steel : Vec<Option<SteelArea>>,
+    /// The raw name, including any padding.
+    pub name : [u8; LEVEL_NAME_LEN],
+    /// Which game the level is for. This isn't part of the binary format.
+    pub variant : LevelVariant,
+}
+
+impl Level {
+    /// Read a level from its 2048-byte record, for game @variant, or (if it's None) the
+    /// game guessed from its graphics set.
+    pub fn read(reader : &mut dyn std::io::Read, variant : Option<LevelVariant>) -> std::io::Result<Level> {
+        let release_rate = read_be16(reader)?;
+        let num_lemmings = read_be16(reader)?;
+        let num_to_rescue = read_be16(reader)?;
+        let time_limit = read_be16(reader)?;
+        let mut skills = [0_u16; 8];
+        for skill in skills.iter_mut() {
+            *skill = read_be16(reader)?;
+        }
+        let screen_start = read_be16(reader)?;
+        let graphics_set = read_be16(reader)?;
+        let special_graphics = read_be16(reader)?;
+        let _unknown0 = read_be16(reader)?;
+
+        let mut objects = Vec::new();
+        for _ in 0..NUM_LEVEL_OBJECTS {
+            objects.push(LevelObject::read(reader)?);
+        }
+        let mut terrain = Vec::new();
+        for _ in 0..NUM_LEVEL_TERRAIN {
+            terrain.push(TerrainPlacement::read(reader)?);
+        }
+        let mut steel = Vec::new();
+        for _ in 0..NUM_LEVEL_STEEL {
+            steel.push(SteelArea::read(reader)?);
+        }
+        let name = read_array(reader)?;
+
+        Ok(Level {
+            release_rate,
+            num_lemmings,
+            num_to_rescue,
+            time_limit,
+            skills,
+            screen_start,
+            graphics_set,
+            special_graphics,
+            _unknown0,
+            objects,
+            terrain,
+            steel,
+            name,
+            variant : variant.or_else(|| LevelVariant::detect(graphics_set)).unwrap_or(LevelVariant::Original),
+        })
+    }
+
+    /// Decode a level from a section of a levelNNN.dat file, which must be exactly
+    /// LEVEL_SIZE bytes. See read() for @variant.
+    pub fn from_bytes(data : &[u8], variant : Option<LevelVariant>) -> std::io::Result<Level> {
+        if data.len() != LEVEL_SIZE {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+                format!("levels are {} bytes, not {}", LEVEL_SIZE, data.len())));
+        }
+        Level::read(&mut ByteReader::new(data), variant)
+    }
+
+    pub fn write(&self, writer : &mut dyn std::io::Write) -> std::io::Result<()> {
+        write_be16(self.release_rate, writer)?;
+        write_be16(self.num_lemmings, writer)?;
+        write_be16(self.num_to_rescue, writer)?;
+        write_be16(self.time_limit, writer)?;
+        for &skill in &self.skills {
+            write_be16(skill, writer)?;
+        }
+        write_be16(self.screen_start, writer)?;
+        write_be16(self.graphics_set, writer)?;
+        write_be16(self.special_graphics, writer)?;
+        write_be16(self._unknown0, writer)?;
+        for object in &self.objects {
+            match object {
+                Some(object) => object.write(writer)?,
+                None => writer.write_all(&[0; 8])?,
+            }
+        }
+        for piece in &self.terrain {
+            match piece {
+                Some(piece) => piece.write(writer)?,
+                None => write_be32(0xFFFF_FFFF, writer)?,
+            }
+        }
+        for area in &self.steel {
+            match area {
+                Some(area) => area.write(writer)?,
+                None => writer.write_all(&[0; 4])?,
+            }
+        }
+        writer.write_all(&self.name)
+    }
+
+    /// Encode the level as a LEVEL_SIZE byte record.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(LEVEL_SIZE);
+        self.write(&mut data).unwrap();
+        data
+    }
+
+    /// The level name, without the padding.
+    pub fn name(&self) -> String {
+        display_name(&self.name)
+    }
+
+    /// The number of the graphics set's files (groundNo.dat and vgagrN.dat) in the level's
+    /// game, whichever numbering graphics_set uses.
+    pub fn ground_set(&self) -> u16 {
+        let first = self.variant.first_graphics_set();
+        if self.graphics_set >= first { self.graphics_set - first } else { self.graphics_set }
+    }
+
+    /// Describe any of the stats which are out of the game's range (see stat_problems()).
+    pub fn stat_problems(&self) -> Vec<String> {
+        stat_problems(self.release_rate, self.num_lemmings, self.num_to_rescue, self.time_limit, &self.skills)
+    }
+
+    /// The special graphics the level uses, if it's for a game which has them.
+    pub fn special_graphics_set(&self) -> Option<u16> {
+        if self.variant.has_special_graphics() && self.special_graphics != 0 {
+            Some(self.special_graphics)
+        } else {
+            None
+        }
+    }
+
+    /// Write the level in the text format. Empty object slots are skipped (objects give
+    /// their slot number), but empty terrain and steel slots before the last used one are
+    /// written as "none", so the order is kept.
+    pub fn write_text(&self, writer : &mut dyn std::io::Write) -> std::io::Result<()> {
+        write_name(writer, &self.name)?;
+        if self.variant != LevelVariant::Original {
+            writeln!(writer, "Variant {}", self.variant.name())?;
+        }
+        writeln!(writer, "GraphicsSet {}", self.graphics_set)?;
+        if self.special_graphics != 0 {
+            writeln!(writer, "SpecialGraphics {}", self.special_graphics)?;
+        }
+        writeln!(writer, "ReleaseRate {}", self.release_rate)?;
+        writeln!(writer, "Lemmings {}", self.num_lemmings)?;
+        writeln!(writer, "Rescue {}", self.num_to_rescue)?;
+        writeln!(writer, "Time {}", self.time_limit)?;
+        writeln!(writer, "ScreenStart {}", self.screen_start)?;
+        if self._unknown0 != 0 {
+            writeln!(writer, "Unknown {}", self._unknown0)?;
+        }
+        write_skills(writer, &self.skills)?;
+        writeln!(writer)?;
+
+        for (index, object) in self.objects.iter().enumerate() {
+            if let Some(object) = object {
+                write!(writer, "Object {} at ({}, {}) id {}", index, object.x, object.y, object.id)?;
+                write_flags(writer, object.modifier, &[(OBJECT_NO_OVERWRITE, "no_overwrite"), (OBJECT_ONLY_ON_TERRAIN, "only_on_terrain")],
+                            object.display & OBJECT_UPSIDE_DOWN != 0)?;
+                if object.display & !OBJECT_UPSIDE_DOWN != OBJECT_DISPLAY_DEFAULT {
+                    write!(writer, " display {}", object.display & !OBJECT_UPSIDE_DOWN)?;
+                }
+                writeln!(writer)?;
+            }
+        }
+        writeln!(writer)?;
+
+        let used_terrain = self.terrain.iter().rposition(Option::is_some).map_or(0, |last| last + 1);
+        for piece in &self.terrain[..used_terrain] {
+            match piece {
+                Some(piece) => {
+                    write!(writer, "Terrain at ({}, {}) piece {}", piece.x, piece.y, piece.piece)?;
+                    write_flags(writer, piece.flags & !TERRAIN_UPSIDE_DOWN, &TERRAIN_FLAG_NAMES,
+                                piece.flags & TERRAIN_UPSIDE_DOWN != 0)?;
+                    writeln!(writer)?;
+                }
+                None => writeln!(writer, "Terrain none")?,
+            }
+        }
+        writeln!(writer)?;
+
+        let used_steel = self.steel.iter().rposition(Option::is_some).map_or(0, |last| last + 1);
+        for area in &self.steel[..used_steel] {
+            match area {
+                Some(area) => {
+                    write!(writer, "Steel at ({}, {}) size ({}, {})", area.x, area.y, area.width, area.height)?;
+                    if area._unknown0 != 0 {
+                        write!(writer, " unknown {}", area._unknown0)?;
+                    }
+                    writeln!(writer)?;
+                }
+                None => writeln!(writer, "Steel none")?,
+            }
+        }
+        Ok(())
+    }
+
+    /// The level in the text format, as a string.
+    pub fn to_text(&self) -> String {
+        let mut text = Vec::new();
+        self.write_text(&mut text).unwrap();
+        String::from_utf8(text).unwrap()
+    }
+
+    /// Parse a level from the text format written by write_text(). Anything which isn't
+    /// given is left at 0 (or empty), except the name, which is all spaces.
+    pub fn parse(lex : &mut parser::Lexer) -> Result<Level, parser::ParseError> {
+        let mut level = Level::default();
+        let mut terrain = Vec::new();
+        let mut steel = Vec::new();
+        while let Some(tok) = lex.next_spanned()? {
+            match tok.token.borrowed() {
+                parser::Token::Ident("Name") => level.name = parse_name(lex)?,
+                parser::Token::Ident("Variant") => {
+                    level.variant = match lex.next_spanned()? {
+                        Some(tok) => match tok.token.ident().and_then(LevelVariant::from_name) {
+                            Some(variant) => variant,
+                            None => return Err(tok.unknown_name_error("a variant", &["original", "onml", "holiday"])),
+                        },
+                        None => return Err(lex.error("a variant", None)),
+                    };
+                }
+                parser::Token::Ident("GraphicsSet") => level.graphics_set = lex.get_u16("graphics set")?,
+                parser::Token::Ident("SpecialGraphics") => level.special_graphics = lex.get_u16("special graphics")?,
+                parser::Token::Ident("ReleaseRate") => level.release_rate = lex.get_u16("release rate")?,
+                parser::Token::Ident("Lemmings") => level.num_lemmings = lex.get_u16("number of lemmings")?,
+                parser::Token::Ident("Rescue") => level.num_to_rescue = lex.get_u16("number to rescue")?,
+                parser::Token::Ident("Time") => level.time_limit = lex.get_u16("time limit")?,
+                parser::Token::Ident("ScreenStart") => level.screen_start = lex.get_u16("screen start")?,
+                parser::Token::Ident("Unknown") => level._unknown0 = lex.get_u16("unknown header value")?,
+                parser::Token::Ident("Skills") => level.skills = parse_skills(lex)?,
+                parser::Token::Ident("Object") => {
+                    let index = lex.get_int_in_range("object slot", 0, NUM_LEVEL_OBJECTS as i64 - 1)? as usize;
+                    if level.objects[index].is_some() {
+                        return Err(lex.invalid(format!("Duplicate object slot {}", index)));
+                    }
+                    level.objects[index] = Some(LevelObject::parse(lex)?);
+                }
+                parser::Token::Ident("Terrain") => {
+                    if terrain.len() == NUM_LEVEL_TERRAIN {
+                        return Err(tok.error(&format!("at most {} terrain pieces", NUM_LEVEL_TERRAIN)));
+                    }
+                    terrain.push(if lex.is_next_ident("none") {
+                        lex.next_token()?;
+                        None
+                    } else {
+                        Some(TerrainPlacement::parse(lex)?)
+                    });
+                }
+                parser::Token::Ident("Steel") => {
+                    if steel.len() == NUM_LEVEL_STEEL {
+                        return Err(tok.error(&format!("at most {} steel areas", NUM_LEVEL_STEEL)));
+                    }
+                    steel.push(if lex.is_next_ident("none") {
+                        lex.next_token()?;
+                        None
+                    } else {
+                        Some(SteelArea::parse(lex)?)
+                    });
+                }
+                _ => return Err(tok.error("a level property")),
+            }
+        }
+        level.terrain[..terrain.len()].copy_from_slice(&terrain);
+        level.steel[..steel.len()].copy_from_slice(&steel);
+        Ok(level)
+    }
+
+    /// Draw the level using the pieces from its graphics set, returning the image and the
+    /// (1-plane) terrain mask, which is what lemmings collide with. The terrain is drawn in
+    /// order, then the objects are drawn over the top (using the frame shown in previews),
+    /// without affecting the mask. Any pieces missing from the set are skipped with a warning to
+    /// @log.
+    pub fn render(&self, set : &graphics_set::GraphicsSet, log : &Log) -> (PlanarBMP, PlanarBMP) {
+        let mut image = PlanarBMP::new(LEVEL_WIDTH, LEVEL_HEIGHT, 4, &set.palette).unwrap();
+        // The mask is black, with the solid parts in white.
+        let mask_palette = planar_bmp::PaletteRGB::from_vga_data(2, &[0, 0, 0, 63, 63, 63]);
+        let mut mask = PlanarBMP::new(LEVEL_WIDTH, LEVEL_HEIGHT, 1, &mask_palette).unwrap();
+
+        for placement in self.terrain.iter().flatten() {
+            let piece = match set.terrain.iter().find(|piece| piece.index == placement.piece as usize) {
+                Some(piece) => piece,
+                None => {
+                    log_warning!(log, "Warning: terrain piece {} isn't in the graphics set", placement.piece);
+                    continue;
+                }
+            };
+            let (piece_image, piece_mask) = if placement.flags & TERRAIN_UPSIDE_DOWN != 0 {
+                (piece.image.flip_vertical(), piece.mask.flip_vertical())
+            } else {
+                (piece.image.clone(), piece.mask.clone())
+            };
+            let (x, y) = (placement.x as isize, placement.y as isize);
+            if placement.flags & TERRAIN_ERASE != 0 {
+                let blank = PlanarBMP::new(piece_mask.width, piece_mask.height, 1, &set.palette).unwrap();
+                image.blit_masked(&blank, &piece_mask, x, y, &|_, _| true);
+                mask.blit_masked(&blank, &piece_mask, x, y, &|_, _| true);
+                continue;
+            }
+            if placement.flags & TERRAIN_NO_OVERWRITE != 0 {
+                image.blit_masked(&piece_image, &piece_mask, x, y, &|x, y| mask.get_packed_pixel(x, y) == 0);
+            } else {
+                image.blit_masked(&piece_image, &piece_mask, x, y, &|_, _| true);
+            }
+            mask.blit_masked(&piece_mask, &piece_mask, x, y, &|_, _| true);
+        }
+
+        for placement in self.objects.iter().flatten() {
+            let object = match set.objects.iter().find(|object| object.index == placement.id as usize) {
+                Some(object) if !object.frames.is_empty() => object,
+                _ => {
+                    log_warning!(log, "Warning: object {} isn't in the graphics set", placement.id);
+                    continue;
+                }
+            };
+            let frame = object.header.preview_frame_number as usize;
+            let (frame_image, frame_mask) = object.frames.get(frame).unwrap_or(&object.frames[0]);
+            let (frame_image, frame_mask) = if placement.is_upside_down() {
+                (frame_image.flip_vertical(), frame_mask.flip_vertical())
+            } else {
+                (frame_image.clone(), frame_mask.clone())
+            };
+            let filter : &dyn Fn(usize, usize) -> bool = if placement.modifier & OBJECT_NO_OVERWRITE != 0 {
+                &|x, y| mask.get_packed_pixel(x, y) == 0
+            } else if placement.modifier & OBJECT_ONLY_ON_TERRAIN != 0 {
+                &|x, y| mask.get_packed_pixel(x, y) != 0
+            } else {
+                &|_, _| true
+            };
+            image.blit_masked(&frame_image, &frame_mask, placement.x as isize, placement.y as isize, filter);
+        }
+
+        (image, mask)
+    }
+
+    /// Check the level against @set, the graphics set it uses, returning a description of each
+    /// problem: stats out of range, objects or terrain pieces which aren't in the set, and
+    /// anything outside the level (see placement_warnings()).
+    pub fn check(&self, set : &graphics_set::GraphicsSet) -> Vec<String> {
+        let mut problems = self.stat_problems();
+        for (slot, placement) in self.objects.iter().enumerate() {
+            if let Some(placement) = placement {
+                if !set.objects.iter().any(|object| object.index == placement.id as usize) {
+                    problems.push(format!("Object {} uses object {}, which graphics set {} doesn't have", slot, placement.id, self.ground_set()));
+                }
+            }
+        }
+        for (slot, placement) in self.terrain.iter().enumerate() {
+            if let Some(placement) = placement {
+                if !set.terrain.iter().any(|piece| piece.index == placement.piece as usize) {
+                    problems.push(format!("Terrain {} uses piece {}, which graphics set {} doesn't have", slot, placement.piece, self.ground_set()));
+                }
+            }
+        }
+        problems.extend(self.placement_warnings(Some(set)));
+        problems
+    }
+
+    /// Describe each object, terrain piece or steel area placed entirely outside the level, and
+    /// each object whose trigger area is, so can never be reached. The game copes badly with
+    /// these, though a few of the original levels have them. Without @set, the graphics set the
+    /// level uses, the size of the pieces isn't known, so only those too far away for any piece
+    /// to reach into the level are found, and the trigger areas aren't checked.
+    pub fn placement_warnings(&self, set : Option<&graphics_set::GraphicsSet>) -> Vec<String> {
+        let mut warnings = Vec::new();
+        for (slot, placement) in self.objects.iter().enumerate() {
+            let placement = match placement {
+                Some(placement) => placement,
+                None => continue,
+            };
+            let object = set.and_then(|set| set.objects.iter().find(|object| object.index == placement.id as usize));
+            let (width, height) = match object {
+                Some(object) => match object.frames.first() {
+                    Some((image, _)) => (image.width, image.height),
+                    None => (object.header.width as usize, object.header.height as usize),
+                },
+                None => (MAX_PIECE_SIZE, MAX_PIECE_SIZE),
+            };
+            if is_outside_level(placement.x, placement.y, width, height) {
+                warnings.push(format!("Object {} at ({}, {}) is entirely outside the level", slot, placement.x, placement.y));
+            } else if let Some((x, y, width, height)) = object.and_then(|object| trigger_area(placement, &object.header)) {
+                if is_outside_level(x, y, width, height) {
+                    warnings.push(format!("Object {} at ({}, {}) has its trigger area at ({}, {}) size ({}, {}) entirely outside the level",
+                                          slot, placement.x, placement.y, x, y, width, height));
+                }
+            }
+        }
+        for (slot, placement) in self.terrain.iter().enumerate() {
+            let placement = match placement {
+                Some(placement) => placement,
+                None => continue,
+            };
+            let (width, height) = match set.and_then(|set| set.terrain.iter().find(|piece| piece.index == placement.piece as usize)) {
+                Some(piece) => (piece.image.width, piece.image.height),
+                None => (MAX_PIECE_SIZE, MAX_PIECE_SIZE),
+            };
+            if is_outside_level(placement.x, placement.y, width, height) {
+                warnings.push(format!("Terrain {} at ({}, {}) is entirely outside the level", slot, placement.x, placement.y));
+            }
+        }
+        for (slot, area) in self.steel.iter().enumerate() {
+            if let Some(area) = area {
+                if is_outside_level(area.x, area.y, area.width as usize, area.height as usize) {
+                    warnings.push(format!("Steel {} at ({}, {}) is entirely outside the level", slot, area.x, area.y));
+                }
+            }
+        }
+        warnings
+    }
+
+    /// Describe how @other differs from this level, one change per entry (e.g. "release rate
+    /// 50 → 65"). Slots are compared in place, so moving something to another slot shows up as
+    /// a removal and an addition.
+    pub fn diff(&self, other : &Level) -> Vec<String> {
+        let mut changes = Vec::new();
+        if self.name != other.name {
+            changes.push(format!("name \"{}\" → \"{}\"", self.name(), other.name()));
+        }
+        if self.variant != other.variant {
+            changes.push(format!("variant {} → {}", self.variant.name(), other.variant.name()));
+        }
+        let stats = [
+            ("graphics set", self.graphics_set, other.graphics_set),
+            ("special graphics", self.special_graphics, other.special_graphics),
+            ("release rate", self.release_rate, other.release_rate),
+            ("lemmings", self.num_lemmings, other.num_lemmings),
+            ("rescue", self.num_to_rescue, other.num_to_rescue),
+            ("time", self.time_limit, other.time_limit),
+            ("screen start", self.screen_start, other.screen_start),
+            ("unknown header value", self._unknown0, other._unknown0),
+        ];
+        for (what, old, new) in stats {
+            if old != new {
+                changes.push(format!("{} {} → {}", what, old, new));
+            }
+        }
+        for ((name, &old), &new) in SKILL_NAMES.iter().zip(self.skills.iter()).zip(other.skills.iter()) {
+            if old != new {
+                changes.push(format!("{} skills {} → {}", name, old, new));
+            }
+        }
+
+        diff_slots(&mut changes, "object", &self.objects, &other.objects, |object| {
+            format!("at ({}, {}) id {} modifier {} display {}", object.x, object.y, object.id, object.modifier, object.display)
+        });
+        diff_slots(&mut changes, "terrain", &self.terrain, &other.terrain, |piece| {
+            format!("at ({}, {}) piece {} flags {}", piece.x, piece.y, piece.piece, piece.flags)
+        });
+        diff_slots(&mut changes, "steel", &self.steel, &other.steel, |area| {
+            format!("at ({}, {}) size ({}, {})", area.x, area.y, area.width, area.height)
+        });
+        changes
+    }
+
+    /// Tint the steel areas of @image (as drawn by render()), so it's easy to see what's
+    /// covered. The result has 8 planes: colours 16 to 31 are tinted copies of the first 16.
+    pub fn tint_steel(&self, image : &PlanarBMP) -> PlanarBMP {
+        let colours = image.rgb_palette();
+        let mut vga_data = Vec::new();
+        for i in 0..16 {
+            let (r, g, b) = colours.get(i).copied().unwrap_or((0, 0, 0));
+            vga_data.extend_from_slice(&[r >> 2, g >> 2, b >> 2]);
+        }
+        for i in 0..16 * 3 {
+            vga_data.push((vga_data[i] + STEEL_TINT[i % 3]) / 2);
+        }
+        let mut tinted = PlanarBMP::new(image.width, image.height, 8, &planar_bmp::PaletteRGB::from_vga_data(32, &vga_data)).unwrap();
+        tinted.blit(image, 0, 0);
+        for area in self.steel.iter().flatten() {
+            let x_range = area.x.max(0) as usize..((area.x + area.width).max(0) as usize).min(image.width);
+            for y in area.y.max(0) as usize..((area.y + area.height).max(0) as usize).min(image.height) {
+                for x in x_range.clone() {
+                    let value = tinted.get_packed_pixel(x, y);
+                    tinted.pset(x, y, (value & 0xF) | 0x10);
+                }
+            }
+        }
+        tinted
+    }
+
+    /// Outline the trigger area of each object in @image (as drawn by render(), and maybe
+    /// tint_steel()), using the object headers from @set. The result has 8 planes, with each
+    /// kind of trigger in its own colour from TRIGGER_COLOUR_BASE on.
+    pub fn draw_triggers(&self, image : &PlanarBMP, set : &graphics_set::GraphicsSet) -> PlanarBMP {
+        let colours = image.rgb_palette();
+        let mut vga_data = Vec::new();
+        for i in 0..TRIGGER_COLOUR_BASE {
+            let (r, g, b) = colours.get(i).copied().unwrap_or((0, 0, 0));
+            vga_data.extend_from_slice(&[r >> 2, g >> 2, b >> 2]);
+        }
+        for colour in &TRIGGER_COLOURS {
+            vga_data.extend_from_slice(colour);
+        }
+        let palette = planar_bmp::PaletteRGB::from_vga_data(TRIGGER_COLOUR_BASE + TRIGGER_COLOURS.len(), &vga_data);
+        let mut outlined = PlanarBMP::new(image.width, image.height, 8, &palette).unwrap();
+        outlined.blit(image, 0, 0);
+
+        for placement in self.objects.iter().flatten() {
+            let header = match set.objects.iter().find(|object| object.index == placement.id as usize) {
+                Some(object) => &object.header,
+                None => continue,
+            };
+            let (left, top, width, height) = match trigger_area(placement, header) {
+                Some(area) => area,
+                None => continue,
+            };
+            let kind = match header.trigger_effect_id {
+                TRIGGER_EXIT => 0,
+                TRIGGER_TRAP => 1,
+                TRIGGER_WATER => 2,
+                TRIGGER_FIRE => 3,
+                TRIGGER_ONE_WAY_LEFT | TRIGGER_ONE_WAY_RIGHT => 4,
+                _ => 5,
+            };
+            let colour = (TRIGGER_COLOUR_BASE + kind) as u8;
+            let right = left + width as i32 - 1;
+            let bottom = top + height as i32 - 1;
+            let mut pset = |x : i32, y : i32| {
+                if (0..image.width as i32).contains(&x) && (0..image.height as i32).contains(&y) {
+                    outlined.pset(x as usize, y as usize, colour);
+                }
+            };
+            for x in left..=right {
+                pset(x, top);
+                pset(x, bottom);
+            }
+            for y in top..=bottom {
+                pset(left, y);
+                pset(right, y);
+            }
+        }
+        outlined
+    }
+
+    /// Parse a level from a string in the text format.
+    pub fn from_text(text : &str) -> Result<Level, parser::ParseError> {
+        Level::parse(&mut parser::Lexer::from_str(text))
+    }
+}
+
+impl Level {
+    /// A minimal level for graphics set @graphics_set (counted from 0) of @variant's game,
+    /// which the game will load: an entrance in view at the start, an exit at the far end,
+    /// no terrain, and middling stats. The game needs an entrance to have anywhere to put
+    /// the lemmings.
+    pub fn blank(graphics_set : u16, variant : LevelVariant) -> Result<Level, String> {
+        if graphics_set >= variant.num_graphics_sets() {
+            return Err(format!("Invalid graphics set {} (the {} game has sets 0 to {})", graphics_set, variant.name(), variant.num_graphics_sets() - 1));
+        }
+        let mut level = Level {
+            release_rate : 50,
+            num_lemmings : 40,
+            num_to_rescue : 20,
+            time_limit : 5,
+            skills : [5; 8],
+            graphics_set,
+            variant,
+            ..Default::default()
+        };
+        level.name[..9].copy_from_slice(b"New level");
+        level.objects[0] = Some(LevelObject { x : 144, y : 16, id : OBJECT_ENTRANCE, modifier : 0, display : OBJECT_DISPLAY_DEFAULT });
+        level.objects[1] = Some(LevelObject { x : 1408, y : 104, id : OBJECT_EXIT, modifier : 0, display : OBJECT_DISPLAY_DEFAULT });
+        Ok(level)
+    }
+}
+
+impl Default for Level {
+    fn default() -> Level {
+        Level {
+            release_rate : 0,
+            num_lemmings : 0,
+            num_to_rescue : 0,
+            time_limit : 0,
+            skills : [0; 8],
+            screen_start : 0,
+            graphics_set : 0,
+            special_graphics : 0,
+            _unknown0 : 0,
+            objects : vec![None; NUM_LEVEL_OBJECTS],
+            terrain : vec![None; NUM_LEVEL_TERRAIN],
+            steel : vec![None; NUM_LEVEL_STEEL],
+            name : [b' '; LEVEL_NAME_LEN],
+            variant : LevelVariant::Original,
+        }
+    }
+}
+
+impl LevelObject {
+    /// Parse the rest of an "Object" line, after the slot number.
+    fn parse(lex : &mut parser::Lexer) -> Result<LevelObject, parser::ParseError> {
+        lex.expect_ident("at")?;
+        // The x position is stored as a signed 16-bit value, offset by 16.
+        let (x, y) = parse_pair(lex, (i16::MIN as i64 - 16, i16::MAX as i64 - 16), (i16::MIN as i64, i16::MAX as i64), "object position")?;
+        lex.expect_ident("id")?;
+        let id = lex.get_u16("object id")?;
+        let mut object = LevelObject { x, y, id, modifier : 0, display : OBJECT_DISPLAY_DEFAULT };
+        if lex.is_next_ident("flags") {
+            let (bits, flip) = parse_flags(lex, &[(OBJECT_NO_OVERWRITE, "no_overwrite"), (OBJECT_ONLY_ON_TERRAIN, "only_on_terrain")])?;
+            object.modifier = bits;
+            if flip {
+                object.display |= OBJECT_UPSIDE_DOWN;
+            }
+        }
+        if lex.is_next_ident("display") {
+            lex.next_token()?;
+            let display = lex.get_int_in_range("object display value", 0, !OBJECT_UPSIDE_DOWN as i64)? as u8;
+            object.display = (object.display & OBJECT_UPSIDE_DOWN) | display;
+        }
+        Ok(object)
+    }
+}
+
+impl TerrainPlacement {
+    /// Parse the rest of a "Terrain" line.
+    fn parse(lex : &mut parser::Lexer) -> Result<TerrainPlacement, parser::ParseError> {
+        lex.expect_ident("at")?;
+        // 12 bits of x (offset by 16), and a signed 9-bit y (offset by 4).
+        let (x, y) = parse_pair(lex, (-16, 0xFFF - 16), (-0x100 - 4, 0xFF - 4), "terrain position")?;
+        lex.expect_ident("piece")?;
+        let piece = lex.get_int_in_range("terrain piece", 0, 0x7F)? as u8;
+        let mut flags = 0;
+        if lex.is_next_ident("flags") {
+            // "no_overwrite" is accepted too, as objects call it that.
+            let (bits, flip) = parse_flags(lex, &[TERRAIN_FLAG_NAMES[0], TERRAIN_FLAG_NAMES[1], (TERRAIN_NO_OVERWRITE, "no_overwrite")])?;
+            if bits > 0xF {
+                return Err(lex.invalid(format!("Invalid terrain flags {} (only 4 bits are stored)", bits)));
+            }
+            flags = bits | if flip { TERRAIN_UPSIDE_DOWN } else { 0 };
+        }
+        Ok(TerrainPlacement { x, y, piece, flags })
+    }
+}
+
+impl SteelArea {
+    /// Parse the rest of a "Steel" line.
+    fn parse(lex : &mut parser::Lexer) -> Result<SteelArea, parser::ParseError> {
+        lex.expect_ident("at")?;
+        let (x, y) = parse_pair(lex, (-16, 0x1FF * 4 - 16), (0, 0x7F * 4), "steel position")?;
+        lex.expect_ident("size")?;
+        let (width, height) = parse_pair(lex, (4, 64), (4, 64), "steel size")?;
+        if [x, y, width, height].iter().any(|val| val % STEEL_GRANULARITY != 0) {
+            return Err(lex.invalid(format!("Invalid steel area at ({}, {}) size ({}, {}) (positions and sizes must be multiples of {} pixels)",
+                                           x, y, width, height, STEEL_GRANULARITY)));
+        }
+        let _unknown0 = if lex.is_next_ident("unknown") {
+            lex.next_token()?;
+            lex.get_int_in_range("unknown steel value", 0, 0xFF)? as u8
+        } else { 0 };
+        Ok(SteelArea { x, y, width, height, _unknown0 })
+    }
+}
+
+/// Where to find a level, as listed in a repack-levels manifest.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LevelSource {
+    pub file_name : String,
+    /// The section of a levelNNN.dat file, or None for a file with just the one level (a raw
+    /// .lvl file, or one in the text format).
+    pub index : Option<usize>,
+}
+
+/// Parse a manifest: one level per line, giving a file, and (for .dat files) the section in it
+/// (counting from 0), separated by spaces. Blank lines, and those starting with '#', are skipped.
+pub fn parse_manifest(text : &str) -> Result<Vec<LevelSource>, String> {
+    let mut sources = Vec::new();
+    for (line_num, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields : Vec<&str> = line.split_whitespace().collect();
+        let index = match fields[1..] {
+            [] => None,
+            [index] => match index.parse::<usize>() {
+                Ok(index) => Some(index),
+                Err(_) => return Err(format!("Invalid level index \"{}\" on line {} of the manifest", index, line_num + 1)),
+            },
+            _ => return Err(format!("Expected a file and level index on line {} of the manifest, got \"{}\"", line_num + 1, line)),
+        };
+        let is_dat = fields[0].to_ascii_lowercase().ends_with(".dat");
+        if is_dat != index.is_some() {
+            return Err(format!("Line {} of the manifest should give a level index for .dat files (and only for them)", line_num + 1));
+        }
+        sources.push(LevelSource { file_name : fields[0].to_string(), index });
+    }
+    Ok(sources)
+}
+
+/// Add the differences between two lists of slots to @changes, using @describe to say what's in
+/// a slot.
+fn diff_slots<T : PartialEq>(changes : &mut Vec<String>, what : &str, old : &[Option<T>], new : &[Option<T>], describe : impl Fn(&T) -> String) {
+    for (slot, (old, new)) in old.iter().zip(new.iter()).enumerate() {
+        match (old, new) {
+            (None, Some(new)) => changes.push(format!("{} {} added {}", what, slot, describe(new))),
+            (Some(old), None) => changes.push(format!("{} {} removed (was {})", what, slot, describe(old))),
+            (Some(old), Some(new)) if old != new => {
+                changes.push(format!("{} {} changed {} → {}", what, slot, describe(old), describe(new)));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Shrink a terrain mask (as drawn by Level::render()) to the proportions of the game's
+/// minimap. Each pixel is solid if any of the MINIMAP_SCALE_X×MINIMAP_SCALE_Y pixels it
+/// covers are.
+pub fn minimap(mask : &PlanarBMP) -> PlanarBMP {
+    let mask_palette = planar_bmp::PaletteRGB::from_vga_data(2, &[0, 0, 0, 63, 63, 63]);
+    let mut minimap = PlanarBMP::new(mask.width.div_ceil(MINIMAP_SCALE_X), mask.height.div_ceil(MINIMAP_SCALE_Y), 1, &mask_palette).unwrap();
+    for y in 0..mask.height {
+        for x in 0..mask.width {
+            if mask.get_packed_pixel(x, y) != 0 {
+                minimap.pset(x / MINIMAP_SCALE_X, y / MINIMAP_SCALE_Y, 1);
+            }
+        }
+    }
+    minimap
+}
+
+/// Whether a @width × @height rectangle at (@x, @y) is entirely outside the level.
+pub fn is_outside_level(x : i32, y : i32, width : usize, height : usize) -> bool {
+    x + width as i32 <= 0 || x >= LEVEL_WIDTH as i32 || y + height as i32 <= 0 || y >= LEVEL_HEIGHT as i32
+}
+
+/// The trigger area of object @placement, whose header is @header, as (x, y, width, height) in
+/// the level. None if it doesn't have one.
+pub fn trigger_area(placement : &LevelObject, header : &graphics_set::ObjectHeader) -> Option<(i32, i32, usize, usize)> {
+    if header.trigger_effect_id == 0 || header.trigger_w == 0 || header.trigger_h == 0 {
+        return None;
+    }
+    Some((placement.x + header.trigger_x as i32 * TRIGGER_GRANULARITY,
+          placement.y + header.trigger_y as i32 * TRIGGER_GRANULARITY,
+          header.trigger_w as usize * TRIGGER_GRANULARITY as usize,
+          header.trigger_h as usize * TRIGGER_GRANULARITY as usize))
+}
+
+/// How many times a terrain piece or object of a graphics set is used, and how much of the set's
+/// (decompressed) data it takes up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SlotUse {
+    pub index : usize,
+    pub uses : usize,
+    pub size : usize,
+}
+
+/// How many times a group of levels use each terrain piece and object of a graphics set.
+#[derive(Default)]
+pub struct SetUsage {
+    pub num_levels : usize,
+    /// Uses of each terrain piece, and of each object, by slot.
+    pub terrain : std::collections::BTreeMap<usize, usize>,
+    pub objects : std::collections::BTreeMap<usize, usize>,
+}
+
+impl SetUsage {
+    /// Count the terrain and objects @level uses.
+    pub fn add_level(&mut self, level : &Level) {
+        self.num_levels += 1;
+        for placement in level.terrain.iter().flatten() {
+            *self.terrain.entry(placement.piece as usize).or_insert(0) += 1;
+        }
+        for placement in level.objects.iter().flatten() {
+            *self.objects.entry(placement.id as usize).or_insert(0) += 1;
+        }
+    }
+
+    /// How often each of @set's terrain pieces, and each of its objects, is used.
+    pub fn slot_uses(&self, set : &graphics_set::GraphicsSet) -> (Vec<SlotUse>, Vec<SlotUse>) {
+        let uses = |counts : &std::collections::BTreeMap<usize, usize>, index : usize| counts.get(&index).copied().unwrap_or(0);
+        (set.terrain.iter().map(|piece| SlotUse { index : piece.index, uses : uses(&self.terrain, piece.index), size : piece.header.data_size() }).collect(),
+         set.objects.iter().map(|object| SlotUse { index : object.index, uses : uses(&self.objects, object.index), size : object.header.data_size() }).collect())
+    }
+
+    /// Write a report of how often each of @set's terrain pieces and objects is used, and how
+    /// much space the unused ones take up.
+    pub fn write_report(&self, set : &graphics_set::GraphicsSet, writer : &mut dyn std::io::Write) -> std::io::Result<()> {
+        let (terrain, objects) = self.slot_uses(set);
+        for (kind, slots) in [("Terrain", &terrain), ("Object", &objects)] {
+            for slot in slots.iter() {
+                writeln!(writer, "\t{} {}: {} use{}", kind, slot.index, slot.uses, if slot.uses == 1 { "" } else { "s" })?;
+            }
+        }
+        for (kind, slots) in [("pieces", &terrain), ("objects", &objects)] {
+            let unused : Vec<usize> = slots.iter().filter(|slot| slot.uses == 0).map(|slot| slot.index).collect();
+            if slots.is_empty() {
+                writeln!(writer, "\tThe set has no {}", kind)?;
+                continue;
+            }
+            if unused.is_empty() {
+                writeln!(writer, "\tAll {} are used", kind)?;
+                continue;
+            }
+            let unused_size : usize = slots.iter().filter(|slot| slot.uses == 0).map(|slot| slot.size).sum();
+            let total_size : usize = slots.iter().map(|slot| slot.size).sum();
+            writeln!(writer, "\tRemoving unused {} {} would free {} of {} bytes", kind, format_ranges(&unused), format_count(unused_size), format_count(total_size))?;
+        }
+        // Anything used which isn't in the set is a problem with the level (see check()).
+        for (kind, counts, slots) in [("terrain piece", &self.terrain, &terrain), ("object", &self.objects, &objects)] {
+            for (&index, &count) in counts {
+                if !slots.iter().any(|slot| slot.index == index) {
+                    writeln!(writer, "\tWarning: {} {} is used {} time{}, but isn't in the set", kind, index, count, if count == 1 { "" } else { "s" })?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Write a sorted list of numbers as ranges, e.g. "1, 40–47".
+fn format_ranges(values : &[usize]) -> String {
+    let mut ranges : Vec<(usize, usize)> = Vec::new();
+    for &value in values {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == value => *end = value,
+            _ => ranges.push((value, value)),
+        }
+    }
+    ranges.iter().map(|&(start, end)| if start == end { start.to_string() } else { format!("{}–{}", start, end) }).collect::<Vec<String>>().join(", ")
+}
+
+/// Write a count with commas between the thousands, e.g. "6,120".
+fn format_count(count : usize) -> String {
+    let digits = count.to_string();
+    let mut formatted = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i != 0 && (digits.len() - i).is_multiple_of(3) {
+            formatted.push(',');
+        }
+        formatted.push(c);
+    }
+    formatted
+}
+
+/// A section of a levelNNN.dat file, for list-levels.
+pub struct LevelListEntry {
+    pub file_name : String,
+    pub index : usize,
+    /// None if the section isn't a level.
+    pub level : Option<Level>,
+    /// The level's ground file, if it came from a set map (see set_map::SetMap).
+    pub ground_file : Option<String>,
+}
+
+/// The column headings for the skill counts in the level list.
+const SKILL_HEADINGS : [&str; 8] = ["Cl", "Fl", "Bo", "Bl", "Bu", "Ba", "Mi", "Di"];
+
+/// Write a table with a row for each level, giving its index, name, game, graphics set (counted
+/// from 0 for its game) and stats. If any entries have a ground file, that's listed last.
+pub fn write_level_list(entries : &[LevelListEntry], writer : &mut dyn std::io::Write) -> std::io::Result<()> {
+    let file_width = entries.iter().map(|entry| entry.file_name.chars().count()).chain(std::iter::once(4)).max().unwrap();
+    let name_width = entries.iter().map(|entry| entry.level.as_ref().map_or(0, |level| level.name().chars().count())).chain(std::iter::once(4)).max().unwrap();
+    write!(writer, "{:<fw$}  {:>5}  {:<nw$}  {:<8}  {:>3}  {:>4}  {:>4}  {:>3}  {:>4}", "File", "Index", "Name", "Game", "Set", "Lems", "Save", "RR", "Time", fw = file_width, nw = name_width)?;
+    for heading in &SKILL_HEADINGS {
+        write!(writer, "  {:>3}", heading)?;
+    }
+    let has_ground_files = entries.iter().any(|entry| entry.ground_file.is_some());
+    if has_ground_files {
+        write!(writer, "  Ground")?;
+    }
+    writeln!(writer)?;
+    for entry in entries {
+        write!(writer, "{:<fw$}  {:>5}  ", entry.file_name, entry.index, fw = file_width)?;
+        let level = match &entry.level {
+            Some(level) => level,
+            None => {
+                writeln!(writer, "(not a level)")?;
+                continue;
+            }
+        };
+        write!(writer, "{:<nw$}  {:<8}  {:>3}  {:>4}  {:>4}  {:>3}  {:>4}", level.name(), level.variant.name(), level.ground_set(), level.num_lemmings,
+               level.num_to_rescue, level.release_rate, level.time_limit, nw = name_width)?;
+        for count in &level.skills {
+            write!(writer, "  {:>3}", count)?;
+        }
+        if has_ground_files {
+            write!(writer, "  {}", entry.ground_file.as_deref().unwrap_or("-"))?;
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+/// A level name, without the padding.
+/// Names are treated as Latin-1, so every byte is a character and none are lost.
+pub fn display_name(name : &[u8; LEVEL_NAME_LEN]) -> String {
+    name.iter().map(|&c| c as char).collect::<String>().trim_end_matches([' ', '\0']).to_string()
+}
+
+/// Whether a name character can be written in a string: printable Latin-1, other than '"'
+/// (which the Lexer can't escape).
+fn is_name_char(c : char) -> bool {
+    ((' '..='~').contains(&c) && c != '"') || ('\u{A0}'..='\u{FF}').contains(&c)
+}
+
+/// Write a "Name" line. Names which are text padded with spaces (as in all the original levels,
+/// including the few with stray non-ASCII characters) are written as a string, otherwise every
+/// byte is written out.
+pub fn write_name(writer : &mut dyn std::io::Write, name : &[u8; LEVEL_NAME_LEN]) -> std::io::Result<()> {
+    let trimmed = name.iter().rposition(|&c| c != b' ').map_or(0, |end| end + 1);
+    if name[..trimmed].iter().all(|&c| is_name_char(c as char)) {
+        writeln!(writer, "Name {}", parser::quote(&name[..trimmed].iter().map(|&c| c as char).collect::<String>()))
+    } else {
+        let bytes : Vec<String> = name.iter().map(|c| c.to_string()).collect();
+        writeln!(writer, "Name bytes({})", bytes.join(", "))
+    }
+}
+
+/// Write the "Skills" block.
+pub fn write_skills(writer : &mut dyn std::io::Write, skills : &[u16; 8]) -> std::io::Result<()> {
+    writeln!(writer, "Skills {{")?;
+    for (name, count) in SKILL_NAMES.iter().zip(skills.iter()) {
+        writeln!(writer, "\t{} = {}", name, count)?;
+    }
+    writeln!(writer, "}}")
+}
+
+/// Parse the "{ ... }" of a "Skills" block. Any skills left out are 0.
+pub fn parse_skills(lex : &mut parser::Lexer) -> Result<[u16; 8], parser::ParseError> {
+    let mut skills = [0; 8];
+    lex.expect_symbol('{')?;
+    loop {
+        let tok = match lex.next_spanned()? {
+            Some(tok) => tok,
+            None => return Err(lex.error("a skill or '}'", None)),
+        };
+        match tok.token.borrowed() {
+            parser::Token::Ident(skill) => {
+                let index = match SKILL_NAMES.iter().position(|&name| name == skill) {
+                    Some(index) => index,
+                    None => return Err(tok.unknown_name_error("a skill", &SKILL_NAMES)),
+                };
+                lex.expect_symbol('=')?;
+                skills[index] = lex.get_u16(skill)?;
+            }
+            parser::Token::Symbol('}') => break,
+            _ => return Err(tok.error("a skill or '}'")),
+        }
+    }
+    Ok(skills)
+}
+
+/// Describe any of the stats shared by levels and oddtable.dat entries which are out of the
+/// range the game accepts, e.g. "Invalid release rate 0 (must be from 1 to 99)". The text
+/// formats don't accept these, but the binary ones can hold anything.
+pub fn stat_problems(release_rate : u16, num_lemmings : u16, num_to_rescue : u16, time_limit : u16, skills : &[u16; 8]) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut check = |val : u16, (min, max) : (u16, u16), what : &str| {
+        if val < min || val > max {
+            problems.push(format!("Invalid {} {} (must be from {} to {})", what, val, min, max));
+        }
+    };
+    check(release_rate, RELEASE_RATE_RANGE, "release rate");
+    check(num_lemmings, (1, MAX_LEMMINGS), "number of lemmings");
+    check(num_to_rescue, (0, num_lemmings.max(1)), "number to rescue");
+    check(time_limit, TIME_LIMIT_RANGE, "time limit");
+    for (&count, name) in skills.iter().zip(SKILL_NAMES.iter()) {
+        check(count, (0, MAX_SKILL_COUNT), &format!("number of {} skills", name));
+    }
+    problems
+}
+
+/// Parse "(x, y)", with each value in the given (inclusive) range.
+fn parse_pair(lex : &mut parser::Lexer, x_range : (i64, i64), y_range : (i64, i64), what : &str) -> Result<(i32, i32), parser::ParseError> {
+    lex.expect_symbol('(')?;
+    let x = lex.get_int_in_range(what, x_range.0, x_range.1)?;
+    lex.expect_symbol(',')?;
+    let y = lex.get_int_in_range(what, y_range.0, y_range.1)?;
+    lex.expect_symbol(')')?;
+    Ok((x as i32, y as i32))
+}
+
+/// Parse "flags(...)", as written by write_flags(). Returns the named (and numbered) bits,
+/// and whether "flip" was given.
+fn parse_flags(lex : &mut parser::Lexer, names : &[(u8, &str)]) -> Result<(u8, bool), parser::ParseError> {
+    lex.expect_ident("flags")?;
+    lex.expect_symbol('(')?;
+    let mut bits = 0;
+    let mut flip = false;
+    loop {
+        let tok = match lex.next_spanned()? {
+            Some(tok) => tok,
+            None => return Err(lex.error("a flag", None)),
+        };
+        match tok.token.borrowed() {
+            parser::Token::Ident("flip") => flip = true,
+            parser::Token::Ident(flag) => match names.iter().find(|&&(_, name)| name == flag) {
+                Some(&(bit, _)) => bits |= bit,
+                None => {
+                    let known : Vec<&str> = names.iter().map(|&(_, name)| name).chain(std::iter::once("flip")).collect();
+                    return Err(tok.unknown_name_error("a flag", &known));
+                }
+            },
+            parser::Token::NumericLiteral(val) if (0..=0xFF).contains(&val) => bits |= val as u8,
+            _ => return Err(tok.error("a flag")),
+        }
+        match lex.next_token()? {
+            Some(parser::Token::Symbol(',')) => {},
+            Some(parser::Token::Symbol(')')) => break,
+            tok => return Err(lex.error("',' or ')'", tok.as_ref())),
+        }
+    }
+    Ok((bits, flip))
+}
+
+/// Parse a level name (after "Name"): either a string of up to LEVEL_NAME_LEN printable
+/// Latin-1 characters (which is padded with spaces), or "bytes(...)" giving every byte.
+pub fn parse_name(lex : &mut parser::Lexer) -> Result<[u8; LEVEL_NAME_LEN], parser::ParseError> {
+    let mut name = [b' '; LEVEL_NAME_LEN];
+    if lex.is_next_ident("bytes") {
+        lex.next_token()?;
+        lex.expect_symbol('(')?;
+        for (i, byte) in name.iter_mut().enumerate() {
+            if i != 0 {
+                lex.expect_symbol(',')?;
+            }
+            *byte = lex.get_int_in_range("name byte", 0, 0xFF)? as u8;
+        }
+        lex.expect_symbol(')')?;
+        return Ok(name);
+    }
+    let text = lex.get_string_literal()?;
+    if !text.chars().all(is_name_char) || text.chars().count() > LEVEL_NAME_LEN {
+        return Err(lex.invalid(format!("Invalid level name \"{}\" (must be at most {} printable Latin-1 characters)", text, LEVEL_NAME_LEN)));
+    }
+    for (byte, c) in name.iter_mut().zip(text.chars()) {
+        *byte = c as u8;
+    }
+    Ok(name)
+}
+
+/// Write " flags(...)" listing the named bits of @bits (plus "flip" if @flip is set), with
+/// any other bits as numbers. Nothing is written if there are no flags.
+fn write_flags(writer : &mut dyn std::io::Write, bits : u8, names : &[(u8, &str)], flip : bool) -> std::io::Result<()> {
+    let mut flags = Vec::new();
+    let mut other_bits = bits;
+    for &(bit, name) in names {
+        if bits & bit != 0 {
+            flags.push(name.to_string());
+            other_bits &= !bit;
+        }
+    }
+    if flip {
+        flags.push("flip".to_string());
+    }
+    if other_bits != 0 {
+        flags.push(other_bits.to_string());
+    }
+    if !flags.is_empty() {
+        write!(writer, " flags({})", flags.join(", "))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use json;
+    use schemas;
+
+    fn test_level() -> Level {
+        let mut name = [b' '; LEVEL_NAME_LEN];
+        name[..9].copy_from_slice(b"Just dig!");
+        let mut objects = vec![None; NUM_LEVEL_OBJECTS];
+        objects[0] = Some(LevelObject { x : 312, y : -8, id : 1, modifier : 0, display : 0x0F });
+        objects[2] = Some(LevelObject { x : -16, y : 40, id : 3, modifier : OBJECT_NO_OVERWRITE, display : 0x8F });
+        let mut terrain = vec![None; NUM_LEVEL_TERRAIN];
+        terrain[0] = Some(TerrainPlacement { x : 0, y : 80, piece : 12, flags : 0 });
+        terrain[2] = Some(TerrainPlacement { x : -16, y : -38, piece : 63, flags : TERRAIN_UPSIDE_DOWN | TERRAIN_ERASE });
+        let mut steel = vec![None; NUM_LEVEL_STEEL];
+        steel[0] = Some(SteelArea { x : 400, y : 120, width : 64, height : 8, _unknown0 : 0 });
+        Level {
+            release_rate : 50,
+            num_lemmings : 50,
+            num_to_rescue : 10,
+            time_limit : 5,
+            skills : [0, 0, 0, 0, 0, 0, 0, 10],
+            screen_start : 160,
+            graphics_set : 0,
+            special_graphics : 0,
+            _unknown0 : 0,
+            objects,
+            terrain,
+            steel,
+            name,
+            variant : LevelVariant::Original,
+        }
+    }
+
+    #[test]
+    fn level_binary_round_trip() {
+        let data = test_level().to_bytes();
+        assert_eq!(data.len(), LEVEL_SIZE);
+        assert_eq!(&data[0x20..0x28], &[0x01, 0x48, 0xFF, 0xF8, 0x00, 0x01, 0x00, 0x0F]);
+        assert_eq!(&data[0x120..0x124], &[0x00, 0x10, 0x2A, 0x0C]);
+        assert_eq!(&data[0x124..0x128], &[0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(&data[0x128..0x12C], &[0x60, 0x00, 0xEF, 0x3F]);
+        assert_eq!(&data[0x760..0x764], &[0x34, 0x1E, 0xF1, 0x00]);
+        assert_eq!(Level::from_bytes(&data, None).unwrap(), test_level());
+        assert!(Level::from_bytes(&data[1..], None).is_err());
+    }
+
+    #[test]
+    fn level_to_text() {
+        let level = test_level();
+        assert_eq!(level.name(), "Just dig!");
+        let text = level.to_text();
+        assert!(text.starts_with("Name \"Just dig!\"\nGraphicsSet 0\nReleaseRate 50\n"));
+        assert!(text.contains("\tdig = 10\n}\n"));
+        assert!(text.contains("\nObject 0 at (312, -8) id 1\nObject 2 at (-16, 40) id 3 flags(no_overwrite, flip)\n"));
+        assert!(text.contains("\nTerrain at (0, 80) piece 12\nTerrain none\nTerrain at (-16, -38) piece 63 flags(erase, flip)\n\n"));
+        assert!(text.ends_with("\nSteel at (400, 120) size (64, 8)\n"));
+        assert_eq!(Level::from_text(&text).unwrap(), level);
+    }
+
+    /// A graphics set with one 8×4 terrain piece (colour 1, with the bottom row masked out),
+    /// and one 8×2 object (colour 2).
+    fn test_graphics_set() -> graphics_set::GraphicsSet {
+        let palette = planar_bmp::PaletteRGB::new(16);
+        let mut image = PlanarBMP::new(8, 4, 4, &palette).unwrap();
+        let mut piece_mask = PlanarBMP::new(8, 4, 1, &palette).unwrap();
+        for y in 0..3 {
+            for x in 0..8 {
+                image.pset(x, y, 1);
+                piece_mask.pset(x, y, 1);
+            }
+        }
+        let mut frame = PlanarBMP::new(8, 2, 4, &palette).unwrap();
+        let mut frame_mask = PlanarBMP::new(8, 2, 1, &palette).unwrap();
+        for y in 0..2 {
+            for x in 0..8 {
+                frame.pset(x, y, 2);
+                frame_mask.pset(x, y, 1);
+            }
+        }
+        graphics_set::GraphicsSet {
+            terrain : vec![graphics_set::TerrainPiece { index : 5, header : Default::default(), image, mask : piece_mask }],
+            objects : vec![graphics_set::GraphicsObject { index : 3, header : Default::default(), frames : vec![(frame, frame_mask)] }],
+            palettes : Default::default(),
+            palette,
+        }
+    }
+
+    #[test]
+    fn level_render() {
+        let mut level = Level::default();
+        level.terrain[0] = Some(TerrainPlacement { x : -4, y : 0, piece : 5, flags : 0 });
+        level.terrain[1] = Some(TerrainPlacement { x : 100, y : 10, piece : 5, flags : TERRAIN_UPSIDE_DOWN });
+        level.terrain[2] = Some(TerrainPlacement { x : 102, y : 10, piece : 5, flags : TERRAIN_ERASE });
+        level.objects[0] = Some(LevelObject { x : 0, y : 2, id : 3, modifier : OBJECT_NO_OVERWRITE, display : OBJECT_DISPLAY_DEFAULT });
+        level.objects[1] = Some(LevelObject { x : 96, y : 13, id : 3, modifier : OBJECT_ONLY_ON_TERRAIN, display : OBJECT_DISPLAY_DEFAULT });
+        let (image, mask) = level.render(&test_graphics_set(), &Log::default());
+        assert_eq!((image.width, image.height), (LEVEL_WIDTH, LEVEL_HEIGHT));
+
+        // The first piece is clipped at the left edge. The object only shows below it.
+        assert_eq!((image.get_packed_pixel(3, 0), mask.get_packed_pixel(3, 0)), (1, 1));
+        assert_eq!((image.get_packed_pixel(4, 0), mask.get_packed_pixel(4, 0)), (0, 0));
+        assert_eq!(image.get_packed_pixel(3, 2), 1);
+        assert_eq!((image.get_packed_pixel(3, 3), mask.get_packed_pixel(3, 3)), (2, 0));
+
+        // The flipped piece is missing its top row, and most of it has been erased.
+        assert_eq!(mask.get_packed_pixel(100, 10), 0);
+        assert_eq!((image.get_packed_pixel(101, 11), mask.get_packed_pixel(101, 11)), (1, 1));
+        assert_eq!((image.get_packed_pixel(102, 11), mask.get_packed_pixel(102, 11)), (0, 0));
+        assert_eq!((image.get_packed_pixel(104, 13), mask.get_packed_pixel(104, 13)), (1, 1));
+        // The second object only shows on the remaining terrain.
+        assert_eq!(image.get_packed_pixel(97, 13), 0);
+        assert_eq!(image.get_packed_pixel(101, 13), 2);
+        assert_eq!(image.get_packed_pixel(103, 14), 0);
+
+        level.steel[0] = Some(SteelArea { x : -16, y : 156, width : 24, height : 8, _unknown0 : 0 });
+        let tinted = level.tint_steel(&image);
+        assert_eq!(tinted.planes, 8);
+        assert_eq!(tinted.get_packed_pixel(3, 0), 1);
+        assert_eq!(tinted.get_packed_pixel(7, 159), 16);
+        assert_eq!(tinted.get_packed_pixel(8, 159), 0);
+        assert_eq!(tinted.get_packed_pixel(7, 155), 0);
+        assert_eq!(tinted.rgb_palette()[16], (12 * 4, 20 * 4, 31 * 4));
+    }
+
+    #[test]
+    fn level_draw_triggers() {
+        let mut set = test_graphics_set();
+        set.objects[0].header.trigger_x = 1;
+        set.objects[0].header.trigger_y = 0;
+        set.objects[0].header.trigger_w = 2;
+        set.objects[0].header.trigger_h = 1;
+        set.objects[0].header.trigger_effect_id = TRIGGER_TRAP;
+        let mut level = Level::default();
+        level.objects[0] = Some(LevelObject { x : 100, y : 20, id : 3, modifier : 0, display : OBJECT_DISPLAY_DEFAULT });
+        level.objects[1] = Some(LevelObject { x : -6, y : 158, id : 3, modifier : 0, display : OBJECT_DISPLAY_DEFAULT });
+        let (image, _) = level.render(&set, &Log::default());
+        let outlined = level.draw_triggers(&image, &set);
+        assert_eq!(outlined.planes, 8);
+        let trap = (TRIGGER_COLOUR_BASE + 1) as u8;
+        assert_eq!(outlined.rgb_palette()[trap as usize], (252, 0, 0));
+        // An 8×4 outline, 4 pixels in from the object.
+        for x in 104..112 {
+            assert_eq!((outlined.get_packed_pixel(x, 20), outlined.get_packed_pixel(x, 23)), (trap, trap));
+        }
+        assert_eq!((outlined.get_packed_pixel(104, 21), outlined.get_packed_pixel(111, 22)), (trap, trap));
+        assert_eq!(outlined.get_packed_pixel(105, 21), 2);
+        assert_eq!(outlined.get_packed_pixel(103, 20), 2);
+        assert_eq!(outlined.get_packed_pixel(112, 20), 0);
+        // Clipped to the level.
+        assert_eq!(outlined.get_packed_pixel(0, 158), trap);
+        assert_eq!((outlined.get_packed_pixel(5, 159), outlined.get_packed_pixel(1, 159)), (trap, 2));
+    }
+
+    #[test]
+    fn level_blank() {
+        let level = Level::blank(2, LevelVariant::Onml).unwrap();
+        assert!(level.stat_problems().is_empty());
+        assert_eq!(level.name(), "New level");
+        assert_eq!(level.objects.iter().flatten().filter(|object| object.id == OBJECT_ENTRANCE).count(), 1);
+        assert!(level.terrain.iter().all(Option::is_none));
+        let text = level.to_text();
+        assert!(text.contains("Variant onml\n"));
+        assert_eq!(Level::from_text(&text).unwrap(), level);
+        assert_eq!(Level::from_bytes(&level.to_bytes(), Some(LevelVariant::Onml)).unwrap(), level);
+    }
+
+    #[test]
+    fn level_blank_invalid_set() {
+        assert_eq!(Level::blank(5, LevelVariant::Original).unwrap_err(), "Invalid graphics set 5 (the original game has sets 0 to 4)");
+    }
+
+    #[test]
+    fn level_stat_ranges() {
+        let mut level = test_level();
+        assert!(level.stat_problems().is_empty());
+        level.release_rate = 0;
+        level.num_to_rescue = 51;
+        level.skills[3] = 100;
+        assert_eq!(level.stat_problems(), vec![
+            "Invalid release rate 0 (must be from 1 to 99)".to_string(),
+            "Invalid number to rescue 51 (must be from 0 to 50)".to_string(),
+            "Invalid number of block skills 100 (must be from 0 to 99)".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn level_stats_out_of_range_round_trip() {
+        // The original levels haven't all been checked against the ranges, so stats outside
+        // them are only warned about, and are kept as they are.
+        let level = Level::from_text("ReleaseRate 0 Lemmings 50 Rescue 51 Time 5 Skills { dig = 100 }").unwrap();
+        assert_eq!(level.stat_problems().len(), 3);
+        assert_eq!(Level::from_text(&level.to_text()).unwrap(), level);
+        assert_eq!(Level::from_bytes(&level.to_bytes(), None).unwrap(), level);
+    }
+
+    #[test]
+    fn level_steel_granularity() {
+        let err = Level::from_text("ReleaseRate 50 Lemmings 50 Rescue 10 Time 5 Steel at (400, 122) size (64, 8)").unwrap_err();
+        assert!(err.to_string().starts_with("1:76: Invalid steel area at (400, 122) size (64, 8)"), "{}", err);
+        assert!(err.to_string().ends_with("multiples of 4 pixels)"), "{}", err);
+    }
+
+    #[test]
+    fn level_check() {
+        let mut level = test_level();
+        level.objects[0] = Some(LevelObject { x : 312, y : -1, id : 3, modifier : 0, display : OBJECT_DISPLAY_DEFAULT });
+        level.objects[2] = None;
+        level.terrain = vec![None; NUM_LEVEL_TERRAIN];
+        level.terrain[0] = Some(TerrainPlacement { x : 0, y : 80, piece : 5, flags : 0 });
+        assert!(level.check(&test_graphics_set()).is_empty());
+
+        level.objects[1] = Some(LevelObject { x : 1600, y : 0, id : 3, modifier : 0, display : OBJECT_DISPLAY_DEFAULT });
+        level.objects[4] = Some(LevelObject { x : 0, y : 0, id : 9, modifier : 0, display : OBJECT_DISPLAY_DEFAULT });
+        level.terrain[1] = Some(TerrainPlacement { x : -8, y : 80, piece : 5, flags : 0 });
+        level.terrain[2] = Some(TerrainPlacement { x : -7, y : 80, piece : 5, flags : 0 });
+        level.terrain[3] = Some(TerrainPlacement { x : 0, y : 0, piece : 7, flags : 0 });
+        level.num_to_rescue = 60;
+        assert_eq!(level.check(&test_graphics_set()), vec![
+            "Invalid number to rescue 60 (must be from 0 to 50)".to_string(),
+            "Object 4 uses object 9, which graphics set 0 doesn't have".to_string(),
+            "Terrain 3 uses piece 7, which graphics set 0 doesn't have".to_string(),
+            "Object 1 at (1600, 0) is entirely outside the level".to_string(),
+            "Terrain 1 at (-8, 80) is entirely outside the level".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn level_placement_warnings() {
+        let mut set = test_graphics_set();
+        set.objects[0].header.trigger_x = 0;
+        set.objects[0].header.trigger_y = 1;
+        set.objects[0].header.trigger_w = 2;
+        set.objects[0].header.trigger_h = 1;
+        set.objects[0].header.trigger_effect_id = TRIGGER_WATER;
+        let mut level = Level::default();
+        level.objects[0] = Some(LevelObject { x : 0, y : 150, id : 3, modifier : 0, display : OBJECT_DISPLAY_DEFAULT });
+        level.objects[1] = Some(LevelObject { x : 0, y : 158, id : 3, modifier : 0, display : OBJECT_DISPLAY_DEFAULT });
+        level.objects[2] = Some(LevelObject { x : -200, y : 0, id : 3, modifier : 0, display : OBJECT_DISPLAY_DEFAULT });
+        level.terrain[0] = Some(TerrainPlacement { x : 1600, y : 0, piece : 5, flags : 0 });
+        level.terrain[1] = Some(TerrainPlacement { x : -300, y : 0, piece : 5, flags : 0 });
+        level.steel[0] = Some(SteelArea { x : 0, y : 160, width : 8, height : 8, _unknown0 : 0 });
+        assert_eq!(level.placement_warnings(Some(&set)), vec![
+            "Object 1 at (0, 158) has its trigger area at (0, 162) size (8, 4) entirely outside the level".to_string(),
+            "Object 2 at (-200, 0) is entirely outside the level".to_string(),
+            "Terrain 0 at (1600, 0) is entirely outside the level".to_string(),
+            "Terrain 1 at (-300, 0) is entirely outside the level".to_string(),
+            "Steel 0 at (0, 160) is entirely outside the level".to_string(),
+        ]);
+        // Without the graphics set, only the ones no piece could reach into the level.
+        assert_eq!(level.placement_warnings(None), vec![
+            "Terrain 0 at (1600, 0) is entirely outside the level".to_string(),
+            "Terrain 1 at (-300, 0) is entirely outside the level".to_string(),
+            "Steel 0 at (0, 160) is entirely outside the level".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn level_names() {
+        let names : [&[u8]; 6] = [
+            b"Just dig!",
+            b"  Leading spaces",
+            b"Stray\xA0byte\xE9",
+            b"All thirty-two characters in it!",
+            b"\"Quoted\"",
+            b"Tab\tand NULs\0\0\0",
+        ];
+        for raw in names {
+            let mut name = [b' '; LEVEL_NAME_LEN];
+            name[..raw.len()].copy_from_slice(raw);
+            let mut text = Vec::new();
+            write_name(&mut text, &name).unwrap();
+            let text = String::from_utf8(text).unwrap();
+            let mut lex = parser::Lexer::from_str(&text);
+            lex.expect_ident("Name").unwrap();
+            assert_eq!(parse_name(&mut lex).unwrap(), name, "{}", text);
+        }
+
+        let mut name = [b' '; LEVEL_NAME_LEN];
+        name[..11].copy_from_slice(b"Stray\xA0byte\xE9");
+        assert_eq!(display_name(&name), "Stray\u{A0}byte\u{E9}");
+        let mut text = Vec::new();
+        write_name(&mut text, &name).unwrap();
+        assert_eq!(String::from_utf8(text).unwrap(), "Name \"Stray\u{A0}byte\u{E9}\"\n");
+    }
+
+    #[test]
+    fn level_name_too_long() {
+        let err = parse_name(&mut parser::Lexer::from_str("\"This name is one character too long\"")).unwrap_err();
+        assert!(err.to_string().ends_with("must be at most 32 printable Latin-1 characters)"), "{}", err);
+    }
+
+    #[test]
+    fn level_diff() {
+        let old = test_level();
+        assert!(old.diff(&old).is_empty());
+        let mut new = test_level();
+        new.release_rate = 65;
+        new.skills[7] = 9;
+        new.terrain[1] = Some(TerrainPlacement { x : 1204, y : 80, piece : 3, flags : 0 });
+        new.terrain[2] = None;
+        new.objects[0].as_mut().unwrap().x = 320;
+        assert_eq!(old.diff(&new), vec![
+            "release rate 50 → 65".to_string(),
+            "dig skills 10 → 9".to_string(),
+            "object 0 changed at (312, -8) id 1 modifier 0 display 15 → at (320, -8) id 1 modifier 0 display 15".to_string(),
+            "terrain 1 added at (1204, 80) piece 3 flags 0".to_string(),
+            "terrain 2 removed (was at (-16, -38) piece 63 flags 6)".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn level_minimap() {
+        let mut level = Level::default();
+        level.terrain[0] = Some(TerrainPlacement { x : 30, y : 7, piece : 5, flags : 0 });
+        let (_, mask) = level.render(&test_graphics_set(), &Log::default());
+        let map = minimap(&mask);
+        assert_eq!((map.width, map.height, map.planes), (100, 20, 1));
+        // The piece covers (30, 7) to (37, 9).
+        let solid : Vec<(usize, usize)> = (0..20).flat_map(|y| (0..100).map(move |x| (x, y))).filter(|&(x, y)| map.get_packed_pixel(x, y) != 0).collect();
+        assert_eq!(solid, vec![(1, 0), (2, 0), (1, 1), (2, 1)]);
+    }
+
+    #[test]
+    fn level_render_behind() {
+        let mut set = test_graphics_set();
+        let mut image = set.terrain[0].image.clone();
+        for y in 0..3 {
+            for x in 0..8 {
+                image.pset(x, y, 3);
+            }
+        }
+        let mask = set.terrain[0].mask.clone();
+        set.terrain.push(graphics_set::TerrainPiece { index : 6, header : Default::default(), image, mask });
+        let mut level = Level { release_rate : 50, num_lemmings : 10, time_limit : 5, ..Default::default() };
+        level.terrain[0] = Some(TerrainPlacement { x : 200, y : 0, piece : 5, flags : 0 });
+        level.terrain[1] = Some(TerrainPlacement { x : 204, y : 1, piece : 6, flags : TERRAIN_NO_OVERWRITE });
+        let (image, mask) = level.render(&set, &Log::default());
+        assert_eq!(image.get_packed_pixel(205, 1), 1);
+        assert_eq!(image.get_packed_pixel(209, 1), 3);
+        assert_eq!(image.get_packed_pixel(205, 3), 3);
+        assert_eq!(mask.get_packed_pixel(209, 3), 1);
+
+        let text = level.to_text();
+        assert!(text.contains("\nTerrain at (204, 1) piece 6 flags(behind)\n"));
+        assert_eq!(Level::from_text(&text.replace("behind", "no_overwrite")).unwrap(), level);
+    }
+
+    #[test]
+    fn level_variants() {
+        let mut level = test_level();
+        level.graphics_set = 6;
+        level.special_graphics = 2;
+        let data = level.to_bytes();
+        let onml = Level::from_bytes(&data, None).unwrap();
+        assert_eq!(onml.variant, LevelVariant::Onml);
+        assert_eq!(onml.ground_set(), 1);
+        assert_eq!(onml.special_graphics_set(), None);
+        assert!(onml.to_text().starts_with("Name \"Just dig!\"\nVariant onml\nGraphicsSet 6\nSpecialGraphics 2\n"));
+        assert_eq!(Level::from_text(&onml.to_text()).unwrap(), onml);
+        assert_eq!(Level::from_text(&onml.to_text()).unwrap().to_bytes(), data);
+
+        // ONML's own files count from 0, so the game has to be given.
+        level.graphics_set = 1;
+        let data = level.to_bytes();
+        assert_eq!(Level::from_bytes(&data, None).unwrap().variant, LevelVariant::Original);
+        assert_eq!(Level::from_bytes(&data, None).unwrap().special_graphics_set(), Some(2));
+        let holiday = Level::from_bytes(&data, Some(LevelVariant::Holiday)).unwrap();
+        assert_eq!(holiday.ground_set(), 1);
+        assert_eq!(holiday.special_graphics_set(), None);
+        assert_eq!(LevelVariant::detect(10), Some(LevelVariant::Holiday));
+        assert_eq!(LevelVariant::detect(11), None);
+    }
+
+    #[test]
+    fn level_manifest() {
+        let sources = parse_manifest("# Fun\nlevel000.dat 0\n\n  LEVEL003.DAT\t7 \nmine.lvl\n").unwrap();
+        assert_eq!(sources, vec![
+            LevelSource { file_name : "level000.dat".to_string(), index : Some(0) },
+            LevelSource { file_name : "LEVEL003.DAT".to_string(), index : Some(7) },
+            LevelSource { file_name : "mine.lvl".to_string(), index : None },
+        ]);
+    }
+
+    #[test]
+    fn level_manifest_missing_index() {
+        let err = parse_manifest("level000.dat 0\nlevel001.dat\n").unwrap_err();
+        assert!(err.starts_with("Line 2 of the manifest"), "{}", err);
+    }
+
+    #[test]
+    fn level_set_usage() {
+        let mut set = test_graphics_set();
+        set.terrain[0].header.width = 8;
+        set.terrain[0].header.height = 4;
+        set.terrain.push(graphics_set::TerrainPiece {
+            index : 6,
+            header : graphics_set::TerrainHeader { width : 16, height : 100, ..Default::default() },
+            image : PlanarBMP::new(16, 100, 4, &set.palette).unwrap(),
+            mask : PlanarBMP::new(16, 100, 1, &set.palette).unwrap(),
+        });
+        let mut usage = SetUsage::default();
+        usage.add_level(&test_level());
+        let mut level = Level::default();
+        level.terrain[0] = Some(TerrainPlacement { x : 0, y : 0, piece : 5, flags : 0 });
+        level.objects[0] = Some(LevelObject { x : 0, y : 0, id : 3, modifier : 0, display : OBJECT_DISPLAY_DEFAULT });
+        usage.add_level(&level);
+        assert_eq!(usage.num_levels, 2);
+        assert_eq!(usage.terrain.get(&5), Some(&1));
+        assert_eq!(usage.objects.get(&3), Some(&2));
+
+        let mut report = Vec::new();
+        usage.write_report(&set, &mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.starts_with("\tTerrain 5: 1 use\n\tTerrain 6: 0 uses\n\tObject 3: 2 uses\n"));
+        assert!(report.contains("\tRemoving unused pieces 6 would free 1,000 of 1,020 bytes\n\tAll objects are used\n"));
+        assert!(report.contains("\tWarning: terrain piece 12 is used 1 time, but isn't in the set\n"));
+        assert!(report.contains("\tWarning: object 1 is used 1 time, but isn't in the set\n"));
+
+        assert_eq!(format_ranges(&[1, 40, 41, 42, 47]), "1, 40–42, 47");
+        assert_eq!((format_count(0), format_count(6120), format_count(1234567)), ("0".to_string(), "6,120".to_string(), "1,234,567".to_string()));
+    }
+
+    #[test]
+    fn level_list() {
+        let entries = vec![
+            LevelListEntry { file_name : "level000.dat".to_string(), index : 0, level : Some(test_level()), ground_file : None },
+            LevelListEntry { file_name : "level000.dat".to_string(), index : 1, level : None, ground_file : None },
+        ];
+        let mut table = Vec::new();
+        write_level_list(&entries, &mut table).unwrap();
+        let table = String::from_utf8(table).unwrap();
+        assert_eq!(table, "File          Index  Name       Game      Set  Lems  Save   RR  Time   Cl   Fl   Bo   Bl   Bu   Ba   Mi   Di\n\
+                           level000.dat      0  Just dig!  original    0    50    10   50     5    0    0    0    0    0    0    0   10\n\
+                           level000.dat      1  (not a level)\n");
+
+        let mut json = Vec::new();
+        schemas::level_list(&entries).write(&mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json::parse(&json).is_ok(), "{}", json);
+        assert!(json.contains("{ \"file\": \"level000.dat\", \"index\": 0, \"level\": { \"name\": \"Just dig!\", \"game\": \"original\", \"graphics_set\": 0,"));
+        assert!(json.contains("\"dig\": 10 } } },\n"));
+        assert!(json.ends_with("\"index\": 1, \"level\": null }\n]\n"));
+
+        let entries = vec![LevelListEntry { file_name : "level000.dat".to_string(), index : 0, level : Some(test_level()), ground_file : Some("ohno_ground0o.dat".to_string()) }];
+        let mut table = Vec::new();
+        write_level_list(&entries, &mut table).unwrap();
+        let table = String::from_utf8(table).unwrap();
+        assert!(table.starts_with("File          Index  Name       Game      Set  Lems  Save   RR  Time   Cl   Fl   Bo   Bl   Bu   Ba   Mi   Di  Ground\n"));
+        assert!(table.ends_with("   10  ohno_ground0o.dat\n"));
+        let mut json = Vec::new();
+        schemas::level_list(&entries).write(&mut json).unwrap();
+        assert!(String::from_utf8(json).unwrap().contains("\"graphics_set\": 0, \"ground_file\": \"ohno_ground0o.dat\", \"lemmings\": 50,"));
+    }
+
+    #[test]
+    fn level_text_round_trip_odd_values() {
+        // Arbitrary bytes (with a few empty slots) survive being turned into text and back.
+        let mut seed = 12345_u32;
+        let mut data : Vec<u8> = (0..LEVEL_SIZE).map(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as u8
+        }).collect();
+        // Apart from the stats, which the text format checks.
+        data[0..0x18].copy_from_slice(&[0, 99, 0, 80, 0, 80, 0, 1, 0, 0, 0, 99, 0, 1, 0, 2, 0, 3, 0, 4, 0, 5, 0, 6]);
+        data[0x28..0x30].copy_from_slice(&[0; 8]);
+        data[0x124..0x128].copy_from_slice(&[0xFF; 4]);
+        data[0x764..0x768].copy_from_slice(&[0; 4]);
+        let level = Level::from_bytes(&data, None).unwrap();
+        let text = level.to_text();
+        assert!(text.contains("\nTerrain none\n"));
+        assert!(text.starts_with("Name bytes("));
+        assert_eq!(Level::from_text(&text).unwrap().to_bytes(), data);
+    }
+}
@@ -0,0 +1,94 @@
+/*
+ * modlem: A graphics importer/exporter for Lemmings
+ * Copyright (C) 2022–2026 David Gow <david@davidgow.net>
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Reading and writing the DOS Lemmings data files: the compressed .dat archives, the planar
+//! graphics in them, graphics sets (groundXo.dat and vgagrX.dat), main.dat, and modlem's own
+//! script format.
+//!
+//! Bad data, scripts and files are reported as errors rather than panics: a panic means a bug
+//! in modlem. Some of the modules also have functions which print what they're doing, for the
+//! modlem command itself.
+//!
+//! ```
+//! use modlem::DatSection;
+//!
+//! // Compress some data into a .dat section, then read it back.
+//! let mut file = Vec::new();
+//! DatSection::from_data(b"Let's go!", 9).write(&mut file)?;
+//! let mut section = DatSection::from_file(&mut &file[..])?;
+//! assert_eq!(section.decompress()?, b"Let's go!");
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+// ParseErrors carry the line they're on for their messages, so are on the large side, but
+// they're only made once per script, and stop the parsing.
+#![allow(clippy::result_large_err)]
+
+#[macro_use]
+pub mod binary_io;
+// Before the rest, so they can use its macros.
+#[macro_use]
+pub mod log;
+pub mod case_sensitivity;
+pub mod dat_section;
+pub mod graphics_set;
+pub mod main_dat;
+pub mod outputs;
+pub mod parser;
+pub mod planar_bmp;
+
+// These are used by the modlem command, but aren't (yet) meant for use elsewhere.
+#[doc(hidden)]
+pub mod atlas;
+#[doc(hidden)]
+pub mod backup;
+#[doc(hidden)]
+pub mod dat_diff;
+#[doc(hidden)]
+pub mod file_info;
+#[doc(hidden)]
+pub mod gif;
+#[doc(hidden)]
+pub mod json;
+#[doc(hidden)]
+pub mod level;
+#[doc(hidden)]
+pub mod nxlv;
+#[doc(hidden)]
+pub mod oddtable;
+#[doc(hidden)]
+pub mod palettes;
+#[doc(hidden)]
+pub mod pcspkr;
+#[doc(hidden)]
+pub mod png;
+#[doc(hidden)]
+pub mod schemas;
+#[doc(hidden)]
+pub mod set_map;
+#[doc(hidden)]
+pub mod vgaspec;
+
+pub use binary_io::{BinaryRecord, ByteReader};
+pub use case_sensitivity::{find_file_in_dir, find_file_recursive, find_files_matching, FileLocator};
+pub use dat_section::{decompress_all, replace_section, section_locations, truncate_sections, DatSection, SectionLocation};
+pub use graphics_set::{read_graphics_set, GraphicsSet};
+pub use log::{Log, Verbosity};
+pub use main_dat::{read_base_sections, MainDatSpec, MainDatVariant};
+pub use outputs::{OverwritePolicy, PendingOutputs};
+pub use parser::{Lexer, ParseError};
+pub use planar_bmp::{load_image, ImageFormat, PaletteRGB, PlanarBMP};
@@ -0,0 +1,166 @@
+/*
+ * modlem: A graphics importer/exporter for Lemmings
+ * Copyright (C) 2022–2026 David Gow <david@davidgow.net>
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! How much modlem says about what it's doing. The modlem command picks the verbosity (with
+//! --quiet and --verbose), and passes a Log to anything which prints, which uses the log_*!
+//! macros rather than println!().
+
+use std::fmt;
+
+/// How much is printed, from least to most. Each level includes everything before it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Only errors (--quiet).
+    Errors,
+    /// Warnings, and a line for each file (or other major thing) read or written.
+    #[default]
+    Progress,
+    /// What's happening along the way, e.g. each frame extracted, or backups made (-v).
+    Details,
+    /// Anything which might help with debugging, e.g. every header in a graphics set (-vv).
+    Debug,
+}
+
+impl Verbosity {
+    /// The verbosity numbered @level: 0 for Errors, up to 3 (or more) for Debug.
+    pub fn from_level(level : usize) -> Verbosity {
+        match level {
+            0 => Verbosity::Errors,
+            1 => Verbosity::Progress,
+            2 => Verbosity::Details,
+            _ => Verbosity::Debug,
+        }
+    }
+
+    pub fn level(self) -> usize {
+        self as usize
+    }
+}
+
+/// Prints messages, if they're wanted at the chosen verbosity. Errors and warnings go to stderr,
+/// and everything else to stdout. It's Copy, so can be stored in options or passed on freely.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Log {
+    verbosity : Verbosity,
+}
+
+impl Log {
+    pub fn new(verbosity : Verbosity) -> Log {
+        Log { verbosity }
+    }
+
+    pub fn verbosity(&self) -> Verbosity {
+        self.verbosity
+    }
+
+    /// Whether messages needing @verbosity are printed.
+    pub fn enabled(&self, verbosity : Verbosity) -> bool {
+        verbosity <= self.verbosity
+    }
+
+    /// The same Log, but printing no more than @verbosity allows.
+    pub fn at_most(&self, verbosity : Verbosity) -> Log {
+        Log { verbosity : self.verbosity.min(verbosity) }
+    }
+
+    /// Something has gone wrong. Use log_error!().
+    pub fn error(&self, message : fmt::Arguments) {
+        eprintln!("{}", message);
+    }
+
+    /// Something looks wrong, but it can carry on. Use log_warning!().
+    pub fn warning(&self, message : fmt::Arguments) {
+        if self.enabled(Verbosity::Progress) {
+            eprintln!("{}", message);
+        }
+    }
+
+    /// A summary of what it's doing, at most a line per file. Use log_info!().
+    pub fn info(&self, message : fmt::Arguments) {
+        if self.enabled(Verbosity::Progress) {
+            println!("{}", message);
+        }
+    }
+
+    /// The details of what it's doing. Use log_detail!().
+    pub fn detail(&self, message : fmt::Arguments) {
+        if self.enabled(Verbosity::Details) {
+            println!("{}", message);
+        }
+    }
+
+    /// Anything else. Use log_debug!().
+    pub fn debug(&self, message : fmt::Arguments) {
+        if self.enabled(Verbosity::Debug) {
+            println!("{}", message);
+        }
+    }
+}
+
+/// Print an error to @log, formatted like println!(), e.g. log_error!(log, "Error: {}", err).
+#[macro_export]
+macro_rules! log_error {
+    ($log:expr, $($arg:tt)*) => { $log.error(format_args!($($arg)*)) };
+}
+
+/// Print a warning to @log, unless it's --quiet.
+#[macro_export]
+macro_rules! log_warning {
+    ($log:expr, $($arg:tt)*) => { $log.warning(format_args!($($arg)*)) };
+}
+
+/// Print a progress message to @log, unless it's --quiet.
+#[macro_export]
+macro_rules! log_info {
+    ($log:expr, $($arg:tt)*) => { $log.info(format_args!($($arg)*)) };
+}
+
+/// Print a message to @log with --verbose.
+#[macro_export]
+macro_rules! log_detail {
+    ($log:expr, $($arg:tt)*) => { $log.detail(format_args!($($arg)*)) };
+}
+
+/// Print a message to @log with --verbose twice.
+#[macro_export]
+macro_rules! log_debug {
+    ($log:expr, $($arg:tt)*) => { $log.debug(format_args!($($arg)*)) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_verbosity() {
+        for level in 0..4 {
+            assert_eq!(Verbosity::from_level(level).level(), level);
+        }
+        assert_eq!(Verbosity::from_level(7), Verbosity::Debug);
+
+        let log = Log::default();
+        assert_eq!(log.verbosity(), Verbosity::Progress);
+        assert!(log.enabled(Verbosity::Errors));
+        assert!(log.enabled(Verbosity::Progress));
+        assert!(!log.enabled(Verbosity::Details));
+
+        let log = Log::new(Verbosity::Debug);
+        assert!(log.enabled(Verbosity::Debug));
+        assert_eq!(log.at_most(Verbosity::Progress).verbosity(), Verbosity::Progress);
+        assert_eq!(Log::new(Verbosity::Errors).at_most(Verbosity::Progress).verbosity(), Verbosity::Errors);
+    }
+}
@@ -15,517 +15,738 @@
  * this program. If not, see <https://www.gnu.org/licenses/>.
  */
 
+
+use binary_io::{open_buffered, read_array, read_bytes};
 use dat_section::DatSection;
-use planar_bmp::PaletteRGB;
-use std::fs::File;
+use log::Log;
+use outputs::PendingOutputs;
+use planar_bmp::{ImageFormat, PaletteRGB};
 use std::io::Write;
-use std::path::Path;
-use {case_sensitivity, planar_bmp};
-
-struct LemmingsAnim {
-    name: &'static str,
-    num_frames: usize,
-    width: usize,
-    height: usize,
-    planes: usize,
-}
-
-static LEMMINGS_ANIMS: &[LemmingsAnim] = &[
-    LemmingsAnim {
-        name: "walk_r",
-        num_frames: 8,
-        width: 16,
-        height: 10,
-        planes: 2,
-    },
-    LemmingsAnim {
-        name: "jump_r",
-        num_frames: 1,
-        width: 16,
-        height: 10,
-        planes: 2,
-    },
-    LemmingsAnim {
-        name: "walk_l",
-        num_frames: 8,
-        width: 16,
-        height: 10,
-        planes: 2,
-    },
-    LemmingsAnim {
-        name: "jump_l",
-        num_frames: 1,
-        width: 16,
-        height: 10,
-        planes: 2,
-    },
-    LemmingsAnim {
-        name: "dig",
-        num_frames: 16,
-        width: 16,
-        height: 14,
-        planes: 3,
-    },
-    LemmingsAnim {
-        name: "climb_r",
-        num_frames: 8,
-        width: 16,
-        height: 12,
-        planes: 2,
-    },
-    LemmingsAnim {
-        name: "climb_l",
-        num_frames: 8,
-        width: 16,
-        height: 12,
-        planes: 2,
-    },
-    LemmingsAnim {
-        name: "drown",
-        num_frames: 16,
-        width: 16,
-        height: 10,
-        planes: 2,
-    },
-    LemmingsAnim {
-        name: "pullup_r",
-        num_frames: 8,
-        width: 16,
-        height: 12,
-        planes: 2,
-    },
-    LemmingsAnim {
-        name: "pullup_l",
-        num_frames: 8,
-        width: 16,
-        height: 12,
-        planes: 2,
-    },
-    LemmingsAnim {
-        name: "build_r",
-        num_frames: 16,
-        width: 16,
-        height: 13,
-        planes: 3,
-    },
-    LemmingsAnim {
-        name: "build_l",
-        num_frames: 16,
-        width: 16,
-        height: 13,
-        planes: 3,
-    },
-    LemmingsAnim {
-        name: "bash_r",
-        num_frames: 32,
-        width: 16,
-        height: 10,
-        planes: 3,
-    },
-    LemmingsAnim {
-        name: "bash_l",
-        num_frames: 32,
-        width: 16,
-        height: 10,
-        planes: 3,
-    },
-    LemmingsAnim {
-        name: "mine_r",
-        num_frames: 24,
-        width: 16,
-        height: 13,
-        planes: 3,
-    },
-    LemmingsAnim {
-        name: "mine_l",
-        num_frames: 24,
-        width: 16,
-        height: 13,
-        planes: 3,
-    },
-    LemmingsAnim {
-        name: "fall_r",
-        num_frames: 4,
-        width: 16,
-        height: 10,
-        planes: 2,
-    },
-    LemmingsAnim {
-        name: "fall_l",
-        num_frames: 4,
-        width: 16,
-        height: 10,
-        planes: 2,
-    },
-    LemmingsAnim {
-        name: "brolly_r",
-        num_frames: 4,
-        width: 16,
-        height: 16,
-        planes: 3,
-    },
-    LemmingsAnim {
-        name: "float_r",
-        num_frames: 4,
-        width: 16,
-        height: 16,
-        planes: 3,
-    },
-    LemmingsAnim {
-        name: "brolly_l",
-        num_frames: 4,
-        width: 16,
-        height: 16,
-        planes: 3,
-    },
-    LemmingsAnim {
-        name: "float_l",
-        num_frames: 4,
-        width: 16,
-        height: 16,
-        planes: 3,
-    },
-    LemmingsAnim {
-        name: "splat",
-        num_frames: 16,
-        width: 16,
-        height: 10,
-        planes: 2,
-    },
-    LemmingsAnim {
-        name: "exit",
-        num_frames: 8,
-        width: 16,
-        height: 13,
-        planes: 2,
-    },
-    LemmingsAnim {
-        name: "fry",
-        num_frames: 14,
-        width: 16,
-        height: 14,
-        planes: 4,
-    },
-    LemmingsAnim {
-        name: "block",
-        num_frames: 16,
-        width: 16,
-        height: 10,
-        planes: 2,
-    },
-    LemmingsAnim {
-        name: "shrug_r",
-        num_frames: 8,
-        width: 16,
-        height: 10,
-        planes: 2,
-    },
-    LemmingsAnim {
-        name: "shrug_l",
-        num_frames: 8,
-        width: 16,
-        height: 10,
-        planes: 2,
-    },
-    LemmingsAnim {
-        name: "ohno",
-        num_frames: 16,
-        width: 16,
-        height: 10,
-        planes: 2,
-    },
-    LemmingsAnim {
-        name: "boom",
-        num_frames: 1,
-        width: 32,
-        height: 32,
-        planes: 3,
-    },
-];
+use std::path::{Path, PathBuf};
+use {atlas, gif, graphics_set, palettes, parser, pcspkr, planar_bmp, schemas};
 
-static LEMMINGS_MASKS: &[LemmingsAnim] = &[
-    LemmingsAnim {
-        name: "bash_r",
-        num_frames: 4,
-        width: 16,
-        height: 10,
-        planes: 1,
-    },
-    LemmingsAnim {
-        name: "bash_l",
-        num_frames: 4,
-        width: 16,
-        height: 10,
-        planes: 1,
-    },
-    LemmingsAnim {
-        name: "mine_r",
-        num_frames: 2,
-        width: 16,
-        height: 13,
-        planes: 1,
-    },
-    LemmingsAnim {
-        name: "mine_l",
-        num_frames: 2,
-        width: 16,
-        height: 13,
-        planes: 1,
-    },
-    LemmingsAnim {
-        name: "bomb",
-        num_frames: 1,
-        width: 16,
-        height: 22,
-        planes: 1,
-    },
-    LemmingsAnim {
-        name: "bomb_font",
-        num_frames: 10,
-        width: 8,
-        height: 8,
-        planes: 1,
-    },
-];
+/// The built-in layouts, in the same format as a user-supplied spec file.
+static LEMMINGS_SPEC: &str = include_str!("../data/main_lemmings.txt");
+static OHNO_SPEC: &str = include_str!("../data/main_ohno.txt");
+static CGA_SPEC: &str = include_str!("../data/main_cga.txt");
 
-static LEMMINGS_INTERFACE_HI: &[LemmingsAnim] = &[
-    LemmingsAnim {
-        name: "skills_hi",
-        num_frames: 1,
-        width: 320,
-        height: 40,
-        planes: 4,
-    },
-    LemmingsAnim {
-        name: "skillcount",
-        num_frames: 20,
-        width: 8,
-        height: 8,
-        planes: 1,
-    },
-    LemmingsAnim {
-        name: "font_hi",
-        num_frames: 37,
-        width: 8,
-        height: 16,
-        planes: 3,
-    },
-];
+/// A single animation (or still image, if it has one frame) within a section.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LemmingsAnim {
+    pub name: String,
+    pub num_frames: usize,
+    pub width: usize,
+    pub height: usize,
+    pub planes: usize,
+    /// For fonts, the character each frame is a glyph for.
+    pub glyphs: Option<Vec<char>>,
+    /// A palette to use instead of the section's, when saving the images.
+    pub palette: Option<SectionPalette>,
+}
 
-static LEMMINGS_MAINMENU: &[LemmingsAnim] = &[
-    LemmingsAnim {
-        name: "background",
-        num_frames: 1,
-        width: 320,
-        height: 104,
-        planes: 2,
-    },
-    LemmingsAnim {
-        name: "logo",
-        num_frames: 1,
-        width: 632,
-        height: 94,
-        planes: 4,
-    },
-    LemmingsAnim {
-        name: "oneplayer",
-        num_frames: 1,
-        width: 120,
-        height: 61,
-        planes: 4,
-    },
-    LemmingsAnim {
-        name: "newgame",
-        num_frames: 1,
-        width: 120,
-        height: 61,
-        planes: 4,
-    },
-    LemmingsAnim {
-        name: "sndbutton",
-        num_frames: 1,
-        width: 120,
-        height: 61,
-        planes: 4,
-    },
-    LemmingsAnim {
-        name: "rating",
-        num_frames: 1,
-        width: 120,
-        height: 61,
-        planes: 4,
-    },
-    LemmingsAnim {
-        name: "exittodos",
-        num_frames: 1,
-        width: 120,
-        height: 61,
-        planes: 4,
-    },
-    LemmingsAnim {
-        name: "controls",
-        num_frames: 1,
-        width: 120,
-        height: 61,
-        planes: 4,
-    },
-    LemmingsAnim {
-        name: "musicon",
-        num_frames: 1,
-        width: 64,
-        height: 31,
-        planes: 4,
-    },
-    LemmingsAnim {
-        name: "sfxicon",
-        num_frames: 1,
-        width: 64,
-        height: 31,
-        planes: 4,
-    },
-];
+impl LemmingsAnim {
+    /// Parse an animation, of the form: name = { frames = n size = (w, h) planes = n }
+    /// Fonts also list their characters, either as a string (glyphs = "0123") or as a range of
+    /// character codes (glyphs = (33, 125)). Animations drawn with different colours to the rest
+    /// of their section can give their own palette (palette = fire).
+    fn parse(name: &str, lex: &mut parser::Lexer) -> Result<LemmingsAnim, parser::ParseError> {
+        let mut res = LemmingsAnim {
+            name: name.to_string(),
+            num_frames: 1,
+            width: 0,
+            height: 0,
+            planes: 0,
+            glyphs: None,
+            palette: None,
+        };
 
-static LEMMINGS_MENUANIM: &[LemmingsAnim] = &[
-    LemmingsAnim {
-        name: "blink1",
-        num_frames: 8,
-        width: 32,
-        height: 12,
-        planes: 4,
-    },
-    LemmingsAnim {
-        name: "blink2",
-        num_frames: 8,
-        width: 32,
-        height: 12,
-        planes: 4,
-    },
-    LemmingsAnim {
-        name: "blink3",
-        num_frames: 8,
-        width: 32,
-        height: 12,
-        planes: 4,
-    },
-    LemmingsAnim {
-        name: "blink4",
-        num_frames: 8,
-        width: 32,
-        height: 12,
-        planes: 4,
-    },
-    LemmingsAnim {
-        name: "blink5",
-        num_frames: 8,
-        width: 32,
-        height: 12,
-        planes: 4,
-    },
-    LemmingsAnim {
-        name: "blink6",
-        num_frames: 8,
-        width: 32,
-        height: 12,
-        planes: 4,
-    },
-    LemmingsAnim {
-        name: "blink7",
-        num_frames: 8,
-        width: 32,
-        height: 12,
-        planes: 4,
-    },
-    LemmingsAnim {
-        name: "scroll_l",
-        num_frames: 16,
-        width: 48,
-        height: 16,
-        planes: 4,
-    },
-    LemmingsAnim {
-        name: "scroll_r",
-        num_frames: 16,
-        width: 48,
-        height: 16,
-        planes: 4,
-    },
-    LemmingsAnim {
-        name: "reel",
-        num_frames: 1,
-        width: 16,
-        height: 16,
-        planes: 4,
-    },
-    LemmingsAnim {
-        name: "difficulty4",
-        num_frames: 1,
-        width: 72,
-        height: 27,
-        planes: 4,
-    },
-    LemmingsAnim {
-        name: "difficulty3",
-        num_frames: 1,
-        width: 72,
-        height: 27,
-        planes: 4,
-    },
-    LemmingsAnim {
-        name: "difficulty2",
-        num_frames: 1,
-        width: 72,
-        height: 27,
-        planes: 4,
-    },
-    LemmingsAnim {
-        name: "difficulty1",
-        num_frames: 1,
-        width: 72,
-        height: 27,
-        planes: 4,
-    },
-    // TODO: Support Oh-no! More Lemmings! here.
-    //    LemmingsAnim { name: "difficulty0", num_frames: 1, width: 72, height: 27, planes: 4},
-    LemmingsAnim {
-        name: "menufont",
-        num_frames: 93,
-        width: 16,
-        height: 16,
-        planes: 3,
-    },
-];
+        lex.expect_symbol('=')?;
+        lex.expect_symbol('{')?;
+        loop {
+            let tok = match lex.next_spanned()? {
+                Some(tok) => tok,
+                None => return Err(lex.error("an animation property or '}'", None)),
+            };
+            match tok.token.borrowed() {
+                parser::Token::Ident(var) => {
+                    lex.expect_symbol('=')?;
+                    match var {
+                        "frames" => {
+                            res.num_frames = lex.get_int_in_range("a number of frames", 1, u16::MAX as i64)? as usize;
+                        }
+                        "size" => {
+                            lex.expect_symbol('(')?;
+                            res.width = lex.get_int_in_range("a width", 8, u16::MAX as i64)? as usize;
+                            if !res.width.is_multiple_of(8) {
+                                return Err(lex.invalid(format!("Animation {} must have a width which is a multiple of 8", name)));
+                            }
+                            lex.expect_symbol(',')?;
+                            res.height = lex.get_int_in_range("a height", 1, u16::MAX as i64)? as usize;
+                            lex.expect_symbol(')')?;
+                        }
+                        "planes" => {
+                            res.planes = lex.get_int_in_range("a number of planes", 1, 8)? as usize;
+                        }
+                        "glyphs" => {
+                            res.glyphs = Some(match lex.next_token()? {
+                                Some(parser::Token::StringLiteral(glyphs)) => glyphs.chars().collect(),
+                                Some(parser::Token::Symbol('(')) => {
+                                    let first = lex.get_int_in_range("a first glyph", 0, char::MAX as i64)? as u32;
+                                    lex.expect_symbol(',')?;
+                                    let last = lex.get_int_in_range("a last glyph", first as i64, char::MAX as i64)? as u32;
+                                    lex.expect_symbol(')')?;
+                                    (first..=last).filter_map(char::from_u32).collect()
+                                }
+                                tok => return Err(lex.error(&format!("glyphs for {}", name), tok.as_ref())),
+                            });
+                        }
+                        "palette" => {
+                            let tok = lex.next_spanned()?;
+                            res.palette = tok.as_ref().and_then(|tok| tok.token.ident()).and_then(SectionPalette::from_name);
+                            if res.palette.is_none() {
+                                return Err(unknown_palette_error(lex, tok, &format!("a palette for animation {}", name)));
+                            }
+                        }
+                        _ => {
+                            return Err(tok.unknown_name_error("an animation property", &["frames", "size", "planes", "glyphs", "palette"]));
+                        }
+                    }
+                }
+                parser::Token::Symbol('}') => {
+                    break;
+                }
+                _ => {
+                    return Err(tok.error(&format!("an animation property or '}}' for {}", name)));
+                }
+            }
+        }
+        if let Some(glyphs) = &res.glyphs {
+            if glyphs.len() != res.num_frames {
+                return Err(lex.invalid(format!(
+                    "Animation {} has {} frames, but {} glyphs",
+                    name,
+                    res.num_frames,
+                    glyphs.len()
+                )));
+            }
+        }
+        if res.width == 0 || res.height == 0 || res.planes == 0 {
+            return Err(lex.invalid(format!(
+                "Animation {} must have a size and a number of planes",
+                name
+            )));
+        }
+        Ok(res)
+    }
+}
 
-static LEMMINGS_INTERFACE_LO: &[LemmingsAnim] = &[
-    LemmingsAnim {
-        name: "skills_lo",
-        num_frames: 1,
-        width: 320,
-        height: 40,
-        planes: 4,
-    },
-    LemmingsAnim {
-        name: "font_lo",
-        num_frames: 37,
-        width: 8,
-        height: 16,
-        planes: 3,
-    },
-];
+/// Which of the hardcoded palettes a section's images use.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SectionPalette {
+    Lemming,
+    HighPerformance,
+    Menu,
+    Cga,
+    Player2,
+    Fire,
+}
+
+/// The names of the palettes in spec and palette files.
+const PALETTE_NAMES: [&str; 6] = ["lemming", "hiperf", "menu", "cga", "player2", "fire"];
+
+/// An error for @tok not being the name of a palette (which is @expected).
+fn unknown_palette_error(
+    lex: &parser::Lexer,
+    tok: Option<parser::SpannedToken>,
+    expected: &str,
+) -> parser::ParseError {
+    match tok {
+        Some(tok) => tok.unknown_name_error(expected, &PALETTE_NAMES),
+        None => lex.error(expected, None),
+    }
+}
+
+impl SectionPalette {
+    /// Look up a palette by the name used in spec and palette files.
+    fn from_name(name: &str) -> Option<SectionPalette> {
+        match name {
+            "lemming" => Some(SectionPalette::Lemming),
+            "hiperf" => Some(SectionPalette::HighPerformance),
+            "menu" => Some(SectionPalette::Menu),
+            "cga" => Some(SectionPalette::Cga),
+            "player2" => Some(SectionPalette::Player2),
+            "fire" => Some(SectionPalette::Fire),
+            _ => None,
+        }
+    }
+}
+
+/// How the pixels of a section's images are stored.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum PixelEncoding {
+    /// Each frame is stored one bit plane after another, as used by EGA and VGA.
+    #[default]
+    Planar,
+    /// Each pixel is stored in 'planes' bits, packed into bytes, as used by CGA.
+    Cga,
+}
+
+impl PixelEncoding {
+    /// Look up an encoding by the name used in spec files.
+    fn from_name(name: &str) -> Option<PixelEncoding> {
+        match name {
+            "planar" => Some(PixelEncoding::Planar),
+            "cga" => Some(PixelEncoding::Cga),
+            _ => None,
+        }
+    }
+
+    /// Decode a single @width×@height image.
+    fn decode(
+        self,
+        data: &[u8],
+        width: usize,
+        height: usize,
+        planes: usize,
+        pal: &PaletteRGB,
+    ) -> planar_bmp::PlanarBMP {
+        match self {
+            PixelEncoding::Planar => {
+                planar_bmp::PlanarBMP::from_contiguous_data(data, width, height, planes, pal)
+            }
+            PixelEncoding::Cga => planar_bmp::PlanarBMP::from_cga_data(data, width, height, planes, pal),
+        }
+    }
+
+    /// Encode the @width×@height image at (0, @y) of @image.
+    fn encode(
+        self,
+        image: &planar_bmp::PlanarBMP,
+        y: usize,
+        width: usize,
+        height: usize,
+        planes: usize,
+    ) -> Vec<u8> {
+        match self {
+            PixelEncoding::Planar => {
+                let mut data = Vec::new();
+                for plane in 0..planes {
+                    data.append(&mut image.get_plane_data(plane, 0, y, width, height));
+                }
+                data
+            }
+            PixelEncoding::Cga => image.get_cga_data(planes, 0, y, width, height),
+        }
+    }
+}
+
+/// The palettes used when extracting main.dat, which doesn't contain any palettes itself.
+#[derive(Clone)]
+pub struct MainDatPalettes {
+    pub lemming: PaletteRGB,
+    pub hiperf: PaletteRGB,
+    pub menu: PaletteRGB,
+    pub cga: PaletteRGB,
+    pub player2: PaletteRGB,
+    pub fire: PaletteRGB,
+}
+
+impl MainDatPalettes {
+    /// The built-in palettes, for either the original or the Christmas versions.
+    pub fn preset(xmas_mode: bool) -> MainDatPalettes {
+        let lemming = if xmas_mode {
+            palettes::XMAS_LEMMING_VGA
+        } else {
+            palettes::LEMMING_VGA
+        };
+        MainDatPalettes {
+            lemming: PaletteRGB::from_vga_data(16, &lemming),
+            hiperf: PaletteRGB::from_vga_data(16, &palettes::HIPERF_VGA),
+            menu: PaletteRGB::from_vga_data(16, &palettes::MENU_VGA),
+            cga: PaletteRGB::from_vga_data(16, &palettes::CGA_VGA),
+            player2: PaletteRGB::from_vga_data(16, &palettes::player2_vga(&lemming)),
+            fire: PaletteRGB::from_vga_data(16, &palettes::FIRE_VGA),
+        }
+    }
+
+    fn get(&self, palette: SectionPalette) -> &PaletteRGB {
+        match palette {
+            SectionPalette::Lemming => &self.lemming,
+            SectionPalette::HighPerformance => &self.hiperf,
+            SectionPalette::Menu => &self.menu,
+            SectionPalette::Cga => &self.cga,
+            SectionPalette::Player2 => &self.player2,
+            SectionPalette::Fire => &self.fire,
+        }
+    }
+
+    /// Replace colours with those from a palette file, which lists any of the palettes by name,
+    /// e.g.: hiperf = { (0, 0, 0), (16, 16, 56), ... }. The colours replace the first entries of
+    /// that palette, and any others are left as they were.
+    ///
+    /// Alternatively, the file can be a graphics set's Palettes = { ... }, whose VGA standard
+    /// colours replace those of the lemming and hi-perf palettes, or a plain list of numbers (three
+    /// per colour), which replace the start of the lemming, hi-perf and menu palettes.
+    pub fn parse_overrides(&mut self, lex: &mut parser::Lexer) -> Result<(), parser::ParseError> {
+        while let Some(tok) = lex.peek_token()? {
+            match tok {
+                parser::Token::NumericLiteral(_) => {
+                    let colours = palettes::parse_vga_list(lex)?;
+                    if colours.len() > 16 * 3 {
+                        return Err(lex.invalid("Too many colours in the palette file (at most 16)".to_string()));
+                    }
+                    for pal in [&mut self.lemming, &mut self.hiperf, &mut self.menu] {
+                        pal.set_vga_data(0, colours.len() / 3, &colours);
+                    }
+                    continue;
+                }
+                parser::Token::Ident("Palettes") | parser::Token::Symbol('{') => {
+                    if tok == parser::Token::Ident("Palettes") {
+                        lex.next_token()?;
+                        lex.expect_symbol('=')?;
+                    }
+                    let set_palettes = graphics_set::Palettes::parse(lex)?;
+                    self.lemming.set_vga_data(0, 8, &set_palettes.vga_standard);
+                    self.hiperf.set_vga_data(0, 8, &set_palettes.vga_standard);
+                    continue;
+                }
+                _ => {}
+            }
+            let tok = lex.next_spanned()?;
+            let palette = match tok.as_ref().and_then(|tok| tok.token.ident()).and_then(SectionPalette::from_name) {
+                Some(palette) => palette,
+                None => return Err(unknown_palette_error(lex, tok, "a palette")),
+            };
+            lex.expect_symbol('=')?;
+            let colours = palettes::parse_vga_colours(lex)?;
+            if colours.len() > 16 * 3 {
+                return Err(lex.invalid(format!("Too many colours for the {:?} palette (at most 16)", palette)));
+            }
+            let pal = match palette {
+                SectionPalette::Lemming => &mut self.lemming,
+                SectionPalette::HighPerformance => &mut self.hiperf,
+                SectionPalette::Menu => &mut self.menu,
+                SectionPalette::Cga => &mut self.cga,
+                SectionPalette::Player2 => &mut self.player2,
+                SectionPalette::Fire => &mut self.fire,
+            };
+            pal.set_vga_data(0, colours.len() / 3, &colours);
+        }
+        Ok(())
+    }
+
+    /// Replace colours with those from a palette file. See parse_overrides().
+    pub fn load_overrides(&mut self, path: &Path) -> std::io::Result<()> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| std::io::Error::new(err.kind(), format!("Error opening {}: {}", path.display(), err)))?;
+        Ok(self.parse_overrides(&mut parser::Lexer::from_str(&text)).map_err(|err| err.in_file(path))?)
+    }
+}
+
+/// An error for a file which couldn't be parsed.
+fn invalid_data(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+/// An error for options which can't be used together, or don't match the layout.
+fn invalid_input(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, message)
+}
+
+/// A single section of main.dat.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SectionSpec {
+    /// A set of images, saved as [name]_[anim].bmp (or .png).
+    Anims {
+        name: String,
+        palette: SectionPalette,
+        encoding: PixelEncoding,
+        anims: Vec<LemmingsAnim>,
+    },
+    /// Data which is copied to and from a file unchanged.
+    Raw { filename: String },
+    /// PC speaker sound effects, saved as [name].txt (or [name].snd, if kept raw).
+    Sound { name: String },
+}
+
+impl SectionSpec {
+    /// The name used to refer to the section.
+    pub fn name(&self) -> &str {
+        match self {
+            SectionSpec::Anims { name, .. } => name,
+            SectionSpec::Raw { filename } => filename,
+            SectionSpec::Sound { name } => name,
+        }
+    }
+
+    /// Whether @name refers to this section. Sound sections can also be called "sounds".
+    pub fn is_named(&self, name: &str) -> bool {
+        self.name() == name || (matches!(self, SectionSpec::Sound { .. }) && name == "sounds")
+    }
+}
+
+/// The layout of a main.dat file: the contents of each section, in order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MainDatSpec {
+    pub sections: Vec<SectionSpec>,
+}
+
+impl MainDatSpec {
+    /// Parse a layout spec, made up of "Section [name] Palette [palette] { ... }",
+    /// "Raw [filename]" and "Sound [name]" commands. Sections can also give their pixel
+    /// encoding, as "Section [name] Palette [palette] Encoding [planar|cga] { ... }".
+    pub fn parse(lex: &mut parser::Lexer) -> Result<MainDatSpec, parser::ParseError> {
+        let mut sections = Vec::new();
+        while let Some(tok) = lex.next_spanned()? {
+            match tok.token.borrowed() {
+                parser::Token::Ident("Section") => {
+                    let name = match lex.next_token()? {
+                        Some(parser::Token::Ident(name)) => name.to_string(),
+                        tok => return Err(lex.error("a section name", tok.as_ref())),
+                    };
+                    lex.expect_ident("Palette")?;
+                    let tok = lex.next_spanned()?;
+                    let palette = match tok.as_ref().and_then(|tok| tok.token.ident()).and_then(SectionPalette::from_name) {
+                        Some(palette) => palette,
+                        None => return Err(unknown_palette_error(lex, tok, &format!("a palette for section {}", name))),
+                    };
+                    let mut encoding = PixelEncoding::default();
+                    if lex.peek_token()? == Some(parser::Token::Ident("Encoding")) {
+                        lex.expect_ident("Encoding")?;
+                        let tok = lex.next_spanned()?;
+                        encoding = match tok.as_ref().and_then(|tok| tok.token.ident()).and_then(PixelEncoding::from_name) {
+                            Some(encoding) => encoding,
+                            None => {
+                                let expected = format!("an encoding for section {}", name);
+                                return Err(match tok {
+                                    Some(tok) => tok.unknown_name_error(&expected, &["planar", "cga"]),
+                                    None => lex.error(&expected, None),
+                                });
+                            }
+                        };
+                    }
+                    lex.expect_symbol('{')?;
+                    let mut anims = Vec::new();
+                    loop {
+                        match lex.next_token()? {
+                            Some(parser::Token::Ident(anim_name)) => {
+                                anims.push(LemmingsAnim::parse(anim_name, lex)?);
+                            }
+                            Some(parser::Token::Symbol('}')) => break,
+                            tok => return Err(lex.error(&format!("an animation or '}}' in section {}", name), tok.as_ref())),
+                        }
+                    }
+                    sections.push(SectionSpec::Anims {
+                        name,
+                        palette,
+                        encoding,
+                        anims,
+                    });
+                }
+                parser::Token::Ident("Raw") => {
+                    let filename = lex.get_string_literal()?;
+                    sections.push(SectionSpec::Raw { filename });
+                }
+                parser::Token::Ident("Sound") => {
+                    let name = match lex.next_token()? {
+                        Some(parser::Token::Ident(name)) => name.to_string(),
+                        tok => return Err(lex.error("a sound section name", tok.as_ref())),
+                    };
+                    sections.push(SectionSpec::Sound { name });
+                }
+                _ => {
+                    return Err(tok.unknown_name_error("Section, Raw or Sound", &["Section", "Raw", "Sound"]));
+                }
+            }
+        }
+        Ok(MainDatSpec { sections })
+    }
+
+    /// Parse a layout spec from a string. (This returns a ParseError, so isn't FromStr.)
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(spec: &str) -> Result<MainDatSpec, parser::ParseError> {
+        MainDatSpec::parse(&mut parser::Lexer::from_str(spec))
+    }
+
+    /// Load a layout spec from a file.
+    pub fn from_file(path: &Path) -> std::io::Result<MainDatSpec> {
+        let spec = std::fs::read_to_string(path)
+            .map_err(|err| std::io::Error::new(err.kind(), format!("Error opening {}: {}", path.display(), err)))?;
+        Ok(MainDatSpec::from_str(&spec).map_err(|err| err.in_file(path))?)
+    }
+
+    /// Whether sections with the given (decompressed) sizes could have been produced by this
+    /// layout. Sections which couldn't be decompressed (None) match anything.
+    fn matches(&self, section_sizes: &[Option<usize>]) -> bool {
+        self.sections.len() == section_sizes.len()
+            && self
+                .sections
+                .iter()
+                .zip(section_sizes)
+                .all(|(spec, size)| match spec {
+                    SectionSpec::Anims { anims, .. } => {
+                        size.is_none_or(|size| anims_data_size(anims) == size)
+                    }
+                    SectionSpec::Raw { .. } | SectionSpec::Sound { .. } => true,
+                })
+    }
+}
+
+/// The versions of the game with differing main.dat layouts.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MainDatVariant {
+    Lemmings,
+    OhNo,
+    Cga,
+}
 
-fn extract_anims(data: &[u8], anims: &[LemmingsAnim], name: &str, pal: &PaletteRGB) {
+impl MainDatVariant {
+    pub const ALL: &'static [MainDatVariant] = &[
+        MainDatVariant::Lemmings,
+        MainDatVariant::OhNo,
+        MainDatVariant::Cga,
+    ];
+
+    /// The built-in layout for this variant.
+    pub fn spec(self) -> MainDatSpec {
+        MainDatSpec::from_str(match self {
+            MainDatVariant::Lemmings => LEMMINGS_SPEC,
+            MainDatVariant::OhNo => OHNO_SPEC,
+            MainDatVariant::Cga => CGA_SPEC,
+        })
+        .expect("The built-in layouts should parse")
+    }
+
+    /// The name the game gives this variant's file.
+    pub fn file_name(self) -> &'static str {
+        match self {
+            MainDatVariant::Cga => "cgamain.dat",
+            _ => "main.dat",
+        }
+    }
+
+    /// Guess the variant from the sizes of the sections.
+    pub fn detect(section_sizes: &[Option<usize>]) -> Option<MainDatVariant> {
+        MainDatVariant::ALL
+            .iter()
+            .find(|v| v.spec().matches(section_sizes))
+            .copied()
+    }
+}
+
+/// The number of bytes a table of animations occupies in its (decompressed) section.
+fn anims_data_size(anims: &[LemmingsAnim]) -> usize {
+    anims
+        .iter()
+        .map(|anim| anim.num_frames * anim.planes * anim.width * anim.height / 8)
+        .sum()
+}
+
+/// Options controlling how main.dat is extracted. Use ExtractOptions::builder() to construct one.
+#[derive(Clone, Default)]
+pub struct ExtractOptions {
+    /// The directory all of the files are written to. Created if it doesn't exist.
+    pub output_dir: PathBuf,
+    /// Added to the start of every filename.
+    pub prefix: String,
+    /// Use the Christmas Lemmings palette.
+    pub xmas_mode: bool,
+    /// The variant whose built-in layout is used. Detected from the section sizes if None.
+    pub variant: Option<MainDatVariant>,
+    /// A layout to use instead of any of the built-in ones.
+    pub spec: Option<MainDatSpec>,
+    /// Save the PC speaker sounds as-is, rather than decoding them to text.
+    pub raw_sounds: bool,
+    /// Also write each lemming animation as an animated GIF.
+    pub anim_gif: bool,
+    /// The format the images are saved in.
+    pub format: ImageFormat,
+    /// Only list the sections and the files which would be written, without writing anything.
+    pub list_only: bool,
+    /// A file of colours which replace those of the built-in palettes.
+    pub palette_file: Option<PathBuf>,
+    /// Write each glyph of the fonts to its own image, named after its character.
+    pub split_glyphs: bool,
+    /// Also write each button of the skill panels to its own image.
+    pub split_panel: bool,
+    /// Where to say what's being extracted: each section at Verbosity::Progress, and each frame
+    /// at Verbosity::Details.
+    pub log: Log,
+    /// Also write the lemming animations with the second player's palette.
+    pub player2_palette: bool,
+    /// Also write every lemming animation to a single atlas image, one animation per row.
+    pub atlas: bool,
+}
+
+impl ExtractOptions {
+    pub fn builder() -> ExtractOptionsBuilder {
+        ExtractOptionsBuilder {
+            options: ExtractOptions::default(),
+        }
+    }
+
+    /// Add an output file to @outputs. With @list_only, the filename is just printed, and
+    /// anything written to it is discarded.
+    fn create_file(&self, outputs: &PendingOutputs, filename: &str) -> Box<dyn Write> {
+        let path = self.output_dir.join(format!("{}{}", self.prefix, filename));
+        if self.list_only {
+            println!("\t{}", path.display());
+            return Box::new(std::io::sink());
+        }
+        Box::new(outputs.create(path))
+    }
+}
+
+/// Builds an ExtractOptions, e.g.:
+/// ExtractOptions::builder().output_dir("out").prefix("ohno_").variant(MainDatVariant::OhNo).build()
+pub struct ExtractOptionsBuilder {
+    options: ExtractOptions,
+}
+
+impl ExtractOptionsBuilder {
+    pub fn output_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.options.output_dir = dir.as_ref().to_path_buf();
+        self
+    }
+
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.options.prefix = prefix.to_string();
+        self
+    }
+
+    pub fn xmas_mode(mut self, xmas_mode: bool) -> Self {
+        self.options.xmas_mode = xmas_mode;
+        self
+    }
+
+    pub fn variant(mut self, variant: MainDatVariant) -> Self {
+        self.options.variant = Some(variant);
+        self
+    }
+
+    pub fn spec(mut self, spec: MainDatSpec) -> Self {
+        self.options.spec = Some(spec);
+        self
+    }
+
+    pub fn raw_sounds(mut self, raw_sounds: bool) -> Self {
+        self.options.raw_sounds = raw_sounds;
+        self
+    }
+
+    pub fn anim_gif(mut self, anim_gif: bool) -> Self {
+        self.options.anim_gif = anim_gif;
+        self
+    }
+
+    pub fn format(mut self, format: ImageFormat) -> Self {
+        self.options.format = format;
+        self
+    }
+
+    pub fn list_only(mut self, list_only: bool) -> Self {
+        self.options.list_only = list_only;
+        self
+    }
+
+    pub fn palette_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.options.palette_file = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn split_glyphs(mut self, split_glyphs: bool) -> Self {
+        self.options.split_glyphs = split_glyphs;
+        self
+    }
+
+    pub fn split_panel(mut self, split_panel: bool) -> Self {
+        self.options.split_panel = split_panel;
+        self
+    }
+
+    pub fn log(mut self, log: Log) -> Self {
+        self.options.log = log;
+        self
+    }
+
+    pub fn player2_palette(mut self, player2_palette: bool) -> Self {
+        self.options.player2_palette = player2_palette;
+        self
+    }
+
+    pub fn atlas(mut self, atlas: bool) -> Self {
+        self.options.atlas = atlas;
+        self
+    }
+
+    pub fn build(self) -> ExtractOptions {
+        self.options
+    }
+}
+
+/// The section containing the lemming animations, which are also written as GIFs with
+/// ExtractOptions::anim_gif, with the second player's palette with player2_palette, and to an
+/// atlas with atlas.
+const LEMMING_SECTION: &str = "lemming";
+
+/// The delay between frames of the animated GIFs, in hundredths of a second. The game runs
+/// its animations at roughly 15 frames per second.
+const ANIM_GIF_DELAY: u16 = 7;
+
+/// Extract a section as a set of bitmaps, one per animation. If the section doesn't match the
+/// table, it's saved as-is to [name].raw instead, so that it can still be recreated.
+#[allow(clippy::too_many_arguments)]
+fn extract_anims(
+    data: &[u8],
+    anims: &[LemmingsAnim],
+    name: &str,
+    palettes: &MainDatPalettes,
+    section_palette: SectionPalette,
+    encoding: PixelEncoding,
+    options: &ExtractOptions,
+    outputs: &PendingOutputs,
+) {
+    if anims_data_size(anims) != data.len() {
+        log_warning!(
+            options.log,
+            "Warning: {} section is {} bytes, expected {}. Saving it as {}.raw",
+            name,
+            data.len(),
+            anims_data_size(anims),
+            name
+        );
+        let mut raw_file = options.create_file(outputs, &format!("{}.raw", name));
+        raw_file.write_all(data).unwrap();
+        return;
+    }
+    let player2_pal = if options.player2_palette && name == LEMMING_SECTION {
+        Some(&palettes.player2)
+    } else {
+        None
+    };
+    let mut anims_atlas = if options.atlas && name == LEMMING_SECTION {
+        Some(atlas::Atlas::new())
+    } else {
+        None
+    };
     let mut running_offset: usize = 0;
     for anim in anims {
-        let outfile_name = format!("{}_{}.bmp", name, anim.name);
+        let pal = palettes.get(anim.palette.unwrap_or(section_palette));
         let mut filmstrip_image = planar_bmp::PlanarBMP::new(
             anim.width,
             anim.height * anim.num_frames,
             anim.planes,
             pal,
-        );
+        )
+        .unwrap();
+        let mut gif_frames = Vec::new();
+        let mut frame_images = Vec::new();
         for frame in 0..anim.num_frames {
-            println!("Extracting {} {} frame #{}", name, anim.name, frame);
+            if !options.list_only {
+                log_detail!(options.log, "Extracting {} {} frame #{}", name, anim.name, frame);
+            }
             let planar_size = anim.width * anim.height / 8;
-            let converted_image = planar_bmp::PlanarBMP::from_contiguous_data(
+            let converted_image = encoding.decode(
                 &data[running_offset..(running_offset + planar_size * anim.planes)],
                 anim.width,
                 anim.height,
@@ -534,165 +755,1440 @@ fn extract_anims(data: &[u8], anims: &[LemmingsAnim], name: &str, pal: &PaletteR
             );
             running_offset += planar_size * anim.planes;
             filmstrip_image.blit(&converted_image, 0, frame * anim.height);
+            gif_frames.push(converted_image.to_pal8_data());
+            frame_images.push(converted_image);
+        }
+        if options.anim_gif && name == LEMMING_SECTION {
+            let mut gif_file = options.create_file(outputs, &format!("{}_{}.gif", name, anim.name));
+            gif::write_animated(
+                &mut gif_file,
+                anim.width,
+                anim.height,
+                &filmstrip_image.rgb_palette(),
+                &gif_frames,
+                ANIM_GIF_DELAY,
+                Some(0),
+            )
+            .unwrap();
         }
-        let out_path = Path::new(outfile_name.as_str());
-        let mut output_file = File::create(out_path).unwrap();
-        filmstrip_image.save_as_file(&mut output_file);
+        if let Some(anims_atlas) = &mut anims_atlas {
+            // Each animation is a row of the atlas, with its frames from left to right.
+            let mut row_image = planar_bmp::PlanarBMP::new(
+                anim.width * anim.num_frames,
+                anim.height,
+                anim.planes,
+                pal,
+            )
+            .unwrap();
+            for (frame, frame_image) in frame_images.iter().enumerate() {
+                row_image.blit(frame_image, frame * anim.width, 0);
+            }
+            let row = anims_atlas.entries.len();
+            let row_mask = opaque_mask(&row_image);
+            anims_atlas.add(&anim.name, row_image, row_mask, schemas::anim_atlas_fields(row, anim));
+        }
+        let stem = format!("{}_{}", name, anim.name);
+        match &anim.glyphs {
+            Some(glyphs) if options.split_glyphs => {
+                for (frame_image, &glyph) in frame_images.iter().zip(glyphs) {
+                    let mut output_file = options.create_file(outputs, &format!(
+                        "{}.{}",
+                        glyph_stem(&stem, glyph),
+                        options.format.extension()
+                    ));
+                    frame_image.save_as_format(options.format, &mut output_file);
+                }
+                continue;
+            }
+            Some(glyphs) => {
+                let mut mapping_file = options.create_file(outputs, &format!("{}.txt", stem));
+                write_glyph_mapping(glyphs, &mut mapping_file).unwrap();
+            }
+            None => {}
+        }
+        if let Some(regions) = panel_regions(anim) {
+            let mut regions_file = options.create_file(outputs, &format!("{}.txt", stem));
+            write_panel_regions(regions, &mut regions_file).unwrap();
+            if options.split_panel {
+                for region in regions {
+                    let piece = filmstrip_image.crop(region.x, region.y, region.width, region.height);
+                    let mut output_file = options.create_file(outputs, &format!(
+                        "{}_{}.{}",
+                        stem,
+                        region.name,
+                        options.format.extension()
+                    ));
+                    piece.save_as_format(options.format, &mut output_file);
+                }
+            }
+        }
+        let mut output_file =
+            options.create_file(outputs, &format!("{}.{}", stem, options.format.extension()));
+        filmstrip_image.save_as_format(options.format, &mut output_file);
+        if let Some(player2_pal) = player2_pal {
+            filmstrip_image.set_palette(player2_pal);
+            let mut output_file =
+                options.create_file(outputs, &format!("{}_p2.{}", stem, options.format.extension()));
+            filmstrip_image.save_as_format(options.format, &mut output_file);
+        }
+    }
+    if let Some(mut anims_atlas) = anims_atlas {
+        anims_atlas.pack_rows();
+        let planes = anims.iter().map(|anim| anim.planes).max().unwrap_or(1);
+        let (atlas_image, atlas_mask) = anims_atlas.render(planes, palettes.get(section_palette));
+        let image_filename = format!("{}_atlas.{}", name, options.format.extension());
+        let mask_filename = format!("{}_atlas_mask.{}", name, options.format.extension());
+        atlas_image.save_as_format(options.format, &mut options.create_file(outputs, &image_filename));
+        atlas_mask.save_as_format(options.format, &mut options.create_file(outputs, &mask_filename));
+        let mut json_file = options.create_file(outputs, &format!("{}_atlas.json", name));
+        schemas::atlas(
+            &anims_atlas,
+            &format!("{}{}", options.prefix, image_filename),
+            &format!("{}{}", options.prefix, mask_filename),
+        )
+        .write(&mut json_file)
+        .unwrap();
     }
 }
 
-pub fn extract_main_dat(image: &mut dyn std::io::Read, xmas_mode: bool) {
-    let pal = if xmas_mode {
-        planar_bmp::PaletteRGB::from_vga_data(
-            16,
-            [
-                0, 0, 0, 52, 8, 8, 0, 44, 0, 60, 52, 52, 60, 60, 0, 16, 16, 60, 32, 32, 32, 0, 0,
-                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-            ]
-            .as_slice(),
-        )
+/// A 1-plane mask of the pixels of @image which aren't transparent (colour 0).
+fn opaque_mask(image: &planar_bmp::PlanarBMP) -> planar_bmp::PlanarBMP {
+    let mut mask =
+        planar_bmp::PlanarBMP::new(image.width, image.height, 1, &PaletteRGB::new(2)).unwrap();
+    for y in 0..image.height {
+        for x in 0..image.width {
+            if image.get_packed_pixel(x, y) != 0 {
+                mask.pset(x, y, 1);
+            }
+        }
+    }
+    mask
+}
+
+/// A named rectangle within an image, e.g. one of the buttons on the skill panel.
+pub struct PanelRegion {
+    pub name: &'static str,
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// The images which are laid out as a skill panel.
+const SKILL_PANEL_ANIMS: &[&str] = &["skills_hi", "skills_lo"];
+
+/// The layout of the skill panel. The top 16 rows are left blank for the status line, and the
+/// buttons are below it, followed by the minimap.
+pub const SKILL_PANEL_REGIONS: &[PanelRegion] = &[
+    PanelRegion { name: "release_minus", x: 0, y: 16, width: 16, height: 24 },
+    PanelRegion { name: "release_plus", x: 16, y: 16, width: 16, height: 24 },
+    PanelRegion { name: "climber", x: 32, y: 16, width: 16, height: 24 },
+    PanelRegion { name: "floater", x: 48, y: 16, width: 16, height: 24 },
+    PanelRegion { name: "bomber", x: 64, y: 16, width: 16, height: 24 },
+    PanelRegion { name: "blocker", x: 80, y: 16, width: 16, height: 24 },
+    PanelRegion { name: "builder", x: 96, y: 16, width: 16, height: 24 },
+    PanelRegion { name: "basher", x: 112, y: 16, width: 16, height: 24 },
+    PanelRegion { name: "miner", x: 128, y: 16, width: 16, height: 24 },
+    PanelRegion { name: "digger", x: 144, y: 16, width: 16, height: 24 },
+    PanelRegion { name: "pause", x: 160, y: 16, width: 16, height: 24 },
+    PanelRegion { name: "nuke", x: 176, y: 16, width: 16, height: 24 },
+    PanelRegion { name: "minimap", x: 208, y: 18, width: 104, height: 20 },
+];
+
+/// The regions of an image, if it's a skill panel which fits the layout.
+fn panel_regions(anim: &LemmingsAnim) -> Option<&'static [PanelRegion]> {
+    if SKILL_PANEL_ANIMS.contains(&anim.name.as_str())
+        && anim.num_frames == 1
+        && anim.width == 320
+        && anim.height == 40
+    {
+        Some(SKILL_PANEL_REGIONS)
     } else {
-        planar_bmp::PaletteRGB::from_vga_data(
-            16,
-            [
-                0, 0, 0, 16, 16, 56, 0, 44, 0, 60, 58, 58, 44, 44, 0, 60, 8, 8, 32, 32, 32, 0, 0,
-                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-            ]
-            .as_slice(),
+        None
+    }
+}
+
+/// Write the list of regions of a panel.
+fn write_panel_regions(regions: &[PanelRegion], writer: &mut dyn Write) -> std::io::Result<()> {
+    writeln!(writer, "# name\tx\ty\twidth\theight")?;
+    for region in regions {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}",
+            region.name, region.x, region.y, region.width, region.height
+        )?;
+    }
+    Ok(())
+}
+
+/// Names for the punctuation in the fonts, as most of it can't be used in a filename.
+const GLYPH_SYMBOL_NAMES: &[(char, &str)] = &[
+    ('!', "exclamation"),
+    ('"', "quote"),
+    ('#', "hash"),
+    ('$', "dollar"),
+    ('%', "percent"),
+    ('&', "ampersand"),
+    ('\'', "apostrophe"),
+    ('(', "lparen"),
+    (')', "rparen"),
+    ('*', "asterisk"),
+    ('+', "plus"),
+    (',', "comma"),
+    ('-', "minus"),
+    ('.', "period"),
+    ('/', "slash"),
+    (':', "colon"),
+    (';', "semicolon"),
+    ('<', "less"),
+    ('=', "equals"),
+    ('>', "greater"),
+    ('?', "question"),
+    ('@', "at"),
+    ('[', "lbracket"),
+    ('\\', "backslash"),
+    (']', "rbracket"),
+    ('^', "caret"),
+    ('_', "underscore"),
+    ('`', "backtick"),
+    ('{', "lbrace"),
+    ('|', "bar"),
+    ('}', "rbrace"),
+    ('~', "tilde"),
+];
+
+/// The name used for a glyph in filenames. Lower-case letters get a prefix, so that they don't
+/// clash with upper-case ones on case-insensitive filesystems.
+fn glyph_file_name(glyph: char) -> String {
+    if glyph.is_ascii_digit() || glyph.is_ascii_uppercase() {
+        glyph.to_string()
+    } else if glyph.is_ascii_lowercase() {
+        format!("lower_{}", glyph)
+    } else {
+        match GLYPH_SYMBOL_NAMES.iter().find(|(symbol, _)| *symbol == glyph) {
+            Some((_, name)) => name.to_string(),
+            None => format!("u{:04x}", glyph as u32),
+        }
+    }
+}
+
+/// The filename (without extension) of a single glyph of a font.
+fn glyph_stem(stem: &str, glyph: char) -> String {
+    format!("{}_{}", stem, glyph_file_name(glyph))
+}
+
+/// Write the list of which frame of a font is which character.
+fn write_glyph_mapping(glyphs: &[char], writer: &mut dyn Write) -> std::io::Result<()> {
+    writeln!(writer, "# frame\tcharacter\tname (with --split-glyphs)")?;
+    for (frame, &glyph) in glyphs.iter().enumerate() {
+        writeln!(writer, "{}\t{}\t{}", frame, glyph, glyph_file_name(glyph))?;
+    }
+    Ok(())
+}
+
+/// A section of main.dat, as read by extract_main_dat().
+struct ReadSection {
+    /// The section exactly as it was stored, compressed, including its header.
+    raw: Vec<u8>,
+    /// The size the header claims the section decompresses to.
+    uncomp_size: usize,
+    /// The decompressed section, if it could be decompressed.
+    data: std::io::Result<Vec<u8>>,
+}
+
+/// Format a number of bytes with thousands separators, e.g. 23,456.
+fn format_size(bytes: usize) -> String {
+    let digits = bytes.to_string();
+    let mut formatted = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+    formatted
+}
+
+/// Describe how well a section of @size bytes fits its table of animations, flagging any
+/// leftover (or missing) bytes.
+fn describe_anims_fit(anims: &[LemmingsAnim], size: usize) -> String {
+    let expected = anims_data_size(anims);
+    let summary = format!(
+        "{} image{} expected, accounting for {} bytes",
+        anims.len(),
+        if anims.len() == 1 { "" } else { "s" },
+        format_size(expected)
+    );
+    if size > expected {
+        format!(
+            "{}: {} bytes left over (wrong variant?)",
+            summary,
+            format_size(size - expected)
+        )
+    } else if size < expected {
+        format!(
+            "{}: {} bytes missing (wrong variant?)",
+            summary,
+            format_size(expected - size)
         )
+    } else {
+        summary
+    }
+}
+
+/// Extract main.dat. If no layout spec is given in @options, the built-in layout for the chosen
+/// variant is used, and if there isn't one of those either, the variant is detected from the
+/// section sizes. Anything which doesn't fit the layout is saved as-is with a warning, rather
+/// than giving up: sections which can't be decompressed, or which aren't in the layout at all,
+/// are saved to section_[n].bin. The files are added to @outputs, rather than written straight
+/// away. Returns the layout which was used, or an error if the palette file can't be read.
+pub fn extract_main_dat(
+    image: &mut dyn std::io::Read,
+    options: &ExtractOptions,
+    outputs: &PendingOutputs,
+) -> std::io::Result<MainDatSpec> {
+    let mut image_data = Vec::new();
+    if let Err(err) = image.read_to_end(&mut image_data) {
+        log_error!(options.log, "Error reading main.dat: {}", err);
+    }
+
+    let mut sections = Vec::new();
+    let mut read_error = None;
+    let mut remaining = image_data.as_slice();
+    while !remaining.is_empty() {
+        let start = remaining;
+        match DatSection::from_file(&mut remaining) {
+            Err(err) => {
+                read_error = Some(err);
+                break;
+            }
+            Ok(mut section) => sections.push(ReadSection {
+                raw: start[..(start.len() - remaining.len())].to_vec(),
+                uncomp_size: section.uncompressed_size(),
+                data: section.decompress(),
+            }),
+        }
+    }
+
+    let section_sizes: Vec<Option<usize>> = sections
+        .iter()
+        .map(|section| section.data.as_ref().ok().map(|data| data.len()))
+        .collect();
+    let variant = match (&options.spec, options.variant) {
+        (None, None) => Some(
+            MainDatVariant::detect(&section_sizes).unwrap_or_else(|| {
+                log_warning!(options.log, "Warning: main.dat doesn't match the layout of any known variant");
+                MainDatVariant::Lemmings
+            }),
+        ),
+        _ => options.variant,
+    };
+    let (spec, layout_name) = match &options.spec {
+        Some(spec) => (spec.clone(), "the given spec".to_string()),
+        None => {
+            log_info!(options.log, "Extracting main.dat for {:?}", variant.unwrap());
+            (variant.unwrap().spec(), format!("{:?}", variant.unwrap()))
+        }
     };
 
-    //NOTE: Christmas lemmings doesn't seem to have a valid palette for these at all?
-    // Or, at least, I couldn't get the High Performance mode to launch for them.
-    let hiperf_pal = planar_bmp::PaletteRGB::from_vga_data(
-        16,
-        [
-            0, 0, 0, 16, 16, 56, 0, 44, 0, 60, 58, 58, 44, 44, 0, 60, 8, 8, 32, 32, 32, 0, 0, 0, 0,
-            42, 0, 21, 63, 21, 21, 21, 21, 42, 0, 0, 42, 21, 0, 0, 42, 42, 63, 21, 63, 42, 0, 42,
-        ]
-        .as_slice(),
-    );
-    let menupal = planar_bmp::PaletteRGB::from_vga_data(
-        16,
-        [
-            0, 0, 0, 32, 16, 8, 24, 12, 8, 12, 0, 4, //8,  0,  4,
-            8, 2, 31, 16, 11, 36, 26, 22, 41, 38, 35, 47, 0, 20, 0, 0, 24, 4, 0, 28, 8, 0, 32, 16,
-            52, 52, 52, 44, 44, 0, 16, 20, 44, 56, 32, 36,
-        ]
-        .as_slice(),
-    );
+    if let Some(err) = read_error {
+        log_error!(
+            options.log,
+            "Error: couldn't read section {} ({}) of main.dat: {}. {} sections were expected for {}, so only the first {} will be extracted",
+            sections.len(),
+            spec.sections.get(sections.len()).map_or("not in the layout", |section| section.name()),
+            err,
+            spec.sections.len(),
+            layout_name,
+            sections.len()
+        );
+    } else if sections.len() != spec.sections.len() {
+        log_warning!(
+            options.log,
+            "Warning: main.dat has {} sections, expected {} for {}",
+            sections.len(),
+            spec.sections.len(),
+            layout_name
+        );
+    }
 
-    let mut lemming_anim_section = DatSection::from_file(image).unwrap();
-    let lemming_anim_data = lemming_anim_section.decompress();
+    let mut palettes = MainDatPalettes::preset(options.xmas_mode);
+    if let Some(palette_file) = &options.palette_file {
+        palettes.load_overrides(palette_file)?;
+    }
 
-    extract_anims(&lemming_anim_data, LEMMINGS_ANIMS, "lemming", &pal);
+    for (section_num, section) in sections.iter().enumerate() {
+        let section_spec = spec.sections.get(section_num);
+        let summary = format!(
+            "Section {} ({}): {} bytes compressed → {} bytes",
+            section_num,
+            section_spec.map_or("not in the layout", |section_spec| section_spec.name()),
+            format_size(section.raw.len()),
+            format_size(section.uncomp_size)
+        );
+        // Listing the sections is the whole point of --list, so it isn't silenced by --quiet.
+        if options.list_only {
+            println!("{}", summary);
+        } else {
+            log_info!(options.log, "{}", summary);
+        }
+        if options.list_only {
+            if let (Some(SectionSpec::Anims { anims, .. }), Ok(data)) = (section_spec, &section.data) {
+                println!("\t{}", describe_anims_fit(anims, data.len()));
+            }
+        }
+        let (section_spec, data) = match (section_spec, &section.data) {
+            (_, Err(err)) => {
+                log_warning!(
+                    options.log,
+                    "Warning: couldn't decompress section {} ({}). Saving it as-is to section_{}.bin",
+                    section_num, err, section_num
+                );
+                let mut output_file = options.create_file(outputs, &format!("section_{}.bin", section_num));
+                output_file.write_all(&section.raw).unwrap();
+                continue;
+            }
+            (None, Ok(data)) => {
+                log_info!(options.log, "Saving extra section {} as section_{}.bin", section_num, section_num);
+                let mut output_file = options.create_file(outputs, &format!("section_{}.bin", section_num));
+                output_file.write_all(data).unwrap();
+                continue;
+            }
+            (Some(section_spec), Ok(data)) => (section_spec, data),
+        };
+        match section_spec {
+            SectionSpec::Anims {
+                name,
+                palette,
+                encoding,
+                anims,
+            } => {
+                extract_anims(data, anims, name, &palettes, *palette, *encoding, options, outputs);
+            }
+            SectionSpec::Raw { filename } => {
+                let mut output_file = options.create_file(outputs, filename);
+                output_file.write_all(data).unwrap();
+            }
+            SectionSpec::Sound { name } => {
+                let sounds = if options.raw_sounds {
+                    None
+                } else {
+                    pcspkr::decode(data)
+                };
+                match sounds {
+                    Some(sounds) => {
+                        let mut output_file = options.create_file(outputs, &format!("{}.txt", name));
+                        pcspkr::write_text(&sounds, &mut output_file).unwrap();
+                    }
+                    None => {
+                        if !options.raw_sounds {
+                            log_warning!(
+                                options.log,
+                                "Warning: couldn't decode the {} sound section. Saving it as {}.snd",
+                                name, name
+                            );
+                        }
+                        let mut output_file = options.create_file(outputs, &format!("{}.snd", name));
+                        output_file.write_all(data).unwrap();
+                    }
+                }
+            }
+        }
+    }
+    Ok(spec)
+}
 
-    let mut lemming_mask_section = DatSection::from_file(image).unwrap();
-    let lemming_mask_data = lemming_mask_section.decompress();
-    extract_anims(&lemming_mask_data, LEMMINGS_MASKS, "mask", &pal);
+/// Describe where @offset falls within a section made up of @anims, e.g. "walk_r frame 3".
+fn describe_anims_offset(anims: &[LemmingsAnim], offset: usize) -> Option<String> {
+    let mut anim_start = 0;
+    for anim in anims {
+        let frame_size = anim.planes * anim.width * anim.height / 8;
+        let anim_end = anim_start + anim.num_frames * frame_size;
+        if offset < anim_end {
+            return Some(format!("{} frame {}", anim.name, (offset - anim_start) / frame_size));
+        }
+        anim_start = anim_end;
+    }
+    None
+}
 
-    let mut lemming_interface_hi_section = DatSection::from_file(image).unwrap();
-    let lemming_interface_hi_data = lemming_interface_hi_section.decompress();
-    extract_anims(
-        &lemming_interface_hi_data,
-        LEMMINGS_INTERFACE_HI,
-        "interface_hi",
-        &hiperf_pal,
-    );
+/// The most differing bytes reported for each section by compare_main_dat().
+const MAX_REPORTED_DIFFERENCES: usize = 8;
 
-    let mut lemming_mainmenu_section = DatSection::from_file(image).unwrap();
-    let lemming_mainmenu_data = lemming_mainmenu_section.decompress();
-    extract_anims(&lemming_mainmenu_data, LEMMINGS_MAINMENU, "menu", &menupal);
-
-    let mut lemming_menuanim_section = DatSection::from_file(image).unwrap();
-    let lemming_menuanim_data = lemming_menuanim_section.decompress();
-    extract_anims(
-        &lemming_menuanim_data,
-        LEMMINGS_MENUANIM,
-        "menuanim",
-        &menupal,
-    );
+/// Compare two main.dat files section by section, after decompressing them (as the compressed
+/// data won't necessarily be identical), and describe any differences to @log. Returns the
+/// number of differing bytes, counting any missing from either file.
+pub fn compare_main_dat(
+    original: &[u8],
+    rebuilt: &[u8],
+    spec: &MainDatSpec,
+    log: &Log,
+) -> std::io::Result<usize> {
+    let original = read_base_sections(&mut &original[..])?;
+    let rebuilt = read_base_sections(&mut &rebuilt[..])?;
+    let mut total_differences = 0;
+    if original.len() != rebuilt.len() {
+        log_info!(
+            log,
+            "The original has {} sections, but the rebuilt file has {}",
+            original.len(),
+            rebuilt.len()
+        );
+    }
+    for section_num in 0..original.len().max(rebuilt.len()) {
+        let section_spec = spec.sections.get(section_num);
+        let section_name = section_spec.map_or("not in the layout", |section| section.name());
+        let (original, rebuilt) = match (original.get(section_num), rebuilt.get(section_num)) {
+            (Some(original), Some(rebuilt)) => (&original.data, &rebuilt.data),
+            (Some(section), None) | (None, Some(section)) => {
+                log_info!(
+                    log,
+                    "Section {} ({}): only in the {} file",
+                    section_num,
+                    section_name,
+                    if original.len() > rebuilt.len() { "original" } else { "rebuilt" }
+                );
+                total_differences += section.data.len();
+                continue;
+            }
+            (None, None) => unreachable!(),
+        };
+        let differences: Vec<usize> = original
+            .iter()
+            .zip(rebuilt)
+            .enumerate()
+            .filter(|(_, (a, b))| a != b)
+            .map(|(offset, _)| offset)
+            .collect();
+        let size_difference = original.len().abs_diff(rebuilt.len());
+        if differences.is_empty() && size_difference == 0 {
+            continue;
+        }
+        log_info!(
+            log,
+            "Section {} ({}): {} differing bytes",
+            section_num,
+            section_name,
+            format_size(differences.len())
+        );
+        if size_difference != 0 {
+            log_info!(
+                log,
+                "\tThe original is {} bytes, but the rebuilt section is {} bytes",
+                format_size(original.len()),
+                format_size(rebuilt.len())
+            );
+        }
+        for &offset in differences.iter().take(MAX_REPORTED_DIFFERENCES) {
+            let location = match section_spec {
+                Some(SectionSpec::Anims { anims, .. }) => describe_anims_offset(anims, offset),
+                _ => None,
+            };
+            log_info!(
+                log,
+                "\tOffset {:#x}{}: {:#04x} → {:#04x}",
+                offset,
+                location.map_or(String::new(), |location| format!(" ({})", location)),
+                original[offset],
+                rebuilt[offset]
+            );
+        }
+        if differences.len() > MAX_REPORTED_DIFFERENCES {
+            log_info!(log, "\t… and {} more", differences.len() - MAX_REPORTED_DIFFERENCES);
+        }
+        total_differences += differences.len() + size_difference;
+    }
+    Ok(total_differences)
+}
+
+/// A section of an existing main.dat, used to fill in anything which hasn't been edited.
+pub struct BaseSection {
+    /// The section exactly as it was stored, compressed, including its header.
+    raw: Vec<u8>,
+    /// The decompressed section.
+    data: Vec<u8>,
+}
 
-    let mut pcspk_sound_section = DatSection::from_file(image).unwrap();
-    let pcspk_sound_data = pcspk_sound_section.decompress();
+/// Read every section of an existing main.dat, for use as CreateOptions::base.
+pub fn read_base_sections(reader: &mut dyn std::io::Read) -> std::io::Result<Vec<BaseSection>> {
+    let mut sections = Vec::new();
+    loop {
+        let mut raw = match read_array::<10>(reader) {
+            Err(ref err) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            res => res?.to_vec(),
+        };
+        let comp_size = u32::from_be_bytes([raw[6], raw[7], raw[8], raw[9]]) as usize;
+        if comp_size < 10 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "invalid section size",
+            ));
+        }
+        raw.append(&mut read_bytes(reader, comp_size - 10)?);
+        let data = DatSection::from_file(&mut raw.as_slice())?.decompress()?;
+        sections.push(BaseSection { raw, data });
+    }
+    Ok(sections)
+}
 
-    let mut pcspk_output_file = File::create("pcspkr.snd").unwrap();
-    pcspk_output_file
-        .write_all(pcspk_sound_data.as_slice())
-        .unwrap();
+/// Options controlling how main.dat is created. Use CreateOptions::builder() to construct one.
+#[derive(Default)]
+pub struct CreateOptions {
+    /// The directory the bitmaps (and other files) are read from.
+    pub input_dir: PathBuf,
+    /// Added to the start of every filename.
+    pub prefix: String,
+    /// Read the PC speaker sounds from the raw .snd file, even if there's a text version.
+    pub raw_sounds: bool,
+    /// The sections of an existing main.dat, used for any files which are missing.
+    pub base: Option<Vec<BaseSection>>,
+    /// If set, only these sections are rebuilt, and the rest are copied from the base main.dat.
+    pub only: Option<Vec<String>>,
+    /// Which image to use when there's both a .bmp and a .png. Defaults to the .bmp.
+    pub format: Option<ImageFormat>,
+    /// The palettes the images were extracted with. If given, the colours of each image are
+    /// checked against them, and any which have just moved to a different index are remapped.
+    pub palettes: Option<MainDatPalettes>,
+    /// Where to say what's being read, and anything wrong with it.
+    pub log: Log,
+}
 
-    let mut interface_lo_section = DatSection::from_file(image).unwrap();
-    let interface_lo_data = interface_lo_section.decompress();
-    extract_anims(
-        &interface_lo_data,
-        LEMMINGS_INTERFACE_LO,
-        "interface_lo",
-        &pal,
-    );
+impl CreateOptions {
+    pub fn builder() -> CreateOptionsBuilder {
+        CreateOptionsBuilder {
+            options: CreateOptions::default(),
+        }
+    }
+
+    /// Get the path of an input file.
+    fn input_path(&self, filename: &str) -> PathBuf {
+        self.input_dir.join(format!("{}{}", self.prefix, filename))
+    }
+
+    /// Read an input file.
+    fn read_file(&self, filename: &str) -> std::io::Result<Vec<u8>> {
+        let path = self.input_path(filename);
+        std::fs::read(&path).map_err(|err| std::io::Error::new(err.kind(), format!("Error opening {}: {}", path.display(), err)))
+    }
+
+    /// Find the image for an animation, which may be either a .bmp or a .png. If both exist,
+    /// the one in the chosen format is used.
+    fn find_image(&self, stem: &str) -> Option<PathBuf> {
+        let found: Vec<ImageFormat> = ImageFormat::ALL
+            .iter()
+            .copied()
+            .filter(|format| self.input_path(&format!("{}.{}", stem, format.extension())).exists())
+            .collect();
+        let format = match found.as_slice() {
+            [] => return None,
+            [format] => *format,
+            _ => {
+                let format = self.format.unwrap_or_default();
+                log_warning!(
+                    self.log,
+                    "Warning: both {}{}.bmp and {}{}.png exist, using the .{} (use --format to choose)",
+                    self.prefix,
+                    stem,
+                    self.prefix,
+                    stem,
+                    format.extension()
+                );
+                format
+            }
+        };
+        Some(self.input_path(&format!("{}.{}", stem, format.extension())))
+    }
+
+    /// Whether there's an image (in any format) with the given filename, minus its extension.
+    fn image_exists(&self, stem: &str) -> bool {
+        ImageFormat::ALL
+            .iter()
+            .any(|format| self.input_path(&format!("{}.{}", stem, format.extension())).exists())
+    }
+
+    /// Whether a font has been split into one image per glyph.
+    fn has_glyph_images(&self, stem: &str, anim: &LemmingsAnim) -> bool {
+        anim.glyphs.as_ref().is_some_and(|glyphs| {
+            glyphs
+                .iter()
+                .any(|&glyph| self.image_exists(&glyph_stem(stem, glyph)))
+        })
+    }
+
+    /// Whether any of the buttons of a skill panel have their own image.
+    fn has_panel_pieces(&self, stem: &str, anim: &LemmingsAnim) -> bool {
+        panel_regions(anim).is_some_and(|regions| {
+            regions
+                .iter()
+                .any(|region| self.image_exists(&format!("{}_{}", stem, region.name)))
+        })
+    }
+
+    /// Whether any of the files a section is built from exist.
+    fn has_section_files(&self, section_spec: &SectionSpec) -> bool {
+        match section_spec {
+            SectionSpec::Anims { name, anims, .. } => {
+                self.input_path(&format!("{}.raw", name)).exists()
+                    || anims.iter().any(|anim| {
+                        let stem = format!("{}_{}", name, anim.name);
+                        self.image_exists(&stem)
+                            || self.has_glyph_images(&stem, anim)
+                            || self.has_panel_pieces(&stem, anim)
+                    })
+            }
+            SectionSpec::Raw { filename } => self.input_path(filename).exists(),
+            SectionSpec::Sound { name } => {
+                self.input_path(&format!("{}.txt", name)).exists()
+                    || self.input_path(&format!("{}.snd", name)).exists()
+            }
+        }
+    }
+}
+
+/// Builds a CreateOptions, e.g.:
+/// CreateOptions::builder().input_dir("edited").base(sections).build()
+pub struct CreateOptionsBuilder {
+    options: CreateOptions,
 }
 
-fn compress_anims(anims: &[LemmingsAnim], name: &str) -> DatSection {
+impl CreateOptionsBuilder {
+    pub fn input_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.options.input_dir = dir.as_ref().to_path_buf();
+        self
+    }
+
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.options.prefix = prefix.to_string();
+        self
+    }
+
+    pub fn raw_sounds(mut self, raw_sounds: bool) -> Self {
+        self.options.raw_sounds = raw_sounds;
+        self
+    }
+
+    pub fn base(mut self, base: Vec<BaseSection>) -> Self {
+        self.options.base = Some(base);
+        self
+    }
+
+    pub fn format(mut self, format: ImageFormat) -> Self {
+        self.options.format = Some(format);
+        self
+    }
+
+    pub fn palettes(mut self, palettes: MainDatPalettes) -> Self {
+        self.options.palettes = Some(palettes);
+        self
+    }
+
+    pub fn log(mut self, log: Log) -> Self {
+        self.options.log = log;
+        self
+    }
+
+    /// Only rebuild the named section. Can be used more than once.
+    pub fn only(mut self, section_name: &str) -> Self {
+        self.options
+            .only
+            .get_or_insert_with(Vec::new)
+            .push(section_name.to_string());
+        self
+    }
+
+    pub fn build(self) -> CreateOptions {
+        self.options
+    }
+}
+
+/// Check that a filmstrip bitmap matches its entry in the layout: one frame after another, each
+/// the size given in the table, using only the colours which fit in its planes.
+fn check_filmstrip(image: &planar_bmp::PlanarBMP, anim: &LemmingsAnim, filename: &str) -> Result<(), String> {
+    let expected_height = anim.height * anim.num_frames;
+    if image.width != anim.width || image.height != expected_height {
+        return Err(format!(
+            "{} is {}x{}, but should be {}x{} ({} frame{} of {}x{}, one above the other)",
+            filename,
+            image.width,
+            image.height,
+            anim.width,
+            expected_height,
+            anim.num_frames,
+            if anim.num_frames == 1 { "" } else { "s" },
+            anim.width,
+            anim.height
+        ));
+    }
+    let num_colours = 1 << anim.planes;
+    let pixels = image.to_pal8_data();
+    if let Some(pos) = pixels.iter().position(|&pixel| pixel as usize >= num_colours) {
+        return Err(format!(
+            "{} uses colour {} at ({}, {}), but only colours 0 to {} are available",
+            filename,
+            pixels[pos],
+            pos % image.width,
+            pos / image.width,
+            num_colours - 1
+        ));
+    }
+    Ok(())
+}
+
+/// Compress a set of bitmaps back into a section. If [name].raw exists (because the section
+/// couldn't be decoded when extracting), it's used instead. Any missing bitmaps are taken from
+/// @base, if given.
+fn compress_anims(
+    anims: &[LemmingsAnim],
+    name: &str,
+    section_palette: SectionPalette,
+    encoding: PixelEncoding,
+    options: &CreateOptions,
+    base: Option<&BaseSection>,
+) -> std::io::Result<DatSection> {
+    let raw_path = options.input_path(&format!("{}.raw", name));
+    if raw_path.exists() {
+        log_info!(options.log, "Using raw section data from {}", raw_path.display());
+        let raw_data = options.read_file(&format!("{}.raw", name))?;
+        return Ok(DatSection::from_data(raw_data.as_slice(), raw_data.len()));
+    }
+    if let Some(base) = base {
+        if base.data.len() != anims_data_size(anims) {
+            return Err(invalid_data(format!(
+                "The {} section of the base main.dat is {} bytes, expected {}",
+                name,
+                base.data.len(),
+                anims_data_size(anims)
+            )));
+        }
+    }
     let mut data = std::vec::Vec::<u8>::new();
     for anim in anims {
-        let infile_name = format!("{}_{}.bmp", name, anim.name);
-        let infile_path = Path::new(infile_name.as_str());
-        let mut infile = match File::open(infile_path) {
-            Err(err) => panic!("Error opening {}: {}", infile_name, err),
-            Ok(file) => file,
-        };
-        let mut running_h = 0;
-        let filmstrip_image = planar_bmp::PlanarBMP::from_file(&mut infile).unwrap();
-        for _ in 0..anim.num_frames {
-            for plane in 0..anim.planes {
-                data.append(&mut filmstrip_image.get_plane_data(
-                    plane,
-                    0,
-                    running_h,
+        let anim_size = anim.num_frames * anim.planes * anim.width * anim.height / 8;
+        let stem = format!("{}_{}", name, anim.name);
+        let filmstrip_path = options.find_image(&stem);
+        let pal = options
+            .palettes
+            .as_ref()
+            .map(|palettes| palettes.get(anim.palette.unwrap_or(section_palette)));
+        if filmstrip_path.is_none() && options.has_glyph_images(&stem, anim) {
+            // Reassemble a font from its separate glyphs.
+            let frame_size = anim_size / anim.num_frames;
+            let anim_start = data.len();
+            let glyph_anim = LemmingsAnim {
+                num_frames: 1,
+                glyphs: None,
+                ..anim.clone()
+            };
+            for (frame, &glyph) in anim.glyphs.as_ref().unwrap().iter().enumerate() {
+                match (options.find_image(&glyph_stem(&stem, glyph)), base) {
+                    (Some(path), _) => data.append(&mut filmstrip_data(
+                        &load_filmstrip(&path, &glyph_anim, pal, &options.log)?,
+                        &glyph_anim,
+                        encoding,
+                    )),
+                    (None, Some(base)) => {
+                        log_detail!(options.log, "Using {} {} '{}' from the base main.dat", name, anim.name, glyph);
+                        let frame_start = anim_start + frame * frame_size;
+                        data.extend_from_slice(&base.data[frame_start..(frame_start + frame_size)]);
+                    }
+                    (None, None) => {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::NotFound,
+                            format!(
+                                "Missing {}.bmp (the '{}' glyph of {} {})",
+                                options.input_path(&glyph_stem(&stem, glyph)).display(),
+                                glyph,
+                                name,
+                                anim.name
+                            ),
+                        ))
+                    }
+                }
+            }
+            continue;
+        }
+        if options.has_panel_pieces(&stem, anim) {
+            // Start from the whole panel, and paste any separate buttons over the top.
+            let mut panel = match (&filmstrip_path, base) {
+                (Some(path), _) => load_filmstrip(path, anim, pal, &options.log)?,
+                (None, Some(base)) => encoding.decode(
+                    &base.data[data.len()..(data.len() + anim_size)],
                     anim.width,
                     anim.height,
-                ));
+                    anim.planes,
+                    &PaletteRGB::new(1 << anim.planes),
+                ),
+                (None, None) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        format!(
+                            "Rebuilding {} from its pieces requires {}.bmp (or a base main.dat)",
+                            stem,
+                            options.input_path(&stem).display()
+                        ),
+                    ))
+                }
+            };
+            for region in panel_regions(anim).unwrap() {
+                let piece_path = match options.find_image(&format!("{}_{}", stem, region.name)) {
+                    Some(path) => path,
+                    None => continue,
+                };
+                let piece_anim = LemmingsAnim {
+                    name: region.name.to_string(),
+                    num_frames: 1,
+                    width: region.width,
+                    height: region.height,
+                    planes: anim.planes,
+                    glyphs: None,
+                    palette: None,
+                };
+                let piece = load_filmstrip(&piece_path, &piece_anim, pal, &options.log)?;
+                for y in 0..region.height {
+                    for x in 0..region.width {
+                        panel.pset(region.x + x, region.y + y, piece.get_packed_pixel(x, y));
+                    }
+                }
             }
-            running_h += anim.height;
+            data.append(&mut filmstrip_data(&panel, anim, encoding));
+            continue;
         }
+        let infile_path = match (filmstrip_path, base) {
+            (Some(path), _) => path,
+            (None, Some(base)) => {
+                log_detail!(options.log, "Using {} {} from the base main.dat", name, anim.name);
+                data.extend_from_slice(&base.data[data.len()..(data.len() + anim_size)]);
+                continue;
+            }
+            (None, None) => options.input_path(&format!("{}_{}.bmp", name, anim.name)),
+        };
+        data.append(&mut filmstrip_data(
+            &load_filmstrip(&infile_path, anim, pal, &options.log)?,
+            anim,
+            encoding,
+        ));
     }
-    DatSection::from_data(data.as_slice(), data.len())
+    Ok(DatSection::from_data(data.as_slice(), data.len()))
 }
 
-pub fn create_main_dat() {
-    let dat_path = match case_sensitivity::find_file_in_current_dir("main.dat") {
-        Ok(path) => path,
-        _ => Path::new("main.dat").to_path_buf(),
-    };
+/// Read a filmstrip image, and check it against its entry in the layout. If @pal is given, the
+/// image's colours are matched against it (see match_palette()), with any warnings going to @log.
+fn load_filmstrip(
+    path: &Path,
+    anim: &LemmingsAnim,
+    pal: Option<&PaletteRGB>,
+    log: &Log,
+) -> std::io::Result<planar_bmp::PlanarBMP> {
+    let mut infile = open_buffered(path)
+        .map_err(|err| std::io::Error::new(err.kind(), format!("Error opening {}: {}", path.display(), err)))?;
+    let mut filmstrip_image = planar_bmp::load_image(&mut infile)
+        .map_err(|err| std::io::Error::new(err.kind(), format!("Error reading {}: {}", path.display(), err)))?;
+    if let Some(pal) = pal {
+        match_palette(&mut filmstrip_image, pal, 1 << anim.planes, &path.display().to_string(), log);
+    }
+    check_filmstrip(&filmstrip_image, anim, &path.display().to_string()).map_err(invalid_data)?;
+    Ok(filmstrip_image)
+}
 
-    let mut data = match File::create(&dat_path) {
-        Err(err) => panic!("Error opening main.dat: {}", err),
-        Ok(file) => file,
-    };
+/// Check the colours used by @image against @pal, the palette it was extracted with, of which
+/// the first @num_colours are available. Colours which have just moved to a different index
+/// (e.g. after an editor has reordered the palette) are remapped, and any others are used as
+/// they are, with a warning. Colours which appear more than once in either palette (often
+/// black) are never remapped, as there's no telling which index was meant.
+fn match_palette(image: &mut planar_bmp::PlanarBMP, pal: &PaletteRGB, num_colours: usize, filename: &str, log: &Log) {
+    let colours = image.rgb_palette();
+    let expected = pal.rgb_colours();
+    let available = &expected[..num_colours.min(expected.len()).min(colours.len())];
+    let mut used = vec![false; colours.len()];
+    for y in 0..image.height {
+        for x in 0..image.width {
+            used[image.get_packed_pixel(x, y) as usize] = true;
+        }
+    }
+    let mut remap: Vec<u8> = (0..colours.len()).map(|i| i as u8).collect();
+    let mut remapped = false;
+    let mut mismatched = Vec::new();
+    for (i, &colour) in colours.iter().enumerate() {
+        if !used[i] || expected.get(i) == Some(&colour) {
+            continue;
+        }
+        let unique_in_image = colours.iter().filter(|&&c| c == colour).count() == 1;
+        let matches: Vec<usize> = (0..available.len()).filter(|&j| available[j] == colour).collect();
+        match matches.as_slice() {
+            &[j] if unique_in_image => {
+                remap[i] = j as u8;
+                remapped = true;
+            }
+            _ => mismatched.push(i),
+        }
+    }
+    if let Some(&first) = mismatched.first() {
+        log_warning!(
+            log,
+            "Warning: the colours of {} don't match its palette (colour {} is {:?}, expected {:?}{}). Using them as they are",
+            filename,
+            first,
+            colours[first],
+            expected.get(first).copied().unwrap_or((0, 0, 0)),
+            match mismatched.len() {
+                1 => String::new(),
+                n => format!(", and {} more", n - 1),
+            }
+        );
+    }
+    if remapped {
+        log_detail!(log, "Remapping the colours of {} to match its palette", filename);
+        for y in 0..image.height {
+            for x in 0..image.width {
+                let pixel = image.get_packed_pixel(x, y);
+                image.pset(x, y, remap[pixel as usize]);
+            }
+        }
+    }
+}
+
+/// Convert a filmstrip image back to the section's data, one frame after another.
+fn filmstrip_data(
+    filmstrip_image: &planar_bmp::PlanarBMP,
+    anim: &LemmingsAnim,
+    encoding: PixelEncoding,
+) -> Vec<u8> {
+    let mut data = Vec::new();
+    for frame in 0..anim.num_frames {
+        data.append(&mut encoding.encode(
+            filmstrip_image,
+            frame * anim.height,
+            anim.width,
+            anim.height,
+            anim.planes,
+        ));
+    }
+    data
+}
+
+/// Create main.dat using the layout in @spec, writing the result to @writer. Sounds are read from
+/// their text form, unless raw_sounds is set or there isn't one, in which case the raw .snd file
+/// is used. With a base main.dat, sections with no files at all (or which aren't listed in
+/// options.only) are copied from it unchanged.
+pub fn create_main_dat(
+    writer: &mut dyn std::io::Write,
+    spec: &MainDatSpec,
+    options: &CreateOptions,
+) -> std::io::Result<()> {
+    if let Some(only) = &options.only {
+        if options.base.is_none() {
+            return Err(invalid_input("Rebuilding only some sections requires a base main.dat".to_string()));
+        }
+        for name in only {
+            if !spec.sections.iter().any(|section| section.is_named(name)) {
+                let names: Vec<&str> = spec.sections.iter().map(|section| section.name()).collect();
+                return Err(invalid_input(format!(
+                    "Unknown section \"{}\" (expected one of: {})",
+                    name,
+                    names.join(", ")
+                )));
+            }
+        }
+    }
 
-    compress_anims(LEMMINGS_ANIMS, "lemming")
-        .write(&mut data)
+    for (i, section_spec) in spec.sections.iter().enumerate() {
+        let base = options.base.as_ref().and_then(|base| base.get(i));
+        let selected = match &options.only {
+            Some(only) => only.iter().any(|name| section_spec.is_named(name)),
+            None => true,
+        };
+        if !selected && base.is_none() {
+            return Err(invalid_data(format!(
+                "The base main.dat has no {} section to copy",
+                section_spec.name()
+            )));
+        }
+        if let Some(base) = base {
+            if !selected || !options.has_section_files(section_spec) {
+                log_detail!(
+                    options.log,
+                    "Copying the {} section from the base main.dat",
+                    section_spec.name()
+                );
+                writer.write_all(&base.raw)?;
+                continue;
+            }
+        }
+        let section = match section_spec {
+            SectionSpec::Anims {
+                name,
+                palette,
+                encoding,
+                anims,
+            } => compress_anims(anims, name, *palette, *encoding, options, base)?,
+            SectionSpec::Raw { filename } => {
+                let raw_data = options.read_file(filename)?;
+                DatSection::from_data(raw_data.as_slice(), raw_data.len())
+            }
+            SectionSpec::Sound { name } => {
+                let text_path = options.input_path(&format!("{}.txt", name));
+                let sound_data = match std::fs::read_to_string(&text_path) {
+                    Ok(text) if !options.raw_sounds => pcspkr::encode(
+                        &pcspkr::parse_text(&text)
+                            .map_err(|err| invalid_data(format!("{}: {}", text_path.display(), err)))?,
+                    ),
+                    _ => options.read_file(&format!("{}.snd", name))?,
+                };
+                DatSection::from_data(sound_data.as_slice(), sound_data.len())
+            }
+        };
+        log_detail!(
+            options.log,
+            "Compressed the {} section from {} bytes to {} bytes",
+            section_spec.name(),
+            format_size(section.uncompressed_size()),
+            format_size(section.compressed_size())
+        );
+        section.write(writer)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Verbosity;
+    use outputs::OverwritePolicy;
+    use std::fs::File;
+
+    #[test]
+    fn main_dat_spec_parse() {
+        let spec = MainDatSpec::from_str(
+            "Section lemming Palette lemming\n{\n\twalk_r = { frames = 8 size = (16, 10) planes = 2 }\n}\nRaw \"pcspkr.snd\"\n",
+        )
         .unwrap();
-    compress_anims(LEMMINGS_MASKS, "mask")
-        .write(&mut data)
+        assert_eq!(spec.sections.len(), 2);
+        match &spec.sections[0] {
+            SectionSpec::Anims {
+                name,
+                palette,
+                encoding,
+                anims,
+            } => {
+                assert_eq!(name, "lemming");
+                assert_eq!(*palette, SectionPalette::Lemming);
+                assert_eq!(*encoding, PixelEncoding::Planar);
+                assert_eq!(anims_data_size(anims), 8 * 2 * 16 * 10 / 8);
+            }
+            _ => panic!("Expected an animation section"),
+        }
+        assert_eq!(
+            spec.sections[1],
+            SectionSpec::Raw {
+                filename: "pcspkr.snd".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn main_dat_cga_encoding() {
+        let spec = MainDatSpec::from_str(
+            "Section lemming Palette cga Encoding cga\n{\n\twalk_r = { frames = 8 size = (16, 10) planes = 2 }\n}\n",
+        )
         .unwrap();
-    compress_anims(LEMMINGS_INTERFACE_HI, "interface_hi")
-        .write(&mut data)
+        assert!(matches!(
+            &spec.sections[0],
+            SectionSpec::Anims {
+                palette: SectionPalette::Cga,
+                encoding: PixelEncoding::Cga,
+                ..
+            }
+        ));
+
+        let data = [0x1b, 0xe4];
+        let pal = PaletteRGB::from_vga_data(4, &palettes::CGA_VGA);
+        let image = PixelEncoding::Cga.decode(&data, 4, 2, 2, &pal);
+        let pixels: Vec<u8> = (0..8).map(|i| image.get_packed_pixel(i % 4, i / 4)).collect();
+        assert_eq!(pixels, vec![0, 1, 2, 3, 3, 2, 1, 0]);
+        assert_eq!(PixelEncoding::Cga.encode(&image, 0, 4, 2, 2), data.to_vec());
+    }
+
+    #[test]
+    fn main_dat_spec_glyphs() {
+        let spec = MainDatSpec::from_str(
+            "Section menuanim Palette menu\n{\n\tfont = { frames = 3 size = (8, 8) planes = 1 glyphs = (33, 35) }\n\tdigits = { frames = 2 size = (8, 8) planes = 1 glyphs = \"0A\" }\n}\n",
+        )
         .unwrap();
-    compress_anims(LEMMINGS_MAINMENU, "menu")
-        .write(&mut data)
+        match &spec.sections[0] {
+            SectionSpec::Anims { anims, .. } => {
+                assert_eq!(anims[0].glyphs, Some(vec!['!', '"', '#']));
+                assert_eq!(anims[1].glyphs, Some(vec!['0', 'A']));
+            }
+            _ => panic!("Expected an animation section"),
+        }
+        assert_eq!(glyph_file_name('A'), "A");
+        assert_eq!(glyph_file_name('a'), "lower_a");
+        assert_eq!(glyph_file_name('/'), "slash");
+    }
+
+    #[test]
+    fn main_dat_spec_anim_ranges() {
+        let parse_anim = |anim: &str| {
+            MainDatSpec::from_str(&format!("Section lemming Palette lemming\n{{\n\ta = {{ {} }}\n}}\n", anim))
+                .err()
+                .map(|err| err.to_string())
+        };
+        assert_eq!(parse_anim("frames = 1 size = (8, 1) planes = 8 glyphs = (65, 65)"), None);
+        let errors = [
+            ("frames = -1 size = (16, 10) planes = 2", "a number of frames from 1 to 65535"),
+            ("frames = 0 size = (16, 10) planes = 2", "a number of frames from 1 to 65535"),
+            ("size = (0, 10) planes = 2", "a width from 8 to 65535"),
+            ("size = (12, 10) planes = 2", "a multiple of 8"),
+            ("size = (16, 0) planes = 2", "a height from 1 to 65535"),
+            ("size = (16, 10) planes = 9", "a number of planes from 1 to 8"),
+            ("size = (16, 10) planes = 0", "a number of planes from 1 to 8"),
+            ("frames = 1 size = (8, 8) planes = 1 glyphs = (-1, 65)", "a first glyph from 0 to 1114111"),
+            ("frames = 1 size = (8, 8) planes = 1 glyphs = (66, 65)", "a last glyph from 66 to 1114111"),
+            ("frames = 1 planes = 2", "must have a size and a number of planes"),
+            ("size = (16, 10)", "must have a size and a number of planes"),
+        ];
+        for (anim, error) in errors.iter() {
+            let message = parse_anim(anim).unwrap_or_default();
+            assert!(message.contains(error), "{}: {}", anim, message);
+        }
+    }
+
+    #[test]
+    fn main_dat_builtin_specs() {
+        for variant in MainDatVariant::ALL {
+            assert_eq!(variant.spec().sections.len(), 7);
+        }
+        let menuanim_size = |variant: MainDatVariant| match &variant.spec().sections[4] {
+            SectionSpec::Anims { anims, .. } => anims_data_size(anims),
+            _ => panic!("Expected an animation section"),
+        };
+        match &MainDatVariant::Lemmings.spec().sections[0] {
+            SectionSpec::Anims { anims, .. } => {
+                let fry = anims.iter().find(|anim| anim.name == "fry").unwrap();
+                assert_eq!(fry.palette, Some(SectionPalette::Fire));
+            }
+            _ => panic!("Expected an animation section"),
+        }
+        let banner_size = 72 * 27 * 4 / 8;
+        assert_eq!(
+            menuanim_size(MainDatVariant::OhNo),
+            menuanim_size(MainDatVariant::Lemmings) + banner_size
+        );
+    }
+
+    #[test]
+    fn main_dat_create_round_trip() {
+        let in_dir = std::env::temp_dir().join(format!("modlem_main_dat_{}", std::process::id()));
+        std::fs::create_dir_all(&in_dir).unwrap();
+
+        let spec = MainDatSpec::from_str(
+            "Section test Palette menu\n{\n\tanim = { frames = 2 size = (16, 4) planes = 2 }\n}\nRaw \"test.snd\"\n",
+        )
         .unwrap();
-    compress_anims(LEMMINGS_MENUANIM, "menuanim")
-        .write(&mut data)
+        let pal = PaletteRGB::from_vga_data(4, &[0, 0, 0, 63, 0, 0, 0, 63, 0, 0, 0, 63]);
+
+        // Two frames of 16x4 pixels, each stored as two planes of 8 bytes.
+        let anim_data: Vec<u8> = (0..32).map(|i| (i * 37) as u8).collect();
+        let mut filmstrip = planar_bmp::PlanarBMP::new(16, 8, 2, &pal).unwrap();
+        for (frame, frame_data) in anim_data.chunks(16).enumerate() {
+            let frame_image =
+                planar_bmp::PlanarBMP::from_contiguous_data(frame_data, 16, 4, 2, &pal);
+            filmstrip.blit(&frame_image, 0, frame * 4);
+        }
+        filmstrip.save_as_file(&mut File::create(in_dir.join("test_anim.bmp")).unwrap());
+        let raw_data = vec![1, 2, 3, 4, 5];
+        std::fs::write(in_dir.join("test.snd"), &raw_data).unwrap();
+
+        let mut main_dat = Vec::<u8>::new();
+        let options = CreateOptions::builder().input_dir(&in_dir).build();
+        create_main_dat(&mut main_dat, &spec, &options).unwrap();
+
+        // Rebuilding with the first file missing should take it from the base main.dat instead.
+        std::fs::remove_file(in_dir.join("test_anim.bmp")).unwrap();
+        let base = read_base_sections(&mut main_dat.as_slice()).unwrap();
+        let options = CreateOptions::builder().input_dir(&in_dir).base(base).build();
+        let mut patched_main_dat = Vec::<u8>::new();
+        create_main_dat(&mut patched_main_dat, &spec, &options).unwrap();
+        std::fs::remove_dir_all(&in_dir).unwrap();
+        assert_eq!(patched_main_dat, main_dat);
+
+        let mut reader = main_dat.as_slice();
+        assert_eq!(DatSection::from_file(&mut reader).unwrap().decompress().unwrap(), anim_data);
+        assert_eq!(DatSection::from_file(&mut reader).unwrap().decompress().unwrap(), raw_data);
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn main_dat_unknown_layout_round_trip() {
+        // A section which doesn't match the layout (as Holiday Lemmings' menu animations don't)
+        // is kept as-is, so the file is still rebuilt unchanged.
+        let dir = std::env::temp_dir().join(format!("modlem_main_dat_layout_{}", std::process::id()));
+        let spec = MainDatSpec::from_str(
+            "Section menuanim Palette menu\n{\n\tbanner = { frames = 1 size = (16, 4) planes = 2 }\n}\n",
+        )
         .unwrap();
+        let section_data: Vec<u8> = (0..48).map(|i| (i * 29) as u8).collect();
+        let mut original = Vec::new();
+        DatSection::from_data(&section_data, section_data.len()).write(&mut original).unwrap();
+
+        let options = ExtractOptions::builder().output_dir(&dir).spec(spec).build();
+        let outputs = PendingOutputs::new();
+        let spec = extract_main_dat(&mut original.as_slice(), &options, &outputs).unwrap();
+        assert_eq!(outputs.paths(), vec![dir.join("menuanim.raw")]);
+        outputs.write_all(OverwritePolicy::Force, &Log::default()).unwrap();
 
-    let pcspk_input_file = Path::new("pcspkr.snd");
-    let pcspk_sound_data = std::fs::read(pcspk_input_file).unwrap();
+        let mut rebuilt = Vec::new();
+        create_main_dat(&mut rebuilt, &spec, &CreateOptions::builder().input_dir(&dir).build()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(compare_main_dat(&original, &rebuilt, &spec, &Log::default()).unwrap(), 0);
+    }
 
-    let pcspk_sound_section =
-        DatSection::from_data(pcspk_sound_data.as_slice(), pcspk_sound_data.len());
-    pcspk_sound_section.write(&mut data).unwrap();
+    #[test]
+    fn main_dat_format_size() {
+        assert_eq!(format_size(0), "0");
+        assert_eq!(format_size(999), "999");
+        assert_eq!(format_size(23456), "23,456");
+        assert_eq!(format_size(1234567), "1,234,567");
+    }
+
+    #[test]
+    fn main_dat_describe_anims_fit() {
+        let spec = MainDatSpec::from_str(
+            "Section test Palette menu\n{\n\tanim = { frames = 2 size = (16, 4) planes = 2 }\n}\n",
+        )
+        .unwrap();
+        let anims = match &spec.sections[0] {
+            SectionSpec::Anims { anims, .. } => anims,
+            _ => panic!("Expected an animation section"),
+        };
+        assert_eq!(describe_anims_fit(anims, 32), "1 image expected, accounting for 32 bytes");
+        assert_eq!(
+            describe_anims_fit(anims, 1032),
+            "1 image expected, accounting for 32 bytes: 1,000 bytes left over (wrong variant?)"
+        );
+        assert!(describe_anims_fit(anims, 30).ends_with(": 2 bytes missing (wrong variant?)"));
+    }
 
-    compress_anims(LEMMINGS_INTERFACE_LO, "interface_lo")
-        .write(&mut data)
+    #[test]
+    fn main_dat_describe_anims_offset() {
+        let spec = MainDatSpec::from_str(
+            "Section test Palette menu\n{\n\ta = { frames = 2 size = (16, 4) planes = 2 }\n\tb = { frames = 1 size = (8, 2) planes = 1 }\n}\n",
+        )
         .unwrap();
+        let anims = match &spec.sections[0] {
+            SectionSpec::Anims { anims, .. } => anims,
+            _ => panic!("Expected an animation section"),
+        };
+        assert_eq!(describe_anims_offset(anims, 0).unwrap(), "a frame 0");
+        assert_eq!(describe_anims_offset(anims, 31).unwrap(), "a frame 1");
+        assert_eq!(describe_anims_offset(anims, 32).unwrap(), "b frame 0");
+        assert_eq!(describe_anims_offset(anims, 34), None);
+    }
+
+    #[test]
+    fn main_dat_compare() {
+        let spec = MainDatSpec::from_str("Raw \"a.bin\"\nRaw \"b.bin\"\n").unwrap();
+        let build = |sections: &[&[u8]]| {
+            let mut data = Vec::new();
+            for section in sections {
+                DatSection::from_data(section, section.len()).write(&mut data).unwrap();
+            }
+            data
+        };
+        let log = Log::new(Verbosity::Errors);
+        let original = build(&[b"Lemmings", b"Oh No!"]);
+        assert_eq!(compare_main_dat(&original, &original, &spec, &log).unwrap(), 0);
+        let changed = build(&[b"Lemmings", b"Oh Yes!"]);
+        assert_eq!(compare_main_dat(&original, &changed, &spec, &log).unwrap(), 4);
+        let truncated = build(&[b"Lemmings"]);
+        assert_eq!(compare_main_dat(&original, &truncated, &spec, &log).unwrap(), 6);
+    }
+
+    #[test]
+    fn main_dat_palette_overrides() {
+        let first_colour = |pal: &PaletteRGB| {
+            planar_bmp::PlanarBMP::new(1, 1, 4, pal).unwrap().rgb_palette()[0]
+        };
+        let mut palettes = MainDatPalettes::preset(false);
+        palettes.parse_overrides(&mut parser::Lexer::from_str("menu = { (1, 2, 3) }")).unwrap();
+        assert_eq!(first_colour(&palettes.menu), (4, 8, 12));
+        assert_eq!(first_colour(&palettes.lemming), (0, 0, 0));
+
+        palettes.parse_overrides(&mut parser::Lexer::from_str("4 5 6\n7 8 9\n")).unwrap();
+        assert_eq!(first_colour(&palettes.lemming), (16, 20, 24));
+        assert_eq!(first_colour(&palettes.menu), (16, 20, 24));
+
+        let mut set_palettes = String::from("Palettes = {");
+        for name in ["vga_custom", "vga_standard", "vga_preview"] {
+            set_palettes += &format!(" {} = {{ (10, 11, 12){} }}", name, ", (0, 0, 0)".repeat(7));
+        }
+        palettes.parse_overrides(&mut parser::Lexer::from_str(&(set_palettes + " }"))).unwrap();
+        assert_eq!(first_colour(&palettes.hiperf), (40, 44, 48));
+        assert_eq!(first_colour(&palettes.menu), (16, 20, 24));
+    }
+
+    #[test]
+    fn main_dat_match_palette() {
+        let pal = PaletteRGB::from_vga_data(4, &[0, 0, 0, 63, 0, 0, 0, 63, 0, 0, 0, 63]);
+        let swapped = PaletteRGB::from_vga_data(4, &[0, 0, 0, 0, 63, 0, 63, 0, 0, 0, 0, 63]);
+        let mut image = planar_bmp::PlanarBMP::new(4, 1, 2, &swapped).unwrap();
+        for x in 0..4 {
+            image.pset(x, 0, x as u8);
+        }
+        match_palette(&mut image, &pal, 4, "test", &Log::default());
+        let pixels: Vec<u8> = (0..4).map(|x| image.get_packed_pixel(x, 0)).collect();
+        assert_eq!(pixels, vec![0, 2, 1, 3]);
+
+        // Black appears twice, so can't be remapped.
+        let ambiguous = PaletteRGB::from_vga_data(4, &[0, 0, 0, 0, 0, 0, 0, 63, 0, 0, 0, 63]);
+        let mut image = planar_bmp::PlanarBMP::new(4, 1, 2, &ambiguous).unwrap();
+        image.pset(0, 0, 1);
+        match_palette(&mut image, &pal, 4, "test", &Log::default());
+        assert_eq!(image.get_packed_pixel(0, 0), 1);
+    }
+
+    #[test]
+    fn main_dat_panel_regions() {
+        for (i, region) in SKILL_PANEL_REGIONS.iter().enumerate() {
+            assert!(region.x + region.width <= 320);
+            assert!(region.y + region.height <= 40);
+            for other in &SKILL_PANEL_REGIONS[(i + 1)..] {
+                assert_ne!(region.name, other.name);
+                assert!(
+                    region.x + region.width <= other.x || other.x + other.width <= region.x,
+                    "{} overlaps {}",
+                    region.name,
+                    other.name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn main_dat_check_filmstrip() {
+        let pal = PaletteRGB::from_vga_data(4, &[0, 0, 0, 63, 0, 0, 0, 63, 0, 0, 0, 63]);
+        let anim = LemmingsAnim {
+            name: "anim".to_string(),
+            num_frames: 2,
+            width: 16,
+            height: 4,
+            planes: 1,
+            glyphs: None,
+            palette: None,
+        };
+        let mut image = planar_bmp::PlanarBMP::new(16, 8, 2, &pal).unwrap();
+        assert_eq!(check_filmstrip(&image, &anim, "anim.bmp"), Ok(()));
+
+        let wrong_size = planar_bmp::PlanarBMP::new(16, 4, 2, &pal).unwrap();
+        assert_eq!(
+            check_filmstrip(&wrong_size, &anim, "anim.bmp"),
+            Err("anim.bmp is 16x4, but should be 16x8 (2 frames of 16x4, one above the other)".to_string())
+        );
+
+        image.pset(3, 5, 2);
+        assert_eq!(
+            check_filmstrip(&image, &anim, "anim.bmp"),
+            Err("anim.bmp uses colour 2 at (3, 5), but only colours 0 to 1 are available".to_string())
+        );
+    }
 }
@@ -15,122 +15,1081 @@
  * this program. If not, see <https://www.gnu.org/licenses/>.
  */
 
-pub use std::{convert::TryInto, fs::File, io::Write, path::Path};
+// ParseErrors carry the line they're on for their messages, so are on the large side, but
+// they're only made once per script, and stop the parsing.
+#![allow(clippy::result_large_err)]
 
-mod binary_io;
-mod case_sensitivity;
-mod dat_section;
-mod graphics_set;
-mod main_dat;
-mod parser;
-mod planar_bmp;
+pub use std::{convert::TryInto, io::Write, path::Path, rc::Rc};
+
+#[macro_use]
+extern crate modlem;
+
+mod batch;
+mod commands;
+mod errors;
+
+use modlem::{backup, binary_io, case_sensitivity, dat_diff, dat_section, file_info, graphics_set, level, main_dat, nxlv, oddtable, parser, pcspkr, planar_bmp, schemas, set_map, vgaspec};
 
 use dat_section::DatSection;
+use errors::{Context, Error, Result};
 use graphics_set::ExtractOptions;
+use modlem::{Log, OverwritePolicy, PendingOutputs, Verbosity};
+use main_dat::{MainDatSpec, MainDatVariant};
+use planar_bmp::ImageFormat;
+use set_map::{SetFiles, SetMap};
 
-fn cmd_extract_graphics_set(graphics_set: usize) {
-    let terrain_filenames = format!("set{}_terrain#.bmp", graphics_set);
-    let terrain_mask_filenames = format!("set{}_terrain#_mask.bmp", graphics_set);
-    let object_filenames = format!("set{}_obj#.bmp", graphics_set);
-    let object_mask_filenames = format!("set{}_obj#_mask.bmp", graphics_set);
-    let options = ExtractOptions {
-        terrain_filename_pattern: &terrain_filenames,
-        terrain_mask_filename_pattern: Some(&terrain_mask_filenames),
-        object_filename_pattern: &object_filenames,
-        object_mask_filename_pattern: Some(&object_mask_filenames),
-        ega_mode: false,
-    };
+/// How many directories below --data-dir the game's files are looked for, unless --max-depth or
+/// --recursive say otherwise. This is enough for a CD image's LEMMINGS/DATA.
+const DEFAULT_DATA_DIR_DEPTH: usize = 2;
 
-    let script_filename = format!("theme{}.txt", graphics_set);
-    let script_file = std::fs::File::create(script_filename).unwrap();
-    let mut script_writer = std::io::BufWriter::new(&script_file);
+/// The environment variables which can give the data directory, if --data-dir isn't given, in
+/// the order they're checked.
+const DATA_DIR_VARIABLES: [&str; 2] = ["LEMMINGS_DIR", "MODLEM_DATA_DIR"];
 
-    let ground_filename = format!("ground{}o.dat", graphics_set);
-    let ground_path = case_sensitivity::find_file_in_current_dir(ground_filename.as_str()).unwrap();
-    let mut ground_header_file = match File::open(&ground_path) {
-        Err(err) => panic!("Error opening ground header file: {}|", err),
-        Ok(file) => file,
-    };
+/// A directory's listing, shared by every command which looks in it.
+struct SharedLocator {
+    files: case_sensitivity::FileLocator,
+    /// The command (see Cache::command) which last listed the directory.
+    command: std::cell::Cell<usize>,
+}
+
+impl SharedLocator {
+    /// Read the directory again if an earlier command listed it (and so may have written files
+    /// there since). Returns whether it did.
+    fn refresh(&self, cache: &Cache) -> bool {
+        if self.command.get() == cache.command.get() {
+            return false;
+        }
+        self.files.refresh();
+        self.command.set(cache.command.get());
+        true
+    }
+
+    /// Find @name (see FileLocator::find()). The listing an earlier command made is used, unless
+    /// @name isn't in it.
+    fn find(&self, cache: &Cache, name: &str) -> std::io::Result<std::path::PathBuf> {
+        match self.files.find(name) {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound && self.refresh(cache) => self.files.find(name),
+            found => found,
+        }
+    }
+
+    /// Find the files matching @pattern (see FileLocator::find_matching()), always from a listing
+    /// made by this command.
+    fn find_matching(&self, cache: &Cache, pattern: &str) -> std::io::Result<Vec<std::path::PathBuf>> {
+        self.refresh(cache);
+        self.files.find_matching(pattern)
+    }
+}
+
+/// The files a graphics set was decoded from, and when they were last changed.
+type SetSources = Vec<(std::path::PathBuf, Option<std::time::SystemTime>)>;
+
+/// What's been read from the game's files, kept while modlem runs (across every command of a
+/// batch file), so that nothing's listed or decoded more often than it needs to be.
+#[derive(Default)]
+struct Cache {
+    /// Each directory searched, by its path and how many levels deep.
+    locators: std::cell::RefCell<std::collections::HashMap<(String, usize), Rc<SharedLocator>>>,
+    /// Each graphics set decoded, by the ground and vgagr files it came from, and when they
+    /// were last changed.
+    graphics_sets: std::cell::RefCell<std::collections::HashMap<SetSources, Rc<graphics_set::GraphicsSet>>>,
+    /// How many commands have been started, counting from 0.
+    command: std::cell::Cell<usize>,
+}
+
+impl Cache {
+    /// Note that another command is starting.
+    fn next_command(&self) {
+        self.command.set(self.command.get() + 1);
+    }
+
+    /// The listing of @path, and @max_depth levels of subdirectories below it.
+    fn locator(&self, path: &str, max_depth: usize) -> Rc<SharedLocator> {
+        let command = self.command.get();
+        self.locators.borrow_mut().entry((path.to_string(), max_depth)).or_insert_with(|| {
+            Rc::new(SharedLocator { files: case_sensitivity::FileLocator::new(Path::new(path), max_depth), command: std::cell::Cell::new(command) })
+        }).clone()
+    }
+}
+
+/// Where the game's files (the ground, vgagr, main and level files, and so on) are looked for:
+/// the one given with --data-dir, or else the current directory, then $LEMMINGS_DIR. Outputs
+/// still go where they would. Their listings come from a Cache, so each directory is only listed
+/// once.
+#[derive(Clone)]
+struct DataDir {
+    /// The directories to look in, in order, each with how it's described in errors.
+    dirs: Vec<(String, Rc<SharedLocator>)>,
+    cache: Rc<Cache>,
+}
+
+impl DataDir {
+    /// The data directory @path, and @max_depth levels of subdirectories below it.
+    fn new(cache: &Rc<Cache>, path: &str, max_depth: usize) -> DataDir {
+        DataDir { dirs: vec![(path.to_string(), cache.locator(path, max_depth))], cache: cache.clone() }
+    }
+
+    /// The data directory to use without --data-dir: the current directory (searched @max_depth
+    /// levels deep), then the first of DATA_DIR_VARIABLES which is set (searched @env_max_depth
+    /// levels deep).
+    fn from_env(cache: &Rc<Cache>, max_depth: usize, env_max_depth: usize) -> DataDir {
+        let mut data = DataDir::new(cache, ".", max_depth);
+        let env_dir = DATA_DIR_VARIABLES.iter().filter_map(|name| Some((name, std::env::var(name).ok()?))).find(|(_, dir)| !dir.is_empty());
+        if let Some((name, dir)) = env_dir {
+            data.dirs.push((format!("${}={}", name, dir), cache.locator(&dir, env_max_depth)));
+        }
+        data
+    }
+
+    /// Find @name in the data directory, case-insensitively, or say where it looked.
+    fn try_find(&self, name: &str) -> std::result::Result<std::path::PathBuf, String> {
+        let mut errors = Vec::new();
+        for (_, locator) in &self.dirs {
+            match locator.find(&self.cache, name) {
+                Ok(path) => return Ok(path),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => errors.push(err.to_string()),
+                Err(err) => return Err(err.to_string()),
+            }
+        }
+        if errors.len() == 1 {
+            return Err(errors.remove(0));
+        }
+        let searched: Vec<&str> = self.dirs.iter().map(|(description, _)| description.as_str()).collect();
+        Err(format!("searched {} ({})", searched.join(", then "), errors.join("; ")))
+    }
+
+    /// Find @name in the data directory, case-insensitively.
+    fn find(&self, name: &str) -> Result<std::path::PathBuf> {
+        self.try_find(name).map_err(|err| Error::io(format!("Couldn't find {}: {}", name, err)))
+    }
+
+    /// Find @filename, either as given, or (failing that) case-insensitively in the data directory.
+    fn find_input(&self, filename: &str) -> Result<std::path::PathBuf> {
+        let path = Path::new(filename);
+        if path.exists() {
+            return Ok(path.to_path_buf());
+        }
+        self.find(filename)
+    }
+
+    /// Where to load graphics sets from: just @ground_dir if it's given, or the data directory.
+    fn ground_dir(&self, ground_dir: Option<&str>) -> DataDir {
+        match ground_dir {
+            Some(ground_dir) => DataDir::new(&self.cache, ground_dir, 0),
+            None => self.clone(),
+        }
+    }
+
+    /// The files here whose names match @pattern (see case_sensitivity::matches_pattern()),
+    /// sorted by name. Where there's more than one with the same name, the one which try_find()
+    /// would find is given.
+    fn find_matching(&self, pattern: &str) -> Result<Vec<std::path::PathBuf>> {
+        let mut paths = Vec::new();
+        for (_, locator) in &self.dirs {
+            match locator.find_matching(&self.cache, pattern) {
+                Ok(found) => paths.extend(found),
+                // A directory which isn't there just doesn't have any.
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => return Err(Error::from(err).context(&format!("Couldn't look for {}", pattern))),
+            }
+        }
+        let name = |path: &std::path::PathBuf| path.file_name().map(|name| name.to_string_lossy().to_lowercase());
+        let mut seen = std::collections::HashSet::new();
+        paths.retain(|path| seen.insert(name(path)));
+        paths.sort_by_key(|path| name(path));
+        Ok(paths)
+    }
+
+    /// The numbers of the files here matching @pattern, which has a single '?' standing for the
+    /// number (e.g. the N of each groundNo.dat for "ground?o.dat"), sorted.
+    fn numbered_files(&self, pattern: &str) -> Result<Vec<usize>> {
+        let digit_pos = pattern.find('?').unwrap();
+        let mut numbers: Vec<usize> = self.find_matching(pattern)?.iter().filter_map(|path| {
+            let digit = path.file_name()?.to_str()?.chars().nth(digit_pos)?.to_digit(10)?;
+            Some(digit as usize)
+        }).collect();
+        numbers.dedup();
+        Ok(numbers)
+    }
+
+    /// The numbers of the graphics sets here, i.e. the N of each groundNo.dat, sorted.
+    fn graphics_sets(&self) -> Result<Vec<usize>> {
+        self.numbered_files("ground?o.dat")
+    }
+}
 
-    let data_filename = format!("vgagr{}.dat", graphics_set);
-    let path = case_sensitivity::find_file_in_current_dir(data_filename.as_str()).unwrap();
-    let path_name = path.display();
-    let mut image = match File::open(&path) {
-        Err(err) => panic!("Error opening lemmings file {}: {}", path_name, err),
-        Ok(file) => file,
+/// Open and decode graphics set @graphics_set from @dir.
+fn decode_graphics_set(dir: &DataDir, graphics_set: usize, log: &Log) -> Result<Rc<graphics_set::GraphicsSet>> {
+    decode_graphics_set_files(dir, &SetFiles::standard(graphics_set as u16), log)
+}
+
+/// Open and decode the graphics set made up of @files, from @dir, unless it's in the cache
+/// already (and its files haven't changed since).
+fn decode_graphics_set_files(dir: &DataDir, files: &SetFiles, log: &Log) -> Result<Rc<graphics_set::GraphicsSet>> {
+    let ground_path = match dir.try_find(files.ground_file.as_str()) {
+        Ok(path) => path,
+        Err(err) => {
+            let sets: Vec<String> = dir.graphics_sets().unwrap_or_default().iter().map(|set| set.to_string()).collect();
+            return Err(Error::io(match sets.len() {
+                0 => format!("Couldn't find {}: {}, and there aren't any ground?o.dat files there", files.ground_file, err),
+                _ => format!("Couldn't find {}: {}; the graphics sets there are {}", files.ground_file, err, sets.join(", ")),
+            }));
+        }
     };
+    let path = dir.find(files.vgagr_file.as_str())?;
+    let key: Vec<_> = [&ground_path, &path].iter().map(|path| (path.to_path_buf(), std::fs::metadata(path).and_then(|meta| meta.modified()).ok())).collect();
+    if let Some(set) = dir.cache.graphics_sets.borrow().get(&key) {
+        log_detail!(log, "Using {} and {}, decoded already", ground_path.display(), path.display());
+        return Ok(set.clone());
+    }
+    let mut ground_header_file = binary_io::open_buffered(&ground_path).context(|| format!("Error opening {}", ground_path.display()))?;
+    let mut image = binary_io::open_buffered(&path).context(|| format!("Error opening {}", path.display()))?;
+
+    let set = graphics_set::decode_graphics_set(&mut ground_header_file, &mut image, false, log)
+        .context(|| format!("Error decoding {} and {}", files.ground_file, files.vgagr_file))?;
+    let set = Rc::new(set);
+    dir.cache.graphics_sets.borrow_mut().insert(key, set.clone());
+    Ok(set)
+}
+
+/// The files for the graphics set @level (described by @what) uses: from @set_map, if there is
+/// one, or the standard ones for the level's game otherwise.
+fn level_set_files(level: &level::Level, what: &str, set_map: Option<&SetMap>) -> Result<SetFiles> {
+    match set_map {
+        None => Ok(SetFiles::standard(level.ground_set())),
+        Some(set_map) => match set_map.get(level.graphics_set) {
+            Some(files) => Ok(files.clone()),
+            None => Err(Error::data(format!("Graphics set {} isn't in the set map, but {} (\"{}\") uses it", level.graphics_set, what, level.name()))),
+        },
+    }
+}
+
+/// Extract graphics set @graphics_set as a single atlas image, setN_atlas.bmp (or .png, with
+/// @png), with its mask and a .json file saying where each piece is, all added to @outputs.
+fn cmd_extract_graphics_set_atlas(data: &DataDir, graphics_set: usize, png: bool, outputs: &PendingOutputs, log: &Log) -> Result<()> {
+    let set = decode_graphics_set(data, graphics_set, log)?;
+    let atlas_prefix = format!("set{}_atlas", graphics_set);
+    graphics_set::write_graphics_set_atlas(&set, &atlas_prefix, png, outputs);
+    Ok(())
+}
+
+/// Today's date (in UTC), as YYYY-MM-DD.
+fn today() -> String {
+    let secs = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |since| since.as_secs());
+    // Count from 1 March 2000, so leap days come at the end of each year.
+    let days = (secs / 86400) as i64 - 11017;
+    let (cycles, day_of_cycle) = (days.div_euclid(146097), days.rem_euclid(146097));
+    let years = (day_of_cycle - day_of_cycle / 1460 + day_of_cycle / 36524 - day_of_cycle / 146096) / 365;
+    let day_of_year = day_of_cycle - (365 * years + years / 4 - years / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = 2000 + cycles * 400 + years + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Extract graphics set @graphics_set to @out_dir (which is created if need be), as images and a
+/// themeN.txt script, all added to @outputs.
+fn cmd_extract_graphics_set(data: &DataDir, graphics_set: usize, out_dir: &Path, check_masks: bool, png: bool, outputs: &PendingOutputs, log: &Log) -> Result<()> {
+    let options = ExtractOptions::builder()
+        .output_dir(out_dir)
+        .terrain_filename_pattern(&format!("set{}_terrain#", graphics_set))
+        .terrain_mask_filename_pattern(&format!("set{}_terrain#_mask", graphics_set))
+        .object_filename_pattern(&format!("set{}_obj#", graphics_set))
+        .object_mask_filename_pattern(&format!("set{}_obj#_mask", graphics_set))
+        .check_masks(check_masks)
+        .png(png)
+        .log(*log)
+        .build();
+
+    let set = decode_graphics_set(data, graphics_set, log)?;
+
+    let script_path = out_dir.join(format!("theme{}.txt", graphics_set));
+    log_detail!(log, "Writing {}", script_path.display());
+    let mut script_writer = outputs.create(script_path);
 
     // Write the header for the graphics set script.
-    writeln!(script_writer, "HeaderFile \"{}\"", ground_filename).unwrap();
-    writeln!(script_writer, "DataFile \"{}\"", data_filename).unwrap();
+    writeln!(script_writer, "// Extracted by modlem {} on {}", option_env!("CARGO_PKG_VERSION").unwrap_or("(unknown version)"), today()).unwrap();
+    writeln!(script_writer, "// from ground{}o.dat and vgagr{}.dat", graphics_set, graphics_set).unwrap();
+    writeln!(script_writer).unwrap();
+    writeln!(script_writer, "HeaderFile \"ground{}o.dat\"", graphics_set).unwrap();
+    writeln!(script_writer, "DataFile \"vgagr{}.dat\"", graphics_set).unwrap();
     writeln!(script_writer).unwrap();
 
-    graphics_set::extract_graphics_set(
-        &mut script_writer,
-        &mut ground_header_file,
-        &mut image,
-        &options,
-    );
+    graphics_set::write_graphics_set(&set, &mut script_writer, &options, outputs)?;
+    Ok(())
 }
 
-fn cmd_create_graphics_set(filename: &str) {
-    let script_data = std::fs::read_to_string(filename).unwrap();
-    let mut lexer = parser::Lexer::from_str(script_data.as_str());
+/// Create the graphics set the script @filename describes. Any warnings about the script are
+/// printed even if it fails.
+fn cmd_create_graphics_set(filename: &str, backup: bool, strict: bool, log: &Log) -> Result<()> {
+    let sources = parser::SourceFiles::load(filename)?;
+    let mut lexer = parser::Lexer::from_sources(&sources);
+    lexer.set_strict(strict);
 
-    graphics_set::create_graphics_set(&mut lexer)
+    let result = graphics_set::create_graphics_set(&mut lexer, backup, log);
+    for warning in lexer.take_warnings() {
+        log_warning!(log, "{}", warning);
+    }
+    Ok(result?)
 }
 
-fn cmd_extract_main_dat(xmas_mode: bool) {
-    let path = case_sensitivity::find_file_in_current_dir("main.dat").unwrap();
+fn cmd_extract_main_dat(data: &DataDir, options: &main_dat::ExtractOptions, outputs: &PendingOutputs) -> Result<()> {
+    let filename = options.variant.map_or("main.dat", |variant| variant.file_name());
+    let path = data.find(filename)?;
+
+    let mut main_dat_file = binary_io::open_buffered(&path).context(|| format!("Error opening {}", path.display()))?;
 
-    let mut main_dat_file = match File::open(&path) {
-        Err(err) => panic!("Error opening main.dat: {}", err),
-        Ok(file) => file,
+    main_dat::extract_main_dat(&mut main_dat_file, options, outputs).context(|| format!("Error extracting {}", path.display()))?;
+    Ok(())
+}
+
+fn cmd_create_main_dat(variant: MainDatVariant, spec_path: Option<&str>, out_path: Option<&str>, backup: bool, options: &main_dat::CreateOptions) -> Result<()> {
+    let log = &options.log;
+    let spec = match spec_path {
+        Some(spec_path) => MainDatSpec::from_file(Path::new(spec_path))?,
+        None => variant.spec(),
     };
 
-    main_dat::extract_main_dat(&mut main_dat_file, xmas_mode);
+    let dat_path = Path::new(out_path.unwrap_or(variant.file_name()));
+    log_info!(log, "Writing {}", dat_path.display());
+    let mut data = backup::create_with_backup(dat_path, backup, log)?;
+
+    main_dat::create_main_dat(&mut data, &spec, options)?;
+    data.finish().context(|| format!("Error writing {}", dat_path.display()))
+}
+
+/// Extract main.dat to a temporary directory, rebuild it from the extracted files, and check that
+/// every section decompresses to the same data as the original.
+fn cmd_roundtrip_main_dat(data: &DataDir, variant: Option<MainDatVariant>, xmas_mode: bool, spec_path: Option<&str>, log: &Log) -> Result<()> {
+    let filename = variant.map_or("main.dat", |variant| variant.file_name());
+    let path = data.find(filename)?;
+    let original = std::fs::read(&path).context(|| format!("Error opening {}", path.display()))?;
+
+    let temp_dir = std::env::temp_dir().join(format!("modlem_roundtrip_{}", std::process::id()));
+    let mut extract_options = main_dat::ExtractOptions::builder()
+        .output_dir(&temp_dir)
+        .xmas_mode(xmas_mode)
+        .log(*log);
+    if let Some(variant) = variant {
+        extract_options = extract_options.variant(variant);
+    }
+    if let Some(spec_path) = spec_path {
+        extract_options = extract_options.spec(MainDatSpec::from_file(Path::new(spec_path))?);
+    }
+    log_info!(log, "Extracting {} to {}", filename, temp_dir.display());
+    let outputs = PendingOutputs::new();
+    let spec = main_dat::extract_main_dat(&mut original.as_slice(), &extract_options.build(), &outputs)?;
+    // The directory is its own, so anything there is left over from an earlier round trip.
+    outputs.write_all(OverwritePolicy::Force, log)?;
+
+    log_info!(log, "Rebuilding {}", filename);
+    let mut rebuilt = Vec::new();
+    let create_options = main_dat::CreateOptions::builder().input_dir(&temp_dir).log(*log).build();
+    main_dat::create_main_dat(&mut rebuilt, &spec, &create_options)?;
+
+    let differences = main_dat::compare_main_dat(&original, &rebuilt, &spec, log)?;
+    if differences != 0 {
+        return Err(Error::data(format!(
+            "Round trip failed: {} bytes differ (the extracted files are in {})",
+            differences,
+            temp_dir.display()
+        )));
+    }
+    std::fs::remove_dir_all(&temp_dir).context(|| format!("Error removing {}", temp_dir.display()))?;
+    println!("Round trip OK: every section of {} was rebuilt identically", filename);
+    Ok(())
 }
 
-fn cmd_create_main_dat() {
-    main_dat::create_main_dat();
+/// Read the sections of an existing main.dat, to fill in any missing files when creating one.
+fn read_base_main_dat(filename: &str) -> Result<Vec<main_dat::BaseSection>> {
+    let mut base_file = binary_io::open_buffered(filename).context(|| format!("Error opening {}", filename))?;
+    main_dat::read_base_sections(&mut base_file).context(|| format!("Error reading {}", filename))
+}
+
+/// The sample rate used when rendering PC speaker sounds.
+const PCSPKR_SAMPLE_RATE: u32 = 22050;
+
+/// Render each PC speaker sound in @filename (either the raw section, or the text format) to a WAV
+/// file in @wav_dir, added to @outputs.
+fn cmd_render_pcspkr(filename: &str, wav_dir: &str, outputs: &PendingOutputs, log: &Log) -> Result<()> {
+    let path = Path::new(filename);
+    let data = std::fs::read(path).context(|| format!("Error opening {}", filename))?;
+    let sounds = if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("txt")) {
+        pcspkr::parse_text(&String::from_utf8_lossy(&data)).map_err(|err| Error::data(format!("{}: {}", filename, err)))?
+    } else {
+        match pcspkr::decode(&data) {
+            Some(sounds) => sounds,
+            None => return Err(Error::data(format!("Couldn't decode PC speaker sounds in {}", filename))),
+        }
+    };
+
+    let wav_dir = Path::new(wav_dir);
+    let stem = file_stem(filename)?;
+    for (i, sound) in sounds.iter().enumerate() {
+        let wav_path = wav_dir.join(format!("{}_{:02}.wav", stem, i));
+        log_info!(log, "Rendering sound {} to {}", i, wav_path.display());
+        binary_io::write_wav_u8(&sound.render(PCSPKR_SAMPLE_RATE), PCSPKR_SAMPLE_RATE, &mut outputs.create(&wav_path)).unwrap();
+    }
+    Ok(())
 }
 
 /// Splits and decompresses [name].dat file into its consituant sections,
-/// each named [name].000, [name].001, etc.
-fn extract_dat(name: &std::string::String) {
+/// each named [name].000, [name].001, etc., which are added to @outputs.
+fn extract_dat(data: &DataDir, name: &std::string::String, outputs: &PendingOutputs, log: &Log) -> Result<()> {
     let dat_filename = format!("{}.dat", name);
-    let dat_path = case_sensitivity::find_file_in_current_dir(&dat_filename).unwrap();
+    let dat_path = data.find(&dat_filename)?;
+
+    let file_data = std::fs::read(dat_path).context(|| format!("Error opening {}", dat_filename))?;
+    let sections = dat_section::decompress_all(&file_data).context(|| format!("Error reading {}", dat_filename))?;
+
+    for (section_num, section_data) in sections.into_iter().enumerate() {
+        let outfile_name = format!("{}.{:03}", name, section_num);
+        log_detail!(log, "Section {} ({} bytes) -> {}", section_num, section_data.len(), outfile_name);
+        outputs.create(&outfile_name).write_all(section_data.as_slice()).unwrap();
+    }
+    Ok(())
+}
 
-    let mut data = match File::open(dat_path) {
-        Err(err) => panic!("Error opening {}: {}", dat_filename, err),
-        Ok(file) => file,
+/// Extract each level in a levelNNN.dat file to [name].000.txt, [name].001.txt, etc. in the
+/// text format, or, if @raw is set, to [name].000, [name].001, etc. as-is (like extract-dat).
+/// The levels are for game @variant, or the one guessed from each level if it's None. The files
+/// are added to @outputs, in @out_dir, which is created if need be.
+fn cmd_extract_levels(data: &DataDir, filename: &str, out_dir: &Path, raw: bool, variant: Option<level::LevelVariant>, outputs: &PendingOutputs, log: &Log) -> Result<()> {
+    let path = data.find_input(filename)?;
+    let stem = file_stem(&path.to_string_lossy())?;
+    let file_data = std::fs::read(&path).context(|| format!("Error opening {}", filename))?;
+    let sections = dat_section::decompress_all(&file_data).context(|| format!("Error reading {}", filename))?;
+
+    for (section_num, section_data) in sections.into_iter().enumerate() {
+        let level = if raw { None } else {
+            match level::Level::from_bytes(&section_data, variant) {
+                Ok(level) => Some(level),
+                Err(err) => {
+                    log_warning!(log, "Warning: section {} isn't a level ({}), saving it as-is", section_num, err);
+                    None
+                }
+            }
+        };
+        match level {
+            Some(level) => {
+                let outfile_name = out_dir.join(format!("{}.{:03}.txt", stem, section_num)).to_string_lossy().to_string();
+                log_info!(log, "Level {}: \"{}\" -> {}", section_num, level.name(), outfile_name);
+                level.write_text(&mut outputs.create(&outfile_name)).unwrap();
+                for problem in level.stat_problems() {
+                    log_warning!(log, "Warning: {} (the game may not accept it)", problem);
+                }
+            }
+            None => {
+                let outfile_name = out_dir.join(format!("{}.{:03}", stem, section_num)).to_string_lossy().to_string();
+                log_info!(log, "Section {} -> {}", section_num, outfile_name);
+                outputs.create(&outfile_name).write_all(section_data.as_slice()).unwrap();
+            }
+        }
+    }
+    Ok(())
+}
+
+/// What extract-all did with one of the things it found.
+struct ExtractAllEntry {
+    what: String,
+    /// "extracted", "skipped" or "failed".
+    outcome: &'static str,
+    /// Where it was extracted to, or why it wasn't.
+    detail: String,
+}
+
+/// Extract everything in the data directory to @out_dir: each graphics set (with both its
+/// groundNo.dat and vgagrN.dat) to sets/N/, main.dat to main/, and the levels in each
+/// levelNNN.dat (or other .dat file of levels) to levels/. Something which fails to extract doesn't stop the rest, but none of
+/// its files are added to @outputs. Prints a table of what was found, and fails (with the kind
+/// of the first failure) if anything did.
+fn cmd_extract_all(data: &DataDir, out_dir: &Path, outputs: &PendingOutputs, log: &Log) -> Result<()> {
+    let mut entries = Vec::new();
+    let mut first_failure = None;
+    let mut extract = |what: String, out: std::path::PathBuf, f: &dyn Fn(&PendingOutputs) -> Result<()>| {
+        log_info!(log, "Extracting {} to {}", what, out.display());
+        let extracted = PendingOutputs::new();
+        let (outcome, detail) = match f(&extracted) {
+            Ok(()) => {
+                outputs.append(extracted);
+                ("extracted", out.display().to_string())
+            }
+            Err(err) => {
+                log_error!(log, "Couldn't extract {}: {}", what, err);
+                first_failure.get_or_insert(err.kind);
+                ("failed", err.message)
+            }
+        };
+        entries.push(ExtractAllEntry { what, outcome, detail });
     };
 
-    let mut section_num = 0;
+    let sets = data.graphics_sets()?;
+    let mut skipped = Vec::new();
+    for &set_num in &sets {
+        let vgagr_file = format!("vgagr{}.dat", set_num);
+        match data.try_find(&vgagr_file) {
+            Ok(_) => {
+                let set_dir = out_dir.join("sets").join(set_num.to_string());
+                extract(format!("graphics set {}", set_num), set_dir.clone(), &|outputs| cmd_extract_graphics_set(data, set_num, &set_dir, false, false, outputs, log));
+            }
+            Err(err) => skipped.push(ExtractAllEntry { what: format!("graphics set {}", set_num), outcome: "skipped", detail: format!("no {} ({})", vgagr_file, err) }),
+        }
+    }
+    for set_num in data.numbered_files("vgagr?.dat")? {
+        if !sets.contains(&set_num) {
+            skipped.push(ExtractAllEntry { what: format!("graphics set {}", set_num), outcome: "skipped", detail: format!("no ground{}o.dat", set_num) });
+        }
+    }
+
+    match data.try_find("main.dat") {
+        Ok(path) => {
+            let main_dir = out_dir.join("main");
+            let options = main_dat::ExtractOptions::builder().output_dir(&main_dir).log(*log).build();
+            extract("main.dat".to_string(), main_dir, &|outputs| {
+                let mut main_dat_file = binary_io::open_buffered(&path).context(|| format!("Error opening {}", path.display()))?;
+                main_dat::extract_main_dat(&mut main_dat_file, &options, outputs)?;
+                Ok(())
+            });
+        }
+        Err(err) => skipped.push(ExtractAllEntry { what: "main.dat".to_string(), outcome: "skipped", detail: err }),
+    }
+
+    // The demos name their level files differently, so any other .dat file which is nothing but
+    // levels is extracted too.
+    let mut level_files = data.find_matching("level???.dat")?;
+    for path in data.find_matching("*.dat")? {
+        let is_levels = || std::fs::read(&path).is_ok_and(|contents| file_info::FileInfo::identify(&contents).kind == file_info::FileKind::Levels);
+        if !level_files.contains(&path) && is_levels() {
+            level_files.push(path);
+        }
+    }
+    for path in &level_files {
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        let levels_dir = out_dir.join("levels");
+        extract(name, levels_dir.clone(), &|outputs| cmd_extract_levels(data, &path.to_string_lossy(), &levels_dir, false, None, outputs, log));
+    }
+    if level_files.is_empty() {
+        skipped.push(ExtractAllEntry { what: "levels".to_string(), outcome: "skipped", detail: "no .dat files of levels".to_string() });
+    }
+
+    entries.extend(skipped);
+    let what_width = entries.iter().map(|entry| entry.what.chars().count()).max().unwrap_or(0);
+    for entry in &entries {
+        println!("{:<width$}  {:<9}  {}", entry.what, entry.outcome, entry.detail, width = what_width);
+    }
+    let num_failed = entries.iter().filter(|entry| entry.outcome == "failed").count();
+    match first_failure {
+        Some(kind) => Err(Error::new(kind, format!("{} of {} failed", num_failed, entries.len()))),
+        None => Ok(()),
+    }
+}
+
+/// Create [name].dat from the levels in [name].000.txt, [name].001.txt, etc. Any sections
+/// without a text file are read as-is from [name].000, etc. instead (as extract-levels saves
+/// sections which aren't levels). Anything placed outside the level is warned about (see
+/// Level::placement_warnings(), with the graphics sets from @ground_dir if given), or with
+/// @strict, stops the file being written.
+fn cmd_create_levels(data: &DataDir, name: &str, ground_dir: Option<&str>, strict: bool, backup: bool, log: &Log) -> Result<()> {
+    let dat_filename = format!("{}.dat", name);
+    let mut sections = Vec::new();
+    let mut sets = std::collections::HashMap::new();
+    let mut num_warnings = 0;
+    let ground_dir = ground_dir.map(|ground_dir| data.ground_dir(Some(ground_dir)));
     loop {
-        let section_res = DatSection::from_file(&mut data);
-        match section_res {
-            Err(_) => break,
-            Ok(mut header) => {
-                let section_data = header.decompress();
-                let outfile_name = format!("{}.{:03}", name, section_num);
-                let out_path = Path::new(outfile_name.as_str());
-                let mut output_file = File::create(out_path).unwrap();
-                output_file.write_all(section_data.as_slice()).unwrap();
+        let text_filename = format!("{}.{:03}.txt", name, sections.len());
+        let raw_filename = format!("{}.{:03}", name, sections.len());
+        if let Ok(text) = std::fs::read_to_string(&text_filename) {
+            let level = level::Level::from_text(&text).map_err(|err| err.in_file(Path::new(&text_filename)))?;
+            log_detail!(log, "Level {}: \"{}\" <- {}", sections.len(), level.name(), text_filename);
+            let set = match &ground_dir {
+                Some(ground_dir) => Some(cached_graphics_set(&mut sets, SetFiles::standard(level.ground_set()), ground_dir, log)?),
+                None => None,
+            };
+            for warning in level.stat_problems().into_iter().chain(level.placement_warnings(set)) {
+                log_warning!(log, "Warning: {} (in {})", warning, text_filename);
+                num_warnings += 1;
             }
+            sections.push(level.to_bytes());
+        } else if let Ok(data) = std::fs::read(&raw_filename) {
+            log_detail!(log, "Section {} <- {}", sections.len(), raw_filename);
+            sections.push(data);
+        } else {
+            break;
         }
-        section_num += 1;
     }
+    if sections.is_empty() {
+        return Err(Error::io(format!("No levels found (expected {}.000.txt)", name)));
+    }
+    if strict && num_warnings != 0 {
+        return Err(Error::data(format!("{} warning{} (not writing {} with --strict)", num_warnings, if num_warnings == 1 { "" } else { "s" }, dat_filename)));
+    }
+
+    let mut data = backup::create_with_backup(Path::new(&dat_filename), backup, log)?;
+    for (section_num, section_data) in sections.iter().enumerate() {
+        compress_section(section_data, &format!("section {}", section_num), log).write(&mut data)
+            .context(|| format!("Error writing {}", dat_filename))?;
+    }
+    data.finish().context(|| format!("Error writing {}", dat_filename))
+}
+
+/// Compress @data (described by @what) into a section, saying how well it did with --verbose.
+fn compress_section(data: &[u8], what: &str, log: &Log) -> DatSection {
+    let section = DatSection::from_data(data, data.len());
+    log_detail!(log, "Compressed {} from {} bytes to {} bytes", what, section.uncompressed_size(), section.compressed_size());
+    section
+}
+
+/// Read level @index (counting from 0) from the levelNNN.dat file @filename, for game @variant
+/// (or the one guessed from the level).
+fn read_level(data: &DataDir, filename: &str, index: usize, variant: Option<level::LevelVariant>) -> Result<level::Level> {
+    let path = data.find_input(filename)?;
+    let file_data = std::fs::read(&path).context(|| format!("Error opening {}", filename))?;
+    let mut reader = file_data.as_slice();
+    for section_num in 0..=index {
+        // Only running out of sections at the end of a section means there aren't enough.
+        if reader.is_empty() {
+            return Err(Error::data(format!("{} only has {} sections", filename, section_num)));
+        }
+        let offset = file_data.len() - reader.len();
+        let mut section = DatSection::from_file(&mut reader)
+            .context(|| format!("Error reading {}: section {} at offset {}", filename, section_num, offset))?;
+        if section_num == index {
+            let section_data = section.decompress().context(|| format!("Error decompressing section {} of {}", section_num, filename))?;
+            return level::Level::from_bytes(&section_data, variant).context(|| format!("Section {} of {} isn't a level", section_num, filename));
+        }
+    }
+    unreachable!()
+}
+
+/// Read the level given in a manifest (see level::parse_manifest()): a section of a levelNNN.dat
+/// file, a raw .lvl file, or one in the text format.
+fn read_level_source(data: &DataDir, source: &level::LevelSource, variant: Option<level::LevelVariant>) -> Result<level::Level> {
+    if let Some(index) = source.index {
+        return read_level(data, &source.file_name, index, variant);
+    }
+    let path = data.find_input(&source.file_name)?;
+    let data = std::fs::read(&path).context(|| format!("Error opening {}", source.file_name))?;
+    if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("txt")) {
+        return Ok(level::Level::from_text(&String::from_utf8_lossy(&data)).map_err(|err| err.in_file(&path))?);
+    }
+    level::Level::from_bytes(&data, variant).context(|| format!("{} isn't a level", source.file_name))
+}
+
+/// Write the levels listed in the manifest @manifest_path to [prefix]000.dat, [prefix]001.dat,
+/// etc. in @out_dir, with @per_file levels in each. Any levels whose graphics sets aren't in
+/// game @target are warned about.
+#[allow(clippy::too_many_arguments)]
+fn cmd_repack_levels(data: &DataDir, manifest_path: &str, out_dir: &str, prefix: &str, per_file: usize, target: level::LevelVariant, backup: bool, log: &Log) -> Result<()> {
+    let manifest = std::fs::read_to_string(manifest_path).context(|| format!("Error opening {}", manifest_path))?;
+    let sources = level::parse_manifest(&manifest).map_err(|err| Error::data(format!("{}: {}", manifest_path, err)))?;
+    if sources.is_empty() {
+        return Err(Error::data(format!("{} doesn't list any levels", manifest_path)));
+    }
+    // Read everything first, as the output may replace some of the inputs.
+    let levels = sources.iter().map(|source| read_level_source(data, source, Some(target))).collect::<Result<Vec<level::Level>>>()?;
+
+    for (i, (source, level)) in sources.iter().zip(levels.iter()).enumerate() {
+        let from = match source.index {
+            Some(index) => format!("{} {}", source.file_name, index),
+            None => source.file_name.clone(),
+        };
+        log_detail!(log, "Level {}: \"{}\" <- {}", i, level.name(), from);
+        if level.ground_set() >= target.num_graphics_sets() {
+            log_warning!(log, "Warning: level {} uses graphics set {}, but {} only has {}", i, level.ground_set(), target.name(), target.num_graphics_sets());
+        }
+        if level.special_graphics != 0 && !target.has_special_graphics() {
+            log_warning!(log, "Warning: level {} uses special graphics, which {} doesn't have", i, target.name());
+        }
+    }
+
+    std::fs::create_dir_all(out_dir).context(|| format!("Error creating {}", out_dir))?;
+    for (file_num, file_levels) in levels.chunks(per_file).enumerate() {
+        let out_path = Path::new(out_dir).join(format!("{}{:03}.dat", prefix, file_num));
+        log_info!(log, "Writing {} levels to {}", file_levels.len(), out_path.display());
+        let mut data = backup::create_with_backup(&out_path, backup, log)?;
+        for level in file_levels {
+            compress_section(&level.to_bytes(), &format!("\"{}\"", level.name()), log).write(&mut data)
+                .context(|| format!("Error writing {}", out_path.display()))?;
+        }
+        data.finish().context(|| format!("Error writing {}", out_path.display()))?;
+    }
+    Ok(())
+}
+
+/// Save level @index of @filename to @out_path, as a raw 2048-byte .lvl file.
+fn cmd_extract_lvl(data: &DataDir, filename: &str, index: usize, out_path: &str, outputs: &PendingOutputs, log: &Log) -> Result<()> {
+    let level = read_level(data, filename, index, None)?;
+    log_info!(log, "Level {}: \"{}\" -> {}", index, level.name(), out_path);
+    level.write(&mut outputs.create(out_path)).unwrap();
+    Ok(())
 }
 
-fn create_dat(name: &std::string::String) {
+/// Add @image to @outputs as @filename, a .png or .bmp depending on its extension.
+fn save_image_by_extension(image: &planar_bmp::PlanarBMP, filename: &str, outputs: &PendingOutputs) -> Result<()> {
+    let extension = Path::new(filename).extension().map(|ext| ext.to_string_lossy().to_string());
+    let format = match extension.as_deref().and_then(ImageFormat::from_name) {
+        Some(format) => format,
+        None => return Err(Error::usage(format!("Can't tell what format to save {} as (expected .bmp or .png)", filename))),
+    };
+    image.save_as_format(format, &mut outputs.create(filename));
+    Ok(())
+}
+
+/// Where render-level saves its images, and how it draws them.
+struct RenderLevelOutputs<'a> {
+    out_path: String,
+    mask_path: Option<&'a str>,
+    minimap_path: Option<&'a str>,
+    tint_steel: bool,
+    show_triggers: bool,
+}
+
+/// Render @level with graphics set @set, adding the images named in @options to @outputs.
+fn render_level_to(level: &level::Level, set: &graphics_set::GraphicsSet, options: &RenderLevelOutputs, outputs: &PendingOutputs, log: &Log) -> Result<()> {
+    if let Some(special_graphics) = level.special_graphics_set() {
+        log_warning!(log, "Warning: this level uses special graphics {}, which aren't drawn", special_graphics);
+    }
+    let (mut image, mask) = level.render(set, log);
+    if options.tint_steel {
+        image = level.tint_steel(&image);
+    }
+    if options.show_triggers {
+        image = level.draw_triggers(&image, set);
+    }
+    save_image_by_extension(&image, &options.out_path, outputs)?;
+    if let Some(mask_path) = options.mask_path {
+        save_image_by_extension(&mask, mask_path, outputs)?;
+    }
+    if let Some(minimap_path) = options.minimap_path {
+        save_image_by_extension(&level::minimap(&mask), minimap_path, outputs)?;
+    }
+    Ok(())
+}
+
+/// Render level @index of @filename to an image (and, optionally, its terrain mask and minimap),
+/// using the graphics set from @ground_dir (which should have the files for the level's game, or
+/// those named in @set_map).
+#[allow(clippy::too_many_arguments)]
+fn cmd_render_level(data: &DataDir, filename: &str, index: usize, variant: Option<level::LevelVariant>, ground_dir: &DataDir, set_map: Option<&SetMap>, options: &RenderLevelOutputs, outputs: &PendingOutputs, log: &Log) -> Result<()> {
+    let level = read_level(data, filename, index, variant)?;
+    let files = level_set_files(&level, &format!("level {} of {}", index, filename), set_map)?;
+    log_info!(log, "Rendering level {}: \"{}\" ({} graphics set {}, from {})", index, level.name(), level.variant.name(), level.graphics_set, files.ground_file);
+    let set = decode_graphics_set_files(ground_dir, &files, log)?;
+    render_level_to(&level, &set, options, outputs, log)
+}
+
+/// The graphics set made up of @files, from @sets if it's been decoded already, or else decoded
+/// from @ground_dir and added to @sets.
+fn cached_graphics_set<'a>(sets: &'a mut std::collections::HashMap<SetFiles, Rc<graphics_set::GraphicsSet>>, files: SetFiles, ground_dir: &DataDir, log: &Log) -> Result<&'a graphics_set::GraphicsSet> {
+    Ok(match sets.entry(files) {
+        std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+        std::collections::hash_map::Entry::Vacant(entry) => {
+            let set = decode_graphics_set_files(ground_dir, entry.key(), log)?;
+            entry.insert(set)
+        }
+    })
+}
+
+/// Render every level in each of @filenames into @out_dir, as <file>.<index>.png, decoding each
+/// graphics set from @ground_dir (see @set_map) only once. With --verbose, print how long each
+/// level took.
+#[allow(clippy::too_many_arguments)]
+fn cmd_render_all_levels(data: &DataDir, filenames: &[&str], variant: Option<level::LevelVariant>, ground_dir: &DataDir, set_map: Option<&SetMap>, out_dir: &str, options: &RenderLevelOutputs, outputs: &PendingOutputs, log: &Log) -> Result<()> {
+    let mut sets = std::collections::HashMap::new();
+    let mut num_rendered = 0;
+    let start = std::time::Instant::now();
+    for filename in filenames {
+        let path = data.find_input(filename)?;
+        let stem = file_stem(filename)?;
+        for (index, level) in read_levels(&path, variant)?.into_iter().enumerate() {
+            let level = match level {
+                Some(level) => level,
+                None => {
+                    log_info!(log, "{} section {}: not a level, skipped", filename, index);
+                    continue;
+                }
+            };
+            let level_start = std::time::Instant::now();
+            let out_path = Path::new(out_dir).join(format!("{}.{:03}.png", stem, index)).to_string_lossy().to_string();
+            log_info!(log, "Rendering {} level {}: \"{}\" to {}", filename, index, level.name(), out_path);
+            let files = level_set_files(&level, &format!("level {} of {}", index, filename), set_map)?;
+            let set = cached_graphics_set(&mut sets, files, ground_dir, log)?;
+            render_level_to(&level, set, &RenderLevelOutputs { out_path, ..*options }, outputs, log)?;
+            log_detail!(log, "\tTook {:.1} ms", level_start.elapsed().as_secs_f64() * 1000.0);
+            num_rendered += 1;
+        }
+    }
+    log_info!(log, "Rendered {} level{} with {} graphics set{}", num_rendered, if num_rendered == 1 { "" } else { "s" }, sets.len(), if sets.len() == 1 { "" } else { "s" });
+    log_detail!(log, "Took {:.2} s in total", start.elapsed().as_secs_f64());
+    Ok(())
+}
+
+/// Check each level in @filename against its graphics set (from @ground_dir, see @set_map),
+/// printing a report. Returns false if there were any problems, any sections which aren't
+/// levels, or no levels at all.
+fn cmd_check_level(data: &DataDir, filename: &str, ground_dir: &DataDir, variant: Option<level::LevelVariant>, set_map: Option<&SetMap>, log: &Log) -> Result<bool> {
+    let path = data.find_input(filename)?;
+    let mut sets = std::collections::HashMap::new();
+    let mut num_levels = 0;
+    let mut num_failed = 0;
+    let mut num_not_levels = 0;
+    for (index, level) in read_levels(&path, variant)?.into_iter().enumerate() {
+        let level = match level {
+            Some(level) => level,
+            None => {
+                num_not_levels += 1;
+                println!("Section {}: not a level", index);
+                continue;
+            }
+        };
+        num_levels += 1;
+        let files = level_set_files(&level, &format!("level {} of {}", index, filename), set_map)?;
+        let set = cached_graphics_set(&mut sets, files, ground_dir, log)?;
+        let problems = level.check(set);
+        if problems.is_empty() {
+            println!("Level {}: \"{}\": OK", index, level.name());
+            continue;
+        }
+        num_failed += 1;
+        println!("Level {}: \"{}\": {} problem{}", index, level.name(), problems.len(), if problems.len() == 1 { "" } else { "s" });
+        for problem in problems {
+            println!("\t{}", problem);
+        }
+    }
+    if num_failed != 0 {
+        println!("{} level{} in {} had problems", num_failed, if num_failed == 1 { "" } else { "s" }, filename);
+    }
+    if num_not_levels != 0 {
+        println!("{} section{} in {} aren't levels", num_not_levels, if num_not_levels == 1 { "" } else { "s" }, filename);
+    }
+    if num_levels == 0 {
+        println!("There aren't any levels in {}", filename);
+    }
+    Ok(num_failed == 0 && num_not_levels == 0 && num_levels != 0)
+}
+
+/// Write @level to @out_path in the text format, as a starting point for a new level.
+fn cmd_new_level(level: &level::Level, out_path: &str, outputs: &PendingOutputs, log: &Log) {
+    log_info!(log, "Writing level \"{}\" ({} graphics set {}) to {}", level.name(), level.variant.name(), level.ground_set(), out_path);
+    level.write_text(&mut outputs.create(out_path)).unwrap();
+}
+
+/// Export level @index of @filename to @out_path as a NeoLemmix level, with its pieces from
+/// @style, or else the style @set_map gives its graphics set, or the usual one for its set.
+#[allow(clippy::too_many_arguments)]
+fn cmd_export_nxlv(data: &DataDir, filename: &str, index: usize, variant: Option<level::LevelVariant>, style: Option<&str>, set_map: Option<&SetMap>, out_path: &str, outputs: &PendingOutputs, log: &Log) -> Result<()> {
+    let level = read_level(data, filename, index, variant)?;
+    if let Some(special_graphics) = level.special_graphics_set() {
+        return Err(Error::data(format!("Level {} of {} uses special graphics {}, which can't be exported", index, filename, special_graphics)));
+    }
+    let style = match style.or_else(|| set_map.and_then(|set_map| set_map.style(level.graphics_set))).or_else(|| nxlv::default_style(&level)) {
+        Some(style) => style,
+        None => return Err(Error::usage(format!("No NeoLemmix style is known for {} graphics set {} (give one with --style)", level.variant.name(), level.ground_set()))),
+    };
+    log_info!(log, "Exporting level {}: \"{}\" to {} (style {})", index, level.name(), out_path, style);
+    nxlv::write_nxlv(&level, style, &mut outputs.create(out_path)).unwrap();
+    Ok(())
+}
+
+/// Count how often the levels in each of @filenames use each terrain piece and object of their
+/// graphics sets (from @ground_dir, see @set_map), and print a report for each set (or, with
+/// @json, a JSON list of them).
+fn cmd_stats_levels(data: &DataDir, filenames: &[&str], ground_dir: &DataDir, variant: Option<level::LevelVariant>, set_map: Option<&SetMap>, json: bool, log: &Log) -> Result<()> {
+    let mut usages: Vec<(SetFiles, level::SetUsage)> = Vec::new();
+    for filename in filenames {
+        let path = data.find_input(filename)?;
+        for (index, level) in read_levels(&path, variant)?.into_iter().enumerate() {
+            if let Some(level) = level {
+                let files = level_set_files(&level, &format!("level {} of {}", index, filename), set_map)?;
+                let position = match usages.iter().position(|(set_files, _)| *set_files == files) {
+                    Some(position) => position,
+                    None => {
+                        usages.push((files, level::SetUsage::default()));
+                        usages.len() - 1
+                    }
+                };
+                usages[position].1.add_level(&level);
+            }
+        }
+    }
+    let stdout = std::io::stdout();
+    if json {
+        let mut sets = Vec::new();
+        for (files, _) in &usages {
+            sets.push(decode_graphics_set_files(ground_dir, files, log)?);
+        }
+        let reports: Vec<_> = usages.iter().zip(&sets).map(|((files, usage), set)| (files, usage, &**set)).collect();
+        schemas::set_usages(&reports).write(&mut stdout.lock())?;
+        return Ok(());
+    }
+    for (files, usage) in &usages {
+        let set = decode_graphics_set_files(ground_dir, files, log)?;
+        let mut out = stdout.lock();
+        writeln!(out, "{} and {}, used by {} level{}:", files.ground_file, files.vgagr_file, usage.num_levels, if usage.num_levels == 1 { "" } else { "s" })?;
+        usage.write_report(&set, &mut out)?;
+    }
+    Ok(())
+}
+
+/// Compare the levels in @filename_a and @filename_b, pairing them up by index, and print what
+/// changed (or, if @brief is set, just which levels changed). Returns false if any did.
+fn cmd_diff_levels(data: &DataDir, filename_a: &str, filename_b: &str, brief: bool) -> Result<bool> {
+    let levels_a = read_levels(&data.find_input(filename_a)?, None)?;
+    let levels_b = read_levels(&data.find_input(filename_b)?, None)?;
+    let mut changed = Vec::new();
+    for index in 0..levels_a.len().max(levels_b.len()) {
+        let description = match (levels_a.get(index), levels_b.get(index)) {
+            (Some(Some(a)), Some(Some(b))) => {
+                let changes = a.diff(b);
+                if changes.is_empty() {
+                    continue;
+                }
+                format!("Level {} (\"{}\"): {}", index, a.name(), changes.join("; "))
+            }
+            (Some(_), None) => format!("Level {}: only in {}", index, filename_a),
+            (None, Some(_)) => format!("Level {}: only in {}", index, filename_b),
+            (Some(Some(_)), Some(None)) => format!("Level {}: not a level in {}", index, filename_b),
+            (Some(None), Some(Some(_))) => format!("Level {}: not a level in {}", index, filename_a),
+            _ => continue,
+        };
+        changed.push(index);
+        if !brief {
+            println!("{}", description);
+        }
+    }
+    if changed.is_empty() {
+        println!("No levels changed");
+    } else if brief {
+        let indices: Vec<String> = changed.iter().map(|index| index.to_string()).collect();
+        println!("Changed levels: {}", indices.join(", "));
+    }
+    Ok(changed.is_empty())
+}
+
+/// Print what sort of file @filename looks like, and its sections (as JSON, if @json is set).
+fn cmd_info(data: &DataDir, filename: &str, json: bool) -> Result<()> {
+    let path = data.find_input(filename)?;
+    let file_data = std::fs::read(&path).context(|| format!("Error opening {}", filename))?;
+    let info = file_info::FileInfo::identify(&file_data);
+    let stdout = std::io::stdout();
+    if json {
+        schemas::file_info(filename, &info).write(&mut stdout.lock())?;
+    } else {
+        info.write(filename, &mut stdout.lock())?;
+    }
+    Ok(())
+}
+
+/// Compare the sections of .dat files @filename_a and @filename_b, once they're decompressed, and
+/// print how each compares. Returns false if any differ.
+fn cmd_diff_dat(data: &DataDir, filename_a: &str, filename_b: &str) -> Result<bool> {
+    let mut sections = Vec::new();
+    for filename in [filename_a, filename_b] {
+        let path = data.find_input(filename)?;
+        let file_data = std::fs::read(&path).context(|| format!("Error opening {}", filename))?;
+        sections.push(dat_section::decompress_all(&file_data).context(|| format!("Error reading {}", filename))?);
+    }
+    let diffs = dat_diff::diff_sections(&sections[0], &sections[1]);
+    let stdout = std::io::stdout();
+    dat_diff::write_report(&diffs, &sections[0], &sections[1], filename_a, filename_b, &mut stdout.lock())?;
+    Ok(diffs.iter().all(|diff| *diff == dat_diff::SectionDiff::Identical))
+}
+
+/// Read every section of levelNNN.dat file @path, as a level for game @variant (or None, for
+/// any which aren't).
+fn read_levels(path: &Path, variant: Option<level::LevelVariant>) -> Result<Vec<Option<level::Level>>> {
+    let file_data = std::fs::read(path).context(|| format!("Error opening {}", path.display()))?;
+    let sections = dat_section::decompress_all(&file_data).context(|| format!("Error reading {}", path.display()))?;
+    Ok(sections.iter().map(|section_data| level::Level::from_bytes(section_data, variant).ok()).collect())
+}
+
+/// Read the names of the levels in levelNNN.dat file @path.
+fn read_level_names(path: &Path) -> Result<Vec<String>> {
+    Ok(read_levels(path, None)?.iter().map(|level| level.as_ref().map_or(String::new(), |level| level.name())).collect())
+}
+
+/// Print a table (or, with @json, a JSON list) of the levels in each of @filenames.
+fn cmd_list_levels(data: &DataDir, filenames: &[&str], json: bool, variant: Option<level::LevelVariant>, set_map: Option<&SetMap>) -> Result<()> {
+    let mut entries = Vec::new();
+    for filename in filenames {
+        let path = data.find_input(filename)?;
+        for (index, level) in read_levels(&path, variant)?.into_iter().enumerate() {
+            let ground_file = match (&level, set_map) {
+                (Some(level), Some(_)) => Some(level_set_files(level, &format!("level {} of {}", index, filename), set_map)?.ground_file),
+                _ => None,
+            };
+            entries.push(level::LevelListEntry { file_name: filename.to_string(), index, level, ground_file });
+        }
+    }
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    if json {
+        schemas::level_list(&entries).write(&mut out)?;
+    } else {
+        level::write_level_list(&entries, &mut out)?;
+    }
+    Ok(())
+}
+
+/// Extract oddtable.dat (@filename) to the text file @out_path. If @levels_dir is given, each
+/// entry is labelled with the level (from the levelNNN.dat files there) it goes with.
+fn cmd_extract_oddtable(data: &DataDir, filename: &str, levels_dir: Option<&str>, out_path: &str, outputs: &PendingOutputs, log: &Log) -> Result<()> {
+    let path = data.find_input(filename)?;
+    let data = std::fs::read(&path).context(|| format!("Error opening {}", filename))?;
+    let entries = oddtable::decode(&data).context(|| format!("Error decoding {}", filename))?;
+
+    let mut levels = Vec::new();
+    if let Some(levels_dir) = levels_dir {
+        let level_files = case_sensitivity::FileLocator::new(Path::new(levels_dir), 0);
+        let mut level_names = Vec::new();
+        for i in 0..entries.len() {
+            let (file_num, index) = (i / oddtable::LEVELS_PER_FILE, i % oddtable::LEVELS_PER_FILE);
+            let file_name = format!("level{:03}.dat", file_num);
+            if index == 0 {
+                level_names = match level_files.find(&file_name) {
+                    Ok(level_path) => read_level_names(&level_path)?,
+                    Err(err) => {
+                        log_warning!(log, "Warning: couldn't find {}: {}", file_name, err);
+                        Vec::new()
+                    }
+                };
+            }
+            levels.push(level_names.get(index).map(|name| oddtable::LevelRef { file_name, index, name: name.clone() }));
+        }
+    }
+
+    for (i, entry) in entries.iter().enumerate() {
+        for problem in entry.stat_problems() {
+            log_warning!(log, "Warning: {} in entry {} (the game may not accept it)", problem, i);
+        }
+    }
+
+    log_info!(log, "Writing {} entries to {}", entries.len(), out_path);
+    oddtable::write_text(&entries, &levels, &mut outputs.create(out_path)).unwrap();
+    Ok(())
+}
+
+/// Create oddtable.dat (@out_path) from the text file @filename.
+fn cmd_create_oddtable(filename: &str, out_path: &str, backup: bool, log: &Log) -> Result<()> {
+    let text = std::fs::read_to_string(filename).context(|| format!("Error opening {}", filename))?;
+    let entries = oddtable::parse_text(&text).map_err(|err| err.in_file(Path::new(filename)))?;
+    for (i, entry) in entries.iter().enumerate() {
+        for problem in entry.stat_problems() {
+            log_warning!(log, "Warning: {} in entry {} (the game may not accept it)", problem, i);
+        }
+    }
+    log_info!(log, "Writing {} entries to {}", entries.len(), out_path);
+    let mut data = backup::create_with_backup(Path::new(out_path), backup, log)?;
+    data.write_all(&oddtable::encode(&entries)).and_then(|()| data.finish()).context(|| format!("Error writing {}", out_path))
+}
+
+fn create_dat(name: &std::string::String, backup: bool, log: &Log) -> Result<()> {
     let dat_filename = format!("{}.dat", name);
     let dat_path = Path::new(&dat_filename);
 
-    let mut data = match File::create(dat_path) {
-        Err(err) => panic!("Error opening {}: {}", dat_filename, err),
-        Ok(file) => file,
-    };
+    let mut data = backup::create_with_backup(dat_path, backup, log)?;
 
     let mut section_num = 0;
     loop {
@@ -140,77 +1099,822 @@ fn create_dat(name: &std::string::String) {
         match section_uncomp_data {
             Err(_) => break,
             Ok(uncomp_data) => {
-                let section = DatSection::from_data(uncomp_data.as_slice(), uncomp_data.len());
-                section.write(&mut data).unwrap();
+                compress_section(&uncomp_data, &section_file_name, log).write(&mut data).context(|| format!("Error writing {}", dat_filename))?;
             }
         }
         section_num += 1;
     }
+    data.finish().context(|| format!("Error writing {}", dat_filename))
 }
 
-fn show_usage() {
-    println!("Usage:");
-    println!("\tmodlem extract-set <n>");
-    println!("\t\tExtracts graphics set <n>");
-    println!("\tmodlem create-set <script-name>");
-    println!("\t\tCreates a graphics set from a script file.");
-    println!("\tmodlem extract-main");
-    println!("\t\tExtracts main.dat into its constituent files.");
-    println!("\tmodlem create-main");
-    println!("\t\tCreates a main.dat from bitmaps in the current directory.");
-    println!("\tmodlem extract-dat <name>");
-    println!("\t\tDecompresses <name>.dat into <name>.000, <name>.001, etc.");
-    println!("\tmodlem create-dat <name>");
-    println!("\t\tCompressed <name>.000, <name>.001, etc. into <name>.dat");
+/// Extract the special graphics in vgaspec@num.dat to the image @out_path.
+fn cmd_extract_vgaspec(data: &DataDir, num: usize, out_path: &str, outputs: &PendingOutputs, log: &Log) -> Result<()> {
+    let filename = format!("vgaspec{}.dat", num);
+    let path = data.find_input(&filename)?;
+    let mut data = binary_io::open_buffered(&path).context(|| format!("Error opening {}", filename))?;
+    let section_data = DatSection::from_file(&mut data).and_then(|mut section| section.decompress())
+        .context(|| format!("Error decompressing {}", filename))?;
+    let vgaspec = vgaspec::VgaSpec::decode(&section_data).context(|| format!("Error decoding {}", filename))?;
+    log_info!(log, "{} -> {}", filename, out_path);
+    save_image_by_extension(&vgaspec.image, out_path, outputs)
 }
 
-fn main() {
-    let args: Vec<std::string::String> = std::env::args().collect(); /* Skip the application name. */
+/// Create the special graphics file @out_path from the image @image_path.
+fn cmd_create_vgaspec(image_path: &str, out_path: &str, backup: bool, log: &Log) -> Result<()> {
+    let image = binary_io::open_buffered(image_path).and_then(|mut file| planar_bmp::load_image(&mut file))
+        .context(|| format!("Error reading {}", image_path))?;
+    let vgaspec = vgaspec::VgaSpec::from_image(&image).context(|| format!("Can't use {} as special graphics", image_path))?;
+    log_info!(log, "{} -> {}", image_path, out_path);
+    let mut out_file = backup::create_with_backup(Path::new(out_path), backup, log)?;
+    compress_section(&vgaspec.encode(), image_path, log).write(&mut out_file)
+        .and_then(|()| out_file.finish()).context(|| format!("Error writing {}", out_path))
+}
+
+/// Run each command in the batch file @filename in turn, with @global_args (the options which
+/// came before "batch") before each, and the game's files read through @cache. Stops at the
+/// first which fails (or finds problems) unless @keep_going is set, then lists how each went.
+/// Returns false if any found problems, but none failed.
+fn cmd_batch(filename: &str, global_args: &[String], keep_going: bool, cache: &Rc<Cache>, log: &Log) -> Result<bool> {
+    let text = std::fs::read_to_string(filename).context(|| format!("Error opening {}", filename))?;
+    let commands = batch::parse(&text).map_err(|err| Error::data(format!("{}:{}", filename, err)))?;
+
+    let mut outcomes = Vec::new();
+    let mut first_failure = None;
+    let mut stopped = false;
+    for command in &commands {
+        if stopped {
+            outcomes.push(batch::Outcome::Skipped);
+            continue;
+        }
+        log_detail!(log, "{}:{}: modlem {}", filename, command.line, command.args.join(" "));
+        let result = if command.args[0] == "batch" {
+            Err(Error::usage("A batch file can't run another batch file"))
+        } else {
+            cache.next_command();
+            let args = std::iter::once("modlem".to_string()).chain(global_args.iter().cloned()).chain(command.args.iter().cloned());
+            run(args.collect(), cache)
+        };
+        let outcome = match result {
+            Ok(true) => batch::Outcome::Ok,
+            Ok(false) => batch::Outcome::Problems,
+            Err(err) => {
+                eprintln!("modlem: error: {}:{}: {}", filename, command.line, err);
+                first_failure.get_or_insert(err.kind);
+                batch::Outcome::Failed
+            }
+        };
+        stopped = outcome != batch::Outcome::Ok && !keep_going;
+        outcomes.push(outcome);
+    }
+
+    for (command, outcome) in commands.iter().zip(&outcomes) {
+        println!("{:>5}  {:<8}  {}", command.line, outcome.name(), command.args.join(" "));
+    }
+    let count = |outcome| outcomes.iter().filter(|&&other| other == outcome).count();
+    match first_failure {
+        Some(kind) => Err(Error::new(kind, format!("{} of the {} commands in {} failed", count(batch::Outcome::Failed), commands.len(), filename))),
+        None => Ok(count(batch::Outcome::Problems) == 0),
+    }
+}
+
+/// Parse @arg, the argument to @option, as a number.
+fn parse_number<T: std::str::FromStr>(option: &str, arg: &str) -> Result<T> {
+    arg.parse::<T>().map_err(|_| Error::usage(format!("Invalid {} \"{}\" (expected a number)", option, arg)))
+}
+
+/// The name of @filename without its directory or extension, to name the files made from it.
+fn file_stem(filename: &str) -> Result<String> {
+    match Path::new(filename).file_stem() {
+        Some(stem) => Ok(stem.to_string_lossy().to_string()),
+        None => Err(Error::usage(format!("\"{}\" isn't a file name", filename))),
+    }
+}
+
+/// The value of the option just taken from @arg_iter. CommandSpec::check_args() has already
+/// made sure that every option which takes a value has one, and that there are no others, so
+/// the commands' own option parsing can't go wrong.
+fn option_value<'a>(arg_iter: &mut impl Iterator<Item = &'a String>) -> &'a String {
+    arg_iter.next().unwrap_or_else(|| unreachable!("check_args() doesn't let options through without their values"))
+}
+
+/// Parse the argument to --format.
+fn parse_image_format(name : &str) -> Result<ImageFormat> {
+    match ImageFormat::from_name(name) {
+        Some(format) => Ok(format),
+        None => Err(Error::usage(format!("Unknown image format \"{}\" (expected bmp or png)", name))),
+    }
+}
+
+/// Parse the argument to --set-map: either a list of "num=groundfile", or a set map file.
+fn parse_set_map_arg(data: &DataDir, arg: &str) -> Result<SetMap> {
+    if arg.contains('=') {
+        return SetMap::from_arg(arg).map_err(Error::usage);
+    }
+    let path = data.find_input(arg)?;
+    let text = std::fs::read_to_string(&path).context(|| format!("Error reading set map {}", arg))?;
+    Ok(SetMap::parse(&mut parser::Lexer::from_str(&text)).map_err(|err| err.in_file(&path))?)
+}
+
+/// Parse the argument to --variant.
+fn parse_variant_arg(name : &str) -> Result<level::LevelVariant> {
+    match level::LevelVariant::from_name(name) {
+        Some(variant) => Ok(variant),
+        None => Err(Error::usage(format!("Unknown variant \"{}\" (expected original, onml or holiday)", name))),
+    }
+}
+
+/// Write the usage of modlem, and of each of its commands, to @out.
+fn write_usage(out: &mut dyn Write) {
+    writeln!(out, "Usage:").unwrap();
+    writeln!(out, "\tmodlem [--data-dir <dir>] [--max-depth <n> | --recursive] [--quiet | --verbose...] <command> ...").unwrap();
+    writeln!(out, "\t\t--data-dir looks for the game's files (main.dat, groundNo.dat, vgagrN.dat, levelNNN.dat, and so on) in <dir>").unwrap();
+    writeln!(out, "\t\tinstead of the current directory. Files modlem writes still go where they would without it.").unwrap();
+    writeln!(out, "\t\tSubdirectories of <dir> are searched too, {} levels deep, or <n> levels deep with --max-depth (0 for none),", DEFAULT_DATA_DIR_DEPTH).unwrap();
+    writeln!(out, "\t\tor all the way down with --recursive. The current directory's subdirectories are only searched if asked.").unwrap();
+    writeln!(out, "\t\tWithout --data-dir, files not in the current directory are looked for in $LEMMINGS_DIR (or $MODLEM_DATA_DIR), if it's set.").unwrap();
+    writeln!(out, "\t\tBy default, a line is printed for each file written, along with any warnings. --quiet (-q) only prints errors,").unwrap();
+    writeln!(out, "\t\t--verbose (-v) also prints the details (such as each frame extracted), and -vv prints debugging information too.").unwrap();
+    writeln!(out, "\t\tThese can also go after the command.").unwrap();
+    writeln!(out, "\tmodlem help [<command>]").unwrap();
+    writeln!(out, "\t\tShows this, or just the usage of <command> (as does modlem <command> --help).").unwrap();
+    for command in commands::COMMANDS {
+        command.write_usage(out);
+    }
+    writeln!(out, "create-dat, create-levels, repack-levels, create-oddtable, create-vgaspec, create-set and create-main rename any file they'd overwrite").unwrap();
+    writeln!(out, "to <file>.bak first, unless --no-backup is given.").unwrap();
+    writeln!(out, "The other commands which write files don't write any of them if any are already there, unless --force is given").unwrap();
+    writeln!(out, "to overwrite them, or --skip-existing to write just the rest.").unwrap();
+}
+
+/// Write the files a command has extracted to @outputs, dealing with any already there as
+/// @overwrite says. If it refuses to overwrite them, the error says which they are.
+fn write_outputs(outputs: &PendingOutputs, overwrite: OverwritePolicy, log: &Log) -> Result<()> {
+    outputs.write_all(overwrite, log)?;
+    Ok(())
+}
+
+/// The error for the arguments to @command being wrong, because of @problem, with its usage.
+fn usage_error(command: &commands::CommandSpec, problem: &str) -> Error {
+    let mut usage = Vec::new();
+    command.write_usage(&mut usage);
+    Error::usage(format!("{}\nUsage:\n{}", problem, String::from_utf8_lossy(&usage).trim_end()))
+}
+
+/// The global options which take a value.
+const GLOBAL_OPTIONS: &[&str] = &["--data-dir", "--max-depth"];
+
+/// Which of @args (the program's name, any global options, then the command and its arguments)
+/// are the values of options, so shouldn't be taken as options themselves.
+fn option_values(args: &[String]) -> Vec<bool> {
+    let mut values = vec![false; args.len()];
+    let mut options = GLOBAL_OPTIONS;
+    let mut command_found = false;
+    let mut i = 1;
+    while i < args.len() {
+        if options.contains(&args[i].as_str()) {
+            if let Some(value) = values.get_mut(i + 1) {
+                *value = true;
+            }
+            i += 1;
+        } else if !command_found && !args[i].starts_with('-') {
+            command_found = true;
+            options = commands::find_command(&args[i]).map_or(&[], |command| command.options);
+        }
+        i += 1;
+    }
+    values
+}
+
+/// Run the command given by @args (which start with the program's name), loading the game's
+/// files through @cache. Returns false if it ran, but found something wrong (as check-level and
+/// diff-levels do).
+fn run(mut args: Vec<String>, cache: &Rc<Cache>) -> Result<bool> {
+    // The options which apply to every command, for those a batch file runs.
+    let mut global_args = Vec::new();
+
+    // --quiet and --verbose can go anywhere, as they're useful with every command.
+    let mut verbosity = Verbosity::Progress.level();
+    let mut values = option_values(&args).into_iter();
+    args.retain(|arg| {
+        if values.next() == Some(true) {
+            return true;
+        }
+        match arg.as_str() {
+            "-q" | "--quiet" => verbosity = Verbosity::Errors.level(),
+            "--verbose" => verbosity += 1,
+            _ if arg.len() > 1 && arg.starts_with('-') && arg[1..].chars().all(|c| c == 'v') => verbosity += arg.len() - 1,
+            _ => return true,
+        }
+        global_args.push(arg.clone());
+        false
+    });
+    let log = Log::new(Verbosity::from_level(verbosity));
+
+    // Global options come before the command.
+    let mut data_dir = None;
+    let mut max_depth = None;
+    while args.len() > 1 && args[1].starts_with('-') {
+        let option = args.remove(1);
+        match option.as_str() {
+            "--data-dir" => {
+                if args.len() < 2 {
+                    return Err(Error::usage("--data-dir requires a directory"));
+                }
+                data_dir = Some(args.remove(1));
+                global_args.extend_from_slice(&[option, data_dir.clone().unwrap()]);
+            }
+            "--max-depth" => {
+                if args.len() < 2 {
+                    return Err(Error::usage("--max-depth requires a number"));
+                }
+                let depth = args.remove(1);
+                max_depth = Some(parse_number("--max-depth", &depth)?);
+                global_args.extend_from_slice(&[option, depth]);
+            }
+            "--recursive" => {
+                max_depth = Some(usize::MAX);
+                global_args.push(option);
+            }
+            "--help" | "-h" => {
+                write_usage(&mut std::io::stdout());
+                return Ok(true);
+            }
+            _ => return Err(Error::usage(format!("Unknown option \"{}\" (the command goes after it)", option))),
+        }
+    }
+    // Only --data-dir (or $LEMMINGS_DIR) is looked through by default: the current directory may
+    // well have other copies of the game's files in it, which modlem has extracted.
+    let data = match data_dir {
+        Some(data_dir) => DataDir::new(cache, &data_dir, max_depth.unwrap_or(DEFAULT_DATA_DIR_DEPTH)),
+        None => DataDir::from_env(cache, max_depth.unwrap_or(0), max_depth.unwrap_or(DEFAULT_DATA_DIR_DEPTH)),
+    };
 
     if args.len() < 2 {
-        show_usage();
-        return;
+        write_usage(&mut std::io::stdout());
+        return Ok(true);
     }
 
+    if args[1] == "help" {
+        match args.get(2) {
+            None => write_usage(&mut std::io::stdout()),
+            Some(name) => match commands::find_command(name) {
+                Some(command) => command.write_usage(&mut std::io::stdout()),
+                None => return Err(Error::usage(format!("Unknown command \"{}\"", name))),
+            },
+        }
+        return Ok(true);
+    }
+
+    let command = match commands::find_command(&args[1]) {
+        Some(command) => command,
+        None => return Err(Error::usage(format!("Unknown command \"{}\" (modlem help lists them)", args[1]))),
+    };
+    // Put the arguments in order, so the options can be handled after the rest. As they've all
+    // been checked, each command below can assume its options are the ones it expects.
+    match command.check_args(&args[2..]) {
+        Ok(command_args) => {
+            args.truncate(2);
+            args.extend(command_args);
+        }
+        Err(commands::ArgsError::Help) => {
+            command.write_usage(&mut std::io::stdout());
+            return Ok(true);
+        }
+        Err(commands::ArgsError::Invalid(problem)) => return Err(usage_error(command, &problem)),
+    }
+
+    // Commands which extract something all collect their files in outputs, which are only
+    // written at the end, so that they can all be checked against what's already there first.
+    let outputs = PendingOutputs::new();
+    let mut overwrite = None;
+    let mut conflicting = false;
+    let mut values = option_values(&args).into_iter();
+    args.retain(|arg| {
+        if values.next() == Some(true) {
+            return true;
+        }
+        let policy = match arg.as_str() {
+            "--force" => OverwritePolicy::Force,
+            "--skip-existing" => OverwritePolicy::SkipExisting,
+            _ => return true,
+        };
+        conflicting |= overwrite.is_some_and(|overwrite| overwrite != policy);
+        overwrite = Some(policy);
+        false
+    });
+    if conflicting {
+        return Err(usage_error(command, "--force and --skip-existing can't both be given"));
+    }
+    let overwrite = overwrite.unwrap_or_default();
+
     let command_name = &args[1];
 
     match command_name.as_str() {
         "extract-set" => {
-            let set_num = args[2].parse::<usize>().unwrap();
-            println!("Extracting graphics set {}…", set_num);
-            cmd_extract_graphics_set(set_num);
+            let set_nums = match args[2].as_str() {
+                "all" => data.graphics_sets()?,
+                set_num => vec![parse_number("graphics set", set_num)?],
+            };
+            if set_nums.is_empty() {
+                return Err(Error::io("There aren't any graphics sets (ground?o.dat files) to extract"));
+            }
+            let mut atlas_mode = false;
+            let mut check_masks = false;
+            let mut png = false;
+            let mut arg_iter = args.iter().skip(3);
+            while let Some(arg) = arg_iter.next() {
+                match arg.as_str() {
+                    "--atlas" => atlas_mode = true,
+                    "--check-masks" => check_masks = true,
+                    "--png" => png = true,
+                    "--format" => png = parse_image_format(option_value(&mut arg_iter))? == ImageFormat::Png,
+                    _ => unreachable!("check_args() doesn't let {} through", arg),
+                }
+            }
+            for set_num in set_nums {
+                log_info!(log, "Extracting graphics set {}…", set_num);
+                if atlas_mode {
+                    cmd_extract_graphics_set_atlas(&data, set_num, png, &outputs, &log)?;
+                } else {
+                    cmd_extract_graphics_set(&data, set_num, Path::new(""), check_masks, png, &outputs, &log)?;
+                }
+            }
         }
         "create-set" => {
             let script_name = &args[2];
-            println!("Creating graphics set from \"{}\"", script_name);
-            cmd_create_graphics_set(script_name);
+            let mut backup = true;
+            let mut strict = false;
+            for arg in args.iter().skip(3) {
+                match arg.as_str() {
+                    "--no-backup" => backup = false,
+                    "--strict" => strict = true,
+                    _ => unreachable!("check_args() doesn't let {} through", arg),
+                }
+            }
+            log_info!(log, "Creating graphics set from \"{}\"", script_name);
+            cmd_create_graphics_set(script_name, backup, strict, &log)?;
         }
         "extract-main" => {
+            let mut options = main_dat::ExtractOptions::builder().log(log);
+            let mut arg_iter = args.iter().skip(2);
+            while let Some(arg) = arg_iter.next() {
+                options = match arg.as_str() {
+                    "--xmas" | "--christmas" => options.xmas_mode(true),
+                    "--raw-sounds" => options.raw_sounds(true),
+                    "--anim-gif" => options.anim_gif(true),
+                    "--png" => options.format(ImageFormat::Png),
+                    "--list" => options.list_only(true),
+                    "--split-glyphs" => options.split_glyphs(true),
+                    "--split-panel" => options.split_panel(true),
+                    "--player2-palette" => options.player2_palette(true),
+                    "--atlas" => options.atlas(true),
+                    "--palette-file" => options.palette_file(option_value(&mut arg_iter)),
+                    "--format" => options.format(parse_image_format(option_value(&mut arg_iter))?),
+                    "--out-dir" => options.output_dir(option_value(&mut arg_iter)),
+                    "--prefix" => options.prefix(option_value(&mut arg_iter)),
+                    "--spec" => options.spec(MainDatSpec::from_file(Path::new(option_value(&mut arg_iter)))?),
+                    "--ohno" => options.variant(MainDatVariant::OhNo),
+                    "--cga" => options.variant(MainDatVariant::Cga),
+                    _ => unreachable!("check_args() doesn't let {} through", arg),
+                }
+            }
+            cmd_extract_main_dat(&data, &options.build(), &outputs)?;
+        }
+        "create-main" => {
+            let mut variant = None;
             let mut xmas_mode = false;
-            let arg_iter = args.iter().skip(2);
-            for arg in arg_iter {
+            let mut spec_path = None;
+            let mut out_path = None;
+            let mut backup = true;
+            let mut palette_file = None;
+            let mut options = main_dat::CreateOptions::builder().log(log);
+            let mut arg_iter = args.iter().skip(2);
+            while let Some(arg) = arg_iter.next() {
                 match arg.as_str() {
+                    "--spec" => spec_path = Some(option_value(&mut arg_iter).as_str()),
+                    "--out" => out_path = Some(option_value(&mut arg_iter).as_str()),
+                    "--no-backup" => backup = false,
+                    "--palette-file" => palette_file = Some(option_value(&mut arg_iter)),
+                    "--in-dir" => options = options.input_dir(option_value(&mut arg_iter)),
+                    "--prefix" => options = options.prefix(option_value(&mut arg_iter)),
+                    "--base" => options = options.base(read_base_main_dat(option_value(&mut arg_iter))?),
+                    "--only" => options = options.only(option_value(&mut arg_iter)),
                     "--xmas" | "--christmas" => xmas_mode = true,
-                    _ => panic!("Unknown argument \"{}\"", arg),
+                    "--raw-sounds" => options = options.raw_sounds(true),
+                    "--format" => options = options.format(parse_image_format(option_value(&mut arg_iter))?),
+                    "--ohno" => variant = Some(MainDatVariant::OhNo),
+                    "--cga" => variant = Some(MainDatVariant::Cga),
+                    _ => unreachable!("check_args() doesn't let {} through", arg),
                 }
             }
-            cmd_extract_main_dat(xmas_mode);
+            // The palette isn't stored in main.dat, so --xmas only picks the palette the images'
+            // colours are checked against.
+            let variant = variant.unwrap_or(MainDatVariant::Lemmings);
+            let mut palettes = main_dat::MainDatPalettes::preset(xmas_mode);
+            if let Some(palette_file) = palette_file {
+                palettes.load_overrides(Path::new(palette_file))?;
+            }
+            options = options.palettes(palettes);
+            cmd_create_main_dat(variant, spec_path, out_path, backup, &options.build())?;
         }
-        "create-main" => {
-            cmd_create_main_dat();
+        "roundtrip-main" => {
+            let mut variant = None;
+            let mut xmas_mode = false;
+            let mut spec_path = None;
+            let mut arg_iter = args.iter().skip(2);
+            while let Some(arg) = arg_iter.next() {
+                match arg.as_str() {
+                    "--spec" => spec_path = Some(option_value(&mut arg_iter).as_str()),
+                    "--xmas" | "--christmas" => xmas_mode = true,
+                    "--ohno" => variant = Some(MainDatVariant::OhNo),
+                    "--cga" => variant = Some(MainDatVariant::Cga),
+                    _ => unreachable!("check_args() doesn't let {} through", arg),
+                }
+            }
+            cmd_roundtrip_main_dat(&data, variant, xmas_mode, spec_path, &log)?;
+        }
+        "extract-all" => {
+            let mut out_dir = ".";
+            let mut arg_iter = args.iter().skip(2);
+            while let Some(arg) = arg_iter.next() {
+                match arg.as_str() {
+                    "--out-dir" => out_dir = option_value(&mut arg_iter).as_str(),
+                    _ => unreachable!("check_args() doesn't let {} through", arg),
+                }
+            }
+            let extracted_all = cmd_extract_all(&data, Path::new(out_dir), &outputs, &log);
+            // What did extract is still written.
+            write_outputs(&outputs, overwrite, &log)?;
+            extracted_all?;
         }
         "extract-dat" => {
             let dat_name = &args[2];
-            println!("Extracting {}.dat…", dat_name);
-            extract_dat(dat_name);
+            log_info!(log, "Extracting {}.dat…", dat_name);
+            extract_dat(&data, dat_name, &outputs, &log)?;
+        }
+        "extract-levels" => {
+            let filename = &args[2];
+            let mut raw = false;
+            let mut variant = None;
+            let mut arg_iter = args.iter().skip(3);
+            while let Some(arg) = arg_iter.next() {
+                match arg.as_str() {
+                    "--raw" => raw = true,
+                    "--variant" => variant = Some(parse_variant_arg(option_value(&mut arg_iter))?),
+                    _ => unreachable!("check_args() doesn't let {} through", arg),
+                }
+            }
+            log_info!(log, "Extracting levels from {}…", filename);
+            cmd_extract_levels(&data, filename, Path::new(""), raw, variant, &outputs, &log)?;
+        }
+        "repack-levels" => {
+            let manifest_path = &args[2];
+            let mut out_dir = ".";
+            let mut prefix = "level";
+            let mut per_file = oddtable::LEVELS_PER_FILE;
+            let mut target = level::LevelVariant::Original;
+            let mut backup = true;
+            let mut arg_iter = args.iter().skip(3);
+            while let Some(arg) = arg_iter.next() {
+                match arg.as_str() {
+                    "--out-dir" => out_dir = option_value(&mut arg_iter).as_str(),
+                    "--prefix" => prefix = option_value(&mut arg_iter).as_str(),
+                    "--per-file" => per_file = parse_number("--per-file", option_value(&mut arg_iter))?,
+                    "--variant" => target = parse_variant_arg(option_value(&mut arg_iter))?,
+                    "--no-backup" => backup = false,
+                    _ => unreachable!("check_args() doesn't let {} through", arg),
+                }
+            }
+            if per_file == 0 {
+                return Err(Error::usage("--per-file must be at least 1"));
+            }
+            cmd_repack_levels(&data, manifest_path, out_dir, prefix, per_file, target, backup, &log)?;
+        }
+        "extract-lvl" => {
+            let filename = &args[2];
+            let index = parse_number("level index", &args[3])?;
+            let mut out_path = None;
+            let mut arg_iter = args.iter().skip(4);
+            while let Some(arg) = arg_iter.next() {
+                match arg.as_str() {
+                    "-o" | "--out" => out_path = Some(option_value(&mut arg_iter).to_string()),
+                    _ => unreachable!("check_args() doesn't let {} through", arg),
+                }
+            }
+            let out_path = match out_path {
+                Some(out_path) => out_path,
+                None => format!("{}.{:03}.lvl", file_stem(filename)?, index),
+            };
+            cmd_extract_lvl(&data, filename, index, &out_path, &outputs, &log)?;
+        }
+        "create-levels" => {
+            let name = &args[2];
+            let mut backup = true;
+            let mut strict = false;
+            let mut ground_dir = None;
+            let mut arg_iter = args.iter().skip(3);
+            while let Some(arg) = arg_iter.next() {
+                match arg.as_str() {
+                    "--no-backup" => backup = false,
+                    "--strict" => strict = true,
+                    "--ground-dir" => ground_dir = Some(option_value(&mut arg_iter).as_str()),
+                    _ => unreachable!("check_args() doesn't let {} through", arg),
+                }
+            }
+            log_info!(log, "Creating {}.dat…", name);
+            cmd_create_levels(&data, name, ground_dir, strict, backup, &log)?;
+        }
+        "list-levels" => {
+            let mut filenames = Vec::new();
+            let mut json = false;
+            let mut set_map = None;
+            let mut variant = None;
+            let mut arg_iter = args.iter().skip(2);
+            while let Some(arg) = arg_iter.next() {
+                match arg.as_str() {
+                    "--json" => json = true,
+                    "--set-map" => set_map = Some(parse_set_map_arg(&data, option_value(&mut arg_iter))?),
+                    "--variant" => variant = Some(parse_variant_arg(option_value(&mut arg_iter))?),
+                    _ if !arg.starts_with('-') => filenames.push(arg.as_str()),
+                    _ => unreachable!("check_args() doesn't let {} through", arg),
+                }
+            }
+            cmd_list_levels(&data, &filenames, json, variant, set_map.as_ref())?;
+        }
+        "new-level" => {
+            let mut template = None;
+            let mut blank = false;
+            let mut graphics_set = 0;
+            let mut variant = None;
+            let mut out_path = "level.txt";
+            let mut arg_iter = args.iter().skip(2);
+            while let Some(arg) = arg_iter.next() {
+                match arg.as_str() {
+                    "--template" => template = Some(option_value(&mut arg_iter).as_str()),
+                    "--blank" => blank = true,
+                    "--set" => graphics_set = parse_number("--set", option_value(&mut arg_iter))?,
+                    "--variant" => variant = Some(parse_variant_arg(option_value(&mut arg_iter))?),
+                    "-o" | "--out" => out_path = option_value(&mut arg_iter).as_str(),
+                    _ => unreachable!("check_args() doesn't let {} through", arg),
+                }
+            }
+            let level = match (template, blank) {
+                (Some(template), false) => {
+                    let (filename, index) = match template.rsplit_once(':') {
+                        Some((filename, index)) => (filename, parse_number("template level index", index)?),
+                        None => return Err(Error::usage(format!("Invalid template \"{}\" (should be <file>:<index>)", template))),
+                    };
+                    read_level(&data, filename, index, variant)?
+                }
+                (None, true) => level::Level::blank(graphics_set, variant.unwrap_or(level::LevelVariant::Original)).map_err(Error::usage)?,
+                _ => return Err(usage_error(command, "new-level requires either --template <file>:<index> or --blank")),
+            };
+            cmd_new_level(&level, out_path, &outputs, &log);
+        }
+        "export-nxlv" => {
+            let filename = &args[2];
+            let index = parse_number("level index", &args[3])?;
+            let mut variant = None;
+            let mut style = None;
+            let mut set_map = None;
+            let mut out_path = None;
+            let mut arg_iter = args.iter().skip(4);
+            while let Some(arg) = arg_iter.next() {
+                match arg.as_str() {
+                    "--variant" => variant = Some(parse_variant_arg(option_value(&mut arg_iter))?),
+                    "--style" => style = Some(option_value(&mut arg_iter).as_str()),
+                    "--set-map" => set_map = Some(parse_set_map_arg(&data, option_value(&mut arg_iter))?),
+                    "-o" | "--out" => out_path = Some(option_value(&mut arg_iter).to_string()),
+                    _ => unreachable!("check_args() doesn't let {} through", arg),
+                }
+            }
+            let out_path = match out_path {
+                Some(out_path) => out_path,
+                None => format!("{}.{:03}.nxlv", file_stem(filename)?, index),
+            };
+            cmd_export_nxlv(&data, filename, index, variant, style, set_map.as_ref(), &out_path, &outputs, &log)?;
+        }
+        "stats-levels" => {
+            let mut filenames = Vec::new();
+            let mut ground_dir = None;
+            let mut variant = None;
+            let mut set_map = None;
+            let mut json = false;
+            let mut arg_iter = args.iter().skip(2);
+            while let Some(arg) = arg_iter.next() {
+                match arg.as_str() {
+                    "--ground-dir" => ground_dir = Some(option_value(&mut arg_iter).as_str()),
+                    "--variant" => variant = Some(parse_variant_arg(option_value(&mut arg_iter))?),
+                    "--set-map" => set_map = Some(parse_set_map_arg(&data, option_value(&mut arg_iter))?),
+                    "--json" => json = true,
+                    _ if !arg.starts_with('-') => filenames.push(arg.as_str()),
+                    _ => unreachable!("check_args() doesn't let {} through", arg),
+                }
+            }
+            cmd_stats_levels(&data, &filenames, &data.ground_dir(ground_dir), variant, set_map.as_ref(), json, &log)?;
+        }
+        "diff-levels" => {
+            let mut filenames = Vec::new();
+            let mut brief = false;
+            for arg in args.iter().skip(2) {
+                match arg.as_str() {
+                    "--brief" => brief = true,
+                    _ if !arg.starts_with('-') => filenames.push(arg.as_str()),
+                    _ => unreachable!("check_args() doesn't let {} through", arg),
+                }
+            }
+            if !cmd_diff_levels(&data, filenames[0], filenames[1], brief)? {
+                return Ok(false);
+            }
+        }
+        "check-level" => {
+            let filename = &args[2];
+            let mut ground_dir = None;
+            let mut set_map = None;
+            let mut variant = None;
+            let mut arg_iter = args.iter().skip(3);
+            while let Some(arg) = arg_iter.next() {
+                match arg.as_str() {
+                    "--ground-dir" => ground_dir = Some(option_value(&mut arg_iter).as_str()),
+                    "--set-map" => set_map = Some(parse_set_map_arg(&data, option_value(&mut arg_iter))?),
+                    "--variant" => variant = Some(parse_variant_arg(option_value(&mut arg_iter))?),
+                    _ => unreachable!("check_args() doesn't let {} through", arg),
+                }
+            }
+            if !cmd_check_level(&data, filename, &data.ground_dir(ground_dir), variant, set_map.as_ref(), &log)? {
+                return Ok(false);
+            }
+        }
+        "render-level" => {
+            let filename = &args[2];
+            let index = parse_number("level index", &args[3])?;
+            let mut ground_dir = None;
+            let mut set_map = None;
+            let mut variant = None;
+            let mut out_path = None;
+            let mut mask_path = None;
+            let mut minimap_path = None;
+            let mut tint_steel = false;
+            let mut show_triggers = false;
+            let mut arg_iter = args.iter().skip(4);
+            while let Some(arg) = arg_iter.next() {
+                match arg.as_str() {
+                    "--ground-dir" => ground_dir = Some(option_value(&mut arg_iter).as_str()),
+                    "--set-map" => set_map = Some(parse_set_map_arg(&data, option_value(&mut arg_iter))?),
+                    "--variant" => variant = Some(parse_variant_arg(option_value(&mut arg_iter))?),
+                    "-o" | "--out" => out_path = Some(option_value(&mut arg_iter).to_string()),
+                    "--mask" => mask_path = Some(option_value(&mut arg_iter).as_str()),
+                    "--minimap" => minimap_path = Some(option_value(&mut arg_iter).as_str()),
+                    "--steel" => tint_steel = true,
+                    "--show-triggers" => show_triggers = true,
+                    _ => unreachable!("check_args() doesn't let {} through", arg),
+                }
+            }
+            let out_path = match out_path {
+                Some(out_path) => out_path,
+                None => format!("{}.{:03}.png", file_stem(filename)?, index),
+            };
+            cmd_render_level(&data, filename, index, variant, &data.ground_dir(ground_dir), set_map.as_ref(), &RenderLevelOutputs { out_path, mask_path, minimap_path, tint_steel, show_triggers }, &outputs, &log)?;
+        }
+        "render-all-levels" => {
+            let mut filenames = Vec::new();
+            let mut ground_dir = None;
+            let mut out_dir = ".";
+            let mut set_map = None;
+            let mut variant = None;
+            let mut tint_steel = false;
+            let mut show_triggers = false;
+            let mut arg_iter = args.iter().skip(2);
+            while let Some(arg) = arg_iter.next() {
+                match arg.as_str() {
+                    "--ground-dir" => ground_dir = Some(option_value(&mut arg_iter).as_str()),
+                    "--out-dir" => out_dir = option_value(&mut arg_iter).as_str(),
+                    "--set-map" => set_map = Some(parse_set_map_arg(&data, option_value(&mut arg_iter))?),
+                    "--variant" => variant = Some(parse_variant_arg(option_value(&mut arg_iter))?),
+                    "--steel" => tint_steel = true,
+                    "--show-triggers" => show_triggers = true,
+                    _ if !arg.starts_with('-') => filenames.push(arg.as_str()),
+                    _ => unreachable!("check_args() doesn't let {} through", arg),
+                }
+            }
+            let options = RenderLevelOutputs { out_path: String::new(), mask_path: None, minimap_path: None, tint_steel, show_triggers };
+            cmd_render_all_levels(&data, &filenames, variant, &data.ground_dir(ground_dir), set_map.as_ref(), out_dir, &options, &outputs, &log)?;
+        }
+        "extract-vgaspec" => {
+            let num = parse_number("special graphics number", &args[2])?;
+            let mut out_path = format!("vgaspec{}.png", num);
+            let mut arg_iter = args.iter().skip(3);
+            while let Some(arg) = arg_iter.next() {
+                match arg.as_str() {
+                    "-o" | "--out" => out_path = option_value(&mut arg_iter).to_string(),
+                    _ => unreachable!("check_args() doesn't let {} through", arg),
+                }
+            }
+            cmd_extract_vgaspec(&data, num, &out_path, &outputs, &log)?;
+        }
+        "create-vgaspec" => {
+            let image_path = &args[2];
+            let mut out_path = None;
+            let mut backup = true;
+            let mut arg_iter = args.iter().skip(3);
+            while let Some(arg) = arg_iter.next() {
+                match arg.as_str() {
+                    "-o" | "--out" => out_path = Some(option_value(&mut arg_iter).to_string()),
+                    "--no-backup" => backup = false,
+                    _ => unreachable!("check_args() doesn't let {} through", arg),
+                }
+            }
+            let out_path = out_path.unwrap_or_else(|| Path::new(image_path).with_extension("dat").to_string_lossy().to_string());
+            cmd_create_vgaspec(image_path, &out_path, backup, &log)?;
+        }
+        "extract-oddtable" => {
+            let mut filename = "oddtable.dat";
+            let mut levels_dir = None;
+            let mut out_path = "oddtable.txt";
+            let mut arg_iter = args.iter().skip(2);
+            while let Some(arg) = arg_iter.next() {
+                match arg.as_str() {
+                    "--levels" => levels_dir = Some(option_value(&mut arg_iter).as_str()),
+                    "-o" | "--out" => out_path = option_value(&mut arg_iter).as_str(),
+                    _ if !arg.starts_with('-') => filename = arg.as_str(),
+                    _ => unreachable!("check_args() doesn't let {} through", arg),
+                }
+            }
+            cmd_extract_oddtable(&data, filename, levels_dir, out_path, &outputs, &log)?;
+        }
+        "create-oddtable" => {
+            let mut filename = "oddtable.txt";
+            let mut out_path = "oddtable.dat";
+            let mut backup = true;
+            let mut arg_iter = args.iter().skip(2);
+            while let Some(arg) = arg_iter.next() {
+                match arg.as_str() {
+                    "-o" | "--out" => out_path = option_value(&mut arg_iter).as_str(),
+                    "--no-backup" => backup = false,
+                    _ if !arg.starts_with('-') => filename = arg.as_str(),
+                    _ => unreachable!("check_args() doesn't let {} through", arg),
+                }
+            }
+            cmd_create_oddtable(filename, out_path, backup, &log)?;
         }
         "create-dat" => {
             let dat_name = &args[2];
-            println!("Create {}.dat…", dat_name);
-            create_dat(dat_name);
+            let mut backup = true;
+            for arg in args.iter().skip(3) {
+                match arg.as_str() {
+                    "--no-backup" => backup = false,
+                    _ => unreachable!("check_args() doesn't let {} through", arg),
+                }
+            }
+            log_info!(log, "Creating {}.dat…", dat_name);
+            create_dat(dat_name, backup, &log)?;
         }
-        invalid_cmd => {
-            panic!("Unknown command \"{}\"", invalid_cmd);
+        "info" => {
+            cmd_info(&data, &args[2], args[3..].iter().any(|arg| arg == "--json"))?;
         }
+        "diff-dat" => {
+            if !cmd_diff_dat(&data, &args[2], &args[3])? {
+                return Ok(false);
+            }
+        }
+        "render-pcspkr" => {
+            let filename = &args[2];
+            let mut wav_dir = ".";
+            let mut arg_iter = args.iter().skip(3);
+            while let Some(arg) = arg_iter.next() {
+                match arg.as_str() {
+                    "--wav-dir" => wav_dir = option_value(&mut arg_iter).as_str(),
+                    _ => unreachable!("check_args() doesn't let {} through", arg),
+                }
+            }
+            cmd_render_pcspkr(filename, wav_dir, &outputs, &log)?;
+        }
+        "batch" => {
+            let filename = &args[2];
+            let mut keep_going = false;
+            for arg in args.iter().skip(3) {
+                match arg.as_str() {
+                    "--keep-going" => keep_going = true,
+                    _ => unreachable!("check_args() doesn't let {} through", arg),
+                }
+            }
+            return cmd_batch(filename, &global_args, keep_going, cache, &log);
+        }
+        // find_command() has already turned away any command which isn't in COMMANDS.
+        other => unreachable!("{} is in COMMANDS, but isn't handled", other),
     }
+    write_outputs(&outputs, overwrite, &log)?;
+    Ok(true)
+}
+
+fn main() {
+    let code = match run(std::env::args().collect(), &Rc::new(Cache::default())) {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(err) => {
+            eprintln!("modlem: error: {}", err);
+            err.kind.exit_code()
+        }
+    };
+    std::process::exit(code);
 }
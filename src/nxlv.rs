@@ -0,0 +1,157 @@
+/*
+ * modlem: A graphics importer/exporter for Lemmings
+ * Copyright (C) 2022–2026 David Gow <david@davidgow.net>
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use level::{self, Level, LevelVariant};
+
+/*
+ * NeoLemmix (and SuperLemmix, which is based on it) store levels as text: a list of
+ * "KEY value" lines, with the skills, objects ("gadgets") and terrain each in a
+ * "$SECTION ... $END" block. Graphics come from named styles rather than numbered
+ * sets, with each piece named within its style. The styles converted from the DOS
+ * games keep the original slot numbers as the pieces' names, so a level only needs
+ * to know which style each graphics set became.
+ *
+ * Not everything has an equivalent:
+ *  - NeoLemmix gets steel from the terrain pieces themselves, so steel areas are
+ *    only written as comments, to be checked by hand.
+ *  - Special graphics levels aren't exported, as the picture isn't part of any style.
+ */
+
+/// The NeoLemmix styles for each of the games' graphics sets, counted from 0.
+const ORIGINAL_STYLES : [&str; 5] = ["orig_dirt", "orig_fire", "orig_marble", "orig_pillar", "orig_crystal"];
+const ONML_STYLES : [&str; 4] = ["ohno_brick", "ohno_rock", "ohno_snow", "ohno_bubble"];
+
+/// The names NeoLemmix gives the skills, in the order of level::SKILL_NAMES.
+const SKILL_KEYS : [&str; 8] = ["CLIMBER", "FLOATER", "BOMBER", "BLOCKER", "BUILDER", "BASHER", "MINER", "DIGGER"];
+
+/// The NeoLemmix style which usually stands in for @level's graphics set, if there is one.
+pub fn default_style(level : &Level) -> Option<&'static str> {
+    let styles : &[&str] = match level.variant {
+        LevelVariant::Original => &ORIGINAL_STYLES,
+        LevelVariant::Onml => &ONML_STYLES,
+        LevelVariant::Holiday => &[],
+    };
+    styles.get(level.ground_set() as usize).copied()
+}
+
+/// NeoLemmix gives the time between lemmings rather than the release rate.
+pub fn spawn_interval(release_rate : u16) -> u16 {
+    53 - release_rate.min(99) / 2
+}
+
+/// Write @level as a NeoLemmix level, with all its pieces from @style.
+pub fn write_nxlv(level : &Level, style : &str, writer : &mut dyn std::io::Write) -> std::io::Result<()> {
+    writeln!(writer, "# Converted by modlem from the {} game's level format", level.variant.name())?;
+    writeln!(writer, " TITLE {}", level.name())?;
+    writeln!(writer, " AUTHOR ")?;
+    writeln!(writer, " THEME {}", style)?;
+    writeln!(writer)?;
+    writeln!(writer, " LEMMINGS {}", level.num_lemmings)?;
+    writeln!(writer, " SAVE_REQUIREMENT {}", level.num_to_rescue)?;
+    writeln!(writer, " MAX_SPAWN_INTERVAL {}", spawn_interval(level.release_rate))?;
+    writeln!(writer, " TIME_LIMIT {}", level.time_limit as u32 * 60)?;
+    writeln!(writer)?;
+    writeln!(writer, " WIDTH {}", level::LEVEL_WIDTH)?;
+    writeln!(writer, " HEIGHT {}", level::LEVEL_HEIGHT)?;
+    writeln!(writer)?;
+
+    writeln!(writer, "$SKILLSET")?;
+    for (key, &count) in SKILL_KEYS.iter().zip(level.skills.iter()) {
+        if count != 0 {
+            writeln!(writer, "   {} {}", key, count)?;
+        }
+    }
+    writeln!(writer, "$END")?;
+
+    for object in level.objects.iter().flatten() {
+        writeln!(writer)?;
+        writeln!(writer, "$GADGET")?;
+        write_piece(writer, style, object.id as usize, object.x, object.y)?;
+        if object.is_upside_down() {
+            writeln!(writer, "   FLIP_VERTICAL")?;
+        }
+        if object.modifier & level::OBJECT_NO_OVERWRITE != 0 {
+            writeln!(writer, "   NO_OVERWRITE")?;
+        }
+        if object.modifier & level::OBJECT_ONLY_ON_TERRAIN != 0 {
+            writeln!(writer, "   ONLY_ON_TERRAIN")?;
+        }
+        writeln!(writer, "$END")?;
+    }
+
+    for piece in level.terrain.iter().flatten() {
+        writeln!(writer)?;
+        writeln!(writer, "$TERRAIN")?;
+        write_piece(writer, style, piece.piece as usize, piece.x, piece.y)?;
+        if piece.flags & level::TERRAIN_UPSIDE_DOWN != 0 {
+            writeln!(writer, "   FLIP_VERTICAL")?;
+        }
+        if piece.flags & level::TERRAIN_NO_OVERWRITE != 0 {
+            writeln!(writer, "   NO_OVERWRITE")?;
+        }
+        if piece.flags & level::TERRAIN_ERASE != 0 {
+            writeln!(writer, "   ERASE")?;
+        }
+        writeln!(writer, "$END")?;
+    }
+
+    let steel : Vec<&level::SteelArea> = level.steel.iter().flatten().collect();
+    if !steel.is_empty() {
+        writeln!(writer)?;
+        writeln!(writer, "# The original level has these steel areas, which NeoLemmix takes from the terrain instead:")?;
+        for area in steel {
+            writeln!(writer, "#   at ({}, {}) size ({}, {})", area.x, area.y, area.width, area.height)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_piece(writer : &mut dyn std::io::Write, style : &str, index : usize, x : i32, y : i32) -> std::io::Result<()> {
+    writeln!(writer, "   STYLE {}", style)?;
+    writeln!(writer, "   PIECE {}", index)?;
+    writeln!(writer, "   X {}", x)?;
+    writeln!(writer, "   Y {}", y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nxlv_export() {
+        let mut level = Level::from_text("Name \"Just dig!\"\nGraphicsSet 3\nReleaseRate 50\nLemmings 50\nRescue 10\nTime 5\n\
+                                          Skills { dig = 10 }\n\
+                                          Object 0 at (312, -8) id 1\nObject 2 at (-16, 40) id 3 flags(no_overwrite, flip)\n\
+                                          Terrain at (0, 80) piece 12\nTerrain at (-16, -38) piece 63 flags(erase, flip)\n\
+                                          Steel at (400, 120) size (64, 8)\n").unwrap();
+        assert_eq!(default_style(&level), Some("orig_pillar"));
+        let mut nxlv = Vec::new();
+        write_nxlv(&level, "orig_pillar", &mut nxlv).unwrap();
+        let nxlv = String::from_utf8(nxlv).unwrap();
+        assert!(nxlv.contains("\n TITLE Just dig!\n AUTHOR \n THEME orig_pillar\n"));
+        assert!(nxlv.contains("\n MAX_SPAWN_INTERVAL 28\n TIME_LIMIT 300\n"));
+        assert!(nxlv.contains("\n$SKILLSET\n   DIGGER 10\n$END\n"));
+        assert!(nxlv.contains("\n$GADGET\n   STYLE orig_pillar\n   PIECE 1\n   X 312\n   Y -8\n$END\n"));
+        assert!(nxlv.contains("\n$GADGET\n   STYLE orig_pillar\n   PIECE 3\n   X -16\n   Y 40\n   FLIP_VERTICAL\n   NO_OVERWRITE\n$END\n"));
+        assert!(nxlv.contains("\n$TERRAIN\n   STYLE orig_pillar\n   PIECE 63\n   X -16\n   Y -38\n   FLIP_VERTICAL\n   ERASE\n$END\n"));
+        assert!(nxlv.ends_with("\n#   at (400, 120) size (64, 8)\n"));
+
+        level.variant = LevelVariant::Holiday;
+        assert_eq!(default_style(&level), None);
+        assert_eq!((spawn_interval(1), spawn_interval(99)), (53, 4));
+    }
+}
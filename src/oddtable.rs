@@ -0,0 +1,213 @@
+/*
+ * modlem: A graphics importer/exporter for Lemmings
+ * Copyright (C) 2022–2026 David Gow <david@davidgow.net>
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use binary_io::*;
+use level;
+use parser;
+
+/*
+ * oddtable.dat holds a second set of stats for the levels, which the game uses
+ * when a level appears again under a different difficulty rating. It isn't
+ * compressed: it's just a list of 56-byte entries, each the first 24 bytes of a
+ * level (release rate, number of lemmings, number to rescue, time limit and the
+ * 8 skill counts, all big-endian) followed by the 32-byte name.
+ *
+ * There's one entry for each level in the levelNNN.dat files, in order: entry N
+ * goes with section N % 8 of level00(N / 8).dat.
+ */
+
+/// The size of an entry, in bytes.
+pub const ODDTABLE_ENTRY_SIZE : usize = 56;
+/// The number of levels in each levelNNN.dat file.
+pub const LEVELS_PER_FILE : usize = 8;
+
+/// The replacement stats for one level.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OddTableEntry {
+    pub release_rate : u16,
+    pub num_lemmings : u16,
+    pub num_to_rescue : u16,
+    /// In minutes.
+    pub time_limit : u16,
+    /// In the order of level::SKILL_NAMES.
+    pub skills : [u16; 8],
+    /// The raw name, including any padding.
+    pub name : [u8; level::LEVEL_NAME_LEN],
+}
+
+impl Default for OddTableEntry {
+    fn default() -> OddTableEntry {
+        OddTableEntry {
+            release_rate : 0,
+            num_lemmings : 0,
+            num_to_rescue : 0,
+            time_limit : 0,
+            skills : [0; 8],
+            name : [b' '; level::LEVEL_NAME_LEN],
+        }
+    }
+}
+
+impl OddTableEntry {
+    /// Describe any of the stats which are out of the game's range (see level::stat_problems()).
+    pub fn stat_problems(&self) -> Vec<String> {
+        level::stat_problems(self.release_rate, self.num_lemmings, self.num_to_rescue, self.time_limit, &self.skills)
+    }
+
+    pub fn read(reader : &mut dyn std::io::Read) -> std::io::Result<OddTableEntry> {
+        let release_rate = read_be16(reader)?;
+        let num_lemmings = read_be16(reader)?;
+        let num_to_rescue = read_be16(reader)?;
+        let time_limit = read_be16(reader)?;
+        let mut skills = [0_u16; 8];
+        for skill in skills.iter_mut() {
+            *skill = read_be16(reader)?;
+        }
+        let name = read_array(reader)?;
+        Ok(OddTableEntry { release_rate, num_lemmings, num_to_rescue, time_limit, skills, name })
+    }
+
+    pub fn write(&self, writer : &mut dyn std::io::Write) -> std::io::Result<()> {
+        write_be16(self.release_rate, writer)?;
+        write_be16(self.num_lemmings, writer)?;
+        write_be16(self.num_to_rescue, writer)?;
+        write_be16(self.time_limit, writer)?;
+        for &skill in &self.skills {
+            write_be16(skill, writer)?;
+        }
+        writer.write_all(&self.name)
+    }
+}
+
+/// Decode every entry in oddtable.dat.
+pub fn decode(data : &[u8]) -> std::io::Result<Vec<OddTableEntry>> {
+    if !data.len().is_multiple_of(ODDTABLE_ENTRY_SIZE) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+            format!("oddtable.dat should be a multiple of {} bytes, not {}", ODDTABLE_ENTRY_SIZE, data.len())));
+    }
+    data.chunks(ODDTABLE_ENTRY_SIZE).map(|mut entry| OddTableEntry::read(&mut entry)).collect()
+}
+
+/// Encode a list of entries as oddtable.dat.
+pub fn encode(entries : &[OddTableEntry]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(entries.len() * ODDTABLE_ENTRY_SIZE);
+    for entry in entries {
+        entry.write(&mut data).unwrap();
+    }
+    data
+}
+
+/// The level an entry goes with, for labelling it in the text format.
+pub struct LevelRef {
+    pub file_name : String,
+    pub index : usize,
+    pub name : String,
+}
+
+/// Write the entries as text: each one starts with "Entry [n]", then has the same stats as a
+/// level's text file. If @levels has an entry (or None) for each one, it also names the level
+/// the entry goes with, which is ignored when it's read back.
+pub fn write_text(entries : &[OddTableEntry], levels : &[Option<LevelRef>], writer : &mut dyn std::io::Write) -> std::io::Result<()> {
+    for (i, entry) in entries.iter().enumerate() {
+        if i != 0 {
+            writeln!(writer)?;
+        }
+        match levels.get(i) {
+            Some(Some(level)) => writeln!(writer, "Entry {} Level ({}, {}, {})", i, parser::quote(&level.file_name), level.index, parser::quote(&level.name))?,
+            _ => writeln!(writer, "Entry {}", i)?,
+        }
+        level::write_name(writer, &entry.name)?;
+        writeln!(writer, "ReleaseRate {}", entry.release_rate)?;
+        writeln!(writer, "Lemmings {}", entry.num_lemmings)?;
+        writeln!(writer, "Rescue {}", entry.num_to_rescue)?;
+        writeln!(writer, "Time {}", entry.time_limit)?;
+        level::write_skills(writer, &entry.skills)?;
+    }
+    Ok(())
+}
+
+/// Parse the entries from the text format written by write_text(). Entries must be listed
+/// in order.
+pub fn parse_text(text : &str) -> Result<Vec<OddTableEntry>, parser::ParseError> {
+    let mut lex = parser::Lexer::from_str(text);
+    let mut entries = Vec::<OddTableEntry>::new();
+    while let Some(tok) = lex.next_spanned()? {
+        if tok.token == parser::Token::Ident("Entry") {
+            let index = lex.get_int_literal()?;
+            if index != entries.len() as i64 {
+                return Err(lex.invalid(format!("Expected entry {}, got {}", entries.len(), index)));
+            }
+            entries.push(OddTableEntry::default());
+            if lex.is_next_ident("Level") {
+                // Only there for the reader: skip it.
+                lex.next_token()?;
+                lex.expect_symbol('(')?;
+                while lex.next_token()?.is_some_and(|tok| tok != parser::Token::Symbol(')')) {}
+            }
+            continue;
+        }
+        let entry = match entries.last_mut() {
+            Some(entry) => entry,
+            None => return Err(tok.error("'Entry 0'")),
+        };
+        match tok.token {
+            parser::Token::Ident("Name") => entry.name = level::parse_name(&mut lex)?,
+            parser::Token::Ident("ReleaseRate") => entry.release_rate = lex.get_u16("release rate")?,
+            parser::Token::Ident("Lemmings") => entry.num_lemmings = lex.get_u16("number of lemmings")?,
+            parser::Token::Ident("Rescue") => entry.num_to_rescue = lex.get_u16("number to rescue")?,
+            parser::Token::Ident("Time") => entry.time_limit = lex.get_u16("time limit")?,
+            parser::Token::Ident("Skills") => entry.skills = level::parse_skills(&mut lex)?,
+            _ => return Err(tok.error(&format!("a property of entry {}", entries.len() - 1))),
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_entries() -> Vec<OddTableEntry> {
+        let mut name = [b' '; level::LEVEL_NAME_LEN];
+        name[..13].copy_from_slice(b"Odd one out!!");
+        vec![
+            OddTableEntry { release_rate : 1, num_lemmings : 80, num_to_rescue : 79, time_limit : 9, skills : [1, 2, 3, 4, 5, 6, 7, 8], name },
+            OddTableEntry { release_rate : 99, num_lemmings : 1, num_to_rescue : 0, time_limit : 1, ..Default::default() },
+        ]
+    }
+
+    #[test]
+    fn oddtable_binary_round_trip() {
+        let data = encode(&test_entries());
+        assert_eq!(data.len(), 2 * ODDTABLE_ENTRY_SIZE);
+        assert_eq!(&data[0..8], &[0, 1, 0, 80, 0, 79, 0, 9]);
+        assert_eq!(&data[24..37], b"Odd one out!!");
+        assert_eq!(decode(&data).unwrap(), test_entries());
+        assert!(decode(&data[1..]).is_err());
+    }
+
+    #[test]
+    fn oddtable_text_round_trip() {
+        let levels = vec![Some(LevelRef { file_name : "level000.dat".to_string(), index : 0, name : "Just dig!".to_string() }), None];
+        let mut text = Vec::new();
+        write_text(&test_entries(), &levels, &mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.starts_with("Entry 0 Level (\"level000.dat\", 0, \"Just dig!\")\nName \"Odd one out!!\"\nReleaseRate 1\n"));
+        assert!(text.contains("}\n\nEntry 1\nName \"\"\n"));
+        assert_eq!(parse_text(&text).unwrap(), test_entries());
+    }
+}
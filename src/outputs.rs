@@ -0,0 +1,192 @@
+/*
+ * modlem: A graphics importer/exporter for Lemmings
+ * Copyright (C) 2022–2026 David Gow <david@davidgow.net>
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The files extracting something writes. They're kept in memory until everything has been
+//! extracted, so that the files already there can all be checked first, and (by default) nothing
+//! is written if any of them would be overwritten.
+//!
+//! ```
+//! use modlem::outputs::{OverwritePolicy, PendingOutputs};
+//! use std::io::Write;
+//!
+//! let path = std::env::temp_dir().join(format!("modlem_outputs_doc_{}.txt", std::process::id()));
+//! let outputs = PendingOutputs::new();
+//! outputs.create(&path).write_all(b"Let's go!")?;
+//! outputs.write_all(OverwritePolicy::Refuse, &modlem::Log::default())?;
+//!
+//! // It's there now, so it isn't overwritten.
+//! let outputs = PendingOutputs::new();
+//! outputs.create(&path).write_all(b"Oh no!")?;
+//! assert!(outputs.write_all(OverwritePolicy::Refuse, &modlem::Log::default()).is_err());
+//! assert_eq!(std::fs::read(&path)?, b"Let's go!");
+//! # std::fs::remove_file(&path)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use binary_io::OutputFile;
+use log::Log;
+use std::cell::RefCell;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// What to do about files which are already there.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverwritePolicy {
+    /// Don't write anything if any of the files are already there.
+    #[default]
+    Refuse,
+    /// Overwrite them (--force).
+    Force,
+    /// Leave them as they are, and just write the others (--skip-existing).
+    SkipExisting,
+}
+
+/// The contents of a file, shared between the PendingFile being written and its PendingOutputs.
+type Contents = Rc<RefCell<Vec<u8>>>;
+
+/// Files which are to be written, once they're all ready.
+#[derive(Default)]
+pub struct PendingOutputs {
+    files : RefCell<Vec<(PathBuf, Contents)>>,
+}
+
+/// A file being written to a PendingOutputs.
+pub struct PendingFile {
+    contents : Contents,
+}
+
+impl Write for PendingFile {
+    fn write(&mut self, buf : &[u8]) -> std::io::Result<usize> {
+        self.contents.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl PendingOutputs {
+    pub fn new() -> PendingOutputs {
+        PendingOutputs::default()
+    }
+
+    /// Start writing @path, which is only written to disk by write_all(). If it's created more
+    /// than once, the last one is written.
+    pub fn create<P : AsRef<Path>>(&self, path : P) -> PendingFile {
+        let contents = Contents::default();
+        let mut files = self.files.borrow_mut();
+        files.retain(|(file_path, _)| file_path != path.as_ref());
+        files.push((path.as_ref().to_path_buf(), contents.clone()));
+        PendingFile { contents }
+    }
+
+    /// The files to be written, in the order they were created.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.files.borrow().iter().map(|(path, _)| path.clone()).collect()
+    }
+
+    /// Add the files of @other, as if they'd been created here.
+    pub fn append(&self, other : PendingOutputs) {
+        for (path, contents) in other.files.into_inner() {
+            self.create(&path).write_all(&contents.borrow()).unwrap();
+        }
+    }
+
+    /// Write the files to disk, creating any directories they're in, and dealing with any
+    /// which are already there as @policy says. Returns how many were written. With
+    /// OverwritePolicy::Refuse, if any are there, nothing is written, and the error (of kind
+    /// AlreadyExists) lists them. Either way, there are none left to write afterwards.
+    pub fn write_all(&self, policy : OverwritePolicy, log : &Log) -> std::io::Result<usize> {
+        let files = self.files.take();
+        let existing : Vec<&Path> = files.iter().map(|(path, _)| path.as_path()).filter(|path| path.exists()).collect();
+        if policy == OverwritePolicy::Refuse && !existing.is_empty() {
+            let mut message = format!("{} already there (use --force to overwrite {}, or --skip-existing to leave {} as {}):",
+                if existing.len() == 1 { "A file is" } else { "Some files are" },
+                if existing.len() == 1 { "it" } else { "them" },
+                if existing.len() == 1 { "it" } else { "them" },
+                if existing.len() == 1 { "it is" } else { "they are" });
+            for path in &existing {
+                message.push_str(&format!("\n\t{}", path.display()));
+            }
+            return Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists, message));
+        }
+
+        let mut num_written = 0;
+        for (path, contents) in &files {
+            if policy == OverwritePolicy::SkipExisting && existing.contains(&path.as_path()) {
+                log_detail!(log, "Leaving {} as it is", path.display());
+                continue;
+            }
+            if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                std::fs::create_dir_all(dir)?;
+            }
+            let in_path = |err : std::io::Error| std::io::Error::new(err.kind(), format!("{}: {}", path.display(), err));
+            let mut file = OutputFile::create(path).map_err(in_path)?;
+            file.write_all(&contents.borrow()).map_err(in_path)?;
+            file.finish().map_err(in_path)?;
+            num_written += 1;
+        }
+        if policy == OverwritePolicy::SkipExisting && !existing.is_empty() {
+            log_info!(log, "Left {} file{} which {} already there", existing.len(),
+                if existing.len() == 1 { "" } else { "s" }, if existing.len() == 1 { "was" } else { "were" });
+        }
+        Ok(num_written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Verbosity;
+
+    #[test]
+    fn outputs_overwrite_policy() {
+        let dir = std::env::temp_dir().join(format!("modlem_outputs_{}", std::process::id()));
+        let log = Log::new(Verbosity::Errors);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.txt"), b"edited").unwrap();
+
+        let pending = || {
+            let outputs = PendingOutputs::new();
+            outputs.create(dir.join("a.txt")).write_all(b"old").unwrap();
+            outputs.create(dir.join("sub/b.txt")).write_all(b"first").unwrap();
+            // Only the last of the same file is written.
+            outputs.create(dir.join("sub/b.txt")).write_all(b"new").unwrap();
+            outputs
+        };
+        assert_eq!(pending().paths(), [dir.join("a.txt"), dir.join("sub/b.txt")]);
+
+        // Nothing is written if anything is in the way.
+        let err = pending().write_all(OverwritePolicy::Refuse, &log).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+        assert!(err.to_string().ends_with(&format!(":\n\t{}", dir.join("a.txt").display())));
+        assert!(!dir.join("sub").exists());
+
+        assert_eq!(pending().write_all(OverwritePolicy::SkipExisting, &log).unwrap(), 1);
+        assert_eq!(std::fs::read(dir.join("a.txt")).unwrap(), b"edited");
+        assert_eq!(std::fs::read(dir.join("sub/b.txt")).unwrap(), b"new");
+
+        let outputs = pending();
+        assert_eq!(outputs.write_all(OverwritePolicy::Force, &log).unwrap(), 2);
+        assert_eq!(std::fs::read(dir.join("a.txt")).unwrap(), b"old");
+        assert!(outputs.paths().is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
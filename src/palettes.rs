@@ -0,0 +1,168 @@
+/*
+ * modlem: A graphics importer/exporter for Lemmings
+ * Copyright (C) 2022–2026 David Gow <david@davidgow.net>
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use parser;
+
+/*
+ * Preset palettes, for graphics whose palette isn't stored alongside them. These
+ * are 6-bit VGA values (three bytes per colour), as used by the game.
+ */
+
+/// The colours of the lemmings themselves, and the skill panel. The first 7 of these are the
+/// same as the 'standard' colours of every graphics set.
+pub const LEMMING_VGA : [u8; 48] = [
+    0, 0, 0,    16, 16, 56, 0, 44, 0,   60, 58, 58,
+    44, 44, 0,  60, 8, 8,   32, 32, 32, 0, 0, 0,
+    0, 0, 0,    0, 0, 0,    0, 0, 0,    0, 0, 0,
+    0, 0, 0,    0, 0, 0,    0, 0, 0,    0, 0, 0,
+];
+
+/// The lemming colours in Christmas/Holiday Lemmings, with red and white robes and green hair.
+pub const XMAS_LEMMING_VGA : [u8; 48] = [
+    0, 0, 0,    52, 8, 8,   0, 44, 0,   60, 52, 52,
+    60, 60, 0,  16, 16, 60, 32, 32, 32, 0, 0, 0,
+    0, 0, 0,    0, 0, 0,    0, 0, 0,    0, 0, 0,
+    0, 0, 0,    0, 0, 0,    0, 0, 0,    0, 0, 0,
+];
+
+/// The colours of the second player's lemmings in two-player games: the same as @lemming_vga,
+/// but with the blue (1) and green (2) entries swapped, giving blue hair and green robes.
+pub fn player2_vga(lemming_vga : &[u8; 48]) -> [u8; 48] {
+    let mut player2 = *lemming_vga;
+    player2[3..6].copy_from_slice(&lemming_vga[6..9]);
+    player2[6..9].copy_from_slice(&lemming_vga[3..6]);
+    player2
+}
+
+/// The lemming colours, followed by the colours of the flames in the 'fry' animation. The game
+/// takes these from elsewhere, so they're only an approximation.
+pub const FIRE_VGA : [u8; 48] = [
+    0, 0, 0,    16, 16, 56, 0, 44, 0,   60, 58, 58,
+    44, 44, 0,  60, 8, 8,   32, 32, 32, 0, 0, 0,
+    63, 63, 48, 63, 63, 0,  63, 48, 0,  63, 32, 0,
+    56, 16, 0,  48, 0, 0,   32, 0, 0,   16, 0, 0,
+];
+
+/// The high-performance (hi-res) skill panel: the lemming colours, followed by the standard EGA
+/// colours. This is a best guess: Christmas Lemmings doesn't seem to have a valid palette for
+/// these at all (or, at least, High Performance mode wouldn't launch to check).
+pub const HIPERF_VGA : [u8; 48] = [
+    0, 0, 0,    16, 16, 56, 0, 44, 0,   60, 58, 58,
+    44, 44, 0,  60, 8, 8,   32, 32, 32, 0, 0, 0,
+    0, 42, 0,   21, 63, 21, 21, 21, 21, 42, 0, 0,
+    42, 21, 0,  0, 42, 42,  63, 21, 63, 42, 0, 42,
+];
+
+/// The main menu.
+pub const MENU_VGA : [u8; 48] = [
+    0, 0, 0,    32, 16, 8,  24, 12, 8,  12, 0, 4,
+    8, 2, 31,   16, 11, 36, 26, 22, 41, 38, 35, 47,
+    0, 20, 0,   0, 24, 4,   0, 28, 8,   0, 32, 16,
+    52, 52, 52, 44, 44, 0,  16, 20, 44, 56, 32, 36,
+];
+
+/// The fixed CGA palette used by the CGA version: black, cyan, magenta and white.
+pub const CGA_VGA : [u8; 48] = [
+    0, 0, 0,    21, 63, 63, 63, 21, 63, 63, 63, 63,
+    0, 0, 0,    0, 0, 0,    0, 0, 0,    0, 0, 0,
+    0, 0, 0,    0, 0, 0,    0, 0, 0,    0, 0, 0,
+    0, 0, 0,    0, 0, 0,    0, 0, 0,    0, 0, 0,
+];
+
+/// Check that @val, just read by @lex, is a valid 6-bit VGA colour value.
+fn vga_component(lex : &parser::Lexer, val : i64) -> Result<u8, parser::ParseError> {
+    if !(0..64).contains(&val) {
+        return Err(lex.error("a VGA colour value from 0 to 63", Some(&parser::Token::NumericLiteral(val))));
+    }
+    Ok(val as u8)
+}
+
+/// Parse a list of 6-bit VGA colours, in the form { (r, g, b), (r, g, b), ... }, returning
+/// three bytes per colour.
+pub fn parse_vga_colours(lex : &mut parser::Lexer) -> Result<Vec<u8>, parser::ParseError> {
+    let mut colours = Vec::new();
+    lex.expect_symbol('{')?;
+    loop {
+        lex.expect_symbol('(')?;
+        for component in 0..3 {
+            colours.push(lex.get_int_in_range("a VGA colour value", 0, 63)? as u8);
+            if component < 2 {
+                lex.expect_symbol(',')?;
+            }
+        }
+        lex.expect_list_end(')')?;
+        match lex.next_token()? {
+            Some(parser::Token::Symbol(',')) => {
+                // Allow a trailing comma.
+                if lex.peek_token()? == Some(parser::Token::Symbol('}')) {
+                    lex.next_token()?;
+                    break;
+                }
+            },
+            Some(parser::Token::Symbol('}')) => break,
+            tok => return Err(lex.error("',' or '}'", tok.as_ref())),
+        }
+    }
+    Ok(colours)
+}
+
+/// Parse a plain list of 6-bit VGA colours, three numbers (red, green and blue) per colour,
+/// optionally separated by commas, up to the end of the file.
+pub fn parse_vga_list(lex : &mut parser::Lexer) -> Result<Vec<u8>, parser::ParseError> {
+    let mut colours = Vec::new();
+    while let Some(tok) = lex.next_token()? {
+        match tok {
+            parser::Token::NumericLiteral(val) => colours.push(vga_component(lex, val)?),
+            parser::Token::Symbol(',') => {},
+            tok => return Err(lex.error("a colour value", Some(&tok))),
+        }
+    }
+    if !colours.len().is_multiple_of(3) {
+        return Err(lex.error("three values (red, green and blue) per colour", None));
+    }
+    Ok(colours)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn palettes_parse_vga_colours() {
+        let mut lex = parser::Lexer::from_str("{(0, 0, 0), (63, 32, 1)}");
+        assert_eq!(parse_vga_colours(&mut lex), Ok(vec![0, 0, 0, 63, 32, 1]));
+        // Trailing commas are allowed, in the colours and the list.
+        let mut lex = parser::Lexer::from_str("{(0, 0, 0,), (63, 32, 1),}");
+        assert_eq!(parse_vga_colours(&mut lex), Ok(vec![0, 0, 0, 63, 32, 1]));
+        let mut lex = parser::Lexer::from_str("{(0, 0, 0),, (63, 32, 1)}");
+        assert_eq!(parse_vga_colours(&mut lex).unwrap_err().to_string(), "1:12: expected '(' but found ','");
+    }
+
+    #[test]
+    fn palettes_parse_vga_list() {
+        let mut lex = parser::Lexer::from_str("0 0 0\n63, 32, 1\n");
+        assert_eq!(parse_vga_list(&mut lex).unwrap(), vec![0, 0, 0, 63, 32, 1]);
+    }
+
+    #[test]
+    fn palettes_player2() {
+        let player2 = player2_vga(&LEMMING_VGA);
+        assert_eq!(player2[3..6], LEMMING_VGA[6..9]);
+        assert_eq!(player2[6..9], LEMMING_VGA[3..6]);
+        assert_eq!(player2[9..], LEMMING_VGA[9..]);
+    }
+}
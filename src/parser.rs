@@ -16,88 +16,761 @@
  */
 
 use std::assert;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
 
 #[derive(PartialEq)]
 #[derive(Debug)]
 #[derive(Clone)]
 pub enum Token<'a> {
     Ident(&'a str),
+    /// An identifier from a lexer which doesn't borrow its text (see Lexer::from_reader()).
+    IdentOwned(String),
     Symbol(char),
     StringLiteral(String),
     NumericLiteral(i64),
 }
 
+impl Token<'_> {
+    /// The token with any owned identifier borrowed instead, so it can be matched against
+    /// Token::Ident("...") whichever kind of lexer it came from.
+    pub fn borrowed(&self) -> Token<'_> {
+        match self {
+            Token::Ident(ident) => Token::Ident(ident),
+            Token::IdentOwned(ident) => Token::Ident(ident),
+            tok => tok.clone(),
+        }
+    }
+
+    /// The identifier, if the token is one.
+    pub fn ident(&self) -> Option<&str> {
+        match self {
+            Token::Ident(ident) => Some(ident),
+            Token::IdentOwned(ident) => Some(ident),
+            _ => None,
+        }
+    }
+}
+
+/// Describe @tok (or the end of the file, if it's None) for an error message.
+pub fn describe_token(tok : Option<&Token>) -> String {
+    match tok {
+        None => "the end of the file".to_string(),
+        Some(Token::Ident(ident)) => format!("'{}'", ident),
+        Some(Token::IdentOwned(ident)) => format!("'{}'", ident),
+        Some(Token::Symbol(sym)) => format!("'{}'", sym),
+        Some(Token::StringLiteral(str_val)) => format!("\"{}\"", str_val),
+        Some(Token::NumericLiteral(int_val)) => int_val.to_string(),
+    }
+}
+
+/// A place in a text file. The line and column both count from 1. The file is only known
+/// for lexers made with Lexer::from_sources().
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Position<'a> {
+    pub file : Option<&'a str>,
+    pub line : usize,
+    pub column : usize,
+}
+
+impl std::fmt::Display for Position<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(file) = self.file {
+            write!(f, "{}:", file)?;
+        }
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// A token, with where it came from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpannedToken<'a> {
+    pub token : Token<'a>,
+    /// The byte offsets of the start and end of the token in @source.
+    pub start : usize,
+    pub end : usize,
+    pub line : usize,
+    pub column : usize,
+    /// The name (if it's known) and text of the file the token is in. For a lexer reading
+    /// from a stream, the text only starts at the token's line.
+    pub file : Option<&'a str>,
+    pub source : Cow<'a, str>,
+}
+
+impl<'a> SpannedToken<'a> {
+    pub fn position(&self) -> Position<'a> {
+        Position { file : self.file, line : self.line, column : self.column }
+    }
+
+    /// An error for finding this token instead of @expected.
+    pub fn error(&self, expected : &str) -> ParseError {
+        span_error(self.position(), &self.source, self.start, self.end, expected, describe_token(Some(&self.token)))
+    }
+
+    /// An error for this token not being one of the @known names (which are @expected),
+    /// suggesting the closest one if there's one which is near enough, or listing them all.
+    pub fn unknown_name_error(&self, expected : &str, known : &[&str]) -> ParseError {
+        let name = match self.token.ident() {
+            Some(name) => name,
+            None => return self.error(expected),
+        };
+        let mut err = self.error(expected);
+        err.hint = Some(match closest_name(name, known) {
+            Some(closest) => format!("did you mean '{}'?", closest),
+            None => format!("it should be one of: {}", known.join(", ")),
+        });
+        err
+    }
+}
+
+/// The number of single character insertions, deletions and substitutions to turn @a into @b.
+pub fn edit_distance(a : &str, b : &str) -> usize {
+    let b : Vec<char> = b.chars().collect();
+    let mut prev_row : Vec<usize> = (0..=b.len()).collect();
+    for (i, a_c) in a.chars().enumerate() {
+        let mut row = vec![i + 1];
+        for (j, &b_c) in b.iter().enumerate() {
+            let substitution = prev_row[j] + if a_c == b_c { 0 } else { 1 };
+            row.push(substitution.min(prev_row[j + 1] + 1).min(row[j] + 1));
+        }
+        prev_row = row;
+    }
+    prev_row[b.len()]
+}
+
+/// The one of @known which @name is most likely a typo of, if any are close enough.
+pub fn closest_name<'b>(name : &str, known : &[&'b str]) -> Option<&'b str> {
+    let max_distance = (name.chars().count() / 3).clamp(1, 3);
+    known.iter()
+        .map(|&candidate| (edit_distance(&name.to_ascii_lowercase(), &candidate.to_ascii_lowercase()), candidate))
+        .filter(|&(distance, _)| distance <= max_distance)
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// An error for finding @found, which is at @pos and runs from byte @start to @end of
+/// @source, instead of @expected.
+fn span_error(pos : Position, source : &str, start : usize, end : usize, expected : &str, found : String) -> ParseError {
+    let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[start..].find('\n').map_or(source.len(), |i| start + i);
+    let source_line = source[line_start..line_end].trim_end_matches('\r');
+    // Only underline the first line of a token which goes over several.
+    let width = source[start..end.min(line_end)].chars().count();
+    let mut err = ParseError::new(pos, expected, found);
+    err.source_line = Some(source_line.to_string());
+    err.width = width;
+    err
+}
+
+/// How many columns a tab goes up to the next multiple of, when a line is shown.
+const TAB_WIDTH : usize = 4;
+/// The most of a line a snippet shows: longer lines are cut down to the part around the caret.
+const MAX_SNIPPET_WIDTH : usize = 80;
+
+/// Show line @line of @source, after its number, with carets under the @width characters
+/// from @column (both counting from 1) on the line below. None if @source doesn't have that
+/// many lines.
+pub fn snippet(source : &str, line : usize, column : usize, width : usize) -> Option<String> {
+    let text = source.lines().nth(line.checked_sub(1)?)?;
+    Some(format_snippet(text, line, column, width))
+}
+
+fn format_snippet(text : &str, line : usize, column : usize, width : usize) -> String {
+    // Tabs are expanded, so the carets line up however wide the terminal shows them.
+    let mut shown = Vec::<char>::new();
+    let mut starts = Vec::<usize>::new();
+    for c in text.trim_end_matches('\r').chars() {
+        starts.push(shown.len());
+        if c == '\t' {
+            shown.push(' ');
+            while !shown.len().is_multiple_of(TAB_WIDTH) {
+                shown.push(' ');
+            }
+        } else {
+            shown.push(c);
+        }
+    }
+    // Where a character is shown: anything past the end (like the end of the file) is one column each.
+    let shown_column = |index : usize| starts.get(index).copied().unwrap_or(shown.len() + index - starts.len());
+    let caret_start = shown_column(column - 1);
+    let caret_end = shown_column(column - 1 + width.max(1)).max(caret_start + 1);
+
+    let (mut start, mut end) = (0, shown.len());
+    if shown.len() > MAX_SNIPPET_WIDTH {
+        // Centre what's shown on the carets, as far as the line allows.
+        let middle = (caret_start + caret_end.min(caret_start + MAX_SNIPPET_WIDTH)) / 2;
+        start = middle.saturating_sub(MAX_SNIPPET_WIDTH / 2).min(shown.len() - MAX_SNIPPET_WIDTH);
+        end = start + MAX_SNIPPET_WIDTH;
+    }
+    let mut line_text : String = shown[start..end].iter().collect();
+    let mut caret_column = caret_start - start;
+    if start > 0 {
+        line_text.insert_str(0, "...");
+        caret_column += 3;
+    }
+    if end < shown.len() {
+        line_text.push_str("...");
+    }
+    let carets = caret_end.min(end.max(caret_start + 1)) - caret_start;
+
+    let line_number = line.to_string();
+    format!("{} | {}\n{} | {}{}", line_number, line_text, " ".repeat(line_number.len()), " ".repeat(caret_column), "^".repeat(carets))
+}
+
+/// An error in a text file: at @line and @column (of @file, if it's known), there was
+/// something other than what the parser expected. If the line it's on is known, it can be
+/// shown with the bad part underlined by snippet().
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    pub file : Option<String>,
+    pub line : usize,
+    pub column : usize,
+    /// What should have been there, or empty for an error about something else (such as a
+    /// number out of range), where @found is the whole message.
+    pub expected : String,
+    pub found : String,
+    pub source_line : Option<String>,
+    /// How many characters the bad part covers (0 for the end of the file).
+    pub width : usize,
+    /// Something to add to the message, like a suggestion of what was meant.
+    pub hint : Option<String>,
+}
+
+impl ParseError {
+    /// An error for finding @found (as described by describe_token()) at @pos.
+    pub fn new(pos : Position, expected : &str, found : String) -> ParseError {
+        ParseError {
+            file : pos.file.map(str::to_string),
+            line : pos.line,
+            column : pos.column,
+            expected : expected.to_string(),
+            found,
+            source_line : None,
+            width : 0,
+            hint : None,
+        }
+    }
+
+    /// The line the error is on, with a caret under the bad part, if the line is known.
+    pub fn snippet(&self) -> Option<String> {
+        let source_line = self.source_line.as_ref()?;
+        Some(format_snippet(source_line, self.line, self.column, self.width))
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file)?;
+        }
+        if self.expected.is_empty() {
+            write!(f, "{}:{}: {}", self.line, self.column, self.found)?;
+        } else {
+            write!(f, "{}:{}: expected {} but found {}", self.line, self.column, self.expected, self.found)?;
+        }
+        if let Some(hint) = &self.hint {
+            write!(f, " ({})", hint)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// For functions which read files as well as parsing them, a ParseError becomes an io::Error
+/// of kind InvalidData, which it can be got back from with ParseError::from_io_error().
+impl From<ParseError> for std::io::Error {
+    fn from(err : ParseError) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, err)
+    }
+}
+
+impl ParseError {
+    /// The ParseError @err was made from, if it was.
+    pub fn from_io_error(err : &std::io::Error) -> Option<&ParseError> {
+        err.get_ref().and_then(|inner| inner.downcast_ref::<ParseError>())
+    }
+
+    /// The error, saying it's in @file, unless the lexer already knew which file it was in.
+    pub fn in_file(mut self, file : &Path) -> ParseError {
+        self.file.get_or_insert_with(|| file.display().to_string());
+        self
+    }
+}
+
+/// Something in a text file which is allowed, but probably isn't what was meant.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Warning {
+    pub file : Option<String>,
+    pub line : usize,
+    pub column : usize,
+    pub message : String,
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file)?;
+        }
+        write!(f, "{}:{}: warning: {}", self.line, self.column, self.message)
+    }
+}
+
+/// Where each property in a block was first set, to catch any which are set twice.
+#[derive(Default)]
+pub struct SeenProperties<'a> {
+    first : HashMap<String, Position<'a>>,
+}
+
+impl<'a> SeenProperties<'a> {
+    /// Note that property @name is being set, where @name is the token @lex has just read. If
+    /// it's already been set, that's a warning (or an error if @lex is strict), as only the last
+    /// one counts.
+    pub fn check(&mut self, lex : &mut Lexer<'a>, name : &str) -> Result<(), ParseError> {
+        let pos = lex.token_start();
+        let first = *self.first.entry(name.to_string()).or_insert(pos);
+        if first == pos {
+            return Ok(());
+        }
+        let first_line = match first.file {
+            Some(file) if first.file != pos.file => format!("line {} of {}", first.line, file),
+            _ => format!("line {}", first.line),
+        };
+        let mut error = lex.error(&format!("{} only once", name), Some(&Token::Ident(name)));
+        error.hint = Some(format!("it's already set on {}", first_line));
+        lex.warn(pos, format!("{} is already set on {}, and only the last one counts", name, first_line), error)
+    }
+}
+
+/// Write @text as a string literal, escaping any quotes and backslashes.
+pub fn quote(text : &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The path of a file named in a script. Scripts written on Windows may use backslashes
+/// between directories, so they're changed to the platform's separator.
+pub fn file_path(name : &str) -> PathBuf {
+    PathBuf::from(name.replace('\\', std::path::MAIN_SEPARATOR_STR))
+}
+
+/// How deeply Include directives can nest.
+pub const MAX_INCLUDE_DEPTH : usize = 16;
+
+/// A script, and every file it includes with an Include "file" directive, which are loaded
+/// up front so that the tokens can borrow from them. Included files are found relative to
+/// the file which includes them.
+pub struct SourceFiles {
+    /// The name (as found from the script's name) and contents of each file, starting with
+    /// the script itself.
+    files : Vec<(String, String)>,
+    /// The index of the file each (file index, included name) refers to.
+    includes : HashMap<(usize, String), usize>,
+}
+
+fn invalid_data(message : String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+impl SourceFiles {
+    /// Load the script @path, and everything it includes. Fails if a file can't be read, an
+    /// Include isn't followed by a file name, or files include each other in a loop.
+    pub fn load(path : &str) -> std::io::Result<SourceFiles> {
+        let mut sources = SourceFiles { files : Vec::new(), includes : HashMap::new() };
+        let mut loaded = HashMap::<PathBuf, usize>::new();
+        let mut chain = Vec::new();
+        let (canonical, contents) = open_file(path, &loaded, &chain)?;
+        sources.load_file(path.to_string(), canonical, contents.unwrap_or_default(), &mut loaded, &mut chain)?;
+        Ok(sources)
+    }
+
+    /// Add @name, with the given @contents, and everything it includes (@chain being the files
+    /// which include it). Returns its index.
+    fn load_file(&mut self, name : String, canonical : PathBuf, contents : String, loaded : &mut HashMap<PathBuf, usize>, chain : &mut Vec<(PathBuf, String)>) -> std::io::Result<usize> {
+        let index = self.files.len();
+        // The contents are filled in once the includes have been found.
+        self.files.push((name.clone(), String::new()));
+        loaded.insert(canonical.clone(), index);
+        chain.push((canonical, name.clone()));
+        let mut lexer = Lexer::from_str(&contents);
+        while let Some(tok) = lexer.next_token().map_err(|err| invalid_data(format!("{}:{}", name, err)))? {
+            if tok != Token::Ident("Include") {
+                continue;
+            }
+            let pos = lexer.token_start();
+            let included = lexer.get_string_literal().map_err(|err| invalid_data(format!("{}:{}", name, err)))?;
+            let included_name = Path::new(&name).parent().unwrap_or(Path::new("")).join(file_path(&included)).to_string_lossy().into_owned();
+            let (included_canonical, included_contents) = open_file(&included_name, loaded, chain)
+                .map_err(|err| std::io::Error::new(err.kind(), format!("{}:{}: {}", name, pos, err)))?;
+            let included_index = match included_contents {
+                Some(included_contents) => self.load_file(included_name, included_canonical, included_contents, loaded, chain)?,
+                None => loaded[&included_canonical],
+            };
+            self.includes.insert((index, included), included_index);
+        }
+        chain.pop();
+        self.files[index].1 = contents;
+        Ok(index)
+    }
+}
+
+/// Find the file @name, included by the files in @chain, and read it if it isn't already
+/// @loaded.
+fn open_file(name : &str, loaded : &HashMap<PathBuf, usize>, chain : &[(PathBuf, String)]) -> std::io::Result<(PathBuf, Option<String>)> {
+    let canonical = std::fs::canonicalize(name).map_err(|err| std::io::Error::new(err.kind(), format!("{}: {}", name, err)))?;
+    if let Some(start) = chain.iter().position(|(path, _)| *path == canonical) {
+        let names : Vec<&str> = chain[start..].iter().map(|(_, name)| name.as_str()).chain(std::iter::once(name)).collect();
+        return Err(invalid_data(format!("Include loop: {}", names.join(" -> "))));
+    }
+    if chain.len() >= MAX_INCLUDE_DEPTH {
+        let names : Vec<&str> = chain.iter().map(|(_, name)| name.as_str()).chain(std::iter::once(name)).collect();
+        return Err(invalid_data(format!("Includes are nested more than {} deep: {}", MAX_INCLUDE_DEPTH, names.join(" -> "))));
+    }
+    if loaded.contains_key(&canonical) {
+        return Ok((canonical, None));
+    }
+    let contents = std::fs::read_to_string(name).map_err(|err| std::io::Error::new(err.kind(), format!("{}: {}", name, err)))?;
+    Ok((canonical, Some(contents)))
+}
+
+/// Where the lexer was in a file which has included another.
+struct IncludeFrame<'a> {
+    file : usize,
+    data : &'a str,
+    offset : usize,
+    line : usize,
+    column : usize,
+}
+
+/// The text a lexer made with Lexer::from_reader() still needs, read a line at a time.
+struct Stream {
+    reader : Box<dyn BufRead>,
+    /// From the start of the line the latest token is on, to as far as has been read.
+    buffer : String,
+    /// An error reading the stream, which is returned in place of the next token.
+    error : Option<std::io::Error>,
+}
+
 /// A lexer for a given text file. Produces a stream of Tokens
+///
+/// ```
+/// let mut lex = modlem::Lexer::from_str("release_rate = 50");
+/// lex.expect_ident("release_rate")?;
+/// lex.expect_symbol('=')?;
+/// assert_eq!(lex.get_int_literal()?, 50);
+/// # Ok::<(), modlem::ParseError>(())
+/// ```
 pub struct Lexer<'a> {
+    /// The text, unless it's being read from @stream.
     data : &'a str,
+    stream : Option<Stream>,
     offset : usize,
     line: usize,
-    buffered_token : Option<Token<'a>>,
+    column: usize,
+    /// Where the most recently lexed token started.
+    token_offset: usize,
+    token_line: usize,
+    token_column: usize,
+    /// A token (or error) which has been peeked at, or returned to the stream.
+    buffered_token : Option<Result<Token<'a>, ParseError>>,
+    /// The files to take Includes from, if they're allowed, the index of the one being read,
+    /// and the ones which included it.
+    sources : Option<&'a SourceFiles>,
+    file : usize,
+    token_file : usize,
+    include_stack : Vec<IncludeFrame<'a>>,
+    warnings : Vec<Warning>,
+    /// Whether warnings are errors instead.
+    strict : bool,
 }
 
 impl<'a> Lexer<'a> {
-    /// Create a lexer from an in memory string slice.
+    /// Create a lexer from an in memory string slice. (This borrows @data, so can't be FromStr.)
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(data: &'a str) -> Lexer<'a> {
         Lexer {
             data,
+            stream: None,
             offset: 0,
             line: 1,
-            buffered_token: None
+            column: 1,
+            token_offset: 0,
+            token_line: 1,
+            token_column: 1,
+            buffered_token: None,
+            sources: None,
+            file: 0,
+            token_file: 0,
+            include_stack: Vec::new(),
+            warnings: Vec::new(),
+            strict: false,
+        }
+    }
+
+    /// Create a lexer for a script loaded with SourceFiles::load(), which follows its Include
+    /// directives.
+    pub fn from_sources(sources: &'a SourceFiles) -> Lexer<'a> {
+        let mut lexer = Lexer::from_str(&sources.files[0].1);
+        lexer.sources = Some(sources);
+        lexer
+    }
+
+    /// Create a lexer which reads the text from @reader as it's needed, rather than all at
+    /// once. Its identifiers are Token::IdentOwned, as they can't borrow from the text.
+    pub fn from_reader(reader: Box<dyn BufRead>) -> Lexer<'static> {
+        let mut lexer = Lexer::from_str("");
+        lexer.stream = Some(Stream { reader, buffer : String::new(), error : None });
+        lexer.read_line();
+        lexer
+    }
+
+    /// The text the lexer has to hand.
+    fn data(&self) -> &str {
+        match &self.stream {
+            Some(stream) => &stream.buffer,
+            None => self.data,
+        }
+    }
+
+    /// Read another line from the stream, if there is one.
+    fn read_line(&mut self) {
+        if let Some(stream) = &mut self.stream {
+            if stream.error.is_none() {
+                if let Err(err) = stream.reader.read_line(&mut stream.buffer) {
+                    stream.error = Some(err);
+                }
+            }
+        }
+    }
+
+    /// Forget the stream's lines before the one the lexer is on, as nothing can refer to them.
+    fn discard_old_lines(&mut self) {
+        if let Some(stream) = &mut self.stream {
+            let line_start = stream.buffer[..self.offset].rfind('\n').map_or(0, |i| i + 1);
+            stream.buffer.drain(..line_start);
+            self.offset -= line_start;
         }
     }
 
     /// Return a token to the stream.
     pub fn unget_token(&mut self, token: Token<'a>) {
         assert!(self.buffered_token.is_none());
-        self.buffered_token = Some(token);
+        self.buffered_token = Some(Ok(token));
     }
 
-    /// Peek at the next token in the stream. Can only be used once between token reads.
-    pub fn peek_token(&mut self) -> Option<Token<'a>> {
-        if self.buffered_token.is_some() {
-            return self.buffered_token.clone();
+    /// Peek at the next token in the stream. Can only be used once between token reads. If
+    /// there's an error, the next read returns it again.
+    pub fn peek_token(&mut self) -> Result<Option<Token<'a>>, ParseError> {
+        if let Some(tok) = &self.buffered_token {
+            return tok.clone().map(Some);
         }
-        let tok = self.next_token();
-        if tok.is_some() {
-            self.unget_token(tok.clone().unwrap());
+        match self.next_token() {
+            Ok(Some(tok)) => {
+                self.unget_token(tok.clone());
+                Ok(Some(tok))
+            }
+            Ok(None) => Ok(None),
+            Err(err) => {
+                self.buffered_token = Some(Err(err.clone()));
+                Err(err)
+            }
         }
-        tok.clone()
     }
 
     /// Peek at (return but do not consume) the next character in the stream.
     fn peek_char(&self) -> Option<char> {
-        self.data[self.offset..].chars().next()
+        self.data()[self.offset..].chars().next()
     }
 
     /// Consume the next character in the stream.
     fn eat_char(&mut self) {
         let c = self.peek_char().unwrap();
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
         self.offset += c.len_utf8();
+        if self.offset == self.data().len() {
+            self.read_line();
+        }
     }
 
-    /// Consume until the next non-whitespace character.
-    fn eat_whitespace(&mut self) {
+    /// Consume until the next character which is neither whitespace nor in a comment.
+    /// Comments either start with "//" or "#", and run to the end of the line, or are
+    /// between "/*" and "*/".
+    fn eat_whitespace(&mut self) -> Result<(), ParseError> {
         loop {
-            let c = self.peek_char();
-            if c.is_none() {
-                break;
+            match self.peek_char() {
+                Some(c) if c.is_whitespace() => self.eat_char(),
+                Some('#') => self.eat_line(),
+                Some('/') if self.data()[self.offset..].starts_with("//") => self.eat_line(),
+                Some('/') if self.data()[self.offset..].starts_with("/*") => self.eat_block_comment()?,
+                _ => return Ok(()),
             }
-            if !c.unwrap().is_whitespace() {
-                break;
+        }
+    }
+
+    /// Consume a "/* ... */" comment (which don't nest).
+    fn eat_block_comment(&mut self) -> Result<(), ParseError> {
+        let start = self.position();
+        let start_offset = self.offset;
+        self.eat_char();
+        self.eat_char();
+        while !self.data()[self.offset..].starts_with("*/") {
+            if self.peek_char().is_none() {
+                return Err(span_error(start, self.data(), start_offset, start_offset + 2, "'*/' to close this comment", describe_token(None)));
             }
             self.eat_char();
         }
+        self.eat_char();
+        self.eat_char();
+        Ok(())
+    }
+
+    /// Consume up to (but not including) the end of the line.
+    fn eat_line(&mut self) {
+        while self.peek_char().is_some_and(|c| c != '\n') {
+            self.eat_char();
+        }
+    }
+
+    /// The line the lexer has reached, counting from 1.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Where the lexer has reached: just after the most recently lexed token.
+    pub fn position(&self) -> Position<'a> {
+        Position {
+            file : self.sources.map(|sources| sources.files[self.file].0.as_str()),
+            line : self.line,
+            column : self.column,
+        }
+    }
+
+    /// Where the most recently lexed token started (or the end of the file, if there wasn't
+    /// one). With peek_token(), this is the peeked token.
+    pub fn token_start(&self) -> Position<'a> {
+        Position {
+            file : self.sources.map(|sources| sources.files[self.token_file].0.as_str()),
+            line : self.token_line,
+            column : self.token_column,
+        }
+    }
+
+    /// An error for finding @tok, the most recently lexed token, instead of @expected.
+    pub fn error(&self, expected : &str, tok : Option<&Token>) -> ParseError {
+        span_error(self.token_start(), self.data(), self.token_offset, self.offset, expected, describe_token(tok))
+    }
+
+    /// An error about the most recently lexed token which isn't about what was expected, e.g.
+    /// "Invalid time limit 0 (must be from 1 to 9)".
+    pub fn invalid(&self, message : String) -> ParseError {
+        span_error(self.token_start(), self.data(), self.token_offset, self.offset, "", message)
+    }
+
+    /// Return the next token, if any, with where it is.
+    pub fn next_spanned(&mut self) -> Result<Option<SpannedToken<'a>>, ParseError> {
+        let token = match self.next_token()? {
+            Some(token) => token,
+            None => return Ok(None),
+        };
+        let pos = self.token_start();
+        Ok(Some(SpannedToken {
+            token,
+            start : self.token_offset,
+            end : self.offset,
+            line : pos.line,
+            column : pos.column,
+            file : pos.file,
+            source : match &self.stream {
+                Some(stream) => Cow::Owned(stream.buffer.clone()),
+                None => Cow::Borrowed(self.data),
+            },
+        }))
+    }
+
+    /// Make anything which would be a warning fail with an error instead.
+    pub fn set_strict(&mut self, strict : bool) {
+        self.strict = strict;
     }
 
-    /// Return the next token, if any.
-    pub fn next_token(&mut self) -> Option<Token<'a>> {
-        if self.buffered_token.is_some() {
-            let tok = self.buffered_token.clone();
-            self.buffered_token = None;
-            return tok;
+    /// Note something suspicious at @pos, or if the lexer is strict, fail with @error.
+    pub fn warn(&mut self, pos : Position, message : String, error : ParseError) -> Result<(), ParseError> {
+        if self.strict {
+            return Err(error);
+        }
+        self.warnings.push(Warning { file : pos.file.map(str::to_string), line : pos.line, column : pos.column, message });
+        Ok(())
+    }
+
+    /// Return the warnings so far, and clear them.
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Iterate over the rest of the tokens, with where they are.
+    pub fn tokens(&mut self) -> Tokens<'_, 'a> {
+        Tokens { lexer : self }
+    }
+
+    /// Return the next token, if any. With SourceFiles, an Include directive is replaced by
+    /// the tokens of the file it names.
+    pub fn next_token(&mut self) -> Result<Option<Token<'a>>, ParseError> {
+        if let Some(tok) = self.buffered_token.take() {
+            return tok.map(Some);
+        }
+        let sources = match self.sources {
+            Some(sources) => sources,
+            None => return self.lex_token(),
+        };
+        loop {
+            match self.lex_token()? {
+                Some(Token::Ident("Include")) => {
+                    let name = match self.lex_token()? {
+                        Some(Token::StringLiteral(name)) => name,
+                        _ => unreachable!("Include without a file name (SourceFiles::load() checks for this)"),
+                    };
+                    let file = sources.includes[&(self.file, name)];
+                    self.include_stack.push(IncludeFrame { file : self.file, data : self.data, offset : self.offset, line : self.line, column : self.column });
+                    self.file = file;
+                    self.data = &sources.files[file].1;
+                    self.offset = 0;
+                    self.line = 1;
+                    self.column = 1;
+                }
+                None => {
+                    // The end of an included file: carry on from where it was included.
+                    let frame = match self.include_stack.pop() {
+                        Some(frame) => frame,
+                        None => return Ok(None),
+                    };
+                    self.file = frame.file;
+                    self.data = frame.data;
+                    self.offset = frame.offset;
+                    self.line = frame.line;
+                    self.column = frame.column;
+                }
+                tok => return Ok(tok),
+            }
+        }
+    }
+
+    /// Lex the next token from the current file.
+    fn lex_token(&mut self) -> Result<Option<Token<'a>>, ParseError> {
+        self.eat_whitespace()?;
+        self.discard_old_lines();
+        if let Some(err) = self.stream.as_mut().and_then(|stream| stream.error.take()) {
+            return Err(ParseError::new(self.position(), "the rest of the file", format!("an error reading it ({})", err)));
         }
-        self.eat_whitespace();
         let start_offset = self.offset;
+        self.token_offset = self.offset;
+        self.token_file = self.file;
+        self.token_line = self.line;
+        self.token_column = self.column;
         loop {
             let opt_c = self.peek_char();
             match opt_c {
@@ -113,41 +786,39 @@ impl<'a> Lexer<'a> {
                         loop {
                             let str_c = self.peek_char();
                             if str_c.is_none() {
-                                panic!("Unexpected end of file (missing '\"') on line {}", self.line);
+                                return Err(span_error(self.token_start(), self.data(), start_offset, start_offset + 1, "'\"' to close this string", describe_token(None)));
                             }
                             self.eat_char();
                             if str_c.unwrap() == '\"' {
                                 break;
                             }
+                            if str_c.unwrap() == '\\' {
+                                self.eat_escape(&mut str_val)?;
+                                continue;
+                            }
                             str_val.push(str_c.unwrap());
                         }
-                        return Some(Token::StringLiteral(str_val));
-                    } else if c.is_numeric() || c == '-' {
+                        return Ok(Some(Token::StringLiteral(str_val)));
+                    } else if self.offset == start_offset && (c.is_ascii_digit() || (c == '-' && self.starts_number_after_minus())) {
                         // Start of a numeric (integer) literal.
                         self.eat_char();
-                        loop {
-                            let int_c = self.peek_char();
-                            if int_c.is_none() || !int_c.unwrap().is_numeric() {
-                                break;
-                            }
+                        while self.peek_char().is_some_and(|c| c.is_ascii_digit()) {
                             self.eat_char();
                         }
-                        let int_slice = &self.data[start_offset..self.offset];
-                        let int_val = int_slice.parse::<i64>().unwrap();
-                        return Some(Token::NumericLiteral(int_val));
-                    } else if c.is_whitespace() {
-                        if c == '\n' {
-                            self.line += 1;
-                        }
+                        let int_slice = &self.data()[start_offset..self.offset];
+                        return match int_slice.parse::<i64>() {
+                            Ok(int_val) => Ok(Some(Token::NumericLiteral(int_val))),
+                            Err(_) => Err(span_error(self.token_start(), self.data(), start_offset, self.offset,
+                                &format!("a number from {} to {}", i64::MIN, i64::MAX), int_slice.to_string())),
+                        };
+                    } else if c.is_whitespace() || c == '#' {
                         break;
-                    } else if c == '/' {
-
                     } else if !c.is_alphanumeric() && c != '_' {
                         if self.offset != start_offset {
                             break;
                         }
                         self.eat_char();
-                        return Some(Token::Symbol(c));
+                        return Ok(Some(Token::Symbol(c)));
                     } else {
                         self.eat_char();
                     }
@@ -156,81 +827,134 @@ impl<'a> Lexer<'a> {
         }
         let end_offset = self.offset;
         if start_offset == end_offset {
-            return None;
+            return Ok(None);
         }
-        Some(Token::Ident(&self.data[start_offset..end_offset]))
+        Ok(Some(match &self.stream {
+            Some(stream) => Token::IdentOwned(stream.buffer[start_offset..end_offset].to_string()),
+            None => Token::Ident(&self.data[start_offset..end_offset]),
+        }))
     }
 
-    /// Expect a specific 'ident' token, and panic if not available.
-    pub fn expect_ident(&mut self, ident: &str) {
-        let line = self.line;
-        let tok = self.next_token();
-        if tok.is_none() {
-            panic!("Expected {} on line {}, but got EOF!", ident, line);
+    /// Handle what follows a backslash in a string literal: "\\"" and "\\\\" are a quote and a
+    /// backslash, and anything else (like a Windows path) is left as it is, with a warning.
+    fn eat_escape(&mut self, str_val : &mut String) -> Result<(), ParseError> {
+        match self.peek_char() {
+            Some(c) if c == '"' || c == '\\' => {
+                self.eat_char();
+                str_val.push(c);
+            }
+            next => {
+                let pos = Position { line : self.line, column : self.column - 1, ..self.token_start() };
+                let escape : String = std::iter::once('\\').chain(next).collect();
+                let found = next.map_or("the end of the file".to_string(), |c| format!("'{}'", c));
+                let error = span_error(pos, self.data(), self.offset - 1, self.offset + escape.len() - 1, "'\"' or '\\' after a backslash", found);
+                self.warn(pos, format!("\"{}\" isn't an escape, so the backslash is kept as it is (write \"\\\\\" or use '/' in paths)", escape), error)?;
+                str_val.push('\\');
+            }
         }
-        let tok_value = tok.unwrap();
+        Ok(())
+    }
 
-        if tok_value != Token::Ident(ident) {
-            panic!("Expected {} on line {}, but got {:?}!", ident, line, tok_value);
+    /// Whether the character after the '-' the lexer is at is a digit, making it a negative
+    /// number rather than a symbol.
+    fn starts_number_after_minus(&self) -> bool {
+        self.data()[self.offset + 1..].starts_with(|c : char| c.is_ascii_digit())
+    }
+
+    /// Expect a specific 'ident' token.
+    pub fn expect_ident(&mut self, ident: &str) -> Result<(), ParseError> {
+        match self.next_spanned()? {
+            Some(tok) if tok.token.ident() == Some(ident) => Ok(()),
+            Some(tok) => Err(tok.error(&format!("'{}'", ident))),
+            None => Err(self.error(&format!("'{}'", ident), None)),
         }
     }
 
     /// Peek and see if the next token is a given ident.
     /// Note: requires &mut self because peeking is implemented as a mutation, even if it logically isn't one.
+    /// If there's an error, this is false, and the next read returns it.
     pub fn is_next_ident(&mut self, ident: &str) -> bool {
-        let tok = self.peek_token();
-        if tok.is_none() {
-            return false;
-        }
+        matches!(self.peek_token(), Ok(Some(tok)) if tok.ident() == Some(ident))
+    }
 
-        match tok.unwrap() {
-            Token::Ident(val) => val == ident,
-            _ => false
+    /// Expect a specific symbol.
+    pub fn expect_symbol(&mut self, sym: char) -> Result<(), ParseError> {
+        match self.next_spanned()? {
+            Some(tok) if tok.token == Token::Symbol(sym) => Ok(()),
+            Some(tok) => Err(tok.error(&format!("'{}'", sym))),
+            None => Err(self.error(&format!("'{}'", sym), None)),
         }
     }
 
-    /// Expect a specific symbol, and panic if not available.
-    pub fn expect_symbol(&mut self, sym: char) {
-        let line = self.line;
-        let tok = self.next_token();
-        if tok.is_none() {
-            panic!("Expected '{}' on line {}, but got EOF!", sym, line);
+    /// Expect the @close symbol at the end of a list, allowing a trailing ',' before it.
+    pub fn expect_list_end(&mut self, close: char) -> Result<(), ParseError> {
+        if self.peek_token()? == Some(Token::Symbol(',')) {
+            self.next_token()?;
         }
-        let tok_value = tok.unwrap();
+        self.expect_symbol(close)
+    }
 
-        if tok_value != Token::Symbol(sym) {
-            panic!("Expected '{}' on line {}, but got {:?}!", sym, line, tok_value);
+    /// Expect that the next token is a string, and return it.
+    pub fn get_string_literal(&mut self) -> Result<String, ParseError> {
+        match self.next_token()? {
+            Some(Token::StringLiteral(str_val)) => Ok(str_val),
+            tok => Err(self.error("a string", tok.as_ref())),
         }
     }
 
-    /// Expect that the next token is a string, and return it, or panic if it isn't.
-    pub fn get_string_literal(&mut self) -> String {
-        let line = self.line;
-        let tok = self.next_token();
-        if tok.is_none() {
-            panic!("Expected string literal on line {}, but got EOF!", line);
-        }
-        let tok_value = tok.unwrap();
-        if let Token::StringLiteral(str_val) = tok_value {
-            str_val
-        } else {
-            panic!("Expected string on line {}, but got {:?}!", line, tok_value);
+    /// Expect that the next token is an integer literal, and return it.
+    pub fn get_int_literal(&mut self) -> Result<i64, ParseError> {
+        match self.next_token()? {
+            Some(Token::NumericLiteral(int_val)) => Ok(int_val),
+            tok => Err(self.error("a number", tok.as_ref())),
         }
     }
 
-    /// Expect that the next token is an integer literal, and return it. Or panic if it isn't.'
-    pub fn get_int_literal(&mut self) -> i64 {
-        let line = self.line;
-        let tok = self.next_token();
-        if tok.is_none() {
-            panic!("Expected integer literal on line {}, but got EOF!", line);
+    /// Expect a boolean: true, false, yes or no, or 1 or 0 as in older scripts. Scripts should be
+    /// written with true and false (which is how bools are formatted).
+    pub fn get_bool_literal(&mut self) -> Result<bool, ParseError> {
+        match self.next_spanned()? {
+            Some(tok) => match tok.token.borrowed() {
+                Token::Ident("true") | Token::Ident("yes") | Token::NumericLiteral(1) => Ok(true),
+                Token::Ident("false") | Token::Ident("no") | Token::NumericLiteral(0) => Ok(false),
+                _ => Err(tok.error("true or false")),
+            },
+            None => Err(self.error("true or false", None)),
         }
-        let tok_value = tok.unwrap();
-        if let Token::NumericLiteral(int_val) = tok_value {
-            int_val
-        } else {
-            panic!("Expected integer literal on line {}, but got {:?}!", line, tok_value);
+    }
+
+    /// Expect an integer literal from @min to @max (inclusive), and return it. @what is what
+    /// the number is for, to say in the error if it's out of range.
+    pub fn get_int_in_range(&mut self, what : &str, min : i64, max : i64) -> Result<i64, ParseError> {
+        let val = self.get_int_literal()?;
+        if !(min..=max).contains(&val) {
+            return Err(self.error(&format!("{} from {} to {}", what, min, max), Some(&Token::NumericLiteral(val))));
         }
+        Ok(val)
+    }
+
+    /// Expect an integer literal which fits in a u8 (see get_int_in_range()).
+    pub fn get_u8(&mut self, what : &str) -> Result<u8, ParseError> {
+        self.get_int_in_range(what, 0, u8::MAX as i64).map(|val| val as u8)
+    }
+
+    /// Expect an integer literal which fits in a u16 (see get_int_in_range()).
+    pub fn get_u16(&mut self, what : &str) -> Result<u16, ParseError> {
+        self.get_int_in_range(what, 0, u16::MAX as i64).map(|val| val as u16)
+    }
+}
+
+/// An iterator over a lexer's tokens (see Lexer::tokens()). Iterating carries on after
+/// errors, so stop at the first one if the tokens after it don't matter.
+pub struct Tokens<'l, 'a> {
+    lexer : &'l mut Lexer<'a>,
+}
+
+impl<'a> Iterator for Tokens<'_, 'a> {
+    type Item = Result<SpannedToken<'a>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.lexer.next_spanned().transpose()
     }
 }
 
@@ -241,46 +965,55 @@ mod tests {
     fn lexer_hello() {
         let hello_world = "Hello World";
         let mut lexer = Lexer::from_str(hello_world);
-        let first_token = lexer.next_token().unwrap();
+        let first_token = lexer.next_token().unwrap().unwrap();
         assert_eq!(first_token, Token::Ident("Hello"));
-        let second_token = lexer.next_token().unwrap();
+        let second_token = lexer.next_token().unwrap().unwrap();
         assert_eq!(second_token, Token::Ident("World"));
 
 
-        assert!(lexer.next_token().is_none());
+        assert_eq!(lexer.next_token(), Ok(None));
     }
     #[test]
     fn lexer_string_literal() {
         let input = "  \" This is a string \" ";
         let mut lexer = Lexer::from_str(input);
-        let token = lexer.next_token().unwrap();
+        let token = lexer.next_token().unwrap().unwrap();
         assert_eq!(token, Token::StringLiteral(" This is a string ".to_string()));
-        assert!(lexer.next_token().is_none());
+        assert_eq!(lexer.next_token(), Ok(None));
     }
     #[test]
     fn lexer_script() {
         let test_input = "Filename=\"test.txt\"";
         let mut lexer = Lexer::from_str(test_input);
-        assert_eq!(lexer.next_token().unwrap(), Token::Ident("Filename"));
-        assert_eq!(lexer.next_token().unwrap(), Token::Symbol('='));
-        assert_eq!(lexer.next_token().unwrap(), Token::StringLiteral("test.txt".to_string()));
-        assert!(lexer.next_token().is_none());
+        assert_eq!(lexer.next_token(), Ok(Some(Token::Ident("Filename"))));
+        assert_eq!(lexer.next_token(), Ok(Some(Token::Symbol('='))));
+        assert_eq!(lexer.next_token(), Ok(Some(Token::StringLiteral("test.txt".to_string()))));
+        assert_eq!(lexer.next_token(), Ok(None));
     }
     #[test]
     fn lexer_script_with_ws() {
         let test_input = " Filename  =\n \"test.txt\"\n\n";
         let mut lexer = Lexer::from_str(test_input);
-        assert_eq!(lexer.next_token().unwrap(), Token::Ident("Filename"));
-        assert_eq!(lexer.next_token().unwrap(), Token::Symbol('='));
-        assert_eq!(lexer.next_token().unwrap(), Token::StringLiteral("test.txt".to_string()));
-        assert!(lexer.next_token().is_none());
+        assert_eq!(lexer.next_token(), Ok(Some(Token::Ident("Filename"))));
+        assert_eq!(lexer.next_token(), Ok(Some(Token::Symbol('='))));
+        assert_eq!(lexer.next_token(), Ok(Some(Token::StringLiteral("test.txt".to_string()))));
+        assert_eq!(lexer.next_token(), Ok(None));
+    }
+    #[test]
+    fn lexer_ident_with_digits() {
+        let test_input = "difficulty4 = 72";
+        let mut lexer = Lexer::from_str(test_input);
+        assert_eq!(lexer.next_token(), Ok(Some(Token::Ident("difficulty4"))));
+        assert_eq!(lexer.next_token(), Ok(Some(Token::Symbol('='))));
+        assert_eq!(lexer.next_token(), Ok(Some(Token::NumericLiteral(72))));
+        assert_eq!(lexer.next_token(), Ok(None));
     }
     #[test]
     fn lexer_expect_ident() {
         let test_input = "IdentA IdentB";
         let mut lexer = Lexer::from_str(test_input);
-        lexer.expect_ident("IdentA");
-        assert_eq!(lexer.next_token().unwrap(), Token::Ident("IdentB"));
+        lexer.expect_ident("IdentA").unwrap();
+        assert_eq!(lexer.next_token(), Ok(Some(Token::Ident("IdentB"))));
     }
 
     #[test]
@@ -289,16 +1022,360 @@ mod tests {
         let mut lexer = Lexer::from_str(test_input);
         assert!(lexer.is_next_ident("Terrain"));
         // Doesn't advance the lexer
-        lexer.expect_ident("Terrain");
+        lexer.expect_ident("Terrain").unwrap();
         assert!(!lexer.is_next_ident("Terrain"));
-        assert_eq!(lexer.next_token().unwrap(), Token::StringLiteral("test".to_string()));
+        assert_eq!(lexer.next_token(), Ok(Some(Token::StringLiteral("test".to_string()))));
         assert!(lexer.is_next_ident("Mask"));
         // Doesn't advance the lexer
-        assert_eq!(lexer.next_token().unwrap(), Token::Ident("Mask"));
+        assert_eq!(lexer.next_token(), Ok(Some(Token::Ident("Mask"))));
         assert!(!lexer.is_next_ident("Mask"));
-        assert_eq!(lexer.next_token().unwrap(), Token::StringLiteral("mask".to_string()));
+        assert_eq!(lexer.next_token(), Ok(Some(Token::StringLiteral("mask".to_string()))));
         // Not true at EOF
         assert!(!lexer.is_next_ident("Mask"));
     }
 
+    #[test]
+    fn lexer_line_comments() {
+        let test_input = "// A comment\nTerrain \"a.bmp\" // Another \"comment\"\n# And another\n\nObject# Straight after a token\n  = 5";
+        let mut lexer = Lexer::from_str(test_input);
+        assert_eq!(lexer.next_token(), Ok(Some(Token::Ident("Terrain"))));
+        assert_eq!(lexer.line(), 2);
+        assert_eq!(lexer.next_token(), Ok(Some(Token::StringLiteral("a.bmp".to_string()))));
+        assert_eq!(lexer.next_token(), Ok(Some(Token::Ident("Object"))));
+        assert_eq!(lexer.line(), 5);
+        assert_eq!(lexer.next_token(), Ok(Some(Token::Symbol('='))));
+        assert_eq!(lexer.line(), 6);
+        assert_eq!(lexer.next_token(), Ok(Some(Token::NumericLiteral(5))));
+        assert_eq!(lexer.next_token(), Ok(None));
+        // Strings can still have them in.
+        let mut lexer = Lexer::from_str("\"# // \"");
+        assert_eq!(lexer.next_token(), Ok(Some(Token::StringLiteral("# // ".to_string()))));
+    }
+
+    #[test]
+    fn lexer_block_comments() {
+        let test_input = "/* A comment */ a /* Over\ntwo // lines */= /**/\n/* * / */ 5 /*/ Still a comment */";
+        let mut lexer = Lexer::from_str(test_input);
+        assert_eq!(lexer.next_token(), Ok(Some(Token::Ident("a"))));
+        assert_eq!(lexer.next_token(), Ok(Some(Token::Symbol('='))));
+        assert_eq!(lexer.line(), 2);
+        assert_eq!(lexer.next_token(), Ok(Some(Token::NumericLiteral(5))));
+        assert_eq!(lexer.line(), 3);
+        assert_eq!(lexer.next_token(), Ok(None));
+    }
+
+    #[test]
+    fn lexer_unterminated() {
+        let mut lexer = Lexer::from_str("a\n /* b\n\nc");
+        lexer.next_token().unwrap();
+        let err = lexer.next_token().unwrap_err();
+        assert_eq!(err.to_string(), "2:2: expected '*/' to close this comment but found the end of the file");
+        assert_eq!(err.snippet().unwrap(), "2 |  /* b\n  |  ^^");
+        let mut lexer = Lexer::from_str("a \"b\nc");
+        lexer.next_token().unwrap();
+        assert_eq!(lexer.next_token().unwrap_err().to_string(), "1:3: expected '\"' to close this string but found the end of the file");
+    }
+
+    #[test]
+    fn lexer_lone_slash() {
+        // A '/' which doesn't start a comment is just a symbol (and used to hang the lexer).
+        let mut lexer = Lexer::from_str("a/b / 2/");
+        assert_eq!(lexer.next_token(), Ok(Some(Token::Ident("a"))));
+        assert_eq!(lexer.next_token(), Ok(Some(Token::Symbol('/'))));
+        assert_eq!(lexer.next_token(), Ok(Some(Token::Ident("b"))));
+        assert_eq!(lexer.next_token(), Ok(Some(Token::Symbol('/'))));
+        assert_eq!(lexer.next_token(), Ok(Some(Token::NumericLiteral(2))));
+        assert_eq!(lexer.next_token(), Ok(Some(Token::Symbol('/'))));
+        assert_eq!(lexer.next_token(), Ok(None));
+    }
+
+    #[test]
+    fn lexer_line_numbers() {
+        let mut lexer = Lexer::from_str("a = 1\n\n# b = 2\nc");
+        lexer.expect_ident("a").unwrap();
+        lexer.expect_symbol('=').unwrap();
+        lexer.get_int_literal().unwrap();
+        let err = lexer.get_int_literal().unwrap_err();
+        assert_eq!((err.line, err.column), (4, 1));
+    }
+
+    #[test]
+    fn lexer_minus() {
+        let tokens = |input| {
+            let mut lexer = Lexer::from_str(input);
+            let mut tokens = Vec::new();
+            while let Some(tok) = lexer.next_token().unwrap() {
+                tokens.push(tok);
+            }
+            tokens
+        };
+        assert_eq!(tokens("-"), vec![Token::Symbol('-')]);
+        assert_eq!(tokens("-5"), vec![Token::NumericLiteral(-5)]);
+        assert_eq!(tokens("5-3"), vec![Token::NumericLiteral(5), Token::NumericLiteral(-3)]);
+        assert_eq!(tokens("--3"), vec![Token::Symbol('-'), Token::NumericLiteral(-3)]);
+        assert_eq!(tokens("a- b"), vec![Token::Ident("a"), Token::Symbol('-'), Token::Ident("b")]);
+    }
+
+    #[test]
+    fn lexer_bad_numbers() {
+        let mut lexer = Lexer::from_str("x = 99999999999999999999 -9223372036854775808");
+        assert!(lexer.is_next_ident("x"));
+        lexer.next_token().unwrap();
+        lexer.expect_symbol('=').unwrap();
+        assert!(!lexer.is_next_ident("x"));
+        // The error is kept for the next read after peeking.
+        let err = lexer.get_int_literal().unwrap_err();
+        assert_eq!(err.to_string(), "1:5: expected a number from -9223372036854775808 to 9223372036854775807 but found 99999999999999999999");
+        assert_eq!(err.width, 20);
+        assert_eq!(lexer.get_int_literal(), Ok(i64::MIN));
+    }
+
+    #[test]
+    fn lexer_positions() {
+        let test_input = "Terrain \"two\nlines\" /* a\nb */ x\n\t// c\n  = \"é\" 12 # d\n\n}";
+        let mut lexer = Lexer::from_str(test_input);
+        let mut positions = Vec::new();
+        while lexer.next_token().unwrap().is_some() {
+            let start = lexer.token_start();
+            let end = lexer.position();
+            positions.push(((start.line, start.column), (end.line, end.column)));
+        }
+        assert_eq!(positions, vec![
+            ((1, 1), (1, 8)),
+            ((1, 9), (2, 7)),
+            ((3, 6), (3, 7)),
+            ((5, 3), (5, 4)),
+            ((5, 5), (5, 8)),
+            ((5, 9), (5, 11)),
+            ((7, 1), (7, 2)),
+        ]);
+        // At the end of the file, the "token" starts at the end.
+        assert_eq!(lexer.token_start(), Position { file : None, line : 7, column : 2 });
+        assert_eq!(lexer.position().to_string(), "7:2");
+    }
+
+    #[test]
+    fn lexer_errors() {
+        let mut lexer = Lexer::from_str("frames = (1, 2 =\n  \"x\" Terrain");
+        lexer.expect_ident("frames").unwrap();
+        lexer.expect_symbol('=').unwrap();
+        lexer.expect_symbol('(').unwrap();
+        assert_eq!(lexer.get_int_literal(), Ok(1));
+        lexer.expect_symbol(',').unwrap();
+        assert_eq!(lexer.get_int_literal(), Ok(2));
+        let err = lexer.expect_symbol(')').unwrap_err();
+        assert_eq!(err.to_string(), "1:16: expected ')' but found '='");
+        assert_eq!(lexer.get_int_literal().unwrap_err().to_string(), "2:3: expected a number but found \"x\"");
+        assert_eq!(lexer.get_string_literal().unwrap_err().to_string(), "2:7: expected a string but found 'Terrain'");
+        assert_eq!(lexer.expect_ident("Object").unwrap_err().to_string(), "2:14: expected 'Object' but found the end of the file");
+    }
+
+    #[test]
+    fn lexer_includes() {
+        let dir = std::env::temp_dir().join(format!("modlem_include_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("common")).unwrap();
+        std::fs::write(dir.join("theme.txt"), "a Include \"common/pal.txt\" b\nInclude \"common/pal.txt\" c").unwrap();
+        std::fs::write(dir.join("common/pal.txt"), "x\nInclude \"empty.txt\"\n  y").unwrap();
+        std::fs::write(dir.join("common/empty.txt"), "// Nothing here").unwrap();
+        std::fs::write(dir.join("loop_a.txt"), "Include \"loop_b.txt\"").unwrap();
+        std::fs::write(dir.join("loop_b.txt"), "\n\nInclude \"loop_a.txt\"").unwrap();
+        let theme = dir.join("theme.txt").to_string_lossy().into_owned();
+
+        let sources = SourceFiles::load(&theme).unwrap();
+        let mut lexer = Lexer::from_sources(&sources);
+        let mut tokens = Vec::new();
+        while let Some(tok) = lexer.next_token().unwrap() {
+            tokens.push(tok);
+        }
+        let idents : Vec<Token> = "a x y b x y c".split(' ').map(Token::Ident).collect();
+        assert_eq!(tokens, idents);
+
+        // Errors say which file they're in.
+        let mut lexer = Lexer::from_sources(&sources);
+        lexer.expect_ident("a").unwrap();
+        lexer.expect_ident("x").unwrap();
+        let err = lexer.expect_ident("z").unwrap_err();
+        assert_eq!(err.file, Some(dir.join("common/pal.txt").to_string_lossy().into_owned()));
+        assert_eq!((err.line, err.column), (3, 3));
+
+        let (loop_a, loop_b) = (dir.join("loop_a.txt"), dir.join("loop_b.txt"));
+        let err = SourceFiles::load(&loop_a.to_string_lossy()).err().unwrap();
+        assert_eq!(err.to_string(), format!("{1}:3:1: Include loop: {0} -> {1} -> {0}", loop_a.display(), loop_b.display()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn lexer_bools() {
+        let mut lexer = Lexer::from_str("true false yes no 1 0 2 True");
+        let mut bools = Vec::new();
+        for _ in 0..6 {
+            bools.push(lexer.get_bool_literal().unwrap());
+        }
+        assert_eq!(bools, vec![true, false, true, false, true, false]);
+        assert_eq!(lexer.get_bool_literal().unwrap_err().to_string(), "1:23: expected true or false but found 2");
+        assert_eq!(lexer.get_bool_literal().unwrap_err().to_string(), "1:25: expected true or false but found 'True'");
+        assert_eq!(lexer.get_bool_literal().unwrap_err().to_string(), "1:29: expected true or false but found the end of the file");
+    }
+
+    #[test]
+    fn lexer_ranged_ints() {
+        let mut lexer = Lexer::from_str("300 300 -1 65536 65535");
+        assert_eq!(lexer.get_int_in_range("a value", 0, 300), Ok(300));
+        let err = lexer.get_u8("the trigger width").unwrap_err();
+        assert_eq!(err.to_string(), "1:5: expected the trigger width from 0 to 255 but found 300");
+        assert_eq!(err.width, 3);
+        assert!(lexer.get_u8("a value").is_err());
+        assert!(lexer.get_u16("a value").is_err());
+        assert_eq!(lexer.get_u16("a value"), Ok(65535));
+    }
+
+    #[test]
+    fn lexer_spanned_tokens() {
+        let mut lexer = Lexer::from_str("a = {\n  size = (8, 16 = 4)\n\"b\nc\"");
+        let tok = lexer.next_spanned().unwrap().unwrap();
+        assert_eq!((tok.token, tok.start, tok.end, tok.line, tok.column), (Token::Ident("a"), 0, 1, 1, 1));
+        for _ in 0..7 {
+            lexer.next_token().unwrap();
+        }
+        let tok = lexer.next_spanned().unwrap().unwrap();
+        assert_eq!((tok.token, tok.start, tok.end, tok.line, tok.column), (Token::NumericLiteral(16), 19, 21, 2, 14));
+
+        let err = lexer.expect_symbol(')').unwrap_err();
+        assert_eq!(err.snippet().unwrap(), "2 |   size = (8, 16 = 4)\n  |                 ^");
+        lexer.next_token().unwrap();
+        lexer.next_token().unwrap();
+        // Only the first line of a multi-line token is shown.
+        let err = lexer.get_int_literal().unwrap_err();
+        assert_eq!(err.snippet().unwrap(), "3 | \"b\n  | ^^");
+        let err = lexer.expect_symbol('}').unwrap_err();
+        assert_eq!(err.snippet().unwrap(), "4 | c\"\n  |   ^");
+    }
+
+    #[test]
+    fn source_snippets() {
+        let source = "a\n\tsize = (8,\t16 = 4)\n";
+        assert_eq!(snippet(source, 2, 13, 2).unwrap(), "2 |     size = (8,  16 = 4)\n  |                 ^^");
+        // Past the end of the line, and of the file.
+        assert_eq!(snippet(source, 1, 2, 0).unwrap(), "1 | a\n  |  ^");
+        assert_eq!(snippet(source, 3, 1, 1), None);
+
+        // Long lines are cut down to the part around the carets.
+        let long = format!("{}bad{}", "x".repeat(100), "y".repeat(100));
+        let shown = snippet(&format!("{}{}", "\n".repeat(11), long), 12, 101, 3).unwrap();
+        assert_eq!(shown, format!("12 | ...{}bad{}...\n   | {}^^^", "x".repeat(39), "y".repeat(38), " ".repeat(42)));
+        assert_eq!(snippet(&long, 1, 2, 1).unwrap(), format!("1 | {}...\n  |  ^", "x".repeat(80)));
+        assert_eq!(snippet(&long, 1, 204, 1).unwrap(), format!("1 | ...{}\n  | {}^", "y".repeat(80), " ".repeat(83)));
+    }
+
+    #[test]
+    fn lexer_escapes() {
+        let mut lexer = Lexer::from_str("\"a \\\"b\\\" \\\\\" \"tiles\\rock.bmp\"\n  \"C:\\\\Lemmings\\\\\"");
+        assert_eq!(lexer.get_string_literal(), Ok("a \"b\" \\".to_string()));
+        assert_eq!(lexer.get_string_literal(), Ok("tiles\\rock.bmp".to_string()));
+        assert_eq!(lexer.get_string_literal(), Ok("C:\\Lemmings\\".to_string()));
+        let warnings = lexer.take_warnings();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].to_string(), "1:20: warning: \"\\r\" isn't an escape, so the backslash is kept as it is (write \"\\\\\" or use '/' in paths)");
+        assert!(lexer.take_warnings().is_empty());
+
+        let mut lexer = Lexer::from_str("\"tiles\\rock.bmp\"");
+        lexer.set_strict(true);
+        let err = lexer.get_string_literal().unwrap_err();
+        assert_eq!(err.to_string(), "1:7: expected '\"' or '\\' after a backslash but found 'r'");
+        assert_eq!(err.width, 2);
+
+        for text in ["plain", "C:\\Lemmings\\", "\"quoted\\\""] {
+            assert_eq!(Lexer::from_str(&quote(text)).get_string_literal(), Ok(text.to_string()));
+        }
+        assert_eq!(file_path("tiles\\rock.bmp"), Path::new("tiles").join("rock.bmp"));
+        assert_eq!(file_path("tiles/rock.bmp"), Path::new("tiles").join("rock.bmp"));
+    }
+
+    #[test]
+    fn lexer_tokens() {
+        let script = "// Extracted by modlem\nHeaderFile \"ground0o.dat\"\nDataFile \"vgagr0.dat\"\n\n\
+                      Object 0 \"obj0.bmp\" = {\n\tanimation_flags = 0\n\tframes = (0,1)\n\ttrigger = (0,0,0,0)\n}\n\
+                      Terrain \"ter0.bmp\" Mask \"ter0_mask.bmp\"\n\
+                      Palettes = {\n\tvga_custom = {(0, 0, 0), (63, 32, -1)}\n}\n";
+        let mut lexer = Lexer::from_str(script);
+        // Peeked tokens aren't skipped.
+        assert!(lexer.is_next_ident("HeaderFile"));
+        let tokens : Vec<SpannedToken> = lexer.tokens().collect::<Result<_, _>>().unwrap();
+        assert_eq!(tokens.len(), 58);
+        assert_eq!(tokens[0].token, Token::Ident("HeaderFile"));
+        assert_eq!((tokens[4].token.clone(), tokens[4].line, tokens[4].column), (Token::Ident("Object"), 5, 1));
+        assert_eq!(tokens[6].token, Token::StringLiteral("obj0.bmp".to_string()));
+        let last_number = tokens.iter().rev().find_map(|tok| match tok.token {
+            Token::NumericLiteral(val) => Some(val),
+            _ => None,
+        });
+        assert_eq!(last_number, Some(-1));
+        assert_eq!(tokens.last().unwrap().token, Token::Symbol('}'));
+
+        let mut lexer = Lexer::from_str("a 99999999999999999999 b");
+        let tokens : Vec<_> = lexer.tokens().collect();
+        assert_eq!(tokens.len(), 3);
+        assert!(tokens[1].is_err());
+        assert!(Lexer::from_str("a 99999999999999999999 b").tokens().collect::<Result<Vec<_>, _>>().is_err());
+    }
+
+    #[test]
+    fn lexer_from_reader() {
+        let script = "HeaderFile \"ground0o.dat\" /* a comment\nover two lines */ Object 0 = {\n\tframes = (0, -1)\n\ttrigger = \"two\nlines\"\n}\n";
+        let reader = |text : &str| -> Box<dyn BufRead> { Box::new(std::io::Cursor::new(text.as_bytes().to_vec())) };
+        let borrowed : Vec<SpannedToken> = Lexer::from_str(script).tokens().collect::<Result<_, _>>().unwrap();
+        let streamed : Vec<SpannedToken> = Lexer::from_reader(reader(script)).tokens().collect::<Result<_, _>>().unwrap();
+        assert_eq!(streamed.len(), borrowed.len());
+        for (streamed, borrowed) in streamed.iter().zip(borrowed.iter()) {
+            assert_eq!(streamed.token.borrowed(), borrowed.token);
+            assert_eq!(streamed.position(), borrowed.position());
+            assert_eq!(streamed.error("x"), borrowed.error("x"));
+        }
+        assert_eq!(streamed[0].token, Token::IdentOwned("HeaderFile".to_string()));
+        // Only the lines from the token's on are kept.
+        assert!(streamed[6].source.starts_with("\tframes = "));
+
+        let mut lexer = Lexer::from_reader(reader("a = {\n  size = (8, 16 = 4)\n"));
+        assert!(lexer.is_next_ident("a"));
+        for _ in 0..9 {
+            lexer.next_token().unwrap();
+        }
+        let err = lexer.expect_symbol(')').unwrap_err();
+        assert_eq!(err.snippet().unwrap(), "2 |   size = (8, 16 = 4)\n  |                 ^");
+
+        let mut lexer = Lexer::from_reader(Box::new(&b"a b\n\xff\n"[..]));
+        assert_eq!(lexer.next_token(), Ok(Some(Token::IdentOwned("a".to_string()))));
+        assert_eq!(lexer.next_token(), Ok(Some(Token::IdentOwned("b".to_string()))));
+        let err = lexer.next_token().unwrap_err();
+        assert!(err.to_string().starts_with("2:1: expected the rest of the file but found an error reading it"), "{}", err);
+    }
+
+    #[test]
+    fn lexer_unknown_names() {
+        assert_eq!(edit_distance("trigge", "trigger"), 1);
+        assert_eq!(edit_distance("size", "frames"), 5);
+        assert_eq!(edit_distance("", "abc"), 3);
+        let known = ["frames", "size", "trigger", "trigger_effect"];
+        let mut lexer = Lexer::from_str("trigge Sise colour 5");
+        let mut errors = Vec::new();
+        while let Some(tok) = lexer.next_spanned().unwrap() {
+            errors.push(tok.unknown_name_error("a property", &known).to_string());
+        }
+        assert_eq!(errors, vec![
+            "1:1: expected a property but found 'trigge' (did you mean 'trigger'?)",
+            "1:8: expected a property but found 'Sise' (did you mean 'size'?)",
+            "1:13: expected a property but found 'colour' (it should be one of: frames, size, trigger, trigger_effect)",
+            "1:20: expected a property but found 5",
+        ]);
+    }
+
+    #[test]
+    fn parse_error_io_error() {
+        let err = Lexer::from_str("b").expect_ident("a").unwrap_err().in_file(Path::new("spec.txt"));
+        let io_err = std::io::Error::from(err.clone());
+        assert_eq!(io_err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(io_err.to_string(), "spec.txt:1:1: expected 'a' but found 'b'");
+        assert_eq!(ParseError::from_io_error(&io_err), Some(&err));
+        assert_eq!(ParseError::from_io_error(&std::io::Error::other("b")), None);
+    }
 }
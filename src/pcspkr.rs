@@ -0,0 +1,254 @@
+/*
+ * modlem: A graphics importer/exporter for Lemmings
+ * Copyright (C) 2022–2026 David Gow <david@davidgow.net>
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use binary_io::*;
+
+/*
+ * The PC speaker sound effects section of main.dat. This starts with a table of
+ * 16-bit offsets (one per sound effect, relative to the start of the section), so
+ * the first offset also tells us the number of effects. Each effect runs until the
+ * next one starts, and is a list of notes: a 16-bit divisor for the PIT (the
+ * programmable interval timer which drives the speaker), and an 8-bit duration in
+ * timer ticks. A divisor of 0 is silence.
+ *
+ * As this isn't documented anywhere, decode() only accepts data which it can
+ * re-encode byte-for-byte, so anything else can still be kept as-is.
+ */
+
+/// The frequency of the PIT's input clock, in Hz.
+pub const PIT_FREQUENCY : f64 = 1193182.0;
+
+/// A single note: a tone (or silence) held for a number of timer ticks.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Note {
+    pub divisor : u16,
+    pub ticks : u8,
+}
+
+impl Note {
+    /// The frequency of the note in Hz, or None if it's silent.
+    pub fn frequency(&self) -> Option<f64> {
+        if self.divisor == 0 {
+            None
+        } else {
+            Some(PIT_FREQUENCY / self.divisor as f64)
+        }
+    }
+
+    /// Format the frequency with as few decimal places as possible while still
+    /// giving back the same divisor when parsed.
+    fn frequency_string(&self) -> String {
+        let freq = match self.frequency() {
+            None => return "0".to_string(),
+            Some(freq) => freq,
+        };
+        for precision in 0..8 {
+            let freq_str = format!("{:.*}", precision, freq);
+            if divisor_from_frequency(freq_str.parse().unwrap()) == Some(self.divisor) {
+                return freq_str;
+            }
+        }
+        format!("{}", freq)
+    }
+}
+
+/// Convert a frequency in Hz back to a PIT divisor. 0 Hz is silence.
+fn divisor_from_frequency(freq : f64) -> Option<u16> {
+    if freq == 0.0 {
+        return Some(0);
+    }
+    let divisor = (PIT_FREQUENCY / freq).round();
+    if divisor < 1.0 || divisor > u16::MAX as f64 {
+        None
+    } else {
+        Some(divisor as u16)
+    }
+}
+
+/// A sound effect, made up of a sequence of notes.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Sound {
+    pub notes : Vec<Note>,
+}
+
+/// The default rate of the PC's timer tick interrupt, which sets the length of a tick.
+pub const TICK_FREQUENCY : f64 = PIT_FREQUENCY / 65536.0;
+
+impl Sound {
+    /// Synthesise the sound as a square wave, returning 8-bit unsigned samples.
+    pub fn render(&self, sample_rate : u32) -> Vec<u8> {
+        let mut samples = Vec::new();
+        let mut phase = 0.0;
+        for note in &self.notes {
+            let num_samples = (note.ticks as f64 * sample_rate as f64 / TICK_FREQUENCY).round() as usize;
+            match note.frequency() {
+                None => samples.resize(samples.len() + num_samples, 0x80),
+                Some(freq) => {
+                    for _ in 0..num_samples {
+                        samples.push(if phase < 0.5 { 0xC0 } else { 0x40 });
+                        phase = (phase + freq / sample_rate as f64).fract();
+                    }
+                },
+            }
+        }
+        samples
+    }
+}
+
+/// Decode the PC speaker section into its sound effects. Returns None if the data
+/// doesn't follow the expected layout.
+pub fn decode(data : &[u8]) -> Option<Vec<Sound>> {
+    let mut reader = data;
+    let first_offset = read_le16(&mut reader).ok()? as usize;
+    if first_offset == 0 || !first_offset.is_multiple_of(2) || first_offset > data.len() {
+        return None;
+    }
+    let mut offsets = vec![first_offset];
+    for _ in 1..(first_offset / 2) {
+        offsets.push(read_le16(&mut reader).ok()? as usize);
+    }
+    offsets.push(data.len());
+
+    let mut sounds = Vec::new();
+    for bounds in offsets.windows(2) {
+        if bounds[1] < bounds[0] || bounds[1] > data.len() || (bounds[1] - bounds[0]) % 3 != 0 {
+            return None;
+        }
+        let notes = data[bounds[0]..bounds[1]].chunks(3).map(|note| Note {
+            divisor : u16::from_le_bytes([note[0], note[1]]),
+            ticks : note[2],
+        }).collect();
+        sounds.push(Sound { notes });
+    }
+
+    if encode(&sounds) != data {
+        return None;
+    }
+    Some(sounds)
+}
+
+/// Encode a set of sound effects back into the PC speaker section format.
+pub fn encode(sounds : &[Sound]) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut offset = sounds.len() * 2;
+    for sound in sounds {
+        write_le16(offset as u16, &mut data).unwrap();
+        offset += sound.notes.len() * 3;
+    }
+    for sound in sounds {
+        for note in &sound.notes {
+            write_le16(note.divisor, &mut data).unwrap();
+            write_byte(note.ticks, &mut data).unwrap();
+        }
+    }
+    data
+}
+
+/// Write the sound effects as text: one block per sound, with one note per line,
+/// giving the frequency (in Hz, 0 for silence) and the duration (in ticks).
+pub fn write_text(sounds : &[Sound], writer : &mut dyn std::io::Write) -> std::io::Result<()> {
+    for (i, sound) in sounds.iter().enumerate() {
+        writeln!(writer, "Sound {}", i)?;
+        writeln!(writer, "{{")?;
+        for note in &sound.notes {
+            writeln!(writer, "\t{} {}", note.frequency_string(), note.ticks)?;
+        }
+        writeln!(writer, "}}")?;
+    }
+    Ok(())
+}
+
+/// Parse sound effects from the text format written by write_text(). Sounds must be
+/// listed in order.
+pub fn parse_text(text : &str) -> Result<Vec<Sound>, String> {
+    let mut sounds = Vec::new();
+    let mut current : Option<Sound> = None;
+    for (line_num, line) in text.lines().enumerate() {
+        let fields : Vec<&str> = line.split_whitespace().collect();
+        match (fields.as_slice(), current.as_mut()) {
+            ([], _) => {},
+            (["Sound", index], None) => {
+                if index.parse::<usize>() != Ok(sounds.len()) {
+                    return Err(format!("Expected sound {} on line {}, got {}", sounds.len(), line_num + 1, index));
+                }
+            },
+            (["{"], None) => current = Some(Sound::default()),
+            (["}"], Some(_)) => sounds.push(current.take().unwrap()),
+            ([freq, ticks], Some(sound)) => {
+                let divisor = freq.parse::<f64>().ok().and_then(divisor_from_frequency);
+                let ticks = ticks.parse::<u8>().ok();
+                match (divisor, ticks) {
+                    (Some(divisor), Some(ticks)) => sound.notes.push(Note { divisor, ticks }),
+                    _ => return Err(format!("Invalid note \"{}\" on line {}", line.trim(), line_num + 1)),
+                }
+            },
+            _ => return Err(format!("Unexpected \"{}\" on line {}", line.trim(), line_num + 1)),
+        }
+    }
+    if current.is_some() {
+        return Err("Unexpected end of file (missing '}')".to_string());
+    }
+    Ok(sounds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_sounds() -> Vec<Sound> {
+        vec![
+            Sound { notes : vec![Note { divisor : 2712, ticks : 3 }, Note { divisor : 0, ticks : 1 }, Note { divisor : 65535, ticks : 255 }] },
+            Sound { notes : vec![] },
+            Sound { notes : vec![Note { divisor : 1, ticks : 0 }] },
+        ]
+    }
+
+    #[test]
+    fn pcspkr_binary_round_trip() {
+        let data = encode(&test_sounds());
+        assert_eq!(&data[0..6], &[6, 0, 15, 0, 15, 0]);
+        assert_eq!(decode(&data), Some(test_sounds()));
+    }
+
+    #[test]
+    fn pcspkr_rejects_unknown_layout() {
+        assert_eq!(decode(&[]), None);
+        assert_eq!(decode(&[3, 0, 1, 2, 3]), None);
+        assert_eq!(decode(&[2, 0, 1, 2]), None);
+    }
+
+    #[test]
+    fn pcspkr_render() {
+        let sound = Sound { notes : vec![Note { divisor : 0, ticks : 1 }, Note { divisor : 2712, ticks : 2 }] };
+        let samples = sound.render(22050);
+        // About 1211 samples per tick.
+        assert_eq!(samples.len(), 1211 + 2422);
+        assert!(samples[..1211].iter().all(|&s| s == 0x80));
+        // 440Hz at 22050Hz is about 25 samples high, then 25 low.
+        assert_eq!(&samples[1211..1213], &[0xC0, 0xC0]);
+        assert_eq!(samples[1211 + 30], 0x40);
+    }
+
+    #[test]
+    fn pcspkr_text_round_trip() {
+        let mut text = Vec::new();
+        write_text(&test_sounds(), &mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.starts_with("Sound 0\n{\n\t440 3\n\t0 1\n"));
+        assert_eq!(parse_text(&text).unwrap(), test_sounds());
+    }
+}
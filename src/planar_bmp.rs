@@ -17,6 +17,7 @@
 
 use std::fmt::{Debug, Formatter};
 use binary_io::*;
+use png;
 
 const BITMAP_SIGNATURE : u16 = 0x4D42; // 'MB', in little-endian
 const BI_RGB : u32 = 0;
@@ -33,11 +34,17 @@ struct BitmapFileHeader {
     bfOffBits : u32
 }
 
-impl BitmapFileHeader {
-    const STRUCT_SIZE : usize = 14;
+binary_record!(BitmapFileHeader {
+    bfType : le16,
+    bfSize : le32,
+    _bfReserved1 : le16,
+    _bfReserved2 : le16,
+    bfOffBits : le32,
+});
 
+impl BitmapFileHeader {
     /// Create a new, empty bitmap file header, of a given size and data offset.
-    /// @size should probably be BitmapFileHeader::STRUCT_SIZE
+    /// @size should probably be BitmapFileHeader::byte_size()
     fn new(size : usize, data_offset : usize) -> BitmapFileHeader {
         BitmapFileHeader {
             bfType: BITMAP_SIGNATURE,
@@ -47,27 +54,6 @@ impl BitmapFileHeader {
             bfOffBits: data_offset as u32
         }
     }
-
-    /// Parse a bitmap file header from an input stream.
-    fn from_data(reader : &mut dyn std::io::Read) -> std::io::Result<BitmapFileHeader> {
-        Ok(BitmapFileHeader {
-        bfType : read_le16(reader)?,
-        bfSize : read_le32(reader)?,
-        _bfReserved1 : read_le16(reader)?,
-        _bfReserved2 : read_le16(reader)?,
-        bfOffBits : read_le32(reader)?
-    })
-    }
-
-    /// Write a bitmap file header to an output stream.
-    fn write(self: &BitmapFileHeader, writer : &mut dyn std::io::Write) -> std::io::Result<()> {
-        write_le16(self.bfType, writer)?;
-        write_le32(self.bfSize, writer)?;
-        write_le16(self._bfReserved1, writer)?;
-        write_le16(self._bfReserved2, writer)?;
-        write_le32(self.bfOffBits, writer)?;
-        Ok(())
-    }
 }
 
 #[allow(non_snake_case)]
@@ -86,12 +72,24 @@ struct BitmapInfoHeader {
     _biClrImportant : u32
 }
 
-impl BitmapInfoHeader {
-    const STRUCT_SIZE : usize = 40;
+binary_record!(BitmapInfoHeader {
+    biSize : le32,
+    biWidth : le32,
+    biHeight : le32,
+    biPlanes : le16,
+    biBitCount : le16,
+    biCompression : le32,
+    biSizeImage : le32,
+    _biXPelsPerMeter : le32,
+    _biYPelsPerMeter : le32,
+    biClrUsed : le32,
+    _biClrImportant : le32,
+});
 
+impl BitmapInfoHeader {
     fn new(width : usize, height : usize, bpp : usize, pal_size : usize, image_size : usize) -> BitmapInfoHeader {
         BitmapInfoHeader {
-            biSize : BitmapInfoHeader::STRUCT_SIZE as u32,
+            biSize : BitmapInfoHeader::byte_size() as u32,
             biWidth : width as u32,
             biHeight : height as u32,
             biPlanes : 1,
@@ -104,38 +102,12 @@ impl BitmapInfoHeader {
             _biClrImportant : 0
         }
     }
+    /// Read the header, skipping anything newer versions of it have added to the end.
     fn from_data(reader : &mut dyn std::io::Read) -> std::io::Result<BitmapInfoHeader> {
-        let res = BitmapInfoHeader {
-            biSize : read_le32(reader)?,
-            biWidth : read_le32(reader)?,
-            biHeight : read_le32(reader)?,
-            biPlanes : read_le16(reader)?,
-            biBitCount : read_le16(reader)?,
-            biCompression : read_le32(reader)?,
-            biSizeImage : read_le32(reader)?,
-            _biXPelsPerMeter : read_le32(reader)?,
-            _biYPelsPerMeter : read_le32(reader)?,
-            biClrUsed : read_le32(reader)?,
-            _biClrImportant : read_le32(reader)?,
-        };
-        let mut unused = vec![0u8; res.biSize as usize - 40];
-        let _ = reader.read_exact(&mut unused[..]);
+        let res = BitmapInfoHeader::read_from(reader)?;
+        let _ = skip_bytes(reader, (res.biSize as usize).saturating_sub(BitmapInfoHeader::byte_size()));
         Ok(res)
     }
-    fn write(self: &BitmapInfoHeader, writer : &mut dyn std::io::Write) -> std::io::Result<()> {
-        write_le32(self.biSize, writer)?;
-        write_le32(self.biWidth, writer)?;
-        write_le32(self.biHeight, writer)?;
-        write_le16(self.biPlanes, writer)?;
-        write_le16(self.biBitCount, writer)?;
-        write_le32(self.biCompression, writer)?;
-        write_le32(self.biSizeImage, writer)?;
-        write_le32(self._biXPelsPerMeter, writer)?;
-        write_le32(self._biYPelsPerMeter, writer)?;
-        write_le32(self.biClrUsed, writer)?;
-        write_le32(self._biClrImportant, writer)?;
-        Ok(())
-    }
 }
 
 /// An RGB colour tuple, 8 bits per channel.
@@ -176,6 +148,15 @@ impl ColourRGB {
     }
 }
 
+/// The EGA palette register value (see ColourRGB::ega_rgb()) closest to an 8-bit RGB colour.
+pub fn nearest_ega_value(r : u8, g : u8, b : u8) -> u8 {
+    let distance = |val : u8| {
+        let c = ColourRGB::ega_rgb(val);
+        [(c.r, r), (c.g, g), (c.b, b)].iter().map(|&(a, b)| (a as i32 - b as i32).pow(2)).sum::<i32>()
+    };
+    (0..32_u8).filter(|val| val & 0b01000 == 0).min_by_key(|&val| distance(val)).unwrap()
+}
+
 #[derive(Default)]
 #[derive(Clone)]
 pub struct PaletteRGB {
@@ -189,6 +170,11 @@ impl PaletteRGB {
         PaletteRGB { colours: vec![ColourRGB::rgb(0,0,0); num_colours] }
     }
 
+    /// The colours of the palette, as 8-bit (r, g, b) values.
+    pub fn rgb_colours(&self) -> Vec<(u8, u8, u8)> {
+        self.colours.iter().map(|c| (c.r, c.g, c.b)).collect()
+    }
+
     /// Update num_colours contiguous elements from a slice of 6-bit VGA values.
     pub fn set_vga_data(&mut self, start_offset : usize, num_colours : usize, data : &[u8]) {
         for i in 0..num_colours {
@@ -260,9 +246,46 @@ impl PaletteRGB {
 
 }
 
+/// The image file formats bitmaps can be saved as.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ImageFormat {
+    #[default]
+    Bmp,
+    Png,
+}
+
+impl ImageFormat {
+    pub const ALL : [ImageFormat; 2] = [ImageFormat::Bmp, ImageFormat::Png];
+
+    /// The file extension for this format, without the dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Bmp => "bmp",
+            ImageFormat::Png => "png",
+        }
+    }
+
+    /// Look up a format by name (its extension), e.g. for the --format option.
+    pub fn from_name(name : &str) -> Option<ImageFormat> {
+        ImageFormat::ALL.iter().copied().find(|format| format.extension().eq_ignore_ascii_case(name))
+    }
+
+    /// Work out the format of an image file from its first few bytes.
+    pub fn detect(data : &[u8]) -> Option<ImageFormat> {
+        if data.starts_with(&BITMAP_SIGNATURE.to_le_bytes()) {
+            Some(ImageFormat::Bmp)
+        } else if data.starts_with(&png::PNG_SIGNATURE) {
+            Some(ImageFormat::Png)
+        } else {
+            None
+        }
+    }
+}
+
 /// A Bitmap consisting of several 'planes': 1bpp images which are overlaid.
 /// For example, EGA/16-colour VGA uses a 4-plane format.
 /// 4- and 8- plane images can be saved as Windows .BMP files.
+#[derive(Clone)]
 pub struct PlanarBMP {
     pub width: usize,
     pub height: usize,
@@ -274,33 +297,59 @@ pub struct PlanarBMP {
 
 impl PlanarBMP {
     /// Create a new empty (all palette entry 0) bitmap, of size @width×@height, and @planes planes.
-    pub fn new(width: usize, height: usize, planes: usize, palette : &PaletteRGB) -> PlanarBMP {
+    /// Returns an error if the bitmap would be empty.
+    pub fn new(width: usize, height: usize, planes: usize, palette : &PaletteRGB) -> std::io::Result<PlanarBMP> {
         assert!(planes <= 8);
+        if width == 0 || height == 0 || planes == 0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+                format!("Can't create an empty {}×{}×{} bitmap", width, height, planes)));
+        }
         let pitch = width.div_ceil(8);
         let plane_size = pitch * height;
-        PlanarBMP {
+        Ok(PlanarBMP {
             width,
             height,
             pitch,
             planes,
             data: vec![0; plane_size * planes],
             palette : palette.clone()
-        }
+        })
     }
 
     /// Create a new bitmap from 'contiguous' data, i.e., where all of plane 0 is stored, followed immediately by plane 1, etc.
     pub fn from_contiguous_data(data: &[u8], width: usize, height: usize, planes: usize, palette: &PaletteRGB) -> PlanarBMP {
         let pitch = width.div_ceil(8);
+        // Pad it out, so a width which isn't a multiple of 8 (and so is short by part of a byte a
+        // row) can't read past the end.
+        let mut data = data.to_vec();
+        data.resize(data.len().max(pitch * height * planes), 0);
         PlanarBMP {
             width,
             height,
             pitch,
             planes,
-            data: data.to_vec(),
+            data,
             palette : palette.clone()
         }
     }
 
+    /// Create a new bitmap from CGA data, where each pixel is @planes bits, packed into bytes with
+    /// the leftmost pixel in the highest bits (e.g. four pixels per byte for 4-colour images).
+    pub fn from_cga_data(data: &[u8], width: usize, height: usize, planes: usize, palette: &PaletteRGB) -> PlanarBMP {
+        assert!(8usize.is_multiple_of(planes));
+        let mut output = PlanarBMP::new(width, height, planes, palette).unwrap();
+        let pixels_per_byte = 8 / planes;
+        let mask = ((1u16 << planes) - 1) as u8;
+        for y in 0..height {
+            for x in 0..width {
+                let offset = y * width + x;
+                let shift = (pixels_per_byte - 1 - offset % pixels_per_byte) * planes;
+                output.pset(x, y, (data[offset / pixels_per_byte] >> shift) & mask);
+            }
+        }
+        output
+    }
+
     /// Create a new bitmap from 'packed' (or chunky) data, i.e. all data for each pixel are packed tightly together.
     /// Think RGBIRGBIRGBI not RRRRGGGGBBBBIIII.
     /// Note: only supports 8 and 4 plane images.
@@ -395,20 +444,48 @@ impl PlanarBMP {
     /// Load a PlanarBMP from a Windows .BMP file. Both Windows 3.1 and Windows 98 formats are
     /// supported, in 4- or 8- bit depths.
     pub fn from_file(reader : &mut dyn std::io::Read) -> std::io::Result<PlanarBMP> {
-        let bfh = BitmapFileHeader::from_data(reader)?;
-
-        assert_eq!(bfh.bfType, BITMAP_SIGNATURE);
+        let invalid = |message : String| std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+        let bfh = BitmapFileHeader::read_from(reader)?;
+        if bfh.bfType != BITMAP_SIGNATURE {
+            return Err(invalid("not a .BMP file".to_string()));
+        }
 
         let bih = BitmapInfoHeader::from_data(reader)?;
+        if ![1, 4, 8].contains(&bih.biBitCount) {
+            return Err(invalid(format!("{}-bit .BMP files aren't supported (save it with 4 or 8 bits per pixel)", bih.biBitCount)));
+        }
+        if bih.biCompression != BI_RGB {
+            return Err(invalid("compressed .BMP files aren't supported".to_string()));
+        }
 
         let pal = PaletteRGB::read_as_rgbquads(reader, bih.biClrUsed as usize)?;
 
-        let mut data = vec![0; (bih.biSizeImage) as usize];
-        reader.read_exact(&mut data)?;
+        let data = read_bytes(reader, bih.biSizeImage as usize)?;
+        let pitch = ((bih.biWidth as usize * bih.biBitCount as usize).div_ceil(8) + 3) & !3;
+        if (bih.biHeight as usize).checked_mul(pitch).is_none_or(|size| size > data.len()) {
+            return Err(invalid(format!("the image data is {} bytes, which is too small for {}x{} pixels", data.len(), bih.biWidth, bih.biHeight)));
+        }
 
         Ok(PlanarBMP::from_packed_data(&data[..], bih.biWidth as usize, bih.biHeight as usize, bih.biBitCount as usize, &pal))
     }
 
+    /// Load a PlanarBMP from a paletted .PNG file. The result always has 8 planes, so any
+    /// palette index can be stored.
+    pub fn from_png(reader : &mut dyn std::io::Read) -> std::io::Result<PlanarBMP> {
+        let image = png::read_indexed(reader)?;
+        let mut pal = PaletteRGB::new(256);
+        for (i, &(r, g, b)) in image.palette.iter().enumerate().take(256) {
+            pal.colours[i] = ColourRGB::rgb(r, g, b);
+        }
+        let mut bmp = PlanarBMP::new(image.width, image.height, 8, &pal)?;
+        for y in 0..image.height {
+            for x in 0..image.width {
+                bmp.pset(x, y, image.pixels[y * image.width + x]);
+            }
+        }
+        Ok(bmp)
+    }
+
     /// Read one pixel value, with all planes packed together.
     pub fn get_packed_pixel(&self, x : usize, y : usize) -> u8 {
         let mut pixel_value: u8 = 0;
@@ -476,11 +553,32 @@ impl PlanarBMP {
         output
     }
 
+    /// Get the CGA data (see from_cga_data()) for a rectangle, with @planes bits per pixel.
+    pub fn get_cga_data(&self, planes : usize, x : usize, y : usize, w : usize, h : usize) -> Vec<u8> {
+        assert!(8usize.is_multiple_of(planes));
+        let mut output = std::vec::Vec::<u8>::new();
+        let pixels_per_byte = 8 / planes;
+        let mask = ((1u16 << planes) - 1) as u8;
+        let mut out_byte : u8 = 0;
+        for yi in 0..h {
+            for xi in 0..w {
+                let offset = yi * w + xi;
+                let shift = (pixels_per_byte - 1 - offset % pixels_per_byte) * planes;
+                out_byte |= (self.get_packed_pixel(x + xi, y + yi) & mask) << shift;
+                if offset % pixels_per_byte == pixels_per_byte - 1 {
+                    output.push(out_byte);
+                    out_byte = 0;
+                }
+            }
+        }
+        output
+    }
+
     pub fn blit(&mut self, src : &PlanarBMP, x : usize, y : usize) {
         assert!(src.width + x <= self.width);
         assert!(src.height + y <= self.height);
-        // TODO: Support this?
-        assert_eq!(src.planes, self.planes);
+        // Images with fewer planes only use the first few colours, so fit in as they are.
+        assert!(src.planes <= self.planes);
         for src_y in 0..src.height {
             for src_x in 0..src.width {
                 let val = src.get_packed_pixel(src_x, src_y);
@@ -489,30 +587,67 @@ impl PlanarBMP {
         }
     }
 
-    #[allow(dead_code)] // We're not using this yet, but will for levels.
-    pub fn blit_masked(&mut self, src : &PlanarBMP, x : usize, y : usize) {
-        assert!(src.width + x <= self.width);
-        assert!(src.height + y <= self.height);
-        assert_eq!(src.planes, self.planes + 1);
+    /// Replace the palette, leaving the pixels as they are.
+    pub fn set_palette(&mut self, palette : &PaletteRGB) {
+        self.palette = palette.clone();
+    }
+
+    /// Copy a @w×@h rectangle, starting at (@x, @y), into a new bitmap.
+    pub fn crop(&self, x : usize, y : usize, w : usize, h : usize) -> PlanarBMP {
+        assert!(x + w <= self.width);
+        assert!(y + h <= self.height);
+        let mut output = PlanarBMP::new(w, h, self.planes, &self.palette).unwrap();
+        for out_y in 0..h {
+            for out_x in 0..w {
+                output.pset(out_x, out_y, self.get_packed_pixel(x + out_x, y + out_y));
+            }
+        }
+        output
+    }
+
+    /// Copy the pixels of @src which are set in @mask (a 1-plane bitmap of the same size) to
+    /// (@x, @y), skipping any which @filter rejects (it's given the destination coordinates).
+    /// The source can be partly, or entirely, outside this bitmap: only the overlap is copied.
+    pub fn blit_masked(&mut self, src : &PlanarBMP, mask : &PlanarBMP, x : isize, y : isize, filter : &dyn Fn(usize, usize) -> bool) {
+        assert_eq!((src.width, src.height), (mask.width, mask.height));
+        assert!(src.planes <= self.planes);
         for src_y in 0..src.height {
+            let dst_y = y + src_y as isize;
+            if dst_y < 0 || dst_y >= self.height as isize {
+                continue;
+            }
             for src_x in 0..src.width {
-                let val = src.get_packed_pixel(src_x, src_y);
-                if val & (1 << self.planes) != 0 {
-                    self.pset(src_x + x, src_y + y, val & ((1 << self.planes) - 1));
+                let dst_x = x + src_x as isize;
+                if dst_x < 0 || dst_x >= self.width as isize {
+                    continue;
+                }
+                if mask.get_packed_pixel(src_x, src_y) != 0 && filter(dst_x as usize, dst_y as usize) {
+                    self.pset(dst_x as usize, dst_y as usize, src.get_packed_pixel(src_x, src_y));
                 }
             }
         }
     }
 
+    /// Make an upside-down copy of the bitmap.
+    pub fn flip_vertical(&self) -> PlanarBMP {
+        let mut output = PlanarBMP::new(self.width, self.height, self.planes, &self.palette).unwrap();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                output.pset(x, self.height - 1 - y, self.get_packed_pixel(x, y));
+            }
+        }
+        output
+    }
+
     pub fn save_as_pal8(&self, writer : &mut dyn std::io::Write) {
         /* For PAL8, pitch == width. */
         let data_size = self.width * self.height;
         let num_colours = self.palette.colours.len();
-        let data_offset = BitmapFileHeader::STRUCT_SIZE + BitmapInfoHeader::STRUCT_SIZE + 4 * num_colours;
+        let data_offset = BitmapFileHeader::byte_size() + BitmapInfoHeader::byte_size() + 4 * num_colours;
         let bmp_file_header = BitmapFileHeader::new(data_offset + data_size, data_offset);
         let bmp_info_header = BitmapInfoHeader::new(self.width, self.height, 8, num_colours, data_size);
-        bmp_file_header.write(writer).unwrap();
-        bmp_info_header.write(writer).unwrap();
+        bmp_file_header.write_to(writer).unwrap();
+        bmp_info_header.write_to(writer).unwrap();
         self.palette.write_prefix_as_rgbquads(writer, num_colours).unwrap();
         let data = self.to_pal8_data();
         for line in data.rchunks(self.width) {
@@ -524,11 +659,11 @@ impl PlanarBMP {
         let pitch = ((self.width * bpp).div_ceil(8) + 3) & !3;
         let data_size = pitch * self.height;
         let num_colours = std::cmp::min(self.palette.colours.len(), 1 << bpp);
-        let data_offset = BitmapFileHeader::STRUCT_SIZE + BitmapInfoHeader::STRUCT_SIZE + 4 * num_colours;
+        let data_offset = BitmapFileHeader::byte_size() + BitmapInfoHeader::byte_size() + 4 * num_colours;
         let bmp_file_header = BitmapFileHeader::new(data_offset + data_size, data_offset);
         let bmp_info_header = BitmapInfoHeader::new(self.width, self.height, bpp, num_colours, data_size);
-        bmp_file_header.write(writer).unwrap();
-        bmp_info_header.write(writer).unwrap();
+        bmp_file_header.write_to(writer).unwrap();
+        bmp_info_header.write_to(writer).unwrap();
         self.palette.write_prefix_as_rgbquads(writer, num_colours).unwrap();
         let data = self.to_pal8_data();
         for line in data.rchunks(self.width) {
@@ -555,6 +690,29 @@ impl PlanarBMP {
         }
     }
 
+    /// Get the palette as (r, g, b) triples, with one entry for every possible pixel value.
+    /// Short palettes are padded with black.
+    pub fn rgb_palette(&self) -> Vec<(u8, u8, u8)> {
+        let num_colours = 1 << self.planes;
+        (0..num_colours).map(|i| {
+            let c = self.palette.colours.get(i).copied().unwrap_or(ColourRGB::rgb(0, 0, 0));
+            (c.r, c.g, c.b)
+        }).collect()
+    }
+
+    /// Save as an 8-bit paletted .PNG file.
+    pub fn save_as_png(&self, writer : &mut dyn std::io::Write) {
+        png::write_indexed(writer, self.width, self.height, &self.rgb_palette(), &self.to_pal8_data(), None).unwrap();
+    }
+
+    /// Save in the given format.
+    pub fn save_as_format(&self, format : ImageFormat, writer : &mut dyn std::io::Write) {
+        match format {
+            ImageFormat::Bmp => self.save_as_file(writer),
+            ImageFormat::Png => self.save_as_png(writer),
+        }
+    }
+
     pub fn save_as_file(&self, writer : &mut dyn std::io::Write) {
         match self.planes {
             1 => self.save_as_bpp(1, writer),
@@ -566,3 +724,52 @@ impl PlanarBMP {
 
 }
 
+
+/// Load a PlanarBMP from an image file, picking the decoder by the file's signature. Only .BMP
+/// and .PNG files can be read, but PCX files are recognised, to say so.
+///
+/// ```no_run
+/// let image = modlem::load_image(&mut std::fs::File::open("rock.png")?)?;
+/// image.save_as_file(&mut std::fs::File::create("rock.bmp")?);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn load_image(reader : &mut dyn std::io::Read) -> std::io::Result<PlanarBMP> {
+    let mut reader = SniffReader::new(reader);
+    let signature = reader.peek(8)?.to_vec();
+    match ImageFormat::detect(&signature) {
+        Some(ImageFormat::Bmp) => PlanarBMP::from_file(&mut reader),
+        Some(ImageFormat::Png) => PlanarBMP::from_png(&mut reader),
+        None if signature.len() >= 3 && signature[0] == 0x0A && signature[2] == 1 =>
+            Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "PCX images aren't supported (save it as a .BMP or .PNG)")),
+        None => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "not a .BMP or .PNG file")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn planar_bmp_headers() {
+        assert_eq!((BitmapFileHeader::byte_size(), BitmapInfoHeader::byte_size()), (14, 40));
+        check_round_trips::<BitmapFileHeader>();
+        check_round_trips::<BitmapInfoHeader>();
+    }
+
+    #[test]
+    fn planar_bmp_load_image() {
+        let mut bmp = PlanarBMP::new(8, 4, 4, &PaletteRGB::new(16)).unwrap();
+        bmp.pset(3, 2, 9);
+        for format in ImageFormat::ALL {
+            let mut data = Vec::new();
+            bmp.save_as_format(format, &mut data);
+            let loaded = load_image(&mut data.as_slice()).unwrap();
+            assert_eq!((loaded.width, loaded.height, loaded.get_packed_pixel(3, 2)), (8, 4, 9));
+        }
+
+        let err = load_image(&mut &[0x0A, 5, 1, 8, 0, 0][..]).err().unwrap();
+        assert!(err.to_string().starts_with("PCX images aren't supported"));
+        assert_eq!(load_image(&mut &b"GIF89a"[..]).err().unwrap().to_string(), "not a .BMP or .PNG file");
+        assert!(load_image(&mut &[][..]).is_err());
+    }
+}
@@ -0,0 +1,433 @@
+/*
+ * modlem: A graphics importer/exporter for Lemmings
+ * Copyright (C) 2022–2026 David Gow <david@davidgow.net>
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use binary_io::*;
+
+/*
+ * A minimal PNG writer, for export only. We don't attempt any compression: the
+ * image data is wrapped in 'stored' deflate blocks, which every PNG reader
+ * supports. Lemmings graphics are small enough that this doesn't matter much.
+ */
+
+pub const PNG_SIGNATURE : [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+/// The largest amount of data which fits in a single 'stored' deflate block.
+const MAX_STORED_BLOCK : usize = 65535;
+
+/// Calculate the CRC-32 (as used by PNG and zlib's gzip format) of some data.
+pub fn crc32(data : &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFF_u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// Calculate the Adler-32 checksum of some data, as used by zlib.
+fn adler32(data : &[u8]) -> u32 {
+    let mut a = 1_u32;
+    let mut b = 0_u32;
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+/// Wrap some data in a zlib stream, using uncompressed ('stored') blocks.
+fn zlib_store(data : &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut chunks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if chunks.peek().is_none() {
+        // An empty, final block.
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(chunk) = chunks.next() {
+        let is_final = chunks.peek().is_none();
+        out.push(if is_final { 1 } else { 0 });
+        let len = chunk.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(chunk);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+/// Write a single PNG chunk, with its length and CRC.
+fn write_chunk(writer : &mut dyn std::io::Write, chunk_type : &[u8; 4], data : &[u8]) -> std::io::Result<()> {
+    write_be32(data.len() as u32, writer)?;
+    writer.write_all(chunk_type)?;
+    writer.write_all(data)?;
+    let mut crc_data = chunk_type.to_vec();
+    crc_data.extend_from_slice(data);
+    write_be32(crc32(&crc_data), writer)
+}
+
+/// Reads bits from a deflate stream, least significant bit first.
+struct BitReader<'a> {
+    data : &'a [u8],
+    pos : usize,
+    bit : u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, count : u32) -> std::io::Result<u32> {
+        let mut val = 0;
+        for i in 0..count {
+            let byte = match self.data.get(self.pos) {
+                Some(&byte) => byte,
+                None => return Err(invalid_data("unexpected end of deflate stream")),
+            };
+            val |= (((byte >> self.bit) & 1) as u32) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+        Ok(val)
+    }
+
+    /// Skip to the start of the next byte.
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+}
+
+fn invalid_data(msg : &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+/// A canonical Huffman code, as used by deflate.
+struct Huffman {
+    /// The number of codes of each length.
+    counts : [u16; 16],
+    /// The symbols, ordered by their codes.
+    symbols : Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths : &[u8]) -> Huffman {
+        let mut counts = [0_u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0_u16; 16];
+        for len in 1..16 {
+            offsets[len] = offsets[len - 1] + counts[len - 1];
+        }
+        let mut symbols = vec![0; lengths.iter().filter(|&&len| len != 0).count()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, reader : &mut BitReader) -> std::io::Result<u16> {
+        let mut code : i32 = 0;
+        let mut first : i32 = 0;
+        let mut index : i32 = 0;
+        for len in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid_data("invalid Huffman code"))
+    }
+}
+
+const LENGTH_BASE : [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA : [u32; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE : [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DIST_EXTRA : [u32; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+/// The order in which the code length code lengths are stored in a dynamic block.
+const CODE_LENGTH_ORDER : [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// Decode one compressed block, using the given literal/length and distance codes.
+fn inflate_block(reader : &mut BitReader, out : &mut Vec<u8>, lit_code : &Huffman, dist_code : &Huffman) -> std::io::Result<()> {
+    loop {
+        let symbol = lit_code.decode(reader)? as usize;
+        if symbol < 256 {
+            out.push(symbol as u8);
+        } else if symbol == 256 {
+            return Ok(());
+        } else {
+            let symbol = symbol - 257;
+            if symbol >= LENGTH_BASE.len() {
+                return Err(invalid_data("invalid length code"));
+            }
+            let len = LENGTH_BASE[symbol] as usize + reader.bits(LENGTH_EXTRA[symbol])? as usize;
+            let dist_symbol = dist_code.decode(reader)? as usize;
+            if dist_symbol >= DIST_BASE.len() {
+                return Err(invalid_data("invalid distance code"));
+            }
+            let dist = DIST_BASE[dist_symbol] as usize + reader.bits(DIST_EXTRA[dist_symbol])? as usize;
+            if dist > out.len() {
+                return Err(invalid_data("distance too far back"));
+            }
+            for _ in 0..len {
+                out.push(out[out.len() - dist]);
+            }
+        }
+    }
+}
+
+/// Read the code lengths for a block with dynamic Huffman codes.
+fn read_dynamic_codes(reader : &mut BitReader) -> std::io::Result<(Huffman, Huffman)> {
+    let num_lit = reader.bits(5)? as usize + 257;
+    let num_dist = reader.bits(5)? as usize + 1;
+    let num_code_len = reader.bits(4)? as usize + 4;
+
+    let mut code_len_lengths = [0_u8; 19];
+    for &idx in CODE_LENGTH_ORDER.iter().take(num_code_len) {
+        code_len_lengths[idx] = reader.bits(3)? as u8;
+    }
+    let code_len_code = Huffman::new(&code_len_lengths);
+
+    let mut lengths = Vec::<u8>::new();
+    while lengths.len() < num_lit + num_dist {
+        let symbol = code_len_code.decode(reader)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => match lengths.last() {
+                Some(&prev) => (prev, 3 + reader.bits(2)?),
+                None => return Err(invalid_data("repeated code length with no previous length")),
+            },
+            17 => (0, 3 + reader.bits(3)?),
+            _ => (0, 11 + reader.bits(7)?),
+        };
+        for _ in 0..repeat {
+            lengths.push(value);
+        }
+    }
+    if lengths.len() > num_lit + num_dist {
+        return Err(invalid_data("too many code lengths"));
+    }
+    Ok((Huffman::new(&lengths[..num_lit]), Huffman::new(&lengths[num_lit..])))
+}
+
+/// Decompress a zlib stream.
+fn zlib_inflate(data : &[u8]) -> std::io::Result<Vec<u8>> {
+    if data.len() < 2 || data[0] & 0x0F != 8 || !u16::from_be_bytes([data[0], data[1]]).is_multiple_of(31) {
+        return Err(invalid_data("invalid zlib header"));
+    }
+    let mut reader = BitReader { data : &data[2..], pos : 0, bit : 0 };
+    let mut out = Vec::new();
+    loop {
+        let is_final = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align();
+                let start = reader.pos;
+                if start + 4 > reader.data.len() {
+                    return Err(invalid_data("unexpected end of deflate stream"));
+                }
+                let len = u16::from_le_bytes([reader.data[start], reader.data[start + 1]]) as usize;
+                let block = reader.data.get((start + 4)..(start + 4 + len)).ok_or_else(|| invalid_data("unexpected end of deflate stream"))?;
+                out.extend_from_slice(block);
+                reader.pos = start + 4 + len;
+            },
+            1 => {
+                let mut lit_lengths = [8_u8; 288];
+                lit_lengths[144..256].fill(9);
+                lit_lengths[256..280].fill(7);
+                inflate_block(&mut reader, &mut out, &Huffman::new(&lit_lengths), &Huffman::new(&[5; 30]))?;
+            },
+            2 => {
+                let (lit_code, dist_code) = read_dynamic_codes(&mut reader)?;
+                inflate_block(&mut reader, &mut out, &lit_code, &dist_code)?;
+            },
+            _ => return Err(invalid_data("invalid deflate block type")),
+        }
+        if is_final {
+            return Ok(out);
+        }
+    }
+}
+
+/// The Paeth predictor, used by PNG's filter type 4.
+fn paeth(a : u8, b : u8, c : u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+}
+
+/// A paletted image read from a PNG file.
+pub struct IndexedImage {
+    pub width : usize,
+    pub height : usize,
+    /// The number of bits per pixel (1, 2, 4, or 8).
+    pub bit_depth : usize,
+    pub palette : Vec<(u8, u8, u8)>,
+    /// One palette index per pixel, row by row.
+    pub pixels : Vec<u8>,
+}
+
+/// Read a paletted PNG, keeping the palette indices. Other kinds of PNG aren't supported.
+pub fn read_indexed(reader : &mut dyn std::io::Read) -> std::io::Result<IndexedImage> {
+    let signature : [u8; 8] = read_array(reader)?;
+    if signature != PNG_SIGNATURE {
+        return Err(invalid_data("not a PNG file"));
+    }
+
+    let mut header = None;
+    let mut palette = Vec::new();
+    let mut image_data = Vec::new();
+    loop {
+        let len = read_be32(reader)? as usize;
+        let chunk_type : [u8; 4] = read_array(reader)?;
+        let data = read_bytes(reader, len)?;
+        let mut crc_data = chunk_type.to_vec();
+        crc_data.extend_from_slice(&data);
+        if read_be32(reader)? != crc32(&crc_data) {
+            return Err(invalid_data("PNG chunk CRC mismatch"));
+        }
+        match &chunk_type {
+            b"IHDR" => {
+                if len != 13 {
+                    return Err(invalid_data("invalid PNG header"));
+                }
+                let width = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+                let height = u32::from_be_bytes([data[4], data[5], data[6], data[7]]) as usize;
+                let (bit_depth, colour_type, interlace) = (data[8], data[9], data[12]);
+                if colour_type != 3 || interlace != 0 || ![1, 2, 4, 8].contains(&bit_depth) {
+                    return Err(invalid_data("only non-interlaced, paletted PNG files are supported"));
+                }
+                header = Some((width, height, bit_depth as usize));
+            },
+            b"PLTE" => palette = data.chunks_exact(3).map(|c| (c[0], c[1], c[2])).collect(),
+            b"IDAT" => image_data.extend_from_slice(&data),
+            b"IEND" => break,
+            _ => {},
+        }
+    }
+
+    let (width, height, bit_depth) = header.ok_or_else(|| invalid_data("missing PNG header"))?;
+    let raw = zlib_inflate(&image_data)?;
+    let stride = (width * bit_depth).div_ceil(8);
+    if raw.len() < (stride + 1) * height {
+        return Err(invalid_data("not enough PNG image data"));
+    }
+
+    // Undo the per-scanline filters. Paletted images always have one byte per pixel for
+    // filtering purposes.
+    let mut prev_line = vec![0_u8; stride];
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let filter = raw[y * (stride + 1)];
+        let mut line = raw[(y * (stride + 1) + 1)..((y + 1) * (stride + 1))].to_vec();
+        for x in 0..stride {
+            let left = if x > 0 { line[x - 1] } else { 0 };
+            let up = prev_line[x];
+            let up_left = if x > 0 { prev_line[x - 1] } else { 0 };
+            line[x] = line[x].wrapping_add(match filter {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                4 => paeth(left, up, up_left),
+                _ => return Err(invalid_data("invalid PNG filter type")),
+            });
+        }
+        for x in 0..width {
+            let bit = x * bit_depth;
+            let shift = 8 - bit_depth - (bit % 8);
+            pixels.push((line[bit / 8] >> shift) & ((1 << bit_depth) - 1) as u8);
+        }
+        prev_line = line;
+    }
+
+    Ok(IndexedImage { width, height, bit_depth, palette, pixels })
+}
+
+/// Write an 8-bit paletted PNG. @pixels contains one palette index per pixel, row by row, and
+/// @palette contains (r, g, b) triples. If @transparent_index is given, that palette entry is
+/// fully transparent.
+pub fn write_indexed(writer : &mut dyn std::io::Write, width : usize, height : usize, palette : &[(u8, u8, u8)], pixels : &[u8], transparent_index : Option<u8>) -> std::io::Result<()> {
+    assert_eq!(pixels.len(), width * height);
+    writer.write_all(&PNG_SIGNATURE)?;
+
+    let mut ihdr = Vec::<u8>::new();
+    ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+    ihdr.extend_from_slice(&[8, 3, 0, 0, 0]); // 8-bit, indexed, deflate, no filter, no interlace
+    write_chunk(writer, b"IHDR", &ihdr)?;
+
+    let mut plte = Vec::<u8>::new();
+    for &(r, g, b) in palette {
+        plte.extend_from_slice(&[r, g, b]);
+    }
+    write_chunk(writer, b"PLTE", &plte)?;
+
+    if let Some(transparent_index) = transparent_index {
+        let mut trns = vec![0xFF_u8; transparent_index as usize + 1];
+        trns[transparent_index as usize] = 0;
+        write_chunk(writer, b"tRNS", &trns)?;
+    }
+
+    // Each scanline is prefixed with its filter type (0: none).
+    let mut raw = Vec::<u8>::with_capacity((width + 1) * height);
+    for line in pixels.chunks(std::cmp::max(width, 1)) {
+        raw.push(0);
+        raw.extend_from_slice(line);
+    }
+    write_chunk(writer, b"IDAT", &zlib_store(&raw))?;
+    write_chunk(writer, b"IEND", &[])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn png_round_trip() {
+        let palette = vec![(0, 0, 0), (255, 0, 0), (0, 255, 0)];
+        let pixels : Vec<u8> = (0..(7 * 5)).map(|i| (i % 3) as u8).collect();
+        let mut data = Vec::new();
+        write_indexed(&mut data, 7, 5, &palette, &pixels, Some(0)).unwrap();
+        let image = read_indexed(&mut data.as_slice()).unwrap();
+        assert_eq!((image.width, image.height, image.bit_depth), (7, 5, 8));
+        assert_eq!(image.palette, palette);
+        assert_eq!(image.pixels, pixels);
+    }
+
+    #[test]
+    fn png_inflate_compressed() {
+        // zlib.compress(b"Lemmings Lemmings Lemmings!"), which uses fixed Huffman codes.
+        let data = [0x78, 0x9c, 0xf3, 0x49, 0xcd, 0xcd, 0xcd, 0xcc, 0x4b, 0x2f, 0x56, 0xf0, 0x41, 0x67, 0x28, 0x02, 0x00, 0x8e, 0xe7, 0x0a, 0x16];
+        assert_eq!(zlib_inflate(&data).unwrap(), b"Lemmings Lemmings Lemmings!");
+    }
+}
@@ -0,0 +1,306 @@
+/*
+ * modlem: A graphics importer/exporter for Lemmings
+ * Copyright (C) 2022–2026 David Gow <david@davidgow.net>
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! What the informational commands print with --json, and the .json files written with atlases.
+//! They're all here, so the same things have the same names in each. Other tools read these, so
+//! fields can be added, but shouldn't be renamed or removed.
+
+use atlas::Atlas;
+use file_info::{FileInfo, FileKind};
+use graphics_set::{GraphicsObject, GraphicsSet, TerrainPiece};
+use json::Value;
+use level::{LevelListEntry, SetUsage, SlotUse, SKILL_NAMES};
+use main_dat::LemmingsAnim;
+use set_map::SetFiles;
+
+/// list-levels: a list with an entry for each section of the files, of the form:
+///
+/// { "file": "level000.dat", "index": 0, "level": { "name": "Just dig!", "game": "original",
+///   "graphics_set": 0, "ground_file": "ground0o.dat", "lemmings": 50, "rescue": 10,
+///   "release_rate": 50, "time": 5, "skills": { "climb": 0, ..., "dig": 10 } } }
+///
+/// "level" is null for sections which aren't levels, and "ground_file" is only there if a set
+/// map was given.
+pub fn level_list(entries : &[LevelListEntry]) -> Value {
+    entries.iter().map(|entry| {
+        let level = entry.level.as_ref().map_or(Value::Null, |level| {
+            let mut fields = vec![
+                ("name", level.name().into()),
+                ("game", level.variant.name().into()),
+                ("graphics_set", level.ground_set().into()),
+            ];
+            if let Some(ground_file) = &entry.ground_file {
+                fields.push(("ground_file", ground_file.as_str().into()));
+            }
+            let skills = SKILL_NAMES.iter().zip(level.skills.iter()).map(|(&name, &count)| (name, count.into()));
+            fields.extend([
+                ("lemmings", level.num_lemmings.into()),
+                ("rescue", level.num_to_rescue.into()),
+                ("release_rate", level.release_rate.into()),
+                ("time", level.time_limit.into()),
+                ("skills", Value::object(skills)),
+            ]);
+            Value::object(fields)
+        });
+        Value::object([("file", entry.file_name.as_str().into()), ("index", entry.index.into()), ("level", level)])
+    }).collect::<Vec<Value>>().into()
+}
+
+/// info: an object of the form:
+///
+/// { "file": "vgagr0.dat", "size": 30510, "kind": "vgagr", "layout": null,
+///   "description": "a graphics set data file (vgagrX.dat)",
+///   "sections": [{ "index": 0, "offset": 0, "compressed_size": 18214,
+///   "decompressed_size": 37380, "error": null }, ...], "sections_error": null }
+///
+/// "kind" is one of "ground", "vgagr", "main", "levels", "level", "vgaspec", "oddtable", "dat",
+/// "damaged_dat" and "unknown". For "main", "layout" is the game its layout is for (e.g.
+/// "Lemmings" or "OhNo"). "sections" is null for files which aren't made up of sections. Each
+/// section's "error" says why it couldn't be decompressed, and "sections_error" why what's after
+/// the last section isn't one.
+pub fn file_info(file_name : &str, info : &FileInfo) -> Value {
+    let layout = match info.kind {
+        FileKind::MainDat(variant) => Value::from(format!("{:?}", variant)),
+        _ => Value::Null,
+    };
+    let sections = if info.kind.has_sections() {
+        info.sections.iter().enumerate().map(|(index, section)| Value::object([
+            ("index", index.into()),
+            ("offset", section.offset.into()),
+            ("compressed_size", section.compressed_size.into()),
+            ("decompressed_size", section.uncompressed_size.into()),
+            ("error", section.data.as_ref().err().cloned().into()),
+        ])).collect::<Vec<Value>>().into()
+    } else {
+        Value::Null
+    };
+    Value::object([
+        ("file", file_name.into()),
+        ("size", info.len.into()),
+        ("kind", info.kind.id().into()),
+        ("layout", layout),
+        ("description", info.kind.description().into()),
+        ("sections", sections),
+        ("sections_error", info.section_error.clone().into()),
+    ])
+}
+
+/// stats-levels: a list with an entry for each graphics set the levels use, of the form:
+///
+/// { "ground_file": "ground0o.dat", "vgagr_file": "vgagr0.dat", "levels": 30,
+///   "terrain": [{ "index": 0, "uses": 12, "size": 1000 }, ...], "objects": [...],
+///   "missing_terrain": [{ "index": 60, "uses": 1 }], "missing_objects": [] }
+///
+/// "terrain" and "objects" list every piece and object in the set, with how many times the
+/// levels use them, and how many bytes of the set's (decompressed) data they take up. The
+/// "missing" lists are of those the levels use which aren't in the set.
+pub fn set_usages(usages : &[(&SetFiles, &SetUsage, &GraphicsSet)]) -> Value {
+    let slots = |slots : &[SlotUse]| -> Value {
+        slots.iter().map(|slot| Value::object([("index", slot.index.into()), ("uses", slot.uses.into()), ("size", slot.size.into())])).collect::<Vec<Value>>().into()
+    };
+    let missing = |counts : &std::collections::BTreeMap<usize, usize>, slots : &[SlotUse]| -> Value {
+        counts.iter().filter(|(index, _)| !slots.iter().any(|slot| slot.index == **index))
+            .map(|(&index, &uses)| Value::object([("index", index.into()), ("uses", uses.into())])).collect::<Vec<Value>>().into()
+    };
+    usages.iter().map(|(files, usage, set)| {
+        let (terrain, objects) = usage.slot_uses(set);
+        Value::object([
+            ("ground_file", files.ground_file.as_str().into()),
+            ("vgagr_file", files.vgagr_file.as_str().into()),
+            ("levels", usage.num_levels.into()),
+            ("terrain", slots(&terrain)),
+            ("objects", slots(&objects)),
+            ("missing_terrain", missing(&usage.terrain, &terrain)),
+            ("missing_objects", missing(&usage.objects, &objects)),
+        ])
+    }).collect::<Vec<Value>>().into()
+}
+
+/// An atlas's .json file: an object of the form:
+///
+/// { "image": "set0_atlas.bmp", "mask": "set0_atlas_mask.bmp", "width": 512, "height": 300,
+///   "entries": [{ "name": "terrain0", "x": 0, "y": 0, "width": 64, "height": 32, ... }, ...] }
+///
+/// Each entry has the extra fields it was added to the atlas with: terrain_atlas_fields(),
+/// object_atlas_fields() or anim_atlas_fields().
+pub fn atlas(atlas : &Atlas, image_filename : &str, mask_filename : &str) -> Value {
+    let entries = atlas.entries.iter().map(|entry| {
+        let mut fields = vec![
+            ("name", entry.name.as_str().into()),
+            ("x", entry.x.into()),
+            ("y", entry.y.into()),
+            ("width", entry.width.into()),
+            ("height", entry.height.into()),
+        ];
+        fields.extend(entry.metadata.iter().cloned());
+        Value::object(fields)
+    }).collect::<Vec<Value>>();
+    Value::object([
+        ("image", image_filename.into()),
+        ("mask", mask_filename.into()),
+        ("width", atlas.width.into()),
+        ("height", atlas.height.into()),
+        ("entries", entries.into()),
+    ])
+}
+
+/// The extra fields of a terrain piece's atlas entry: { "type": "terrain", "index": 3 }.
+pub fn terrain_atlas_fields(piece : &TerrainPiece) -> Vec<(&'static str, Value)> {
+    vec![("type", "terrain".into()), ("index", piece.index.into())]
+}
+
+/// The extra fields of the atlas entry for @frame of @object: its "type" ("object"), "index"
+/// and "frame", and from its header, "animation_flags", "frames" ([start, end]), "trigger" ([x,
+/// y, width, height]), "trigger_effect", "preview_frame" and "trap_sound".
+pub fn object_atlas_fields(object : &GraphicsObject, frame : usize) -> Vec<(&'static str, Value)> {
+    let header = &object.header;
+    vec![
+        ("type", "object".into()),
+        ("index", object.index.into()),
+        ("frame", frame.into()),
+        ("animation_flags", header.animation_flags.into()),
+        ("frames", vec![header.frame_start, header.frame_end].into()),
+        ("trigger", vec![header.trigger_x, header.trigger_y, header.trigger_w as u16, header.trigger_h as u16].into()),
+        ("trigger_effect", header.trigger_effect_id.into()),
+        ("preview_frame", header.preview_frame_number.into()),
+        ("trap_sound", header.trap_sound.into()),
+    ]
+}
+
+/// The extra fields of the atlas entry for @anim, which is on row @row of a lemming atlas:
+/// "row", "frame_width", "frame_height", "frames" and "planes".
+pub fn anim_atlas_fields(row : usize, anim : &LemmingsAnim) -> Vec<(&'static str, Value)> {
+    vec![
+        ("row", row.into()),
+        ("frame_width", anim.width.into()),
+        ("frame_height", anim.height.into()),
+        ("frames", anim.num_frames.into()),
+        ("planes", anim.planes.into()),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dat_section::DatSection;
+    use graphics_set::ObjectHeader;
+    use json;
+    use level::{Level, LevelObject, TerrainPlacement};
+    use planar_bmp::{PaletteRGB, PlanarBMP};
+
+    /// Write @value as the commands do, and parse it back.
+    fn round_trip(value : &Value) -> Value {
+        let mut text = Vec::new();
+        value.write(&mut text).unwrap();
+        json::parse(&String::from_utf8(text).unwrap()).unwrap()
+    }
+
+    /// The field @name of @value, which must be an object.
+    fn field<'a>(value : &'a Value, name : &str) -> &'a Value {
+        match value {
+            Value::Object(fields) => &fields.iter().find(|(field, _)| field == name).unwrap_or_else(|| panic!("no {} in {:?}", name, value)).1,
+            _ => panic!("{:?} isn't an object", value),
+        }
+    }
+
+    /// The items of @value, which must be a list.
+    fn items(value : &Value) -> &[Value] {
+        match value {
+            Value::Array(items) => items,
+            _ => panic!("{:?} isn't a list", value),
+        }
+    }
+
+    #[test]
+    fn schemas_file_info() {
+        let mut data = Vec::new();
+        DatSection::from_data(b"terrain", 7).write(&mut data).unwrap();
+        DatSection::from_data(b"objects", 7).write(&mut data).unwrap();
+        let value = round_trip(&file_info("vgagr0.dat", &FileInfo::identify(&data)));
+        assert_eq!(field(&value, "file"), &Value::from("vgagr0.dat"));
+        assert_eq!(field(&value, "size"), &Value::from(data.len()));
+        assert_eq!(field(&value, "kind"), &Value::from("vgagr"));
+        assert_eq!(field(&value, "sections_error"), &Value::Null);
+        let sections = items(field(&value, "sections"));
+        assert_eq!(sections.len(), 2);
+        assert_eq!(field(&sections[1], "decompressed_size"), &Value::from(7_usize));
+        assert_eq!(field(&sections[1], "error"), &Value::Null);
+
+        let value = round_trip(&file_info("notes.txt", &FileInfo::identify(b"Let's go!\n")));
+        assert_eq!(field(&value, "kind"), &Value::from("unknown"));
+        assert_eq!(field(&value, "sections"), &Value::Null);
+        assert!(matches!(field(&value, "sections_error"), Value::String(_)));
+    }
+
+    #[test]
+    fn schemas_level_list() {
+        let mut level = Level::default();
+        level.name[..9].copy_from_slice(b"Tab\there!");
+        level.skills[7] = 10;
+        let entries = vec![
+            LevelListEntry { file_name : "level000.dat".to_string(), index : 0, level : Some(level), ground_file : Some("ground0o.dat".to_string()) },
+            LevelListEntry { file_name : "level000.dat".to_string(), index : 1, level : None, ground_file : None },
+        ];
+        let value = round_trip(&level_list(&entries));
+        let entries = items(&value);
+        let level = field(&entries[0], "level");
+        assert_eq!(field(level, "name"), &Value::from("Tab\there!"));
+        assert_eq!(field(level, "ground_file"), &Value::from("ground0o.dat"));
+        assert_eq!(field(field(level, "skills"), "dig"), &Value::from(10_u16));
+        assert_eq!(field(&entries[1], "level"), &Value::Null);
+    }
+
+    #[test]
+    fn schemas_atlas() {
+        let pal = PaletteRGB::new(16);
+        let image = || PlanarBMP::new(16, 4, 4, &pal).unwrap();
+        let mask = || PlanarBMP::new(16, 4, 1, &pal).unwrap();
+        let header = ObjectHeader { frame_end : 2, trigger_w : 3, trap_sound : 7, ..Default::default() };
+        let object = GraphicsObject { index : 5, header, frames : Vec::new() };
+        let mut atlas = Atlas::new();
+        atlas.add("\"quoted\"\\name", image(), mask(), object_atlas_fields(&object, 1));
+        atlas.add("plain", image(), mask(), Vec::new());
+        atlas.pack_rows();
+        let value = round_trip(&super::atlas(&atlas, "a\tb.bmp", "mask \"1\".bmp"));
+        assert_eq!(field(&value, "image"), &Value::from("a\tb.bmp"));
+        assert_eq!(field(&value, "height"), &Value::from(8_usize));
+        let entries = items(field(&value, "entries"));
+        assert_eq!(field(&entries[0], "name"), &Value::from("\"quoted\"\\name"));
+        assert_eq!(field(&entries[0], "index"), &Value::from(5_usize));
+        assert_eq!(field(&entries[0], "frames"), &Value::from(vec![0_u8, 2]));
+        assert_eq!(field(&entries[0], "trap_sound"), &Value::from(7_u8));
+        assert_eq!(field(&entries[1], "y"), &Value::from(4_usize));
+    }
+
+    #[test]
+    fn schemas_set_usages() {
+        let set = GraphicsSet { terrain : Vec::new(), objects : Vec::new(), palettes : Default::default(), palette : PaletteRGB::new(16) };
+        let mut level = Level::default();
+        level.terrain[0] = Some(TerrainPlacement { x : 0, y : 0, piece : 5, flags : 0 });
+        level.objects[0] = Some(LevelObject { x : 0, y : 0, id : 3, modifier : 0, display : 0 });
+        let mut usage = SetUsage::default();
+        usage.add_level(&level);
+        usage.add_level(&level);
+        let value = round_trip(&set_usages(&[(&SetFiles::standard(2), &usage, &set)]));
+        let sets = items(&value);
+        assert_eq!(field(&sets[0], "ground_file"), &Value::from("ground2o.dat"));
+        assert_eq!(field(&sets[0], "levels"), &Value::from(2_usize));
+        assert_eq!(field(&sets[0], "terrain"), &Value::Array(Vec::new()));
+        let missing = items(field(&sets[0], "missing_objects"));
+        assert_eq!(missing, [Value::object([("index", 3_usize.into()), ("uses", 2_usize.into())])]);
+    }
+}
@@ -0,0 +1,184 @@
+/*
+ * modlem: A graphics importer/exporter for Lemmings
+ * Copyright (C) 2022–2026 David Gow <david@davidgow.net>
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use parser;
+use std::collections::BTreeMap;
+
+/*
+ * Levels name their graphics set with a number, which normally means the
+ * groundNo.dat and vgagrN.dat files of the level's game. Setups which combine
+ * several games (and some fan level packs) number the sets differently, so a set
+ * map says which files each number means instead.
+ *
+ * On the command line, a map is a list like "5=ohno_ground0o.dat,6=ohno_ground1o.dat";
+ * the vgagr file's name is worked out from the ground file's. In a set map file,
+ * each set is given as:
+ *
+ *	Set 5 "ohno_ground0o.dat"
+ *	Set 6 "ohno_ground1o.dat" "ohno_vgagr1.dat" Style "ohno_rock"
+ *
+ * where the vgagr file can be named explicitly. The Style is the name of the
+ * matching NeoLemmix style, for export-nxlv.
+ */
+
+/// The two files which make up a graphics set.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SetFiles {
+    pub ground_file : String,
+    pub vgagr_file : String,
+}
+
+impl SetFiles {
+    /// The files for set @num in the standard numbering: groundNo.dat and vgagrN.dat.
+    pub fn standard(num : u16) -> SetFiles {
+        SetFiles { ground_file : format!("ground{}o.dat", num), vgagr_file : format!("vgagr{}.dat", num) }
+    }
+
+    /// The files for a set with ground file @ground_file, and the vgagr file named to match
+    /// (ohno_ground2o.dat goes with ohno_vgagr2.dat). None if the name doesn't say which.
+    pub fn from_ground_file(ground_file : &str) -> Option<SetFiles> {
+        let lower = ground_file.to_ascii_lowercase();
+        let start = lower.rfind("ground")?;
+        let digits_start = start + "ground".len();
+        let digits_end = digits_start + lower[digits_start..].find(|c : char| !c.is_ascii_digit())?;
+        if digits_end == digits_start || !lower[digits_end..].starts_with('o') {
+            return None;
+        }
+        let vgagr_file = format!("{}vgagr{}{}", &ground_file[..start], &ground_file[digits_start..digits_end], &ground_file[digits_end + 1..]);
+        Some(SetFiles { ground_file : ground_file.to_string(), vgagr_file })
+    }
+}
+
+/// Which files each graphics set number means.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SetMap {
+    pub sets : BTreeMap<u16, SetFiles>,
+    /// The NeoLemmix style for each set, where one's given.
+    pub styles : BTreeMap<u16, String>,
+}
+
+fn duplicate_message(num : u16) -> String {
+    format!("Graphics set {} is in the set map more than once", num)
+}
+
+impl SetMap {
+    /// Parse a map given on the command line, as a comma-separated list of "num=groundfile".
+    pub fn from_arg(arg : &str) -> Result<SetMap, String> {
+        let mut map = SetMap::default();
+        for item in arg.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+            let (num, ground_file) = match item.split_once('=') {
+                Some(pair) => pair,
+                None => return Err(format!("Invalid set map entry \"{}\" (should be <set>=<ground file>)", item)),
+            };
+            let num = match num.trim().parse::<u16>() {
+                Ok(num) => num,
+                Err(_) => return Err(format!("Invalid graphics set number \"{}\" in the set map", num)),
+            };
+            let files = match SetFiles::from_ground_file(ground_file.trim()) {
+                Some(files) => files,
+                None => return Err(format!("Can't tell which vgagr file goes with \"{}\" (name it in a set map file instead)", ground_file)),
+            };
+            map.insert(num, files)?;
+        }
+        Ok(map)
+    }
+
+    /// Parse a set map file, made up of "Set num "groundfile" ["vgagrfile"] [Style "style"]"
+    /// lines.
+    pub fn parse(lex : &mut parser::Lexer) -> Result<SetMap, parser::ParseError> {
+        let mut map = SetMap::default();
+        while lex.peek_token()?.is_some() {
+            lex.expect_ident("Set")?;
+            let num = lex.get_int_in_range("graphics set number", 0, u16::MAX as i64)? as u16;
+            if map.sets.contains_key(&num) {
+                return Err(lex.invalid(duplicate_message(num)));
+            }
+            let ground_file = lex.get_string_literal()?;
+            let files = match lex.peek_token()? {
+                Some(parser::Token::StringLiteral(_)) => SetFiles { ground_file, vgagr_file : lex.get_string_literal()? },
+                _ => match SetFiles::from_ground_file(&ground_file) {
+                    Some(files) => files,
+                    None => return Err(lex.invalid(format!("Can't tell which vgagr file goes with \"{}\" (name it after the ground file)", ground_file))),
+                },
+            };
+            map.sets.insert(num, files);
+            if lex.is_next_ident("Style") {
+                lex.next_token()?;
+                map.styles.insert(num, lex.get_string_literal()?);
+            }
+        }
+        Ok(map)
+    }
+
+    fn insert(&mut self, num : u16, files : SetFiles) -> Result<(), String> {
+        if self.sets.insert(num, files).is_some() {
+            return Err(duplicate_message(num));
+        }
+        Ok(())
+    }
+
+    /// The files for graphics set @num, if it's in the map.
+    pub fn get(&self, num : u16) -> Option<&SetFiles> {
+        self.sets.get(&num)
+    }
+
+    /// The NeoLemmix style for graphics set @num, if the map gives one.
+    pub fn style(&self, num : u16) -> Option<&str> {
+        self.styles.get(&num).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(ground_file : &str, vgagr_file : &str) -> SetFiles {
+        SetFiles { ground_file : ground_file.to_string(), vgagr_file : vgagr_file.to_string() }
+    }
+
+    #[test]
+    fn set_files_from_ground_file() {
+        assert_eq!(SetFiles::from_ground_file("ground3o.dat"), Some(SetFiles::standard(3)));
+        assert_eq!(SetFiles::from_ground_file("ohno_GROUND12O.DAT"), Some(files("ohno_GROUND12O.DAT", "ohno_vgagr12.DAT")));
+        assert_eq!(SetFiles::from_ground_file("ground.dat"), None);
+        assert_eq!(SetFiles::from_ground_file("ground1x.dat"), None);
+    }
+
+    #[test]
+    fn set_map_from_arg() {
+        let map = SetMap::from_arg("5=ohno_ground0o.dat, 6=ohno_ground1o.dat").unwrap();
+        assert_eq!(map.get(5), Some(&files("ohno_ground0o.dat", "ohno_vgagr0.dat")));
+        assert_eq!(map.get(6), Some(&files("ohno_ground1o.dat", "ohno_vgagr1.dat")));
+        assert_eq!(map.get(0), None);
+    }
+
+    #[test]
+    fn set_map_parse() {
+        let map = SetMap::parse(&mut parser::Lexer::from_str("Set 0 \"ground3o.dat\" Style \"orig_pillar\"\nSet 9 \"xmas.dat\" \"xmasgr.dat\"\n")).unwrap();
+        assert_eq!(map.sets.len(), 2);
+        assert_eq!(map.get(0), Some(&SetFiles::standard(3)));
+        assert_eq!(map.get(9), Some(&files("xmas.dat", "xmasgr.dat")));
+        assert_eq!((map.style(0), map.style(9)), (Some("orig_pillar"), None));
+    }
+
+    #[test]
+    fn set_map_duplicate() {
+        assert_eq!(SetMap::from_arg("5=ground0o.dat,5=ground1o.dat").unwrap_err(), "Graphics set 5 is in the set map more than once");
+        let err = SetMap::parse(&mut parser::Lexer::from_str("Set 5 \"ground0o.dat\"\nSet 5 \"ground1o.dat\"\n")).unwrap_err();
+        assert_eq!(err.to_string(), "2:5: Graphics set 5 is in the set map more than once");
+    }
+}
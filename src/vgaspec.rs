@@ -0,0 +1,274 @@
+/*
+ * modlem: A graphics importer/exporter for Lemmings
+ * Copyright (C) 2022–2026 David Gow <david@davidgow.net>
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use planar_bmp::{self, PaletteRGB, PlanarBMP};
+
+/*
+ * The special graphics levels (the ones with a SpecialGraphics number) replace the
+ * terrain with a single 960×160 picture from vgaspecN.dat. That's a .dat file with
+ * one section, which (once decompressed) holds:
+ *
+ *   0x00  The picture's 8 colours, as 6-bit VGA (r, g, b) values.
+ *   0x18  16 bytes of EGA palette, which aren't used by the VGA version.
+ *   0x28  The picture, as 4 strips of 960×40 pixels, each compressed separately.
+ *
+ * Each strip decompresses to 3 bit-planes, one after the other (like
+ * PlanarBMP::from_contiguous_data()). The compression is a simple run-length
+ * encoding: a byte n from 0x00 to 0x7F is followed by n + 1 bytes to copy as-is,
+ * one from 0x81 to 0xFF by a single byte to repeat 257 - n times, and 0x80 ends the
+ * strip.
+ *
+ * Colour 0 is transparent: it's where there's no terrain.
+ *
+ * When creating one, the EGA palette is filled in with the closest EGA colours to
+ * the VGA ones (twice over), which is good enough as the VGA version ignores it.
+ */
+
+pub const VGASPEC_WIDTH : usize = 960;
+pub const VGASPEC_HEIGHT : usize = 160;
+pub const VGASPEC_PLANES : usize = 3;
+pub const VGASPEC_NUM_COLOURS : usize = 1 << VGASPEC_PLANES;
+/// The height of each separately-compressed strip.
+pub const VGASPEC_STRIP_HEIGHT : usize = 40;
+/// The size of a decompressed strip, in bytes.
+const STRIP_SIZE : usize = VGASPEC_WIDTH / 8 * VGASPEC_STRIP_HEIGHT * VGASPEC_PLANES;
+/// The end-of-strip marker.
+const RLE_END : u8 = 0x80;
+
+/// A decoded vgaspecN.dat.
+#[derive(Clone)]
+pub struct VgaSpec {
+    /// The 8 colours, as 6-bit VGA values.
+    pub vga_palette : [u8; VGASPEC_NUM_COLOURS * 3],
+    pub ega_palette : [u8; 16],
+    /// The 960×160, 3-plane picture, with the VGA palette.
+    pub image : PlanarBMP,
+}
+
+impl VgaSpec {
+    /// Decode the (decompressed) section of a vgaspecN.dat file.
+    pub fn decode(data : &[u8]) -> std::io::Result<VgaSpec> {
+        let header_size = VGASPEC_NUM_COLOURS * 3 + 16;
+        if data.len() < header_size {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+                format!("vgaspec data is only {} bytes, too short for the palette", data.len())));
+        }
+        let mut vga_palette = [0_u8; VGASPEC_NUM_COLOURS * 3];
+        vga_palette.copy_from_slice(&data[..VGASPEC_NUM_COLOURS * 3]);
+        let mut ega_palette = [0_u8; 16];
+        ega_palette.copy_from_slice(&data[VGASPEC_NUM_COLOURS * 3..header_size]);
+
+        let palette = PaletteRGB::from_vga_data(VGASPEC_NUM_COLOURS, &vga_palette);
+        let mut image = PlanarBMP::new(VGASPEC_WIDTH, VGASPEC_HEIGHT, VGASPEC_PLANES, &palette).unwrap();
+        let mut pos = header_size;
+        for strip_num in 0..VGASPEC_HEIGHT / VGASPEC_STRIP_HEIGHT {
+            let strip_data = decode_rle_strip(data, &mut pos).map_err(|err| {
+                std::io::Error::new(err.kind(), format!("strip {}: {}", strip_num, err))
+            })?;
+            let strip = PlanarBMP::from_contiguous_data(&strip_data, VGASPEC_WIDTH, VGASPEC_STRIP_HEIGHT, VGASPEC_PLANES, &palette);
+            image.blit(&strip, 0, strip_num * VGASPEC_STRIP_HEIGHT);
+        }
+        Ok(VgaSpec { vga_palette, ega_palette, image })
+    }
+
+    /// The picture's palette.
+    pub fn palette(&self) -> PaletteRGB {
+        PaletteRGB::from_vga_data(VGASPEC_NUM_COLOURS, &self.vga_palette)
+    }
+
+    /// Make special graphics from @image, which must be 960×160 and only use colours 0 to 7.
+    /// The palette is taken from the image's first 8 colours.
+    pub fn from_image(image : &PlanarBMP) -> std::io::Result<VgaSpec> {
+        if image.width != VGASPEC_WIDTH || image.height != VGASPEC_HEIGHT {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+                format!("The image is {}×{}, but special graphics must be {}×{}", image.width, image.height, VGASPEC_WIDTH, VGASPEC_HEIGHT)));
+        }
+        let mut out_image = PlanarBMP::new(VGASPEC_WIDTH, VGASPEC_HEIGHT, VGASPEC_PLANES, &PaletteRGB::new(VGASPEC_NUM_COLOURS)).unwrap();
+        for y in 0..VGASPEC_HEIGHT {
+            for x in 0..VGASPEC_WIDTH {
+                let value = image.get_packed_pixel(x, y);
+                if value as usize >= VGASPEC_NUM_COLOURS {
+                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+                        format!("The image uses colour {} (at {}, {}), but special graphics can only use colours 0 to {}",
+                                value, x, y, VGASPEC_NUM_COLOURS - 1)));
+                }
+                out_image.pset(x, y, value);
+            }
+        }
+
+        let colours = image.rgb_palette();
+        let mut vga_palette = [0_u8; VGASPEC_NUM_COLOURS * 3];
+        let mut ega_palette = [0_u8; 16];
+        for i in 0..VGASPEC_NUM_COLOURS {
+            let (r, g, b) = colours.get(i).copied().unwrap_or((0, 0, 0));
+            vga_palette[i * 3..i * 3 + 3].copy_from_slice(&[r >> 2, g >> 2, b >> 2]);
+            ega_palette[i] = planar_bmp::nearest_ega_value(r, g, b);
+            ega_palette[i + VGASPEC_NUM_COLOURS] = ega_palette[i];
+        }
+        out_image.set_palette(&PaletteRGB::from_vga_data(VGASPEC_NUM_COLOURS, &vga_palette));
+        Ok(VgaSpec { vga_palette, ega_palette, image : out_image })
+    }
+
+    /// Encode as the (uncompressed) section of a vgaspecN.dat file.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&self.vga_palette);
+        data.extend_from_slice(&self.ega_palette);
+        for strip_y in (0..VGASPEC_HEIGHT).step_by(VGASPEC_STRIP_HEIGHT) {
+            let mut strip_data = Vec::with_capacity(STRIP_SIZE);
+            for plane in 0..VGASPEC_PLANES {
+                strip_data.extend(self.image.get_plane_data(plane, 0, strip_y, VGASPEC_WIDTH, VGASPEC_STRIP_HEIGHT));
+            }
+            encode_rle_strip(&strip_data, &mut data);
+        }
+        data
+    }
+}
+
+/// Decompress one strip of the picture, starting at @pos in @data, and leave @pos just after
+/// its end marker.
+fn decode_rle_strip(data : &[u8], pos : &mut usize) -> std::io::Result<Vec<u8>> {
+    let truncated = || std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "data ends in the middle of the strip");
+    let mut output = Vec::with_capacity(STRIP_SIZE);
+    loop {
+        let control = *data.get(*pos).ok_or_else(truncated)?;
+        *pos += 1;
+        match control {
+            RLE_END => break,
+            0x00..=0x7F => {
+                let count = control as usize + 1;
+                let literal = data.get(*pos..*pos + count).ok_or_else(truncated)?;
+                output.extend_from_slice(literal);
+                *pos += count;
+            }
+            _ => {
+                let value = *data.get(*pos).ok_or_else(truncated)?;
+                output.extend(std::iter::repeat_n(value, 257 - control as usize));
+                *pos += 1;
+            }
+        }
+        if output.len() > STRIP_SIZE {
+            break;
+        }
+    }
+    if output.len() != STRIP_SIZE {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+            format!("strip decompresses to {} bytes, not {}", output.len(), STRIP_SIZE)));
+    }
+    Ok(output)
+}
+
+/// Compress one strip of the picture (see decode_rle_strip()), appending it to @output.
+fn encode_rle_strip(data : &[u8], output : &mut Vec<u8>) {
+    // The length of the run of identical bytes starting at @pos.
+    let run_length = |pos : usize| data[pos..].iter().take(128).take_while(|&&b| b == data[pos]).count();
+    let mut pos = 0;
+    while pos < data.len() {
+        let run = run_length(pos);
+        if run >= 3 {
+            output.extend_from_slice(&[(257 - run) as u8, data[pos]]);
+            pos += run;
+            continue;
+        }
+        // Copy bytes as-is until the next run worth compressing.
+        let start = pos;
+        while pos < data.len() && pos - start < 128 && run_length(pos) < 3 {
+            pos += 1;
+        }
+        output.push((pos - start - 1) as u8);
+        output.extend_from_slice(&data[start..pos]);
+    }
+    output.push(RLE_END);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encode @count zero bytes, as runs of at most 128.
+    fn zero_runs(mut count : usize, out : &mut Vec<u8>) {
+        while count > 0 {
+            let run = count.min(128);
+            out.extend_from_slice(&[(257 - run) as u8, 0]);
+            count -= run;
+        }
+    }
+
+    #[test]
+    fn vgaspec_decode() {
+        let mut data : Vec<u8> = (0..24).collect();
+        data.extend_from_slice(&[0; 16]);
+        // The first strip starts with a literal 0xC0 (the first two pixels of plane 0), and the
+        // last strip's plane 2 ends with a run of 0xFF.
+        data.extend_from_slice(&[0x00, 0xC0]);
+        zero_runs(STRIP_SIZE - 1, &mut data);
+        data.push(RLE_END);
+        for _ in 0..2 {
+            zero_runs(STRIP_SIZE, &mut data);
+            data.push(RLE_END);
+        }
+        zero_runs(STRIP_SIZE - 3, &mut data);
+        data.extend_from_slice(&[0xFE, 0xFF, RLE_END]);
+
+        let vgaspec = VgaSpec::decode(&data).unwrap();
+        assert_eq!(vgaspec.vga_palette[23], 23);
+        assert_eq!(vgaspec.image.get_packed_pixel(0, 0), 1);
+        assert_eq!(vgaspec.image.get_packed_pixel(1, 0), 1);
+        assert_eq!(vgaspec.image.get_packed_pixel(2, 0), 0);
+        assert_eq!(vgaspec.image.get_packed_pixel(VGASPEC_WIDTH - 24, VGASPEC_HEIGHT - 1), 4);
+        assert_eq!(vgaspec.image.get_packed_pixel(VGASPEC_WIDTH - 25, VGASPEC_HEIGHT - 1), 0);
+        assert_eq!(vgaspec.image.get_packed_pixel(100, 100), 0);
+
+        // Missing the last end marker.
+        assert!(VgaSpec::decode(&data[..data.len() - 1]).is_err());
+        // A strip which is too long.
+        let mut long = data.clone();
+        long.insert(long.len() - 1, 0x00);
+        long.insert(long.len() - 1, 0x00);
+        assert!(VgaSpec::decode(&long).is_err());
+    }
+
+    #[test]
+    fn vgaspec_round_trip() {
+        let palette = PaletteRGB::from_vga_data(8, &[0, 0, 0, 63, 0, 0, 0, 63, 0, 0, 0, 63, 63, 63, 0, 63, 0, 63, 0, 63, 63, 63, 63, 63]);
+        let mut image = PlanarBMP::new(VGASPEC_WIDTH, VGASPEC_HEIGHT, 4, &palette).unwrap();
+        let mut seed : u32 = 1;
+        for y in 0..VGASPEC_HEIGHT {
+            for x in 0..VGASPEC_WIDTH {
+                // Noise in some places, and plain areas elsewhere, so both kinds of encoding are used.
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                let value = if x < 300 { (seed >> 16) as u8 % 8 } else if y > 100 { 5 } else { 0 };
+                image.pset(x, y, value);
+            }
+        }
+
+        let vgaspec = VgaSpec::from_image(&image).unwrap();
+        assert_eq!(&vgaspec.vga_palette[3..6], &[63, 0, 0]);
+        let data = vgaspec.encode();
+        let decoded = VgaSpec::decode(&data).unwrap();
+        assert_eq!(decoded.vga_palette, vgaspec.vga_palette);
+        assert_eq!(decoded.ega_palette, vgaspec.ega_palette);
+        assert_eq!(decoded.image.to_pal8_data(), image.to_pal8_data());
+        assert_eq!(decoded.encode(), data);
+
+        let small = image.crop(0, 0, 320, 160);
+        assert!(VgaSpec::from_image(&small).is_err());
+        image.pset(10, 10, 8);
+        let err = VgaSpec::from_image(&image).err().unwrap();
+        assert!(err.to_string().contains("uses colour 8 (at 10, 10)"));
+    }
+}
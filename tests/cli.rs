@@ -0,0 +1,288 @@
+/*
+ * modlem: A graphics importer/exporter for Lemmings
+ * Copyright (C) 2022–2026 David Gow <david@davidgow.net>
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Runs the modlem command with bad input, and checks how it reports it.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A directory of its own for a test to run modlem in, removed afterwards.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> TempDir {
+        let dir = std::env::temp_dir().join(format!("modlem_cli_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Run modlem in @dir with @args, returning its exit code and what it printed to stderr.
+fn modlem(dir: &Path, args: &[&str]) -> (Option<i32>, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_modlem"))
+        .args(args)
+        .current_dir(dir)
+        .env_remove("LEMMINGS_DIR")
+        .env_remove("MODLEM_DATA_DIR")
+        .output()
+        .unwrap();
+    (output.status.code(), String::from_utf8_lossy(&output.stderr).to_string())
+}
+
+#[test]
+fn cli_usage_errors() {
+    let dir = TempDir::new("usage");
+    let (code, stderr) = modlem(&dir.0, &["frobnicate"]);
+    assert_eq!(code, Some(2));
+    assert_eq!(stderr, "modlem: error: Unknown command \"frobnicate\" (modlem help lists them)\n");
+
+    let (code, stderr) = modlem(&dir.0, &["extract-lvl", "level000.dat", "five"]);
+    assert_eq!(code, Some(2));
+    assert!(stderr.starts_with("modlem: error: Invalid level index \"five\""), "{}", stderr);
+
+    // The usage of the command follows the problem.
+    let (code, stderr) = modlem(&dir.0, &["extract-dat"]);
+    assert_eq!(code, Some(2));
+    assert!(stderr.starts_with("modlem: error: "), "{}", stderr);
+    assert!(stderr.contains("Usage:\n\tmodlem extract-dat"), "{}", stderr);
+
+    // - isn't taken to mean standard input.
+    for args in &[&["list-levels", "-"][..], &["stats-levels", "-"], &["diff-levels", "-", "-"], &["render-all-levels", "-"]] {
+        let (code, stderr) = modlem(&dir.0, args);
+        assert_eq!(code, Some(2), "{:?}", args);
+        assert!(stderr.starts_with("modlem: error: modlem doesn't read from standard input"), "{}", stderr);
+    }
+
+    // Directories don't have a name to give the files made from them.
+    for args in &[&["extract-levels", ".."][..], &["extract-levels", "/"], &["render-all-levels", ".."]] {
+        let (code, stderr) = modlem(&dir.0, args);
+        assert_eq!(code, Some(2), "{:?}", args);
+        assert!(stderr.ends_with("isn't a file name\n"), "{}", stderr);
+    }
+}
+
+#[test]
+fn cli_data_errors() {
+    let dir = TempDir::new("data");
+    std::fs::write(dir.0.join("bad.000.txt"), "ReleaseRate 50\nLemmings 50\nRescue 10\nTime 5\nSkills { dig = five }\n").unwrap();
+    let (code, stderr) = modlem(&dir.0, &["create-levels", "bad"]);
+    assert_eq!(code, Some(3));
+    assert!(stderr.starts_with("modlem: error: bad.000.txt:5:16: "), "{}", stderr);
+    // The line's shown too.
+    assert!(stderr.contains("\n5 | Skills { dig = five }\n"), "{}", stderr);
+    assert!(!dir.0.join("bad.dat").exists());
+
+    // Stats out of the game's range are only refused with --strict.
+    std::fs::write(dir.0.join("odd.000.txt"), "ReleaseRate 0\nLemmings 50\nRescue 10\nTime 5\n").unwrap();
+    let (code, stderr) = modlem(&dir.0, &["create-levels", "odd", "--strict"]);
+    assert_eq!(code, Some(3));
+    assert!(stderr.contains("Warning: Invalid release rate 0 (must be from 1 to 99) (in odd.000.txt)\n"), "{}", stderr);
+    assert!(!dir.0.join("odd.dat").exists());
+    assert_eq!(modlem(&dir.0, &["create-levels", "odd"]).0, Some(0));
+    assert!(dir.0.join("odd.dat").exists());
+
+    std::fs::write(dir.0.join("level000.dat"), b"Not a .dat file at all").unwrap();
+    let (code, stderr) = modlem(&dir.0, &["extract-lvl", "level000.dat", "0"]);
+    assert_eq!(code, Some(3));
+    assert!(stderr.starts_with("modlem: error: "), "{}", stderr);
+
+    // A cut-off last section is an error, not the end of the file.
+    std::fs::write(dir.0.join("level001.000"), [0u8; 2048]).unwrap();
+    std::fs::write(dir.0.join("level001.001"), [0u8; 2048]).unwrap();
+    assert_eq!(modlem(&dir.0, &["-q", "create-dat", "level001"]).0, Some(0));
+    let level_dat = std::fs::read(dir.0.join("level001.dat")).unwrap();
+    std::fs::write(dir.0.join("level001.dat"), &level_dat[..level_dat.len() - 4]).unwrap();
+    for command in ["list-levels", "extract-levels"] {
+        let (code, stderr) = modlem(&dir.0, &[command, "level001.dat"]);
+        assert_eq!(code, Some(3), "{}", command);
+        assert!(stderr.starts_with("modlem: error: Error reading level001.dat: section 1 at offset "), "{}", stderr);
+    }
+    for command in ["render-level", "extract-lvl", "export-nxlv"] {
+        let (code, stderr) = modlem(&dir.0, &[command, "level001.dat", "1"]);
+        assert_eq!(code, Some(3), "{}", command);
+        assert!(stderr.starts_with("modlem: error: Error reading level001.dat: section 1 at offset "), "{}", stderr);
+    }
+    let (code, stderr) = modlem(&dir.0, &["extract-lvl", "level001.dat", "2"]);
+    assert_eq!(code, Some(3));
+    assert!(stderr.starts_with("modlem: error: Error reading level001.dat: section 1 at offset "), "{}", stderr);
+    std::fs::write(dir.0.join("level001.dat"), &level_dat).unwrap();
+    let (code, stderr) = modlem(&dir.0, &["extract-lvl", "level001.dat", "2"]);
+    assert_eq!(code, Some(3));
+    assert_eq!(stderr, "modlem: error: level001.dat only has 2 sections\n");
+}
+
+#[test]
+fn cli_graphics_set_errors() {
+    // Terrain piece 0 says it's 248×200, which is far more than the 64 bytes of terrain data.
+    let dir = TempDir::new("graphics_set");
+    let mut ground = vec![0u8; 1056];
+    ground[448..450].copy_from_slice(&[248, 200]);
+    std::fs::write(dir.0.join("ground0o.dat"), &ground).unwrap();
+    std::fs::write(dir.0.join("vgagr0.000"), [0u8; 64]).unwrap();
+    std::fs::write(dir.0.join("vgagr0.001"), [0u8; 64]).unwrap();
+    assert_eq!(modlem(&dir.0, &["-q", "create-dat", "vgagr0"]).0, Some(0));
+    let (code, stderr) = modlem(&dir.0, &["extract-set", "0"]);
+    assert_eq!(code, Some(3));
+    assert!(stderr.contains("terrain piece 0: its graphics"), "{}", stderr);
+}
+
+#[test]
+fn cli_check_level() {
+    // Sections which aren't levels, or files without any levels, don't pass.
+    let dir = TempDir::new("check_level");
+    std::fs::write(dir.0.join("pack.000"), b"Let's go!").unwrap();
+    assert_eq!(modlem(&dir.0, &["-q", "create-dat", "pack"]).0, Some(0));
+    assert_eq!(modlem(&dir.0, &["check-level", "pack.dat"]).0, Some(1));
+    std::fs::write(dir.0.join("empty.dat"), b"").unwrap();
+    assert_eq!(modlem(&dir.0, &["check-level", "empty.dat"]).0, Some(1));
+}
+
+#[test]
+fn cli_io_errors() {
+    let dir = TempDir::new("io");
+    let (code, stderr) = modlem(&dir.0, &["extract-dat", "level000"]);
+    assert_eq!(code, Some(4));
+    assert!(stderr.starts_with("modlem: error: Couldn't find level000.dat"), "{}", stderr);
+
+    let (code, stderr) = modlem(&dir.0, &["create-oddtable", "missing.txt"]);
+    assert_eq!(code, Some(4));
+    assert!(stderr.starts_with("modlem: error: Error opening missing.txt: "), "{}", stderr);
+}
+
+#[test]
+fn cli_option_values() {
+    // Values which look like the options every command takes are still values.
+    let dir = TempDir::new("option_values");
+    let (code, stderr) = modlem(&dir.0, &["-q", "new-level", "--blank", "-o", "-v"]);
+    assert_eq!(code, Some(0), "{}", stderr);
+    assert!(dir.0.join("-v").exists());
+    let (code, stderr) = modlem(&dir.0, &["new-level", "--out", "--force", "--blank", "--quiet"]);
+    assert_eq!(code, Some(0), "{}", stderr);
+    assert_eq!(stderr, "");
+    assert!(dir.0.join("--force").exists());
+}
+
+#[test]
+fn cli_batch() {
+    let dir = TempDir::new("batch");
+    std::fs::write(dir.0.join("pack.000"), b"Let's go!").unwrap();
+    std::fs::write(dir.0.join("build.txt"), "# Each command sees what the last one wrote.\n\
+                                            create-dat pack --no-backup\n\
+                                            extract-dat pack --force\n\
+                                            extract-lvl missing.dat 3\n\
+                                            create-dat pack\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_modlem"))
+        .args(["-q", "batch", "build.txt"])
+        .current_dir(&dir.0)
+        .env_remove("LEMMINGS_DIR")
+        .env_remove("MODLEM_DATA_DIR")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(4));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with("modlem: error: build.txt:4: Couldn't find missing.dat"), "{}", stderr);
+    assert!(stderr.ends_with("modlem: error: 1 of the 4 commands in build.txt failed\n"), "{}", stderr);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let outcomes: Vec<&str> = stdout.lines().map(|line| line.split_whitespace().nth(1).unwrap()).collect();
+    assert_eq!(outcomes, ["ok", "ok", "failed", "skipped"]);
+    assert!(!dir.0.join("pack.dat.bak").exists());
+
+    let (code, _) = modlem(&dir.0, &["-q", "batch", "build.txt", "--keep-going"]);
+    assert_eq!(code, Some(4));
+    assert!(dir.0.join("pack.dat.bak").exists());
+}
+
+#[test]
+fn cli_diff_dat() {
+    let dir = TempDir::new("diff_dat");
+    std::fs::write(dir.0.join("pack.000"), b"Let's go!").unwrap();
+    std::fs::write(dir.0.join("pack.001"), b"Oh no!").unwrap();
+    assert_eq!(modlem(&dir.0, &["-q", "create-dat", "pack"]).0, Some(0));
+    std::fs::rename(dir.0.join("pack.dat"), dir.0.join("original.dat")).unwrap();
+    assert_eq!(modlem(&dir.0, &["-q", "create-dat", "pack"]).0, Some(0));
+    assert_eq!(modlem(&dir.0, &["diff-dat", "original.dat", "pack.dat"]).0, Some(0));
+
+    std::fs::write(dir.0.join("pack.001"), b"Oh yes!").unwrap();
+    assert_eq!(modlem(&dir.0, &["-q", "create-dat", "pack", "--no-backup"]).0, Some(0));
+    assert_eq!(modlem(&dir.0, &["diff-dat", "original.dat", "pack.dat"]).0, Some(1));
+
+    // Anything which isn't a .dat file is an error, rather than a difference.
+    let (code, stderr) = modlem(&dir.0, &["diff-dat", "original.dat", "pack.000"]);
+    assert_eq!(code, Some(3));
+    assert!(stderr.starts_with("modlem: error: Error reading pack.000: "), "{}", stderr);
+}
+
+#[test]
+fn cli_info() {
+    let dir = TempDir::new("info");
+    std::fs::write(dir.0.join("pack.000"), [0_u8; 2048]).unwrap();
+    assert_eq!(modlem(&dir.0, &["-q", "create-dat", "pack"]).0, Some(0));
+    let output = Command::new(env!("CARGO_BIN_EXE_modlem"))
+        .args(["info", "pack.dat"])
+        .current_dir(&dir.0)
+        .env_remove("LEMMINGS_DIR")
+        .env_remove("MODLEM_DATA_DIR")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("look like a level file (levelNNN.dat) with 1 section\n"), "{}", stdout);
+
+    // Anything which isn't a Lemmings file is still fine.
+    assert_eq!(modlem(&dir.0, &["info", "pack.000"]).0, Some(0));
+    std::fs::write(dir.0.join("empty.dat"), b"").unwrap();
+    assert_eq!(modlem(&dir.0, &["info", "empty.dat"]).0, Some(0));
+}
+
+#[test]
+fn cli_extract_all_levels() {
+    // Level files are found by what's in them, as the demos' aren't called levelNNN.dat.
+    let dir = TempDir::new("extract_all_levels");
+    std::fs::write(dir.0.join("demo.000"), [0_u8; 2048]).unwrap();
+    std::fs::write(dir.0.join("notes.000"), b"Not a level").unwrap();
+    assert_eq!(modlem(&dir.0, &["-q", "create-dat", "demo"]).0, Some(0));
+    assert_eq!(modlem(&dir.0, &["-q", "create-dat", "notes"]).0, Some(0));
+    let (code, stderr) = modlem(&dir.0, &["-q", "--data-dir", ".", "extract-all", "--out-dir", "out"]);
+    assert_eq!(code, Some(0), "{}", stderr);
+    assert!(dir.0.join("out/levels/demo.000.txt").exists());
+    assert!(!dir.0.join("out/levels/notes.000").exists());
+}
+
+#[test]
+fn cli_json() {
+    let dir = TempDir::new("json");
+    std::fs::write(dir.0.join("pack.000"), [0_u8; 2048]).unwrap();
+    assert_eq!(modlem(&dir.0, &["-q", "create-dat", "pack"]).0, Some(0));
+    for args in [&["info", "pack.dat", "--json"][..], &["list-levels", "pack.dat", "--json"]] {
+        let output = Command::new(env!("CARGO_BIN_EXE_modlem"))
+            .args(args)
+            .current_dir(&dir.0)
+            .env_remove("LEMMINGS_DIR")
+            .env_remove("MODLEM_DATA_DIR")
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(0));
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("\"file\": \"pack.dat\""), "{}", stdout);
+    }
+}